@@ -1,53 +1,394 @@
+use std::collections::HashSet;
+use std::path::PathBuf;
 use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
 use quote::quote;
-use serde_json::from_str;
-use syn::{parse_macro_input, LitStr};
+use serde_json::{from_str, Value};
+use syn::parse::{Parse, ParseStream};
+use syn::{parse_macro_input, Ident, LitStr, Path, Token};
 use serde::Deserialize;
 
 #[derive(Deserialize)]
-struct StructField {
+struct Abi {
+    headers: AbiHeader,
+    functions: Vec<AbiFunction>,
+    classes: Vec<AbiClass>,
+}
+
+#[derive(Deserialize)]
+struct AbiHeader {
+    header: String,
+}
+
+#[derive(Deserialize)]
+struct AbiFunction {
     name: String,
-    typ: String,
+    #[serde(default)]
+    params: Vec<AbiField>,
+    result: String,
+    selector: String,
 }
 
+#[derive(Deserialize)]
+struct AbiClass {
+    name: String,
+    fields: Vec<AbiField>,
+}
+
+#[derive(Deserialize)]
+struct AbiField {
+    name: String,
+    #[serde(rename = "type")]
+    type_: String,
+}
+
+/// Generates one struct per ABI class, named after the class and deriving
+/// `Debug`, `Clone`, `PartialEq`, SCALE `Encode`/`Decode` and serde `Serialize`/`Deserialize`.
+///
+/// The input is either a path to an ABI JSON file (relative to the crate's `Cargo.toml`),
+/// or inline JSON holding a whole ABI, a single class or an array of classes:
+///
+/// ```ignore
+/// generate_struct!("orascript/output/orscript2ABI.json");
+/// generate_struct!(r#"{ "name": "CryptoValue", "fields": [{ "name": "usd", "type": "f32" }] }"#);
+/// ```
+///
+/// Classes with `u256` or `Fixed*` fields need the module defining those types, passed as
+/// `generate_struct!("abi.json", numeric = crate::core::numeric)`.
 #[proc_macro]
 pub fn generate_struct(input: TokenStream) -> TokenStream {
-    let input = parse_macro_input!(input as LitStr);
-    let json_str = input.value();
-
-    let fields: Vec<StructField> = match from_str(&json_str) {
-        Ok(fields) => fields,
-        Err(e) => {
-            return syn::Error::new(
-                input.span(),
-                format!("ERROR: Failed to parse JSON schema: {}", e),
-            )
-                .to_compile_error()
-                .into();
+    let input = parse_macro_input!(input as GenerateStructInput);
+    match expand(&input.source, input.numeric.as_ref()) {
+        Ok(output) => output.into(),
+        Err(message) => syn::Error::new(input.source.span(), message).to_compile_error().into(),
+    }
+}
+
+struct GenerateStructInput {
+    source: LitStr,
+    numeric: Option<Path>,
+}
+
+impl Parse for GenerateStructInput {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let source = input.parse()?;
+        let numeric = parse_numeric(input)?;
+        Ok(GenerateStructInput { source, numeric })
+    }
+}
+
+/// Parses the optional trailing `, numeric = path::to::numeric` argument, the module holding
+/// `U256` and the `Fixed*` types that big-integer and fixed-point fields map to.
+fn parse_numeric(input: ParseStream) -> syn::Result<Option<Path>> {
+    input.parse::<Option<Token![,]>>()?;
+    if input.is_empty() {
+        return Ok(None);
+    }
+    let key: Ident = input.parse()?;
+    if key != "numeric" {
+        return Err(syn::Error::new(key.span(), "expected `numeric = path::to::numeric`"));
+    }
+    input.parse::<Token![=]>()?;
+    let numeric = input.parse()?;
+    input.parse::<Option<Token![,]>>()?;
+    Ok(Some(numeric))
+}
+
+struct IncludeAbiInput {
+    client: Ident,
+    path: LitStr,
+    runtime: Path,
+    numeric: Option<Path>,
+}
+
+impl Parse for IncludeAbiInput {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let client = input.parse()?;
+        input.parse::<Token![,]>()?;
+        let path = input.parse()?;
+        input.parse::<Token![,]>()?;
+        let runtime = input.parse()?;
+        let numeric = parse_numeric(input)?;
+        Ok(IncludeAbiInput { client, path, runtime, numeric })
+    }
+}
+
+/// Generates a typed client module for the script described by an ABI JSON file.
+///
+/// `include_abi!(Orscript2, "orascript/output/orscript2ABI.json", crate::traits::traits::ScriptRuntime)`
+/// expands to a module `orscript2` holding one struct per ABI class and a `Orscript2` client with
+/// one method per function, e.g. `orscript2::Orscript2::process(&registry, json) -> anyhow::Result<orscript2::Output>`.
+/// Methods run the script through the given runtime trait, which provides
+/// `fn call(&self, script_hash: &str, selector: &str, input: &str) -> anyhow::Result<Vec<u8>>`,
+/// by selector and decode the SCALE output into the result class.
+///
+/// Like `generate_struct!`, a trailing `numeric = path::to::numeric` provides the big-integer and
+/// fixed-point types.
+///
+/// Parameters follow the ABI: `(json: string)` and `(json_ptr: usize, len: usize)` both take the
+/// input document as `json: &str`, other signatures cannot be passed by the runtime and are rejected.
+#[proc_macro]
+pub fn include_abi(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as IncludeAbiInput);
+    match expand_client(&input) {
+        Ok(output) => output.into(),
+        Err(message) => syn::Error::new(input.path.span(), message).to_compile_error().into(),
+    }
+}
+
+fn expand_client(input: &IncludeAbiInput) -> Result<TokenStream2, String> {
+    let (json_str, path) = read_source(&input.path.value())?;
+    let path = path.ok_or("ERROR: include_abi! expects a path to an ABI JSON file")?;
+    let abi: Abi = from_str(&json_str)
+        .map_err(|e| format!("ERROR: Failed to parse ABI {:?}: {}", path, e))?;
+    let structs = class_structs(&abi.classes, input.numeric.as_ref())?;
+    let class_names: HashSet<&str> = abi.classes.iter().map(|class| class.name.as_str()).collect();
+    let runtime = &input.runtime;
+
+    let methods = abi.functions.iter().map(|function| {
+        if !class_names.contains(function.result.as_str()) {
+            return Err(format!("ERROR: result {:?} of function {} is not an ABI class", function.result, function.name));
         }
-    };
+        let name = field_ident(&function.name);
+        let input = input_param(function)?;
+        let result = Ident::new(&function.result, Span::call_site());
+        let selector = &function.selector;
+        let doc = format!("Runs `{}` (selector `{}`) and decodes its `{}` result.", function.name, selector, function.result);
+        Ok(quote! {
+            #[doc = #doc]
+            pub fn #name(runtime: &impl #runtime, #input: &str) -> ::anyhow::Result<#result> {
+                let output = runtime.call(Self::SCRIPT_HASH, #selector, #input)?;
+                <#result as ::parity_scale_codec::DecodeAll>::decode_all(&mut &output[..])
+                    .map_err(|e| ::anyhow::anyhow!("ERROR: Failed to decode {} output: {}", #selector, e))
+            }
+        })
+    }).collect::<Result<Vec<_>, String>>()?;
+
+    let client = &input.client;
+    let module = Ident::new(&to_snake_case(&client.to_string()), client.span());
+    let script_hash = &abi.headers.header;
+    let path = path.to_string_lossy().into_owned();
+    Ok(quote! {
+        pub mod #module {
+            const _: &[u8] = include_bytes!(#path);
+
+            #(#structs)*
 
-    let fields_typ = fields.iter().map(|field| {
-        let name = syn::Ident::new(&field.name, input.span());
-        let typ: syn::Type = match field.typ.as_str() {
-            "i32" => syn::parse_quote! { i32 },
-            "i64" => syn::parse_quote! { i64 },
-            "f32" => syn::parse_quote! { f32 },
-            "f64" => syn::parse_quote! { f64 },
-            "string" => syn::parse_quote! { String },
-            _ => syn::parse_quote! { () },
-        };
-        quote! {
-            pub #name: #typ
+            #[allow(dead_code)]
+            pub struct #client;
+
+            #[allow(dead_code)]
+            impl #client {
+                /// Hash of the wasm the ABI was generated from.
+                pub const SCRIPT_HASH: &'static str = #script_hash;
+
+                #(#methods)*
+            }
+        }
+    })
+}
+
+/// Name of the input parameter of a function taking the input document the way the runtime
+/// passes it, as a string or as a pointer and length.
+fn input_param(function: &AbiFunction) -> Result<Ident, String> {
+    let name = match function.params.as_slice() {
+        [param] if param.type_ == "string" => param.name.as_str(),
+        [ptr, len] if ptr.type_ == "usize" && len.type_ == "usize" => ptr.name.strip_suffix("_ptr").unwrap_or(&ptr.name),
+        params => {
+            let params: Vec<String> = params.iter().map(|param| format!("{}: {}", param.name, param.type_)).collect();
+            return Err(format!("ERROR: function {}({}) does not take the input as a string or pointer and length", function.name, params.join(", ")));
         }
+    };
+    Ok(field_ident(name))
+}
+
+fn expand(input: &LitStr, numeric: Option<&Path>) -> Result<TokenStream2, String> {
+    let (json_str, tracked_file) = read_source(&input.value())?;
+    let json: Value = from_str(&json_str)
+        .map_err(|e| format!("ERROR: Failed to parse JSON schema: {}", e))?;
+    let classes_json = match json {
+        Value::Object(ref object) if object.contains_key("classes") => object["classes"].clone(),
+        Value::Object(_) => Value::Array(vec![json]),
+        array => array,
+    };
+    let classes: Vec<AbiClass> = serde_json::from_value(classes_json)
+        .map_err(|e| format!("ERROR: Failed to parse ABI classes: {}", e))?;
+    let structs = class_structs(&classes, numeric)?;
+
+    // Makes cargo rebuild the invoking crate when the ABI file changes.
+    let tracking = tracked_file.map(|path| {
+        let path = path.to_string_lossy().into_owned();
+        quote! { const _: &[u8] = include_bytes!(#path); }
     });
 
-    let struct_name = syn::Ident::new("ASCStruct", input.span());
-    let output = quote! {
-        pub struct #struct_name {
-            #(#fields_typ),*
+    Ok(quote! {
+        #tracking
+        #(#structs)*
+    })
+}
+
+/// Returns inline JSON as is, anything else is read as a file relative to the crate's `Cargo.toml`.
+fn read_source(source: &str) -> Result<(String, Option<PathBuf>), String> {
+    let trimmed = source.trim_start();
+    if trimmed.starts_with('{') || trimmed.starts_with('[') {
+        return Ok((source.to_string(), None));
+    }
+    let manifest_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap_or_default();
+    let path = PathBuf::from(manifest_dir).join(source);
+    let content = std::fs::read_to_string(&path)
+        .map_err(|e| format!("ERROR: Failed to read ABI file {:?}: {}", path, e))?;
+    Ok((content, Some(path)))
+}
+
+fn class_structs(classes: &[AbiClass], numeric: Option<&Path>) -> Result<Vec<TokenStream2>, String> {
+    let class_names: HashSet<&str> = classes.iter().map(|class| class.name.as_str()).collect();
+
+    classes.iter().map(|class| {
+        let struct_name = syn::Ident::new(&class.name, Span::call_site());
+        let fields = class.fields.iter().map(|field| {
+            let name = field_ident(&field.name);
+            let typ = rust_type(&field.type_, &class_names, numeric)
+                .map_err(|e| format!("ERROR: field {}.{}: {}", class.name, field.name, e))?;
+            Ok(quote! {
+                pub #name: #typ
+            })
+        }).collect::<Result<Vec<_>, String>>()?;
+        Ok(quote! {
+            #[allow(dead_code)]
+            #[derive(Debug, Clone, PartialEq, ::parity_scale_codec_derive::Encode, ::parity_scale_codec_derive::Decode, ::serde::Serialize, ::serde::Deserialize)]
+            pub struct #struct_name {
+                #(#fields),*
+            }
+        })
+    }).collect()
+}
+
+fn to_snake_case(name: &str) -> String {
+    let mut snake = String::new();
+    for (i, c) in name.chars().enumerate() {
+        if c.is_uppercase() {
+            if i > 0 {
+                snake.push('_');
+            }
+            snake.extend(c.to_lowercase());
+        } else {
+            snake.push(c);
+        }
+    }
+    snake
+}
+
+fn field_ident(name: &str) -> syn::Ident {
+    syn::parse_str::<syn::Ident>(name)
+        .unwrap_or_else(|_| syn::Ident::new_raw(name, Span::call_site()))
+}
+
+/// Splits the arguments of a two parameter generic at the comma outside of any nested `<>`, so
+/// `Map<string, Array<i32>>` and `Map<Map<u8, u8>, bool>` keep their inner types whole.
+fn split_generics(args: &str) -> Option<(&str, &str)> {
+    let mut depth = 0usize;
+    for (i, c) in args.char_indices() {
+        match c {
+            '<' => depth += 1,
+            '>' => depth = depth.checked_sub(1)?,
+            ',' if depth == 0 => return Some((&args[..i], &args[i + 1..])),
+            _ => {}
+        }
+    }
+    None
+}
+
+fn rust_type(typ: &str, classes: &HashSet<&str>, numeric: Option<&Path>) -> Result<syn::Type, String> {
+    let typ = typ.trim();
+    if let Some(inner) = typ.strip_suffix("| null").or_else(|| typ.strip_suffix("|null")) {
+        let inner = rust_type(inner, classes, numeric)?;
+        return Ok(syn::parse_quote! { ::std::option::Option<#inner> });
+    }
+    if let Some(inner) = typ.strip_prefix("Array<").or_else(|| typ.strip_prefix("StaticArray<")).and_then(|s| s.strip_suffix('>')) {
+        let inner = rust_type(inner, classes, numeric)?;
+        return Ok(syn::parse_quote! { ::std::vec::Vec<#inner> });
+    }
+    if let Some(inner) = typ.strip_prefix("Set<").and_then(|s| s.strip_suffix('>')) {
+        let inner = rust_type(inner, classes, numeric)?;
+        return Ok(syn::parse_quote! { ::std::collections::BTreeSet<#inner> });
+    }
+    if let Some((key, value)) = typ.strip_prefix("Map<").and_then(|s| s.strip_suffix('>')).and_then(split_generics) {
+        let key = rust_type(key, classes, numeric)?;
+        let value = rust_type(value, classes, numeric)?;
+        return Ok(syn::parse_quote! { ::std::collections::BTreeMap<#key, #value> });
+    }
+    let ty: syn::Type = match typ {
+        "bool" => syn::parse_quote! { bool },
+        "i8" => syn::parse_quote! { i8 },
+        "u8" => syn::parse_quote! { u8 },
+        "i16" => syn::parse_quote! { i16 },
+        "u16" => syn::parse_quote! { u16 },
+        "i32" | "isize" => syn::parse_quote! { i32 },
+        "u32" | "usize" => syn::parse_quote! { u32 },
+        "i64" => syn::parse_quote! { i64 },
+        "u64" => syn::parse_quote! { u64 },
+        "u128" => syn::parse_quote! { u128 },
+        "i128" => syn::parse_quote! { i128 },
+        "u256" | "FixedU64" | "FixedI64" | "FixedU128" | "FixedI128" => {
+            let numeric = numeric.ok_or_else(|| format!("type {} needs the `numeric = path::to::numeric` argument", typ))?;
+            let ident = syn::Ident::new(if typ == "u256" { "U256" } else { typ }, Span::call_site());
+            syn::parse_quote! { #numeric::#ident }
+        }
+        "f32" => syn::parse_quote! { f32 },
+        "f64" => syn::parse_quote! { f64 },
+        "string" => syn::parse_quote! { ::std::string::String },
+        "ArrayBuffer" | "Uint8Array" | "Uint8ClampedArray" => syn::parse_quote! { ::std::vec::Vec<u8> },
+        "Int8Array" => syn::parse_quote! { ::std::vec::Vec<i8> },
+        "Int16Array" => syn::parse_quote! { ::std::vec::Vec<i16> },
+        "Uint16Array" => syn::parse_quote! { ::std::vec::Vec<u16> },
+        "Int32Array" => syn::parse_quote! { ::std::vec::Vec<i32> },
+        "Uint32Array" => syn::parse_quote! { ::std::vec::Vec<u32> },
+        "Int64Array" => syn::parse_quote! { ::std::vec::Vec<i64> },
+        "Uint64Array" => syn::parse_quote! { ::std::vec::Vec<u64> },
+        "Float32Array" => syn::parse_quote! { ::std::vec::Vec<f32> },
+        "Float64Array" => syn::parse_quote! { ::std::vec::Vec<f64> },
+        class if classes.contains(class) => {
+            let ident = syn::Ident::new(class, Span::call_site());
+            syn::parse_quote! { #ident }
         }
+        other => return Err(format!("unsupported type {:?}", other)),
     };
+    Ok(ty)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    output.into()
-}
\ No newline at end of file
+    fn rust(typ: &str) -> String {
+        let numeric: Path = syn::parse_quote! { my::numeric };
+        let ty = rust_type(typ, &HashSet::from(["Price"]), Some(&numeric)).unwrap();
+        quote!(#ty).to_string().replace(' ', "")
+    }
+
+    #[test]
+    fn splits_map_generics_at_the_top_level_comma() {
+        assert_eq!(split_generics("string, Array<i32>"), Some(("string", " Array<i32>")));
+        assert_eq!(split_generics("Map<u8, u8>, Map<string, bool>"), Some(("Map<u8, u8>", " Map<string, bool>")));
+        assert_eq!(split_generics("Map<u8, u8>"), None);
+        assert_eq!(split_generics("u8>, u8"), None);
+    }
+
+    #[test]
+    fn maps_nested_generics() {
+        assert_eq!(rust("Map<string, Array<i32>>"), "::std::collections::BTreeMap<::std::string::String,::std::vec::Vec<i32>>");
+        assert_eq!(
+            rust("Map<Map<u8, u8>, Map<string, Price>>"),
+            "::std::collections::BTreeMap<::std::collections::BTreeMap<u8,u8>,::std::collections::BTreeMap<::std::string::String,Price>>",
+        );
+        assert_eq!(rust("Array<Map<string, Set<u64>>> | null"), "::std::option::Option<::std::vec::Vec<::std::collections::BTreeMap<::std::string::String,::std::collections::BTreeSet<u64>>>>");
+        assert!(rust_type("Map<string>", &HashSet::new(), None).is_err());
+    }
+
+    #[test]
+    fn numeric_types_come_from_the_given_module() {
+        assert_eq!(rust("u256"), "my::numeric::U256");
+        assert_eq!(rust("Array<FixedI128>"), "::std::vec::Vec<my::numeric::FixedI128>");
+        assert!(rust_type("FixedU64", &HashSet::new(), None).is_err());
+    }
+}
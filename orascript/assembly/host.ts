@@ -0,0 +1,17 @@
+// Functions provided by the node, see src/core/host.rs. Strings returned by the host are
+// allocated with `__new`, scripts using them are built with `--exportRuntime`.
+
+/** Handle of the script input. */
+export const INPUT: i32 = 0;
+
+/** Number at `path` (e.g. "$.bitcoin.usd"), NaN when it is missing or not a number. */
+@external("orascript", "json_get_f64")
+export declare function json_get_f64(doc: i32, path: string): f64;
+
+/** String at `path`, null when it is missing or not a string. */
+@external("orascript", "json_get_string")
+export declare function json_get_string(doc: i32, path: string): string | null;
+
+/** Length of the array at `path`, -1 when it is missing or not an array. */
+@external("orascript", "json_array_len")
+export declare function json_array_len(doc: i32, path: string): i32;
@@ -0,0 +1,237 @@
+use std::time::Duration;
+use anyhow::{anyhow, Result};
+use futures_util::{SinkExt, StreamExt};
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tokio::time::timeout;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
+use crate::oracle::keystore::decode_hex;
+use crate::traits::traits::{BlockRef, ChainClient};
+
+/// Time a node has to answer a request, or to confirm a subscription.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// JSON-RPC client of a Substrate node's HTTP endpoint, subscribing to finalized heads over its
+/// WebSocket endpoint when one is given.
+#[derive(Clone)]
+pub struct RpcChainClient {
+    url: String,
+    ws_url: Option<String>,
+    http: reqwest::Client,
+}
+
+#[derive(Deserialize)]
+struct RpcResponse {
+    #[serde(default)]
+    id: Option<u64>,
+    #[serde(default)]
+    result: Value,
+    #[serde(default)]
+    error: Option<RpcErrorBody>,
+}
+
+#[derive(Deserialize)]
+struct RpcErrorBody {
+    code: i64,
+    message: String,
+}
+
+#[derive(Deserialize)]
+struct Header {
+    /// Hex encoded block number.
+    number: String,
+}
+
+/// `chain_finalizedHead` notification of a `chain_subscribeFinalizedHeads` subscription.
+#[derive(Deserialize)]
+struct Notification {
+    method: String,
+    params: NotificationParams,
+}
+
+#[derive(Deserialize)]
+struct NotificationParams {
+    result: Header,
+}
+
+impl Header {
+    fn block_number(&self) -> Result<u64> {
+        u64::from_str_radix(self.number.trim_start_matches("0x"), 16)
+            .map_err(|e| anyhow!("ERROR: invalid block number {:?}: {}", self.number, e))
+    }
+}
+
+impl RpcResponse {
+    fn into_result<T: DeserializeOwned>(self, method: &str) -> Result<T> {
+        if let Some(error) = self.error {
+            return Err(anyhow!("ERROR: {} failed with {}: {}", method, error.code, error.message));
+        }
+        serde_json::from_value(self.result)
+            .map_err(|e| anyhow!("ERROR: unexpected {} result: {}", method, e))
+    }
+}
+
+impl RpcChainClient {
+    pub fn new(url: &str) -> Result<Self> {
+        let http = reqwest::Client::builder().timeout(REQUEST_TIMEOUT).build()
+            .map_err(|e| anyhow!("ERROR: failed to build the RPC client of {}: {}", url, e))?;
+        Ok(RpcChainClient { url: url.to_string(), ws_url: None, http })
+    }
+
+    /// Subscribes to finalized heads at the WebSocket endpoint `ws_url` instead of polling them,
+    /// every other request still goes over HTTP.
+    pub fn with_subscriptions(mut self, ws_url: &str) -> Self {
+        self.ws_url = Some(ws_url.to_string());
+        self
+    }
+
+    async fn call<T: DeserializeOwned>(&self, method: &str, params: Value) -> Result<T> {
+        let request = json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params });
+        let response: RpcResponse = self.http.post(&self.url).json(&request).send().await
+            .map_err(|e| anyhow!("ERROR: {} request to {} failed: {}", method, self.url, e))?
+            .json().await
+            .map_err(|e| anyhow!("ERROR: invalid {} response from {}: {}", method, self.url, e))?;
+        response.into_result(method)
+    }
+
+    /// Sends `chain_subscribeFinalizedHeads` and waits for the node to confirm it.
+    async fn subscribe(&self, ws_url: &str) -> Result<WebSocketStream<MaybeTlsStream<TcpStream>>> {
+        const METHOD: &str = "chain_subscribeFinalizedHeads";
+        let subscribe = async {
+            let (mut socket, _) = connect_async(ws_url).await
+                .map_err(|e| anyhow!("ERROR: failed to connect to {}: {}", ws_url, e))?;
+            let request = json!({ "jsonrpc": "2.0", "id": 1, "method": METHOD, "params": [] });
+            socket.send(Message::Text(request.to_string())).await
+                .map_err(|e| anyhow!("ERROR: {} request to {} failed: {}", METHOD, ws_url, e))?;
+            while let Some(message) = socket.next().await {
+                let message = message.map_err(|e| anyhow!("ERROR: {} request to {} failed: {}", METHOD, ws_url, e))?;
+                let Message::Text(text) = message else { continue };
+                let response: RpcResponse = serde_json::from_str(&text)
+                    .map_err(|e| anyhow!("ERROR: invalid {} response from {}: {}", METHOD, ws_url, e))?;
+                if response.id == Some(1) {
+                    let _subscription: Value = response.into_result(METHOD)?;
+                    return Ok(socket);
+                }
+            }
+            Err(anyhow!("ERROR: {} closed the connection before confirming {}", ws_url, METHOD))
+        };
+        timeout(REQUEST_TIMEOUT, subscribe).await
+            .map_err(|_| anyhow!("ERROR: {} request to {} timed out", METHOD, ws_url))?
+    }
+}
+
+impl ChainClient for RpcChainClient {
+    async fn finalized_head(&self) -> Result<BlockRef> {
+        let hash: String = self.call("chain_getFinalizedHead", json!([])).await?;
+        let header: Header = self.call("chain_getHeader", json!([hash])).await?;
+        Ok(BlockRef { number: header.block_number()?, hash })
+    }
+
+    async fn block_hash(&self, number: u64) -> Result<Option<String>> {
+        self.call("chain_getBlockHash", json!([number])).await
+    }
+
+    async fn storage(&self, key: &[u8], at: &str) -> Result<Option<Vec<u8>>> {
+        let key = format!("0x{}", hex::encode(key));
+        let value: Option<String> = self.call("state_getStorage", json!([key, at])).await?;
+        value.map(|value| decode_hex(&value)).transpose()
+    }
+
+    /// Notifications carry the header without its hash, which is looked up by number. The
+    /// receiver is closed once the node drops the connection.
+    async fn finalized_heads(&self) -> Result<Option<mpsc::Receiver<BlockRef>>> {
+        let Some(ws_url) = &self.ws_url else { return Ok(None) };
+        let mut socket = self.subscribe(ws_url).await?;
+        let (sender, heads) = mpsc::channel(16);
+        let client = self.clone();
+        tokio::spawn(async move {
+            while let Some(Ok(message)) = socket.next().await {
+                let Message::Text(text) = message else { continue };
+                let Ok(notification) = serde_json::from_str::<Notification>(&text) else { continue };
+                if notification.method != "chain_finalizedHead" {
+                    continue;
+                }
+                let head = match notification.params.result.block_number() {
+                    Ok(number) => client.block_hash(number).await
+                        .and_then(|hash| hash.ok_or_else(|| anyhow!("ERROR: finalized block {} has no hash", number)))
+                        .map(|hash| BlockRef { number, hash }),
+                    Err(e) => Err(e),
+                };
+                match head {
+                    Ok(head) => {
+                        if sender.send(head).await.is_err() {
+                            return;
+                        }
+                    }
+                    Err(e) => println!("{}", e),
+                }
+            }
+        });
+        Ok(Some(heads))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use tokio::net::TcpListener;
+    use tokio_tungstenite::accept_async;
+    use super::*;
+    use crate::chain::mock::{serve_mock_chain, MockChain};
+    use crate::core::testing::free_addr;
+
+    /// WebSocket endpoint answering `chain_subscribeFinalizedHeads` with `reply`, then pushing a
+    /// notification for every block number in `heads`.
+    async fn serve_subscription(reply: Value, heads: Vec<u64>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut socket = accept_async(stream).await.unwrap();
+            let Some(Ok(Message::Text(request))) = socket.next().await else { panic!("no subscription request") };
+            let request: Value = serde_json::from_str(&request).unwrap();
+            assert_eq!(request["method"], "chain_subscribeFinalizedHeads");
+            socket.send(Message::Text(reply.to_string())).await.unwrap();
+            for number in heads {
+                let header = json!({ "parentHash": "0x00", "number": format!("0x{:x}", number), "digest": { "logs": [] } });
+                let notification = json!({
+                    "jsonrpc": "2.0",
+                    "method": "chain_finalizedHead",
+                    "params": { "subscription": "sub", "result": header },
+                });
+                socket.send(Message::Text(notification.to_string())).await.unwrap();
+            }
+            socket.close(None).await.unwrap();
+        });
+        url
+    }
+
+    #[tokio::test]
+    async fn finalized_heads_are_pushed_over_the_subscription() {
+        let chain = Arc::new(MockChain::new(&[]));
+        let addr = free_addr();
+        tokio::spawn(serve_mock_chain(addr, chain.clone()));
+        let (first, second) = (chain.produce_block(&[]), chain.produce_block(&[]));
+        let http = RpcChainClient::new(&format!("http://{}", addr)).unwrap();
+        while http.finalized_head().await.is_err() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(http.finalized_heads().await.unwrap().is_none());
+
+        let ws_url = serve_subscription(json!({ "jsonrpc": "2.0", "id": 1, "result": "sub" }), vec![1, 2]).await;
+        let mut heads = http.clone().with_subscriptions(&ws_url).finalized_heads().await.unwrap().unwrap();
+        assert_eq!(heads.recv().await, Some(first));
+        assert_eq!(heads.recv().await, Some(second));
+        // Closed along with the connection, the listener then falls back to polling.
+        assert_eq!(heads.recv().await, None);
+
+        let error = json!({ "jsonrpc": "2.0", "id": 1, "error": { "code": -32601, "message": "Method not found" } });
+        let ws_url = serve_subscription(error, vec![]).await;
+        let e = http.with_subscriptions(&ws_url).finalized_heads().await.unwrap_err();
+        assert_eq!(e.to_string(), "ERROR: chain_subscribeFinalizedHeads failed with -32601: Method not found");
+    }
+}
@@ -0,0 +1,257 @@
+use std::sync::Arc;
+use std::time::Duration;
+use anyhow::{anyhow, Result};
+use parity_scale_codec::{Decode, Encode};
+use serde::Serialize;
+use tokio::sync::mpsc;
+use tokio::time::MissedTickBehavior;
+use crate::core::scheduler::{JobRequest, JobStatus, Scheduler};
+use crate::traits::traits::{BlockRef, ChainClient};
+use crate::utils::twox::storage_prefix;
+
+/// `pallet_oracle::Event::OracleRequested`. `System.Events` cannot be decoded without the runtime
+/// metadata, so the pallet also keeps the requests raised in a block in a storage value that is
+/// cleared on every block, which is what the listener reads.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub struct OracleRequested {
+    pub request_id: u64,
+    pub script_hash: [u8; 32],
+    /// Script input, a UTF-8 JSON document.
+    pub input: Vec<u8>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ListenerOptions {
+    /// Storage value holding the `Vec<OracleRequested>` of a block.
+    pub requests_key: Vec<u8>,
+    /// New finalized heads are polled at this interval while the client has no subscription.
+    pub poll_interval: Duration,
+    /// First block to process, the finalized head at startup when omitted.
+    pub from_block: Option<u64>,
+}
+
+impl Default for ListenerOptions {
+    fn default() -> Self {
+        ListenerOptions {
+            requests_key: default_requests_key().to_vec(),
+            poll_interval: Duration::from_secs(2),
+            from_block: None,
+        }
+    }
+}
+
+/// Outcome of one on-chain request.
+#[derive(Debug, Clone, Serialize)]
+pub struct ChainExecution {
+    pub request_id: u64,
+    pub script_hash: String,
+    /// Block the request was raised in.
+    pub block: u64,
+    #[serde(flatten)]
+    pub status: JobStatus,
+}
+
+/// `Oracle.BlockRequests`, the storage value `pallet_oracle` mirrors its requests into.
+pub fn default_requests_key() -> [u8; 32] {
+    storage_prefix("Oracle", "BlockRequests")
+}
+
+/// Follows the finalized heads of the chain over a `chain_subscribeFinalizedHeads`
+/// subscription, polling them every `poll_interval` while the client has none or its
+/// subscription is down. Every head is sent once, failed polls are retried.
+pub fn subscribe_finalized_heads<C: ChainClient>(client: Arc<C>, poll_interval: Duration) -> mpsc::Receiver<BlockRef> {
+    let (sender, heads) = mpsc::channel(16);
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(poll_interval);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        let mut subscription: Option<mpsc::Receiver<BlockRef>> = None;
+        let mut last: Option<u64> = None;
+        loop {
+            let head = match &mut subscription {
+                Some(pushed) => match pushed.recv().await {
+                    Some(head) => Ok(head),
+                    None => {
+                        println!("ERROR: finalized heads subscription closed, polling until it is renewed");
+                        subscription = None;
+                        continue;
+                    }
+                },
+                None => {
+                    interval.tick().await;
+                    match client.finalized_heads().await {
+                        Ok(pushed) => subscription = pushed,
+                        Err(e) => println!("{}", e),
+                    }
+                    client.finalized_head().await
+                }
+            };
+            match head {
+                Ok(head) if last.is_none_or(|last| head.number > last) => {
+                    last = Some(head.number);
+                    if sender.send(head).await.is_err() {
+                        return;
+                    }
+                }
+                Ok(_) => {}
+                Err(e) => println!("{}", e),
+            }
+        }
+    });
+    heads
+}
+
+/// Starts the listener: every request of a finalized block is queued on the scheduler and its
+/// outcome is sent to `executions`.
+pub fn start_chain_listener<C: ChainClient>(
+    client: Arc<C>,
+    scheduler: Scheduler,
+    options: ListenerOptions,
+    executions: mpsc::Sender<ChainExecution>,
+) {
+    tokio::spawn(async move {
+        let mut heads = subscribe_finalized_heads(client.clone(), options.poll_interval);
+        let mut next = options.from_block;
+        while let Some(head) = heads.recv().await {
+            let mut number = next.unwrap_or(head.number);
+            while number <= head.number {
+                // A block that fails to load is retried with the next head.
+                match requests_at(&*client, &options.requests_key, number, &head).await {
+                    Ok(requests) => {
+                        for request in requests {
+                            enqueue(&scheduler, number, request, &executions).await;
+                        }
+                    }
+                    Err(e) => {
+                        println!("{}", e);
+                        break;
+                    }
+                }
+                number += 1;
+            }
+            next = Some(number);
+            if executions.is_closed() {
+                return;
+            }
+        }
+    });
+}
+
+/// Requests raised in finalized block `number`, `head` being a finalized block at or after it.
+pub async fn requests_at<C: ChainClient>(client: &C, key: &[u8], number: u64, head: &BlockRef) -> Result<Vec<OracleRequested>> {
+    let hash = if number == head.number {
+        head.hash.clone()
+    } else {
+        client.block_hash(number).await?
+            .ok_or_else(|| anyhow!("ERROR: finalized block {} has no hash", number))?
+    };
+    let Some(value) = client.storage(key, &hash).await? else { return Ok(Vec::new()) };
+    Vec::<OracleRequested>::decode(&mut &value[..])
+        .map_err(|e| anyhow!("ERROR: failed to decode the requests of block {}: {}", number, e))
+}
+
+/// Queues a request, waiting while the scheduler queue is full so no request of a block is lost.
+async fn enqueue(scheduler: &Scheduler, block: u64, request: OracleRequested, executions: &mpsc::Sender<ChainExecution>) {
+    let script_hash = format!("0x{}", hex::encode(request.script_hash));
+    let submitted = match String::from_utf8(request.input) {
+        Ok(input) => scheduler.submit_wait(JobRequest { script_hash: script_hash.clone(), selector: None, input, timeout_ms: None }).await,
+        Err(_) => Err(anyhow!("ERROR: input of request {} is not UTF-8", request.request_id)),
+    };
+    let scheduler = scheduler.clone();
+    let executions = executions.clone();
+    tokio::spawn(async move {
+        let status = match submitted {
+            Ok(job_id) => scheduler.wait(job_id).await
+                .unwrap_or_else(|| JobStatus::Failed { error: format!("ERROR: job {} disappeared", job_id) }),
+            Err(e) => JobStatus::Failed { error: e.to_string() },
+        };
+        let _ = executions.send(ChainExecution { request_id: request.request_id, script_hash, block, status }).await;
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::fs;
+    use super::*;
+    use crate::chain::client::RpcChainClient;
+    use crate::chain::mock::{serve_mock_chain, MockChain};
+    use crate::core::runtime::DEFAULT_INPUT;
+    use crate::core::scheduler::SchedulerOptions;
+    use crate::core::testing::{free_addr, orscript2, temp_registry};
+    use crate::oracle::extrinsic::fixed_hex;
+
+    fn request(request_id: u64, script_hash: &str, input: &[u8]) -> OracleRequested {
+        OracleRequested { request_id, script_hash: fixed_hex(script_hash, "script hash").unwrap(), input: input.to_vec() }
+    }
+
+    fn options(from_block: u64) -> ListenerOptions {
+        ListenerOptions { poll_interval: Duration::from_millis(10), from_block: Some(from_block), ..Default::default() }
+    }
+
+    async fn collect(executions: &mut mpsc::Receiver<ChainExecution>, count: usize) -> BTreeMap<u64, ChainExecution> {
+        let mut collected = BTreeMap::new();
+        while collected.len() < count {
+            let execution = tokio::time::timeout(Duration::from_secs(30), executions.recv()).await.unwrap().unwrap();
+            collected.insert(execution.request_id, execution);
+        }
+        collected
+    }
+
+    #[tokio::test]
+    async fn requests_beyond_the_queue_capacity_wait_for_room() {
+        let (root, registry) = temp_registry("listener-queue");
+        let (wasm, abi) = orscript2();
+        let hash = registry.insert(&wasm, &abi).unwrap();
+        let expected = registry.execute(&hash, None, DEFAULT_INPUT, &Default::default()).unwrap();
+        let scheduler = Scheduler::start(registry, SchedulerOptions { queue_capacity: 1, workers: 1, ..Default::default() });
+        let chain = Arc::new(MockChain::new(&default_requests_key()));
+        let requests: Vec<_> = (0..5).map(|id| request(id, &hash, DEFAULT_INPUT.as_bytes())).collect();
+        chain.produce_block(&requests);
+        let (sender, mut executions) = mpsc::channel(16);
+        start_chain_listener(chain, scheduler, options(1), sender);
+        let executions = collect(&mut executions, requests.len()).await;
+        for (id, execution) in executions {
+            assert_eq!(execution.block, 1);
+            assert_eq!(execution.script_hash, hash);
+            assert_eq!(execution.status, JobStatus::Succeeded { output: format!("0x{}", hex::encode(&expected)) }, "request {}", id);
+        }
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn follows_the_mock_chain_over_rpc() {
+        let (root, registry) = temp_registry("listener-rpc");
+        let (wasm, abi) = orscript2();
+        let hash = registry.insert(&wasm, &abi).unwrap();
+        let scheduler = Scheduler::start(registry, SchedulerOptions::default());
+        let chain = Arc::new(MockChain::new(&default_requests_key()));
+        let addr = free_addr();
+        tokio::spawn(serve_mock_chain(addr, chain.clone()));
+        let client = Arc::new(RpcChainClient::new(&format!("http://{}", addr)).unwrap());
+        while client.finalized_head().await.is_err() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let missing = format!("0x{}", "11".repeat(32));
+        chain.produce_block(&[request(1, &hash, DEFAULT_INPUT.as_bytes())]);
+        chain.produce_block(&[]);
+        chain.produce_block(&[request(2, &missing, b"{}"), request(3, &hash, &[0xff])]);
+
+        assert!(requests_at(&*client, &default_requests_key(), 2, &chain.head()).await.unwrap().is_empty());
+        assert_eq!(requests_at(&*client, &default_requests_key(), 1, &chain.head()).await.unwrap(),
+            vec![request(1, &hash, DEFAULT_INPUT.as_bytes())]);
+
+        let (sender, mut executions) = mpsc::channel(16);
+        start_chain_listener(client, scheduler, options(1), sender);
+        let mut results = collect(&mut executions, 3).await;
+        assert_eq!(results[&1].block, 1);
+        assert!(matches!(results[&1].status, JobStatus::Succeeded { .. }), "{:?}", results[&1].status);
+        assert_eq!(results[&2].block, 3);
+        assert!(matches!(results[&2].status, JobStatus::Failed { .. }), "{:?}", results[&2].status);
+        assert_eq!(results.remove(&3).unwrap().status, JobStatus::Failed { error: "ERROR: input of request 3 is not UTF-8".to_string() });
+
+        // Blocks finalized later are picked up by the running listener.
+        chain.produce_block(&[request(4, &hash, DEFAULT_INPUT.as_bytes())]);
+        assert_eq!(collect(&mut executions, 1).await[&4].block, 4);
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,204 @@
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use anyhow::{anyhow, Result};
+use hyper::{Body, Request, Response, Server};
+use hyper::header::CONTENT_TYPE;
+use hyper::service::{make_service_fn, service_fn};
+use parity_scale_codec::Encode;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use sp_core_hashing::blake2_256;
+use crate::chain::listener::OracleRequested;
+use crate::oracle::extrinsic::fixed_hex;
+use crate::oracle::keystore::decode_hex;
+use crate::traits::traits::{BlockRef, ChainClient};
+
+type Storage = BTreeMap<Vec<u8>, Vec<u8>>;
+
+/// In-process chain for local runs: every produced block is final and carries a full copy of
+/// the storage, so any block can be queried like on an archive node.
+pub struct MockChain {
+    requests_key: Vec<u8>,
+    state: Mutex<MockState>,
+}
+
+struct MockState {
+    blocks: Vec<MockBlock>,
+    /// Writes applied with the next block.
+    pending: Vec<(Vec<u8>, Option<Vec<u8>>)>,
+}
+
+struct MockBlock {
+    hash: String,
+    storage: Storage,
+}
+
+/// A request of the JSON file given to `mock-chain`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct MockRequest {
+    pub request_id: u64,
+    pub script_hash: String,
+    pub input: String,
+}
+
+impl MockChain {
+    /// Starts the chain at an empty genesis block, requests are stored under `requests_key`.
+    pub fn new(requests_key: &[u8]) -> Self {
+        let genesis = MockBlock { hash: block_hash(0, &[0u8; 32]), storage: Storage::new() };
+        MockChain {
+            requests_key: requests_key.to_vec(),
+            state: Mutex::new(MockState { blocks: vec![genesis], pending: Vec::new() }),
+        }
+    }
+
+    /// Sets or, with `None`, removes a storage value from the next block on.
+    pub fn set_storage(&self, key: &[u8], value: Option<Vec<u8>>) {
+        self.state.lock().unwrap().pending.push((key.to_vec(), value));
+    }
+
+    /// Produces and finalizes a block raising `requests`.
+    pub fn produce_block(&self, requests: &[OracleRequested]) -> BlockRef {
+        let mut state = self.state.lock().unwrap();
+        let parent = state.blocks.last().expect("genesis block");
+        let mut storage = parent.storage.clone();
+        let parent_hash = fixed_hex::<32>(&parent.hash, "block hash").expect("block hashes are 32 bytes");
+        for (key, value) in std::mem::take(&mut state.pending) {
+            match value {
+                Some(value) => storage.insert(key, value),
+                None => storage.remove(&key),
+            };
+        }
+        // Like the pallet, the requests value only holds the requests of the current block.
+        storage.remove(&self.requests_key);
+        if !requests.is_empty() {
+            storage.insert(self.requests_key.clone(), requests.encode());
+        }
+        let number = state.blocks.len() as u64;
+        let hash = block_hash(number, &parent_hash);
+        state.blocks.push(MockBlock { hash: hash.clone(), storage });
+        BlockRef { number, hash }
+    }
+
+    pub fn head(&self) -> BlockRef {
+        let state = self.state.lock().unwrap();
+        let number = state.blocks.len() as u64 - 1;
+        BlockRef { number, hash: state.blocks[number as usize].hash.clone() }
+    }
+
+    fn storage_at(&self, key: &[u8], at: &str) -> Result<Option<Vec<u8>>> {
+        let state = self.state.lock().unwrap();
+        let block = state.blocks.iter().find(|block| block.hash == at)
+            .ok_or_else(|| anyhow!("ERROR: unknown block {}", at))?;
+        Ok(block.storage.get(key).cloned())
+    }
+
+    fn hash_of(&self, number: u64) -> Option<String> {
+        self.state.lock().unwrap().blocks.get(number as usize).map(|block| block.hash.clone())
+    }
+
+    fn number_of(&self, hash: &str) -> Option<u64> {
+        self.state.lock().unwrap().blocks.iter().position(|block| block.hash == hash).map(|n| n as u64)
+    }
+}
+
+impl MockRequest {
+    pub fn to_event(&self) -> Result<OracleRequested> {
+        Ok(OracleRequested {
+            request_id: self.request_id,
+            script_hash: fixed_hex(&self.script_hash, "script hash")?,
+            input: self.input.clone().into_bytes(),
+        })
+    }
+}
+
+impl ChainClient for MockChain {
+    async fn finalized_head(&self) -> Result<BlockRef> {
+        Ok(self.head())
+    }
+
+    async fn block_hash(&self, number: u64) -> Result<Option<String>> {
+        Ok(self.hash_of(number))
+    }
+
+    async fn storage(&self, key: &[u8], at: &str) -> Result<Option<Vec<u8>>> {
+        self.storage_at(key, at)
+    }
+}
+
+fn block_hash(number: u64, parent_hash: &[u8; 32]) -> String {
+    format!("0x{}", hex::encode(blake2_256(&(number, parent_hash).encode())))
+}
+
+/// Serves the subset of the Substrate JSON-RPC API read by `RpcChainClient`:
+/// `chain_getFinalizedHead`, `chain_getHeader`, `chain_getBlockHash` and `state_getStorage`.
+pub async fn serve_mock_chain(addr: SocketAddr, chain: Arc<MockChain>) -> Result<()> {
+    let make_svc = make_service_fn(move |_conn| {
+        let chain = chain.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| handle(chain.clone(), req)))
+        }
+    });
+    println!("Mock chain listening on http://{}", addr);
+    Server::try_bind(&addr)?.serve(make_svc).await?;
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct RpcRequest {
+    #[serde(default)]
+    id: Value,
+    method: String,
+    #[serde(default)]
+    params: Vec<Value>,
+}
+
+async fn handle(chain: Arc<MockChain>, req: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let body = hyper::body::to_bytes(req.into_body()).await.unwrap_or_default();
+    let response = match serde_json::from_slice::<RpcRequest>(&body) {
+        Ok(request) => match dispatch(&chain, &request) {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": request.id, "result": result }),
+            Err(e) => json!({ "jsonrpc": "2.0", "id": request.id, "error": { "code": -32602, "message": e.to_string() } }),
+        },
+        Err(e) => json!({ "jsonrpc": "2.0", "id": null, "error": { "code": -32700, "message": e.to_string() } }),
+    };
+    Ok(Response::builder()
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(response.to_string()))
+        .unwrap())
+}
+
+fn dispatch(chain: &MockChain, request: &RpcRequest) -> Result<Value> {
+    let param = |index: usize| request.params.get(index).cloned().unwrap_or(Value::Null);
+    match request.method.as_str() {
+        "chain_getFinalizedHead" => Ok(json!(chain.head().hash)),
+        "chain_getHeader" => {
+            let hash = match param(0) {
+                Value::String(hash) => hash,
+                _ => chain.head().hash,
+            };
+            Ok(match chain.number_of(&hash) {
+                Some(number) => json!({ "number": format!("0x{:x}", number) }),
+                None => Value::Null,
+            })
+        }
+        "chain_getBlockHash" => {
+            let number = match param(0) {
+                Value::Null => chain.head().number,
+                number => number.as_u64().ok_or_else(|| anyhow!("ERROR: invalid block number {}", number))?,
+            };
+            Ok(json!(chain.hash_of(number)))
+        }
+        "state_getStorage" => {
+            let key = param(0).as_str().map(decode_hex).transpose()?
+                .ok_or_else(|| anyhow!("ERROR: missing storage key"))?;
+            let at = match param(1) {
+                Value::String(at) => at,
+                _ => chain.head().hash,
+            };
+            Ok(json!(chain.storage_at(&key, &at)?.map(|value| format!("0x{}", hex::encode(value)))))
+        }
+        method => Err(anyhow!("ERROR: method {} not found", method)),
+    }
+}
@@ -0,0 +1,4 @@
+pub mod client;
+pub mod listener;
+pub mod mock;
+pub mod scripts;
@@ -0,0 +1,156 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::Arc;
+use anyhow::{anyhow, Result};
+use parity_scale_codec::{Decode, Encode};
+use sp_core_hashing::{blake2_128, twox_64};
+use crate::oracle::extrinsic::fixed_hex;
+use crate::traits::traits::{ChainClient, FetchedScript, ScriptSource};
+use crate::utils::twox::storage_prefix;
+
+/// Value of the `pallet_oracle::Scripts` storage map, keyed by script hash.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub struct OnChainScript {
+    pub wasm: Vec<u8>,
+    /// ABI JSON document.
+    pub abi: Vec<u8>,
+}
+
+/// Hasher of a storage map key, as declared by the pallet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MapHasher {
+    Identity,
+    Blake2_128Concat,
+    Twox64Concat,
+}
+
+/// Resolves scripts from a storage map at the chain's finalized head.
+pub struct ChainScriptSource<C> {
+    client: Arc<C>,
+    prefix: Vec<u8>,
+    hasher: MapHasher,
+}
+
+/// `Oracle.Scripts`, the storage map `pallet_oracle` deploys scripts to.
+pub fn default_scripts_prefix() -> [u8; 32] {
+    storage_prefix("Oracle", "Scripts")
+}
+
+impl MapHasher {
+    /// Full storage key of `key` in the map at `prefix`.
+    pub fn storage_key(&self, prefix: &[u8], key: &[u8]) -> Vec<u8> {
+        let hashed = match self {
+            MapHasher::Identity => Vec::new(),
+            MapHasher::Blake2_128Concat => blake2_128(key).to_vec(),
+            MapHasher::Twox64Concat => twox_64(key).to_vec(),
+        };
+        [prefix, &hashed, key].concat()
+    }
+}
+
+impl FromStr for MapHasher {
+    type Err = anyhow::Error;
+
+    fn from_str(hasher: &str) -> Result<Self> {
+        match hasher {
+            "identity" => Ok(MapHasher::Identity),
+            "blake2-128-concat" => Ok(MapHasher::Blake2_128Concat),
+            "twox-64-concat" => Ok(MapHasher::Twox64Concat),
+            _ => Err(anyhow!("ERROR: unknown storage hasher {:?}", hasher)),
+        }
+    }
+}
+
+impl<C: ChainClient> ChainScriptSource<C> {
+    pub fn new(client: Arc<C>, prefix: &[u8], hasher: MapHasher) -> Self {
+        ChainScriptSource { client, prefix: prefix.to_vec(), hasher }
+    }
+
+    async fn fetch_script(&self, hash: &str) -> Result<Option<FetchedScript>> {
+        let key = self.hasher.storage_key(&self.prefix, &fixed_hex::<32>(hash, "script hash")?);
+        let head = self.client.finalized_head().await?;
+        let Some(value) = self.client.storage(&key, &head.hash).await? else { return Ok(None) };
+        let script = OnChainScript::decode(&mut &value[..])
+            .map_err(|e| anyhow!("ERROR: failed to decode on-chain script {}: {}", hash, e))?;
+        let abi = String::from_utf8(script.abi)
+            .map_err(|_| anyhow!("ERROR: ABI of on-chain script {} is not UTF-8", hash))?;
+        Ok(Some(FetchedScript { wasm: script.wasm, abi }))
+    }
+}
+
+impl<C: ChainClient> ScriptSource for ChainScriptSource<C> {
+    fn fetch<'a>(&'a self, hash: &'a str) -> Pin<Box<dyn Future<Output = Result<Option<FetchedScript>>> + Send + 'a>> {
+        Box::pin(self.fetch_script(hash))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use parity_scale_codec::Encode;
+    use super::*;
+    use crate::chain::listener::default_requests_key;
+    use crate::chain::mock::MockChain;
+    use crate::core::module_cache::EngineOptions;
+    use crate::core::registry::ScriptRegistry;
+    use crate::core::testing::{orscript2, temp_dir};
+
+    /// Header hash of `orscript2`.
+    const ORSCRIPT2: &str = "0x005ae53ac4d6105dcd19b95c19036622c9fd174946d09283cf8cafa41431c9c6";
+
+    /// Mock chain with `scripts` deployed under the given hashes and a registry resolving from it.
+    fn deployed(name: &str, scripts: &[(&str, &[u8], &str)]) -> (std::path::PathBuf, Arc<MockChain>, Arc<ScriptRegistry>) {
+        let chain = Arc::new(MockChain::new(&default_requests_key()));
+        for (hash, wasm, abi) in scripts {
+            let key = MapHasher::Blake2_128Concat.storage_key(&default_scripts_prefix(), &fixed_hex::<32>(hash, "script hash").unwrap());
+            chain.set_storage(&key, Some(OnChainScript { wasm: wasm.to_vec(), abi: abi.as_bytes().to_vec() }.encode()));
+        }
+        chain.produce_block(&[]);
+        let root = temp_dir(name);
+        let source = ChainScriptSource::new(chain.clone(), &default_scripts_prefix(), MapHasher::Blake2_128Concat);
+        let registry = ScriptRegistry::open(&root, &EngineOptions::default()).unwrap().with_source(Arc::new(source));
+        (root, chain, Arc::new(registry))
+    }
+
+    #[tokio::test]
+    async fn resolves_and_caches_on_chain_scripts() {
+        let (wasm, abi) = orscript2();
+        let hash = ORSCRIPT2;
+        let (root, chain, registry) = deployed("scripts-cache", &[(hash, &wasm, &abi)]);
+        registry.resolve(hash).await.unwrap();
+        assert!(registry.contains(hash));
+        // Once cached the script no longer has to be on chain.
+        let key = MapHasher::Blake2_128Concat.storage_key(&default_scripts_prefix(), &fixed_hex::<32>(hash, "script hash").unwrap());
+        chain.set_storage(&key, None);
+        chain.produce_block(&[]);
+        registry.resolve(hash).await.unwrap();
+        assert_eq!(registry.load(hash).unwrap().wasm, wasm);
+        let missing = format!("0x{}", "22".repeat(32));
+        assert!(registry.resolve(&missing).await.is_err());
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn rejects_scripts_not_matching_their_hash() {
+        let (wasm, abi) = orscript2();
+        let wrong = format!("0x{}", "11".repeat(32));
+        let (root, _chain, registry) = deployed("scripts-hash", &[(&wrong, &wasm, &abi)]);
+        let error = registry.resolve(&wrong).await.unwrap_err().to_string();
+        assert!(error.contains("hashes to"), "{}", error);
+        assert!(!registry.contains(&wrong));
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn rejects_scripts_with_mismatched_selectors() {
+        let (wasm, abi) = orscript2();
+        let hash = ORSCRIPT2;
+        let tampered = abi.replace("0x480104f2", "0x00000000");
+        let (root, _chain, registry) = deployed("scripts-selector", &[(hash, &wasm, &tampered)]);
+        let error = registry.resolve(hash).await.unwrap_err().to_string();
+        assert!(error.contains("does not match the hash of its signature"), "{}", error);
+        assert!(!registry.contains(hash));
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -4,17 +4,20 @@ use std::fs::File;
 use std::io::{Read, Write};
 use regex::Regex;
 use clap::Parser as ClapParser;
-use sha2::{Sha256, Digest};
-use hex;
 use std::path::Path;
 use std::process::Command;
 use wasmparser::{Parser, Payload, ImportSectionReader, TypeRef};
+use crate::core::abi_value::split_top_level;
+use crate::core::hashing::HashAlgorithm;
 
 #[derive(ClapParser, Debug)]
 #[command(about = "Generate JSON ABI from an AssemblyScript .ts file")]
 struct Args {
     input: String,
     output: String,
+    /// Hash of the header and selectors, `sha256` or `blake2-256`
+    #[arg(long, default_value = "sha256")]
+    hash_alg: String,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -57,7 +60,8 @@ enum ImportKind {
 #[derive(Serialize,Deserialize,Default)]
 struct AbiHeader {
     name: Option<String>,
-    header: String
+    header: String,
+    hash_alg: HashAlgorithm,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -135,17 +139,8 @@ fn compile_as_to_wasm(input: &str, wasm_output: &str) -> Result<()> {
     Ok(())
 }
 
-fn compute_selector(name: &str, params: &[AbiParam]) -> String {
-    let signature = if params.is_empty() {
-        format!("{}()", name)
-    } else {
-        let param_names = params.iter().map(|p| p.name.as_str()).collect::<Vec<_>>().join(",");
-        format!("{}({})", name, param_names)
-    };
-    let mut hasher = Sha256::new();
-    hasher.update(signature);
-    let result = hasher.finalize();
-    format!("0x{}", hex::encode(&result[..4]))
+fn compute_selector(hash_alg: HashAlgorithm, name: &str, params: &[AbiParam]) -> String {
+    hash_alg.selector(name, params.iter().map(|p| p.name.as_str()))
 }
 
 pub fn abi_parser() -> Result<()> {
@@ -154,16 +149,14 @@ pub fn abi_parser() -> Result<()> {
     let wasm_output = args.input.replace(".ts", ".wasm");
     println!("path {:?}", wasm_output);
     compile_as_to_wasm(&args.input, &wasm_output)?;
+    let hash_alg: HashAlgorithm = args.hash_alg.parse()?;
     let wasm_hash = {
-        let mut header_hasher = Sha256::new();
         let mut wasm_reader = File::open(&wasm_output)
             .map_err(|e| anyhow!("ERROR: failed to read WASM file: {}", e))?;
         let mut wasm_content = Vec::new();
         wasm_reader.read_to_end(&mut wasm_content)
             .map_err(|e| anyhow!("ERROR: failed to parse the wasm content to variable: {}", e))?;
-        header_hasher.update(&wasm_content);
-        let hash = header_hasher.finalize();
-        format!("0x{:x}", hash)
+        hash_alg.header_hash(&wasm_content)
     };
 
     let mut file = File::open(&args.input)?;
@@ -178,13 +171,17 @@ pub fn abi_parser() -> Result<()> {
         imports: Vec::new(),
     };
 
-    let func_re = Regex::new(r"export\s+function\s+(\w+)\s*\((.*?)\)\s*:\s*(\w+|\Array<\w+>|[\w<>]+)\s*\{")?;
+    // A type name, `string`, `Array<T>`, `Map<K, V>`, ... optionally nullable (`T | null`).
+    const TYPE_PATTERN: &str = r"[\w<>]+(?:\s*,\s*[\w<>]+)*(?:\s*\|\s*null)?";
+    // Types are stored as `Map<K, V>` and `T | null` whatever the spacing in the source.
+    let normalize_type = |type_: &str| type_.split_whitespace().collect::<String>().replace(',', ", ").replace('|', " | ");
+    let func_re = Regex::new(&format!(r"export\s+function\s+(\w+)\s*\((.*?)\)\s*:\s*({TYPE_PATTERN})\s*\{{"))?;
     let class_re = Regex::new(r"class\s+(\w+)\s*\{")?;
-    let class_field_re = Regex::new(r"(\w+)\s*:\s*(\w+|\Array<\w+>|[\w<>]+)\s*;")?;
+    let class_field_re = Regex::new(&format!(r"(\w+)\s*:\s*({TYPE_PATTERN})\s*;"))?;
     let constructor_re = Regex::new(r"constructor\s*\((.*?)\)\s*\{")?;
-    let method_re = Regex::new(r"(\w+)\s*\((.*?)\)\s*:\s*(\w+|\Array<\w+>|[\w<>]+)\s*\{")?;
-    let param_re = Regex::new(r"(public\s+)?(\w+)\s*:\s*(\w+|\Array<\w+>|[\w<>]+)")?;
-    let var_re = Regex::new(r"export\s+const\s+(\w+)\s*:\s*(\w+|\Array<\w+>|[\w<>]+)\s*=\s*[^;]+;")?;
+    let method_re = Regex::new(&format!(r"(\w+)\s*\((.*?)\)\s*:\s*({TYPE_PATTERN})\s*\{{"))?;
+    let param_re = Regex::new(&format!(r"(public\s+)?(\w+)\s*:\s*({TYPE_PATTERN})"))?;
+    let var_re = Regex::new(&format!(r"export\s+const\s+(\w+)\s*:\s*({TYPE_PATTERN})\s*=\s*[^;]+;"))?;
     let doc_re = Regex::new(r"/\*\*\s*(.*?)\s*\*/")?;
 
     let lines: Vec<&str> = content.lines().collect();
@@ -202,8 +199,8 @@ pub fn abi_parser() -> Result<()> {
         }
         if let Some(captures) = var_re.captures(line) {
             let name = captures[1].to_string();
-            let type_ = captures[2].to_string();
-            let selector = compute_selector(&name, &[]);
+            let type_ = normalize_type(&captures[2]);
+            let selector = compute_selector(hash_alg, &name, &[]);
             abi.variables.push(AbiVariable {
                 name,
                 type_,
@@ -215,14 +212,14 @@ pub fn abi_parser() -> Result<()> {
         if let Some(captures) = func_re.captures(line) {
             let name = captures[1].to_string();
             let params_str = captures[2].trim();
-            let result = captures[3].to_string();
+            let result = normalize_type(&captures[3]);
 
             let mut params = Vec::new();
             if !params_str.is_empty() {
-                for param in params_str.split(',').map(|p| p.trim()) {
+                for param in split_top_level(params_str, ',') {
                     if let Some(param_cap) = param_re.captures(param) {
                         let param_name = param_cap[2].to_string();
-                        let param_type = param_cap[3].to_string();
+                        let param_type = normalize_type(&param_cap[3]);
                         params.push(AbiParam {
                             name: param_name,
                             type_: param_type,
@@ -231,7 +228,7 @@ pub fn abi_parser() -> Result<()> {
                 }
             }
 
-            let selector = compute_selector(&name, &params);
+            let selector = compute_selector(hash_alg, &name, &params);
             abi.functions.push(AbiFunction {
                 name,
                 params,
@@ -244,7 +241,7 @@ pub fn abi_parser() -> Result<()> {
         if let Some(captures) = class_re.captures(line) {
             let class_name = captures[1].to_string();
             current_class = Some(AbiClass {
-                class_selector: compute_selector(&class_name,&[]),
+                class_selector: compute_selector(hash_alg, &class_name,&[]),
                 name: class_name,
                 fields: Vec::new(),
                 methods: Vec::new(),
@@ -256,11 +253,11 @@ pub fn abi_parser() -> Result<()> {
             if let Some(captures) = constructor_re.captures(line) {
                 let params_str = captures[1].trim();
                 if !params_str.is_empty() {
-                    for param in params_str.split(',').map(|p| p.trim()) {
+                    for param in split_top_level(params_str, ',') {
                         if let Some(param_cap) = param_re.captures(param) {
                             if param_cap.get(1).is_some() { // Public fields
                                 let field_name = param_cap[2].to_string();
-                                let field_type = param_cap[3].to_string();
+                                let field_type = normalize_type(&param_cap[3]);
                                 class.fields.push(AbiField {
                                     name: field_name,
                                     type_: field_type,
@@ -273,7 +270,7 @@ pub fn abi_parser() -> Result<()> {
             }
             if let Some(captures) = class_field_re.captures(line) {
                 let field_name = captures[1].to_string();
-                let field_type = captures[2].to_string();
+                let field_type = normalize_type(&captures[2]);
                 class.fields.push(AbiField {
                     name: field_name,
                     type_: field_type,
@@ -283,14 +280,14 @@ pub fn abi_parser() -> Result<()> {
             if let Some(captures) = method_re.captures(line) {
                 let method_name = captures[1].to_string();
                 let params_str = captures[2].trim();
-                let result = captures[3].to_string();
+                let result = normalize_type(&captures[3]);
 
                 let mut params = Vec::new();
                 if !params_str.is_empty() {
-                    for param in params_str.split(',').map(|p| p.trim()) {
+                    for param in split_top_level(params_str, ',') {
                         if let Some(param_cap) = param_re.captures(param) {
                             let param_name = param_cap[2].to_string();
-                            let param_type = param_cap[3].to_string();
+                            let param_type = normalize_type(&param_cap[3]);
                             params.push(AbiParam {
                                 name: param_name,
                                 type_: param_type,
@@ -298,7 +295,7 @@ pub fn abi_parser() -> Result<()> {
                         }
                     }
                 }
-                let selector = compute_selector(&method_name, &params);
+                let selector = compute_selector(hash_alg, &method_name, &params);
                 class.methods.push(AbiFunction {
                     name: method_name,
                     params,
@@ -319,6 +316,7 @@ pub fn abi_parser() -> Result<()> {
     abi.headers = AbiHeader {
         name: None,
         header: wasm_hash,
+        hash_alg,
     };
 
     let json = serde_json::to_string_pretty(&abi)?;
@@ -0,0 +1,1789 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use std::sync::Arc;
+use anyhow::{anyhow, Result};
+use parity_scale_codec::{Compact, Decode, Encode};
+use serde_json::{Map, Number, Value};
+use crate::core::numeric::{FixedI128, FixedI64, FixedU128, FixedU64, U256};
+use crate::traits::traits::{ABIType, AbiMemory, Layout};
+
+/// AssemblyScript runtime ids of the built-in managed types.
+pub(crate) const ARRAY_BUFFER_ID: u32 = 1;
+pub(crate) const STRING_ID: u32 = 2;
+
+/// ABI type of a value, with classes resolved against the ABI they come from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AbiTypeDesc {
+    Bool,
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    I64,
+    U64,
+    /// `as-bignum`'s `u128`, `i128` and `u256`, objects holding their little endian bytes.
+    U128,
+    I128,
+    U256,
+    F32,
+    F64,
+    /// `sp_arithmetic` fixed-point numbers, the inner integer scaled by `DIV`. The 128-bit ones
+    /// are stored like `u128`/`i128`.
+    FixedU64,
+    FixedI64,
+    FixedU128,
+    FixedI128,
+    String,
+    ArrayBuffer,
+    Array(ArrayKind, Box<AbiTypeDesc>),
+    /// `T | null`, only managed types can be null in AssemblyScript.
+    Nullable(Box<AbiTypeDesc>),
+    Map(Box<AbiTypeDesc>, Box<AbiTypeDesc>),
+    Set(Box<AbiTypeDesc>),
+    Class(Arc<ClassDesc>),
+}
+
+/// Memory layout of a sequence, all of them are SCALE encoded as `Vec<T>`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArrayKind {
+    /// `Array<T>`, a header pointing to a separately allocated buffer.
+    Array,
+    /// `StaticArray<T>`, elements stored inline in the object.
+    Static,
+    /// `Int8Array` to `Float64Array`, a view into an `ArrayBuffer`.
+    Typed,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ClassDesc {
+    pub name: String,
+    pub fields: Vec<(String, AbiTypeDesc)>,
+}
+
+/// Classes of an ABI, used to resolve type names into `AbiTypeDesc`s.
+#[derive(Debug, Default, Clone)]
+pub struct AbiSchema {
+    classes: HashMap<String, Vec<(String, String)>>,
+}
+
+/// Dynamically typed ABI value, the shape of classes comes from the ABI at runtime.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AbiValue {
+    Bool(bool),
+    I8(i8),
+    U8(u8),
+    I16(i16),
+    U16(u16),
+    I32(i32),
+    U32(u32),
+    I64(i64),
+    U64(u64),
+    U128(u128),
+    I128(i128),
+    U256(U256),
+    F32(f32),
+    F64(f64),
+    FixedU64(FixedU64),
+    FixedI64(FixedI64),
+    FixedU128(FixedU128),
+    FixedI128(FixedI128),
+    String(String),
+    ArrayBuffer(Vec<u8>),
+    Array { kind: ArrayKind, elem: AbiTypeDesc, items: Vec<AbiValue> },
+    Nullable { inner: AbiTypeDesc, value: Option<Box<AbiValue>> },
+    /// Entries sorted by key, the order a `BTreeMap` encodes them in.
+    Map { key: AbiTypeDesc, value: AbiTypeDesc, entries: Vec<(AbiValue, AbiValue)> },
+    /// Items sorted, the order a `BTreeSet` encodes them in.
+    Set { elem: AbiTypeDesc, items: Vec<AbiValue> },
+    Class { class: Arc<ClassDesc>, fields: Vec<AbiValue> },
+}
+
+/// Typed array names and their element types.
+const TYPED_ARRAYS: [(&str, AbiTypeDesc); 11] = [
+    ("Int8Array", AbiTypeDesc::I8),
+    ("Uint8Array", AbiTypeDesc::U8),
+    ("Uint8ClampedArray", AbiTypeDesc::U8),
+    ("Int16Array", AbiTypeDesc::I16),
+    ("Uint16Array", AbiTypeDesc::U16),
+    ("Int32Array", AbiTypeDesc::I32),
+    ("Uint32Array", AbiTypeDesc::U32),
+    ("Int64Array", AbiTypeDesc::I64),
+    ("Uint64Array", AbiTypeDesc::U64),
+    ("Float32Array", AbiTypeDesc::F32),
+    ("Float64Array", AbiTypeDesc::F64),
+];
+
+impl fmt::Display for AbiTypeDesc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AbiTypeDesc::Bool => write!(f, "bool"),
+            AbiTypeDesc::I8 => write!(f, "i8"),
+            AbiTypeDesc::U8 => write!(f, "u8"),
+            AbiTypeDesc::I16 => write!(f, "i16"),
+            AbiTypeDesc::U16 => write!(f, "u16"),
+            AbiTypeDesc::I32 => write!(f, "i32"),
+            AbiTypeDesc::U32 => write!(f, "u32"),
+            AbiTypeDesc::I64 => write!(f, "i64"),
+            AbiTypeDesc::U64 => write!(f, "u64"),
+            AbiTypeDesc::U128 => write!(f, "u128"),
+            AbiTypeDesc::I128 => write!(f, "i128"),
+            AbiTypeDesc::U256 => write!(f, "u256"),
+            AbiTypeDesc::F32 => write!(f, "f32"),
+            AbiTypeDesc::F64 => write!(f, "f64"),
+            AbiTypeDesc::FixedU64 => write!(f, "FixedU64"),
+            AbiTypeDesc::FixedI64 => write!(f, "FixedI64"),
+            AbiTypeDesc::FixedU128 => write!(f, "FixedU128"),
+            AbiTypeDesc::FixedI128 => write!(f, "FixedI128"),
+            AbiTypeDesc::String => write!(f, "string"),
+            AbiTypeDesc::ArrayBuffer => write!(f, "ArrayBuffer"),
+            AbiTypeDesc::Array(ArrayKind::Array, elem) => write!(f, "Array<{}>", elem),
+            AbiTypeDesc::Array(ArrayKind::Static, elem) => write!(f, "StaticArray<{}>", elem),
+            AbiTypeDesc::Array(ArrayKind::Typed, elem) => {
+                let name = TYPED_ARRAYS.iter().find(|(_, ty)| ty == elem.as_ref()).map_or("TypedArray", |(name, _)| name);
+                write!(f, "{}", name)
+            }
+            AbiTypeDesc::Nullable(inner) => write!(f, "{} | null", inner),
+            AbiTypeDesc::Map(key, value) => write!(f, "Map<{}, {}>", key, value),
+            AbiTypeDesc::Set(elem) => write!(f, "Set<{}>", elem),
+            AbiTypeDesc::Class(class) => write!(f, "{}", class.name),
+        }
+    }
+}
+
+impl AbiTypeDesc {
+    pub fn layout(&self) -> Layout {
+        match self {
+            AbiTypeDesc::Bool | AbiTypeDesc::I8 | AbiTypeDesc::U8 => Layout::new(1, 1),
+            AbiTypeDesc::I16 | AbiTypeDesc::U16 => Layout::new(2, 2),
+            AbiTypeDesc::I32 | AbiTypeDesc::U32 | AbiTypeDesc::F32 => Layout::new(4, 4),
+            AbiTypeDesc::I64 | AbiTypeDesc::U64 | AbiTypeDesc::F64
+            | AbiTypeDesc::FixedU64 | AbiTypeDesc::FixedI64 => Layout::new(8, 8),
+            _ => Layout::POINTER,
+        }
+    }
+
+    /// Whether the value is a pointer to an object owned by the AssemblyScript runtime.
+    pub fn is_managed(&self) -> bool {
+        self.layout() == Layout::POINTER && !matches!(self, AbiTypeDesc::I32 | AbiTypeDesc::U32 | AbiTypeDesc::F32)
+    }
+}
+
+impl ClassDesc {
+    /// Offsets of the fields inside the object and the object size, AssemblyScript aligns every
+    /// field to its own alignment.
+    pub fn field_offsets(&self) -> (Vec<u32>, u32) {
+        let mut offset = 0u32;
+        let offsets = self.fields.iter().map(|(_, ty)| {
+            let layout = ty.layout();
+            let field_offset = offset.next_multiple_of(layout.align);
+            offset = field_offset + layout.size;
+            field_offset
+        }).collect();
+        (offsets, offset)
+    }
+}
+
+impl AbiSchema {
+    /// `classes` maps every class name to its `(field, type)` pairs in declaration order.
+    pub fn new(classes: HashMap<String, Vec<(String, String)>>) -> Self {
+        AbiSchema { classes }
+    }
+
+    /// Class names in alphabetical order.
+    pub fn class_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.classes.keys().map(String::as_str).collect();
+        names.sort();
+        names
+    }
+
+    pub fn resolve(&self, typ: &str) -> Result<AbiTypeDesc> {
+        self.resolve_nested(typ, &mut Vec::new())
+    }
+
+    pub fn class(&self, name: &str) -> Result<Arc<ClassDesc>> {
+        match self.resolve(name)? {
+            AbiTypeDesc::Class(class) => Ok(class),
+            ty => Err(anyhow!("ERROR: {} is not a class", ty)),
+        }
+    }
+
+    fn resolve_nested(&self, typ: &str, parents: &mut Vec<String>) -> Result<AbiTypeDesc> {
+        let typ = typ.trim();
+        let variants = split_top_level(typ, '|');
+        if variants.len() > 1 {
+            let [inner] = variants.iter().filter(|variant| **variant != "null").collect::<Vec<_>>()[..] else {
+                return Err(anyhow!("ERROR: unsupported union type {}", typ));
+            };
+            let inner = self.resolve_nested(inner, parents)?;
+            if !inner.is_managed() || matches!(inner, AbiTypeDesc::Nullable(_)) {
+                return Err(anyhow!("ERROR: {} can not be nullable, only objects can be null", inner));
+            }
+            return Ok(AbiTypeDesc::Nullable(Box::new(inner)));
+        }
+        if let Some((name, args)) = typ.strip_suffix('>').and_then(|s| s.split_once('<')) {
+            let args = split_top_level(args, ',').into_iter()
+                .map(|arg| self.resolve_nested(arg, parents))
+                .collect::<Result<Vec<_>>>()?;
+            return match (name.trim(), args.as_slice()) {
+                ("Array", [elem]) => Ok(AbiTypeDesc::Array(ArrayKind::Array, Box::new(elem.clone()))),
+                ("StaticArray", [elem]) => Ok(AbiTypeDesc::Array(ArrayKind::Static, Box::new(elem.clone()))),
+                ("Set", [elem]) => Ok(AbiTypeDesc::Set(Box::new(elem.clone()))),
+                ("Map", [key, value]) => Ok(AbiTypeDesc::Map(Box::new(key.clone()), Box::new(value.clone()))),
+                _ => Err(anyhow!("ERROR: unsupported generic type {}", typ)),
+            };
+        }
+        let ty = match typ {
+            "bool" => AbiTypeDesc::Bool,
+            "i8" => AbiTypeDesc::I8,
+            "u8" => AbiTypeDesc::U8,
+            "i16" => AbiTypeDesc::I16,
+            "u16" => AbiTypeDesc::U16,
+            // Scripts are wasm32, pointer sized integers are 32 bits wide.
+            "i32" | "isize" => AbiTypeDesc::I32,
+            "u32" | "usize" => AbiTypeDesc::U32,
+            "i64" => AbiTypeDesc::I64,
+            "u64" => AbiTypeDesc::U64,
+            "u128" => AbiTypeDesc::U128,
+            "i128" => AbiTypeDesc::I128,
+            "u256" => AbiTypeDesc::U256,
+            "f32" => AbiTypeDesc::F32,
+            "f64" => AbiTypeDesc::F64,
+            "FixedU64" => AbiTypeDesc::FixedU64,
+            "FixedI64" => AbiTypeDesc::FixedI64,
+            "FixedU128" => AbiTypeDesc::FixedU128,
+            "FixedI128" => AbiTypeDesc::FixedI128,
+            "string" => AbiTypeDesc::String,
+            "ArrayBuffer" => AbiTypeDesc::ArrayBuffer,
+            name => {
+                if let Some((_, elem)) = TYPED_ARRAYS.iter().find(|(typed, _)| *typed == name) {
+                    return Ok(AbiTypeDesc::Array(ArrayKind::Typed, Box::new(elem.clone())));
+                }
+                let fields = self.classes.get(name)
+                    .ok_or_else(|| anyhow!("ERROR: unsupported ABI type {}", name))?;
+                if parents.iter().any(|parent| parent == name) {
+                    return Err(anyhow!("ERROR: class {} contains itself", name));
+                }
+                parents.push(name.to_string());
+                let fields = fields.iter()
+                    .map(|(field, typ)| Ok((field.clone(), self.resolve_nested(typ, parents)?)))
+                    .collect::<Result<Vec<_>>>()?;
+                parents.pop();
+                AbiTypeDesc::Class(Arc::new(ClassDesc { name: name.to_string(), fields }))
+            }
+        };
+        Ok(ty)
+    }
+}
+
+macro_rules! primitive_abi_type {
+    ($($ty:ty => $desc:ident, $json:expr;)*) => {$(
+        impl ABIType for $ty {
+            fn type_name(&self) -> String {
+                AbiTypeDesc::$desc.to_string()
+            }
+
+            fn layout(&self) -> Layout {
+                AbiTypeDesc::$desc.layout()
+            }
+
+            fn read_from_memory(memory: &mut dyn AbiMemory, ptr: u32, ty: &AbiTypeDesc) -> Result<Self> {
+                expect_type(ty, &AbiTypeDesc::$desc)?;
+                let mut bytes = [0u8; size_of::<$ty>()];
+                memory.read(ptr, &mut bytes)?;
+                Ok(<$ty>::from_le_bytes(bytes))
+            }
+
+            fn write_to_memory(&self, memory: &mut dyn AbiMemory, ptr: u32) -> Result<()> {
+                // Fixed width numbers are SCALE encoded as their little endian bytes.
+                memory.write(ptr, &self.encode())
+            }
+
+            fn encode_scale(&self, dest: &mut Vec<u8>) {
+                self.encode_to(dest);
+            }
+
+            fn decode_scale(input: &mut &[u8], ty: &AbiTypeDesc) -> Result<Self> {
+                expect_type(ty, &AbiTypeDesc::$desc)?;
+                Ok(<$ty>::decode(input)?)
+            }
+
+            fn to_json(&self) -> Value {
+                $json(*self)
+            }
+        }
+    )*};
+}
+
+primitive_abi_type! {
+    i8 => I8, Value::from;
+    u8 => U8, Value::from;
+    i16 => I16, Value::from;
+    u16 => U16, Value::from;
+    i32 => I32, Value::from;
+    u32 => U32, Value::from;
+    i64 => I64, Value::from;
+    u64 => U64, Value::from;
+    // Rendered through the shortest decimal form, so 2523.13f32 is not printed as 2523.1298828125.
+    f32 => F32, |value: f32| float_json(value.to_string().parse().unwrap_or(f64::NAN));
+    f64 => F64, float_json;
+    // Big and fixed-point numbers are rendered as decimal strings, JSON numbers would lose precision.
+    FixedU64 => FixedU64, |value: FixedU64| Value::String(value.to_string());
+    FixedI64 => FixedI64, |value: FixedI64| Value::String(value.to_string());
+}
+
+/// 128 and 256-bit numbers, stored in an object of their own like `as-bignum` does. The object is
+/// laid out like a `StaticArray<u8>` of the little endian bytes.
+macro_rules! bignum_abi_type {
+    ($($ty:ty => $desc:ident;)*) => {$(
+        impl ABIType for $ty {
+            fn type_name(&self) -> String {
+                AbiTypeDesc::$desc.to_string()
+            }
+
+            fn layout(&self) -> Layout {
+                Layout::POINTER
+            }
+
+            fn read_from_memory(memory: &mut dyn AbiMemory, ptr: u32, ty: &AbiTypeDesc) -> Result<Self> {
+                expect_type(ty, &AbiTypeDesc::$desc)?;
+                let object = read_object_ptr(memory, ptr, ty)?;
+                let mut bytes = [0u8; size_of::<$ty>()];
+                memory.read(object, &mut bytes)?;
+                Ok(<$ty>::from_le_bytes(bytes))
+            }
+
+            fn write_to_memory(&self, memory: &mut dyn AbiMemory, ptr: u32) -> Result<()> {
+                let object = write_object(memory, ptr, &self.type_name(), size_of::<$ty>() as u32)?;
+                memory.write(object, &self.encode())
+            }
+
+            fn encode_scale(&self, dest: &mut Vec<u8>) {
+                self.encode_to(dest);
+            }
+
+            fn decode_scale(input: &mut &[u8], ty: &AbiTypeDesc) -> Result<Self> {
+                expect_type(ty, &AbiTypeDesc::$desc)?;
+                Ok(<$ty>::decode(input)?)
+            }
+
+            fn to_json(&self) -> Value {
+                Value::String(self.to_string())
+            }
+        }
+    )*};
+}
+
+bignum_abi_type! {
+    u128 => U128;
+    i128 => I128;
+    U256 => U256;
+    FixedU128 => FixedU128;
+    FixedI128 => FixedI128;
+}
+
+impl ABIType for bool {
+    fn type_name(&self) -> String {
+        AbiTypeDesc::Bool.to_string()
+    }
+
+    fn layout(&self) -> Layout {
+        AbiTypeDesc::Bool.layout()
+    }
+
+    fn read_from_memory(memory: &mut dyn AbiMemory, ptr: u32, ty: &AbiTypeDesc) -> Result<Self> {
+        expect_type(ty, &AbiTypeDesc::Bool)?;
+        let mut byte = [0u8];
+        memory.read(ptr, &mut byte)?;
+        Ok(byte[0] != 0)
+    }
+
+    fn write_to_memory(&self, memory: &mut dyn AbiMemory, ptr: u32) -> Result<()> {
+        memory.write(ptr, &[*self as u8])
+    }
+
+    fn encode_scale(&self, dest: &mut Vec<u8>) {
+        self.encode_to(dest);
+    }
+
+    fn decode_scale(input: &mut &[u8], ty: &AbiTypeDesc) -> Result<Self> {
+        expect_type(ty, &AbiTypeDesc::Bool)?;
+        Ok(bool::decode(input)?)
+    }
+
+    fn to_json(&self) -> Value {
+        Value::Bool(*self)
+    }
+}
+
+impl ABIType for String {
+    fn type_name(&self) -> String {
+        AbiTypeDesc::String.to_string()
+    }
+
+    fn layout(&self) -> Layout {
+        Layout::POINTER
+    }
+
+    fn read_from_memory(memory: &mut dyn AbiMemory, ptr: u32, ty: &AbiTypeDesc) -> Result<Self> {
+        expect_type(ty, &AbiTypeDesc::String)?;
+        let object = read_object_ptr(memory, ptr, ty)?;
+        read_string(memory, object)
+    }
+
+    fn write_to_memory(&self, memory: &mut dyn AbiMemory, ptr: u32) -> Result<()> {
+        let object = alloc_string(memory, self)?;
+        memory.write_u32(ptr, object)
+    }
+
+    fn encode_scale(&self, dest: &mut Vec<u8>) {
+        self.encode_to(dest);
+    }
+
+    fn decode_scale(input: &mut &[u8], ty: &AbiTypeDesc) -> Result<Self> {
+        expect_type(ty, &AbiTypeDesc::String)?;
+        Ok(String::decode(input)?)
+    }
+
+    fn to_json(&self) -> Value {
+        Value::String(self.clone())
+    }
+}
+
+/// Read from any sequence type, written as an `Array<T>`.
+impl<T: ABIType + Default> ABIType for Vec<T> {
+    fn type_name(&self) -> String {
+        format!("Array<{}>", T::default().type_name())
+    }
+
+    fn layout(&self) -> Layout {
+        Layout::POINTER
+    }
+
+    fn read_from_memory(memory: &mut dyn AbiMemory, ptr: u32, ty: &AbiTypeDesc) -> Result<Self> {
+        let (kind, elem) = array_elem(ty)?;
+        element_addresses(memory, ptr, kind, elem)?.into_iter()
+            .map(|address| T::read_from_memory(memory, address, elem))
+            .collect()
+    }
+
+    fn write_to_memory(&self, memory: &mut dyn AbiMemory, ptr: u32) -> Result<()> {
+        let size = T::default().layout().size;
+        write_sequence(memory, ptr, ArrayKind::Array, &self.type_name(), size, self.len(), |memory, i, address| {
+            self[i].write_to_memory(memory, address)
+        })
+    }
+
+    fn encode_scale(&self, dest: &mut Vec<u8>) {
+        Compact(self.len() as u32).encode_to(dest);
+        for item in self {
+            item.encode_scale(dest);
+        }
+    }
+
+    fn decode_scale(input: &mut &[u8], ty: &AbiTypeDesc) -> Result<Self> {
+        let (_, elem) = array_elem(ty)?;
+        let len = Compact::<u32>::decode(input)?.0;
+        (0..len).map(|_| T::decode_scale(input, elem)).collect()
+    }
+
+    fn to_json(&self) -> Value {
+        Value::Array(self.iter().map(ABIType::to_json).collect())
+    }
+}
+
+/// `T | null`, a null pointer in memory and `None` in SCALE.
+impl<T: ABIType + Default> ABIType for Option<T> {
+    fn type_name(&self) -> String {
+        format!("{} | null", T::default().type_name())
+    }
+
+    fn layout(&self) -> Layout {
+        Layout::POINTER
+    }
+
+    fn read_from_memory(memory: &mut dyn AbiMemory, ptr: u32, ty: &AbiTypeDesc) -> Result<Self> {
+        let inner = nullable_inner(ty)?;
+        if memory.read_u32(ptr)? == 0 {
+            return Ok(None);
+        }
+        Ok(Some(T::read_from_memory(memory, ptr, inner)?))
+    }
+
+    fn write_to_memory(&self, memory: &mut dyn AbiMemory, ptr: u32) -> Result<()> {
+        // Classes are unknown to the default schema, every type it does know must be an object.
+        if let Ok(inner) = AbiSchema::default().resolve(&T::default().type_name())
+            && !inner.is_managed()
+        {
+            return Err(anyhow!("ERROR: {} can not be nullable, only objects can be null", inner));
+        }
+        match self {
+            Some(value) => value.write_to_memory(memory, ptr),
+            None => memory.write_u32(ptr, 0),
+        }
+    }
+
+    fn encode_scale(&self, dest: &mut Vec<u8>) {
+        match self {
+            Some(value) => {
+                dest.push(1);
+                value.encode_scale(dest);
+            }
+            None => dest.push(0),
+        }
+    }
+
+    fn decode_scale(input: &mut &[u8], ty: &AbiTypeDesc) -> Result<Self> {
+        let inner = nullable_inner(ty)?;
+        match u8::decode(input)? {
+            0 => Ok(None),
+            1 => Ok(Some(T::decode_scale(input, inner)?)),
+            tag => Err(anyhow!("ERROR: invalid option tag {} for {}", tag, ty)),
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        self.as_ref().map_or(Value::Null, ABIType::to_json)
+    }
+}
+
+/// `Map<K, V>`, SCALE encoded as a `BTreeMap` and rendered as a JSON object.
+impl<K: ABIType + Default + Ord, V: ABIType + Default> ABIType for BTreeMap<K, V> {
+    fn type_name(&self) -> String {
+        format!("Map<{}, {}>", K::default().type_name(), V::default().type_name())
+    }
+
+    fn layout(&self) -> Layout {
+        Layout::POINTER
+    }
+
+    fn read_from_memory(memory: &mut dyn AbiMemory, ptr: u32, ty: &AbiTypeDesc) -> Result<Self> {
+        let (key, value) = map_types(ty)?;
+        let table = HashTable { key: key.layout(), value: Some(value.layout()) };
+        table.entries(memory, ptr, ty)?.into_iter()
+            .map(|entry| Ok((
+                K::read_from_memory(memory, entry, key)?,
+                V::read_from_memory(memory, entry + table.value_offset(), value)?,
+            )))
+            .collect()
+    }
+
+    fn write_to_memory(&self, memory: &mut dyn AbiMemory, ptr: u32) -> Result<()> {
+        let table = HashTable { key: K::default().layout(), value: Some(V::default().layout()) };
+        let key = generic_key::<K>();
+        let entries: Vec<_> = self.iter().collect();
+        table.write(memory, ptr, &self.type_name(), key.as_ref(), entries.len(), |memory, i, entry| {
+            entries[i].0.write_to_memory(memory, entry)?;
+            entries[i].1.write_to_memory(memory, entry + table.value_offset())
+        })
+    }
+
+    fn encode_scale(&self, dest: &mut Vec<u8>) {
+        Compact(self.len() as u32).encode_to(dest);
+        for (key, value) in self {
+            key.encode_scale(dest);
+            value.encode_scale(dest);
+        }
+    }
+
+    fn decode_scale(input: &mut &[u8], ty: &AbiTypeDesc) -> Result<Self> {
+        let (key, value) = map_types(ty)?;
+        let len = Compact::<u32>::decode(input)?.0;
+        (0..len).map(|_| Ok((K::decode_scale(input, key)?, V::decode_scale(input, value)?))).collect()
+    }
+
+    fn to_json(&self) -> Value {
+        Value::Object(self.iter().map(|(key, value)| (json_key(key.to_json()), value.to_json())).collect())
+    }
+}
+
+/// `Set<T>`, SCALE encoded as a `BTreeSet` and rendered as a JSON array.
+impl<T: ABIType + Default + Ord> ABIType for BTreeSet<T> {
+    fn type_name(&self) -> String {
+        format!("Set<{}>", T::default().type_name())
+    }
+
+    fn layout(&self) -> Layout {
+        Layout::POINTER
+    }
+
+    fn read_from_memory(memory: &mut dyn AbiMemory, ptr: u32, ty: &AbiTypeDesc) -> Result<Self> {
+        let elem = set_elem(ty)?;
+        let table = HashTable { key: elem.layout(), value: None };
+        table.entries(memory, ptr, ty)?.into_iter()
+            .map(|entry| T::read_from_memory(memory, entry, elem))
+            .collect()
+    }
+
+    fn write_to_memory(&self, memory: &mut dyn AbiMemory, ptr: u32) -> Result<()> {
+        let table = HashTable { key: T::default().layout(), value: None };
+        let key = generic_key::<T>();
+        let items: Vec<_> = self.iter().collect();
+        table.write(memory, ptr, &self.type_name(), key.as_ref(), items.len(), |memory, i, entry| {
+            items[i].write_to_memory(memory, entry)
+        })
+    }
+
+    fn encode_scale(&self, dest: &mut Vec<u8>) {
+        Compact(self.len() as u32).encode_to(dest);
+        for item in self {
+            item.encode_scale(dest);
+        }
+    }
+
+    fn decode_scale(input: &mut &[u8], ty: &AbiTypeDesc) -> Result<Self> {
+        let elem = set_elem(ty)?;
+        let len = Compact::<u32>::decode(input)?.0;
+        (0..len).map(|_| T::decode_scale(input, elem)).collect()
+    }
+
+    fn to_json(&self) -> Value {
+        Value::Array(self.iter().map(ABIType::to_json).collect())
+    }
+}
+
+impl AbiValue {
+    /// Reads the class object at `object`, e.g. the pointer returned by a script function.
+    pub fn read_class(memory: &mut dyn AbiMemory, object: u32, class: &Arc<ClassDesc>) -> Result<Self> {
+        let (offsets, size) = class.field_offsets();
+        check_range(memory, object, size as u64)?;
+        let fields = class.fields.iter().zip(offsets)
+            .map(|((_, ty), offset)| AbiValue::read_from_memory(memory, object + offset, ty))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(AbiValue::Class { class: class.clone(), fields })
+    }
+
+    pub fn field(&self, name: &str) -> Option<&AbiValue> {
+        let AbiValue::Class { class, fields } = self else { return None };
+        class.fields.iter().position(|(field, _)| field == name).map(|i| &fields[i])
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            AbiValue::I8(value) => Some(*value as f64),
+            AbiValue::U8(value) => Some(*value as f64),
+            AbiValue::I16(value) => Some(*value as f64),
+            AbiValue::U16(value) => Some(*value as f64),
+            AbiValue::I32(value) => Some(*value as f64),
+            AbiValue::U32(value) => Some(*value as f64),
+            AbiValue::I64(value) => Some(*value as f64),
+            AbiValue::U64(value) => Some(*value as f64),
+            AbiValue::U128(value) => Some(*value as f64),
+            AbiValue::I128(value) => Some(*value as f64),
+            AbiValue::U256(value) => Some(value.as_f64()),
+            AbiValue::F32(value) => Some(*value as f64),
+            AbiValue::F64(value) => Some(*value),
+            AbiValue::FixedU64(value) => Some(value.as_f64()),
+            AbiValue::FixedI64(value) => Some(value.as_f64()),
+            AbiValue::FixedU128(value) => Some(value.as_f64()),
+            AbiValue::FixedI128(value) => Some(value.as_f64()),
+            _ => None,
+        }
+    }
+
+    fn as_i128(&self) -> Option<i128> {
+        match self {
+            AbiValue::Bool(value) => Some(*value as i128),
+            AbiValue::I8(value) => Some(*value as i128),
+            AbiValue::U8(value) => Some(*value as i128),
+            AbiValue::I16(value) => Some(*value as i128),
+            AbiValue::U16(value) => Some(*value as i128),
+            AbiValue::I32(value) => Some(*value as i128),
+            AbiValue::U32(value) => Some(*value as i128),
+            AbiValue::I64(value) => Some(*value as i128),
+            AbiValue::U64(value) => Some(*value as i128),
+            AbiValue::I128(value) => Some(*value),
+            AbiValue::FixedU64(value) => Some(value.0 as i128),
+            AbiValue::FixedI64(value) => Some(value.0 as i128),
+            AbiValue::FixedI128(value) => Some(value.0),
+            _ => None,
+        }
+    }
+}
+
+/// Runs `$body` with `$value` bound to the inner value of any primitive or string variant.
+macro_rules! with_scalar {
+    ($self:expr, $value:ident => $body:expr, $other:pat => $fallback:expr) => {
+        match $self {
+            AbiValue::Bool($value) => $body,
+            AbiValue::I8($value) => $body,
+            AbiValue::U8($value) => $body,
+            AbiValue::I16($value) => $body,
+            AbiValue::U16($value) => $body,
+            AbiValue::I32($value) => $body,
+            AbiValue::U32($value) => $body,
+            AbiValue::I64($value) => $body,
+            AbiValue::U64($value) => $body,
+            AbiValue::U128($value) => $body,
+            AbiValue::I128($value) => $body,
+            AbiValue::U256($value) => $body,
+            AbiValue::F32($value) => $body,
+            AbiValue::F64($value) => $body,
+            AbiValue::FixedU64($value) => $body,
+            AbiValue::FixedI64($value) => $body,
+            AbiValue::FixedU128($value) => $body,
+            AbiValue::FixedI128($value) => $body,
+            AbiValue::String($value) => $body,
+            $other => $fallback,
+        }
+    };
+}
+
+impl ABIType for AbiValue {
+    fn type_name(&self) -> String {
+        with_scalar!(self, value => value.type_name(), other => match other {
+            AbiValue::ArrayBuffer(_) => AbiTypeDesc::ArrayBuffer.to_string(),
+            AbiValue::Array { kind, elem, .. } => AbiTypeDesc::Array(*kind, Box::new(elem.clone())).to_string(),
+            AbiValue::Nullable { inner, .. } => AbiTypeDesc::Nullable(Box::new(inner.clone())).to_string(),
+            AbiValue::Map { key, value, .. } => AbiTypeDesc::Map(Box::new(key.clone()), Box::new(value.clone())).to_string(),
+            AbiValue::Set { elem, .. } => AbiTypeDesc::Set(Box::new(elem.clone())).to_string(),
+            AbiValue::Class { class, .. } => class.name.clone(),
+            _ => unreachable!(),
+        })
+    }
+
+    fn layout(&self) -> Layout {
+        with_scalar!(self, value => value.layout(), _ => Layout::POINTER)
+    }
+
+    fn read_from_memory(memory: &mut dyn AbiMemory, ptr: u32, ty: &AbiTypeDesc) -> Result<Self> {
+        Ok(match ty {
+            AbiTypeDesc::Bool => AbiValue::Bool(bool::read_from_memory(memory, ptr, ty)?),
+            AbiTypeDesc::I8 => AbiValue::I8(i8::read_from_memory(memory, ptr, ty)?),
+            AbiTypeDesc::U8 => AbiValue::U8(u8::read_from_memory(memory, ptr, ty)?),
+            AbiTypeDesc::I16 => AbiValue::I16(i16::read_from_memory(memory, ptr, ty)?),
+            AbiTypeDesc::U16 => AbiValue::U16(u16::read_from_memory(memory, ptr, ty)?),
+            AbiTypeDesc::I32 => AbiValue::I32(i32::read_from_memory(memory, ptr, ty)?),
+            AbiTypeDesc::U32 => AbiValue::U32(u32::read_from_memory(memory, ptr, ty)?),
+            AbiTypeDesc::I64 => AbiValue::I64(i64::read_from_memory(memory, ptr, ty)?),
+            AbiTypeDesc::U64 => AbiValue::U64(u64::read_from_memory(memory, ptr, ty)?),
+            AbiTypeDesc::U128 => AbiValue::U128(u128::read_from_memory(memory, ptr, ty)?),
+            AbiTypeDesc::I128 => AbiValue::I128(i128::read_from_memory(memory, ptr, ty)?),
+            AbiTypeDesc::U256 => AbiValue::U256(U256::read_from_memory(memory, ptr, ty)?),
+            AbiTypeDesc::F32 => AbiValue::F32(f32::read_from_memory(memory, ptr, ty)?),
+            AbiTypeDesc::F64 => AbiValue::F64(f64::read_from_memory(memory, ptr, ty)?),
+            AbiTypeDesc::FixedU64 => AbiValue::FixedU64(FixedU64::read_from_memory(memory, ptr, ty)?),
+            AbiTypeDesc::FixedI64 => AbiValue::FixedI64(FixedI64::read_from_memory(memory, ptr, ty)?),
+            AbiTypeDesc::FixedU128 => AbiValue::FixedU128(FixedU128::read_from_memory(memory, ptr, ty)?),
+            AbiTypeDesc::FixedI128 => AbiValue::FixedI128(FixedI128::read_from_memory(memory, ptr, ty)?),
+            AbiTypeDesc::String => AbiValue::String(String::read_from_memory(memory, ptr, ty)?),
+            AbiTypeDesc::ArrayBuffer => {
+                let object = read_object_ptr(memory, ptr, ty)?;
+                AbiValue::ArrayBuffer(read_object_bytes(memory, object)?)
+            }
+            AbiTypeDesc::Array(kind, elem) => {
+                let items = element_addresses(memory, ptr, *kind, elem)?.into_iter()
+                    .map(|address| AbiValue::read_from_memory(memory, address, elem))
+                    .collect::<Result<Vec<_>>>()?;
+                AbiValue::Array { kind: *kind, elem: (**elem).clone(), items }
+            }
+            AbiTypeDesc::Nullable(inner) => {
+                let value = match memory.read_u32(ptr)? {
+                    0 => None,
+                    _ => Some(Box::new(AbiValue::read_from_memory(memory, ptr, inner)?)),
+                };
+                AbiValue::Nullable { inner: (**inner).clone(), value }
+            }
+            AbiTypeDesc::Map(key, value) => {
+                let table = HashTable { key: key.layout(), value: Some(value.layout()) };
+                let mut entries = table.entries(memory, ptr, ty)?.into_iter()
+                    .map(|entry| Ok((
+                        AbiValue::read_from_memory(memory, entry, key)?,
+                        AbiValue::read_from_memory(memory, entry + table.value_offset(), value)?,
+                    )))
+                    .collect::<Result<Vec<_>>>()?;
+                entries.sort_by(|(a, _), (b, _)| compare_keys(a, b));
+                AbiValue::Map { key: (**key).clone(), value: (**value).clone(), entries }
+            }
+            AbiTypeDesc::Set(elem) => {
+                let table = HashTable { key: elem.layout(), value: None };
+                let mut items = table.entries(memory, ptr, ty)?.into_iter()
+                    .map(|entry| AbiValue::read_from_memory(memory, entry, elem))
+                    .collect::<Result<Vec<_>>>()?;
+                items.sort_by(compare_keys);
+                AbiValue::Set { elem: (**elem).clone(), items }
+            }
+            AbiTypeDesc::Class(class) => {
+                let object = read_object_ptr(memory, ptr, ty)?;
+                AbiValue::read_class(memory, object, class)?
+            }
+        })
+    }
+
+    fn write_to_memory(&self, memory: &mut dyn AbiMemory, ptr: u32) -> Result<()> {
+        with_scalar!(self, value => value.write_to_memory(memory, ptr), other => match other {
+            AbiValue::ArrayBuffer(bytes) => {
+                let object = write_object(memory, ptr, &self.type_name(), byte_len(bytes.len())?)?;
+                memory.write(object, bytes)
+            }
+            AbiValue::Array { kind, elem, items } => {
+                write_sequence(memory, ptr, *kind, &self.type_name(), elem.layout().size, items.len(), |memory, i, address| {
+                    items[i].write_to_memory(memory, address)
+                })
+            }
+            AbiValue::Nullable { value, .. } => match value {
+                Some(value) => value.write_to_memory(memory, ptr),
+                None => memory.write_u32(ptr, 0),
+            },
+            AbiValue::Map { key, value, entries } => {
+                let table = HashTable { key: key.layout(), value: Some(value.layout()) };
+                table.write(memory, ptr, &self.type_name(), Some(key), entries.len(), |memory, i, entry| {
+                    entries[i].0.write_to_memory(memory, entry)?;
+                    entries[i].1.write_to_memory(memory, entry + table.value_offset())
+                })
+            }
+            AbiValue::Set { elem, items } => {
+                let table = HashTable { key: elem.layout(), value: None };
+                table.write(memory, ptr, &self.type_name(), Some(elem), items.len(), |memory, i, entry| {
+                    items[i].write_to_memory(memory, entry)
+                })
+            }
+            AbiValue::Class { class, fields } => {
+                let (offsets, size) = class.field_offsets();
+                let object = write_object(memory, ptr, &class.name, size)?;
+                for (field, offset) in fields.iter().zip(offsets) {
+                    field.write_to_memory(memory, object + offset)?;
+                }
+                Ok(())
+            }
+            _ => unreachable!(),
+        })
+    }
+
+    fn encode_scale(&self, dest: &mut Vec<u8>) {
+        with_scalar!(self, value => value.encode_scale(dest), other => match other {
+            AbiValue::ArrayBuffer(bytes) => bytes.encode_to(dest),
+            AbiValue::Array { items, .. } => {
+                Compact(items.len() as u32).encode_to(dest);
+                for item in items {
+                    item.encode_scale(dest);
+                }
+            }
+            AbiValue::Nullable { value, .. } => match value {
+                Some(value) => {
+                    dest.push(1);
+                    value.encode_scale(dest);
+                }
+                None => dest.push(0),
+            },
+            AbiValue::Map { entries, .. } => {
+                Compact(entries.len() as u32).encode_to(dest);
+                for (key, value) in entries {
+                    key.encode_scale(dest);
+                    value.encode_scale(dest);
+                }
+            }
+            AbiValue::Set { items, .. } => {
+                Compact(items.len() as u32).encode_to(dest);
+                for item in items {
+                    item.encode_scale(dest);
+                }
+            }
+            AbiValue::Class { fields, .. } => {
+                for field in fields {
+                    field.encode_scale(dest);
+                }
+            }
+            _ => unreachable!(),
+        })
+    }
+
+    fn decode_scale(input: &mut &[u8], ty: &AbiTypeDesc) -> Result<Self> {
+        Ok(match ty {
+            AbiTypeDesc::Bool => AbiValue::Bool(bool::decode_scale(input, ty)?),
+            AbiTypeDesc::I8 => AbiValue::I8(i8::decode_scale(input, ty)?),
+            AbiTypeDesc::U8 => AbiValue::U8(u8::decode_scale(input, ty)?),
+            AbiTypeDesc::I16 => AbiValue::I16(i16::decode_scale(input, ty)?),
+            AbiTypeDesc::U16 => AbiValue::U16(u16::decode_scale(input, ty)?),
+            AbiTypeDesc::I32 => AbiValue::I32(i32::decode_scale(input, ty)?),
+            AbiTypeDesc::U32 => AbiValue::U32(u32::decode_scale(input, ty)?),
+            AbiTypeDesc::I64 => AbiValue::I64(i64::decode_scale(input, ty)?),
+            AbiTypeDesc::U64 => AbiValue::U64(u64::decode_scale(input, ty)?),
+            AbiTypeDesc::U128 => AbiValue::U128(u128::decode_scale(input, ty)?),
+            AbiTypeDesc::I128 => AbiValue::I128(i128::decode_scale(input, ty)?),
+            AbiTypeDesc::U256 => AbiValue::U256(U256::decode_scale(input, ty)?),
+            AbiTypeDesc::F32 => AbiValue::F32(f32::decode_scale(input, ty)?),
+            AbiTypeDesc::F64 => AbiValue::F64(f64::decode_scale(input, ty)?),
+            AbiTypeDesc::FixedU64 => AbiValue::FixedU64(FixedU64::decode_scale(input, ty)?),
+            AbiTypeDesc::FixedI64 => AbiValue::FixedI64(FixedI64::decode_scale(input, ty)?),
+            AbiTypeDesc::FixedU128 => AbiValue::FixedU128(FixedU128::decode_scale(input, ty)?),
+            AbiTypeDesc::FixedI128 => AbiValue::FixedI128(FixedI128::decode_scale(input, ty)?),
+            AbiTypeDesc::String => AbiValue::String(String::decode_scale(input, ty)?),
+            AbiTypeDesc::ArrayBuffer => AbiValue::ArrayBuffer(Vec::<u8>::decode(input)?),
+            AbiTypeDesc::Array(kind, elem) => {
+                let len = Compact::<u32>::decode(input)?.0;
+                let items = (0..len)
+                    .map(|_| AbiValue::decode_scale(input, elem))
+                    .collect::<Result<Vec<_>>>()?;
+                AbiValue::Array { kind: *kind, elem: (**elem).clone(), items }
+            }
+            AbiTypeDesc::Nullable(inner) => {
+                let value = match u8::decode(input)? {
+                    0 => None,
+                    1 => Some(Box::new(AbiValue::decode_scale(input, inner)?)),
+                    tag => return Err(anyhow!("ERROR: invalid option tag {} for {}", tag, ty)),
+                };
+                AbiValue::Nullable { inner: (**inner).clone(), value }
+            }
+            AbiTypeDesc::Map(key, value) => {
+                let len = Compact::<u32>::decode(input)?.0;
+                let mut entries = (0..len)
+                    .map(|_| Ok((AbiValue::decode_scale(input, key)?, AbiValue::decode_scale(input, value)?)))
+                    .collect::<Result<Vec<_>>>()?;
+                // Decoded like a `BTreeMap`: sorted by key, the last value of a repeated key wins.
+                entries.reverse();
+                entries.sort_by(|(a, _), (b, _)| compare_keys(a, b));
+                entries.dedup_by(|(a, _), (b, _)| compare_keys(a, b) == Ordering::Equal);
+                AbiValue::Map { key: (**key).clone(), value: (**value).clone(), entries }
+            }
+            AbiTypeDesc::Set(elem) => {
+                let len = Compact::<u32>::decode(input)?.0;
+                let mut items = (0..len)
+                    .map(|_| AbiValue::decode_scale(input, elem))
+                    .collect::<Result<Vec<_>>>()?;
+                items.sort_by(compare_keys);
+                items.dedup_by(|a, b| compare_keys(a, b) == Ordering::Equal);
+                AbiValue::Set { elem: (**elem).clone(), items }
+            }
+            AbiTypeDesc::Class(class) => {
+                let fields = class.fields.iter()
+                    .map(|(_, ty)| AbiValue::decode_scale(input, ty))
+                    .collect::<Result<Vec<_>>>()?;
+                AbiValue::Class { class: class.clone(), fields }
+            }
+        })
+    }
+
+    fn to_json(&self) -> Value {
+        with_scalar!(self, value => value.to_json(), other => match other {
+            AbiValue::ArrayBuffer(bytes) => Value::String(format!("0x{}", hex::encode(bytes))),
+            AbiValue::Array { items, .. } => Value::Array(items.iter().map(ABIType::to_json).collect()),
+            AbiValue::Nullable { value, .. } => value.as_ref().map_or(Value::Null, |value| value.to_json()),
+            AbiValue::Map { entries, .. } => Value::Object(entries.iter()
+                .map(|(key, value)| (json_key(key.to_json()), value.to_json()))
+                .collect()),
+            AbiValue::Set { items, .. } => Value::Array(items.iter().map(ABIType::to_json).collect()),
+            AbiValue::Class { class, fields } => {
+                let object: Map<String, Value> = class.fields.iter().zip(fields)
+                    .map(|((name, _), value)| (name.clone(), value.to_json()))
+                    .collect();
+                Value::Object(object)
+            }
+            _ => unreachable!(),
+        })
+    }
+}
+
+fn expect_type(ty: &AbiTypeDesc, expected: &AbiTypeDesc) -> Result<()> {
+    if ty != expected {
+        return Err(anyhow!("ERROR: expected a value of type {}, found {}", expected, ty));
+    }
+    Ok(())
+}
+
+fn array_elem(ty: &AbiTypeDesc) -> Result<(ArrayKind, &AbiTypeDesc)> {
+    match ty {
+        AbiTypeDesc::Array(kind, elem) => Ok((*kind, elem)),
+        ty => Err(anyhow!("ERROR: expected an array, found {}", ty)),
+    }
+}
+
+fn nullable_inner(ty: &AbiTypeDesc) -> Result<&AbiTypeDesc> {
+    match ty {
+        AbiTypeDesc::Nullable(inner) => Ok(inner),
+        ty => Err(anyhow!("ERROR: expected a nullable type, found {}", ty)),
+    }
+}
+
+fn map_types(ty: &AbiTypeDesc) -> Result<(&AbiTypeDesc, &AbiTypeDesc)> {
+    match ty {
+        AbiTypeDesc::Map(key, value) => Ok((key, value)),
+        ty => Err(anyhow!("ERROR: expected a map, found {}", ty)),
+    }
+}
+
+fn set_elem(ty: &AbiTypeDesc) -> Result<&AbiTypeDesc> {
+    match ty {
+        AbiTypeDesc::Set(elem) => Ok(elem),
+        ty => Err(anyhow!("ERROR: expected a set, found {}", ty)),
+    }
+}
+
+/// JSON object keys are strings, any other key is rendered as its JSON text.
+fn json_key(key: Value) -> String {
+    match key {
+        Value::String(key) => key,
+        key => key.to_string(),
+    }
+}
+
+/// Orders map keys and set items like their Rust types do, so they encode like a `BTreeMap` or
+/// `BTreeSet`. Keys without a natural order (classes, arrays) are ordered by their encoding.
+fn compare_keys(a: &AbiValue, b: &AbiValue) -> Ordering {
+    match (a, b) {
+        (AbiValue::String(a), AbiValue::String(b)) => a.cmp(b),
+        (AbiValue::F32(a), AbiValue::F32(b)) => a.total_cmp(b),
+        (AbiValue::F64(a), AbiValue::F64(b)) => a.total_cmp(b),
+        (AbiValue::U128(a), AbiValue::U128(b)) => a.cmp(b),
+        (AbiValue::U256(a), AbiValue::U256(b)) => a.cmp(b),
+        (AbiValue::FixedU128(a), AbiValue::FixedU128(b)) => a.cmp(b),
+        _ => match (a.as_i128(), b.as_i128()) {
+            (Some(a), Some(b)) => a.cmp(&b),
+            _ => {
+                let (mut a_bytes, mut b_bytes) = (Vec::new(), Vec::new());
+                a.encode_scale(&mut a_bytes);
+                b.encode_scale(&mut b_bytes);
+                a_bytes.cmp(&b_bytes)
+            }
+        },
+    }
+}
+
+fn float_json(value: f64) -> Value {
+    Number::from_f64(value).map(Value::Number).unwrap_or(Value::Null)
+}
+
+/// Splits `typ` at every `separator` that is not nested inside `<...>`.
+pub(crate) fn split_top_level(typ: &str, separator: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in typ.char_indices() {
+        match c {
+            '<' => depth += 1,
+            '>' => depth -= 1,
+            c if c == separator && depth == 0 => {
+                parts.push(typ[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(typ[start..].trim());
+    parts
+}
+
+/// Reads the string object at `object`.
+pub(crate) fn read_string(memory: &mut dyn AbiMemory, object: u32) -> Result<String> {
+    let bytes = read_object_bytes(memory, object)?;
+    let units: Vec<u16> = bytes.chunks_exact(2).map(|unit| u16::from_le_bytes([unit[0], unit[1]])).collect();
+    String::from_utf16(&units).map_err(|e| anyhow!("ERROR: invalid UTF-16 string at {}: {}", object, e))
+}
+
+/// Allocates a string object holding `value` and returns it.
+pub(crate) fn alloc_string(memory: &mut dyn AbiMemory, value: &str) -> Result<u32> {
+    let bytes: Vec<u8> = value.encode_utf16().flat_map(u16::to_le_bytes).collect();
+    let object = memory.alloc(bytes.len() as u32, STRING_ID)?;
+    memory.write(object, &bytes)?;
+    Ok(object)
+}
+
+/// Allocates an object of `size` bytes for a value of `type_name` and stores its pointer at `ptr`.
+fn write_object(memory: &mut dyn AbiMemory, ptr: u32, type_name: &str, size: u32) -> Result<u32> {
+    let rt_id = memory.runtime_id(type_name)?;
+    let object = memory.alloc(size, rt_id)?;
+    memory.write_u32(ptr, object)?;
+    Ok(object)
+}
+
+fn byte_len(len: usize) -> Result<u32> {
+    u32::try_from(len).map_err(|_| anyhow!("ERROR: {} bytes do not fit in a script's memory", len))
+}
+
+/// Writes a sequence of `len` elements of `size` bytes in the layout `element_addresses` reads,
+/// `write_elem` stores the element `i` at the given address.
+fn write_sequence(
+    memory: &mut dyn AbiMemory,
+    ptr: u32,
+    kind: ArrayKind,
+    type_name: &str,
+    size: u32,
+    len: usize,
+    mut write_elem: impl FnMut(&mut dyn AbiMemory, usize, u32) -> Result<()>,
+) -> Result<()> {
+    let data_len = byte_len(len)?.checked_mul(size)
+        .ok_or_else(|| anyhow!("ERROR: {} elements of {} bytes do not fit in a script's memory", len, size))?;
+    let data = match kind {
+        ArrayKind::Static => write_object(memory, ptr, type_name, data_len)?,
+        ArrayKind::Array | ArrayKind::Typed => {
+            let header = if kind == ArrayKind::Array { 16 } else { 12 };
+            let object = write_object(memory, ptr, type_name, header)?;
+            let buffer = memory.alloc(data_len, ARRAY_BUFFER_ID)?;
+            memory.write_u32(object, buffer)?;
+            memory.write_u32(object + 4, buffer)?;
+            memory.write_u32(object + 8, data_len)?;
+            if kind == ArrayKind::Array {
+                memory.write_u32(object + 12, len as u32)?;
+            }
+            buffer
+        }
+    };
+    for i in 0..len {
+        write_elem(memory, i, data + i as u32 * size)?;
+    }
+    Ok(())
+}
+
+/// Reads the object pointer stored at `ptr`, non-nullable types never hold a null pointer.
+fn read_object_ptr(memory: &mut dyn AbiMemory, ptr: u32, ty: &AbiTypeDesc) -> Result<u32> {
+    match memory.read_u32(ptr)? {
+        0 => Err(anyhow!("ERROR: null pointer for a value of type {}", ty)),
+        object => Ok(object),
+    }
+}
+
+/// Reads the whole payload of a managed object, its size is stored in the runtime header right
+/// before the object.
+pub(crate) fn read_object_bytes(memory: &mut dyn AbiMemory, object: u32) -> Result<Vec<u8>> {
+    let byte_len = object_size(memory, object)?;
+    let mut bytes = vec![0u8; byte_len as usize];
+    memory.read(object, &mut bytes)?;
+    Ok(bytes)
+}
+
+/// Payload size of the managed object at `object`, checked to lie within the script's memory.
+fn object_size(memory: &mut dyn AbiMemory, object: u32) -> Result<u32> {
+    let header = object.checked_sub(4)
+        .ok_or_else(|| anyhow!("ERROR: invalid object pointer {}", object))?;
+    let byte_len = memory.read_u32(header)?;
+    check_range(memory, object, byte_len as u64)?;
+    Ok(byte_len)
+}
+
+/// Fails unless the `len` bytes starting at `start` lie within the script's memory, guest
+/// supplied lengths are checked before anything is allocated or iterated for them.
+fn check_range(memory: &mut dyn AbiMemory, start: u32, len: u64) -> Result<()> {
+    let size = memory.data_size() as u64;
+    match (start as u64).checked_add(len) {
+        Some(end) if end <= size => Ok(()),
+        _ => Err(anyhow!("ERROR: {} bytes at {} are out of bounds of the {} byte memory", len, start, size)),
+    }
+}
+
+
+/// Addresses of the elements of the sequence whose pointer is stored at `ptr`.
+///
+/// An `Array<T>` object holds `buffer`, `dataStart`, `byteLength` and `length`, typed arrays the
+/// first three, a `StaticArray<T>` stores its elements inline.
+fn element_addresses(memory: &mut dyn AbiMemory, ptr: u32, kind: ArrayKind, elem: &AbiTypeDesc) -> Result<Vec<u32>> {
+    let object = read_object_ptr(memory, ptr, &AbiTypeDesc::Array(kind, Box::new(elem.clone())))?;
+    let size = elem.layout().size;
+    let header_len = match kind {
+        ArrayKind::Array => 16,
+        ArrayKind::Typed => 12,
+        ArrayKind::Static => 0,
+    };
+    check_range(memory, object, header_len)?;
+    let (data, len) = match kind {
+        ArrayKind::Array => (memory.read_u32(object + 4)?, memory.read_u32(object + 12)?),
+        ArrayKind::Typed => (memory.read_u32(object + 4)?, memory.read_u32(object + 8)? / size),
+        ArrayKind::Static => (object, object_size(memory, object)? / size),
+    };
+    check_range(memory, data, len as u64 * size as u64)?;
+    let mut addresses = Vec::new();
+    for i in 0..len {
+        let address = data + i * size;
+        // Arrays created with `new Array<T>(n)` start out with `n` null slots, those are skipped
+        // unless the elements are declared nullable.
+        if elem.is_managed() && !matches!(elem, AbiTypeDesc::Nullable(_)) && memory.read_u32(address)? == 0 {
+            continue;
+        }
+        addresses.push(address);
+    }
+    Ok(addresses)
+}
+
+/// Set in `taggedNext` once an entry is deleted.
+const HASH_TABLE_EMPTY: u32 = 1;
+
+/// Layout of AssemblyScript's `Map<K, V>` and `Set<T>`, objects holding `buckets`, `bucketsMask`,
+/// `entries`, `entriesCapacity`, `entriesOffset` and `entriesCount`. Entries are stored in
+/// insertion order as `key`, `value` (maps only) and `taggedNext`, the next entry of the same
+/// bucket.
+struct HashTable {
+    key: Layout,
+    value: Option<Layout>,
+}
+
+impl HashTable {
+    fn value_offset(&self) -> u32 {
+        self.value.map_or(self.key.size, |value| self.key.size.next_multiple_of(value.align))
+    }
+
+    fn tagged_next_offset(&self) -> u32 {
+        let end = self.value.map_or(self.key.size, |value| self.value_offset() + value.size);
+        end.next_multiple_of(Layout::POINTER.align)
+    }
+
+    /// Entries are padded to the largest of the key, the value and a pointer.
+    fn entry_size(&self) -> u32 {
+        let largest = self.value.map_or(self.key.size, |value| value.size.max(self.key.size)).max(Layout::POINTER.size);
+        (self.tagged_next_offset() + Layout::POINTER.size).next_multiple_of(largest)
+    }
+
+    /// Addresses of the live entries of the table whose pointer is stored at `ptr`.
+    fn entries(&self, memory: &mut dyn AbiMemory, ptr: u32, ty: &AbiTypeDesc) -> Result<Vec<u32>> {
+        let object = read_object_ptr(memory, ptr, ty)?;
+        check_range(memory, object, 24)?;
+        let entries = memory.read_u32(object + 8)?;
+        let entries_offset = memory.read_u32(object + 16)?;
+        check_range(memory, entries, entries_offset as u64 * self.entry_size() as u64)?;
+        let mut live = Vec::new();
+        for i in 0..entries_offset {
+            let entry = entries + i * self.entry_size();
+            if memory.read_u32(entry + self.tagged_next_offset())? & HASH_TABLE_EMPTY == 0 {
+                live.push(entry);
+            }
+        }
+        Ok(live)
+    }
+
+    /// Writes a table of `len` entries for a value of `type_name` and stores its pointer at `ptr`,
+    /// `write_entry` stores the key and value of the entry `i` at the given address. Every entry is
+    /// linked into the bucket of the hash the script computes for the key just written, so the
+    /// script finds it with `has` and `get`.
+    fn write(
+        &self,
+        memory: &mut dyn AbiMemory,
+        ptr: u32,
+        type_name: &str,
+        key: Option<&AbiTypeDesc>,
+        len: usize,
+        mut write_entry: impl FnMut(&mut dyn AbiMemory, usize, u32) -> Result<()>,
+    ) -> Result<()> {
+        let too_large = || anyhow!("ERROR: {} entries do not fit in a script's memory", len);
+        let count = byte_len(len)?;
+        // Sized like the script sizes a table after a rehash: a power of two of buckets, 8/3
+        // entries per bucket.
+        let buckets_len = count.max(4).checked_next_power_of_two().ok_or_else(too_large)?;
+        let capacity = buckets_len.checked_mul(8).ok_or_else(too_large)? / 3;
+        let entries_len = capacity.checked_mul(self.entry_size()).ok_or_else(too_large)?;
+        let object = write_object(memory, ptr, type_name, 24)?;
+        let buckets = memory.alloc(buckets_len * Layout::POINTER.size, ARRAY_BUFFER_ID)?;
+        let entries = memory.alloc(entries_len, ARRAY_BUFFER_ID)?;
+        for (offset, field) in [buckets, buckets_len - 1, entries, capacity, count, count].into_iter().enumerate() {
+            memory.write_u32(object + offset as u32 * 4, field)?;
+        }
+        for i in 0..count {
+            let entry = entries + i * self.entry_size();
+            write_entry(memory, i as usize, entry)?;
+            let bucket = buckets + (key_hash(memory, entry, key)? & (buckets_len - 1)) * Layout::POINTER.size;
+            let next = memory.read_u32(bucket)?;
+            memory.write_u32(entry + self.tagged_next_offset(), next)?;
+            memory.write_u32(bucket, entry)?;
+        }
+        Ok(())
+    }
+}
+
+/// Key type of a generic `Map<K, V>` or `Set<T>`, classes are unknown to the default schema.
+fn generic_key<K: ABIType + Default>() -> Option<AbiTypeDesc> {
+    AbiSchema::default().resolve(&K::default().type_name()).ok()
+}
+
+const XXH32_P1: u32 = 2654435761;
+const XXH32_P2: u32 = 2246822519;
+const XXH32_P3: u32 = 3266489917;
+const XXH32_P4: u32 = 668265263;
+const XXH32_P5: u32 = 374761393;
+
+/// The hash AssemblyScript's `HASH<T>` computes for the key stored at `entry`: strings by their
+/// content, other objects (and keys of an unknown type, which are classes) by their address.
+fn key_hash(memory: &mut dyn AbiMemory, entry: u32, key: Option<&AbiTypeDesc>) -> Result<u32> {
+    let string = match key {
+        Some(AbiTypeDesc::Nullable(inner)) => **inner == AbiTypeDesc::String,
+        key => key == Some(&AbiTypeDesc::String),
+    };
+    if string {
+        return match memory.read_u32(entry)? {
+            0 => Ok(0),
+            object => Ok(xxh32(&read_object_bytes(memory, object)?)),
+        };
+    }
+    let Some(key) = key.filter(|key| !key.is_managed()) else {
+        return Ok(xxh32(&memory.read_u32(entry)?.to_le_bytes()));
+    };
+    let size = key.layout().size;
+    let mut bytes = [0u8; 8];
+    memory.read(entry, &mut bytes[..size as usize])?;
+    Ok(match key {
+        AbiTypeDesc::I8 => hash32(bytes[0] as i8 as u32, size),
+        AbiTypeDesc::I16 => hash32(i16::from_le_bytes([bytes[0], bytes[1]]) as u32, size),
+        AbiTypeDesc::Bool | AbiTypeDesc::U8 | AbiTypeDesc::U16 => hash32(u16::from_le_bytes([bytes[0], bytes[1]]) as u32, size),
+        _ => xxh32(&bytes[..size as usize]),
+    })
+}
+
+/// AssemblyScript's `hash32` of a key narrower than four bytes, its value sign or zero extended.
+fn hash32(key: u32, len: u32) -> u32 {
+    let h = XXH32_P5.wrapping_add(len).wrapping_add(key.wrapping_mul(XXH32_P3));
+    xxh32_avalanche(h.rotate_left(17).wrapping_mul(XXH32_P4))
+}
+
+/// XXH32 with a zero seed, AssemblyScript hashes strings with it and its `hash32` and `hash64`
+/// of four and eight byte keys match it too.
+fn xxh32(bytes: &[u8]) -> u32 {
+    let word = |bytes: &[u8]| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    let mut rest = bytes;
+    let mut h = if bytes.len() >= 16 {
+        let mut lanes = [XXH32_P1.wrapping_add(XXH32_P2), XXH32_P2, 0, XXH32_P1.wrapping_neg()];
+        while rest.len() >= 16 {
+            for (lane, bytes) in lanes.iter_mut().zip(rest[..16].chunks_exact(4)) {
+                *lane = lane.wrapping_add(word(bytes).wrapping_mul(XXH32_P2)).rotate_left(13).wrapping_mul(XXH32_P1);
+            }
+            rest = &rest[16..];
+        }
+        lanes.iter().zip([1, 7, 12, 18]).fold(0u32, |h, (lane, bits)| h.wrapping_add(lane.rotate_left(bits)))
+    } else {
+        XXH32_P5
+    };
+    h = h.wrapping_add(bytes.len() as u32);
+    let mut words = rest.chunks_exact(4);
+    for bytes in &mut words {
+        h = h.wrapping_add(word(bytes).wrapping_mul(XXH32_P3)).rotate_left(17).wrapping_mul(XXH32_P4);
+    }
+    for &byte in words.remainder() {
+        h = h.wrapping_add((byte as u32).wrapping_mul(XXH32_P5)).rotate_left(11).wrapping_mul(XXH32_P1);
+    }
+    xxh32_avalanche(h)
+}
+
+fn xxh32_avalanche(mut h: u32) -> u32 {
+    h ^= h >> 15;
+    h = h.wrapping_mul(XXH32_P2);
+    h ^= h >> 13;
+    h = h.wrapping_mul(XXH32_P3);
+    h ^ (h >> 16)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use super::*;
+    use crate::core::testing::TestMemory;
+
+    fn schema() -> AbiSchema {
+        AbiSchema::new(HashMap::from([
+            ("CryptoValue".to_string(), vec![("usd".to_string(), "f64".to_string())]),
+            ("Output".to_string(), vec![
+                ("greater".to_string(), "string".to_string()),
+                ("custom".to_string(), "Array<CryptoValue>".to_string()),
+                ("primi_i".to_string(), "i32".to_string()),
+            ]),
+        ]))
+    }
+
+    /// Stores an object of `size` bytes holding `words` and a slot pointing to it, returns the slot.
+    fn object(memory: &mut TestMemory, size: u32, words: &[u32]) -> u32 {
+        let object = memory.alloc(size, 3).unwrap();
+        for (i, word) in words.iter().enumerate() {
+            memory.put_u32(object + i as u32 * 4, *word);
+        }
+        let slot = memory.alloc(4, 3).unwrap();
+        memory.put_u32(slot, object);
+        slot
+    }
+
+    #[test]
+    fn reads_classes_in_assemblyscript_layout() {
+        let mut memory = TestMemory::default();
+        let greater = alloc_string(&mut memory, "bitcoin").unwrap();
+        let value = memory.alloc(8, 4).unwrap();
+        memory.write(value, &104700.5f64.to_le_bytes()).unwrap();
+        let data = memory.alloc(8, ARRAY_BUFFER_ID).unwrap();
+        memory.put_u32(data, value);
+        let custom = memory.alloc(16, 5).unwrap();
+        for (i, word) in [data, data, 8, 2].into_iter().enumerate() {
+            memory.put_u32(custom + i as u32 * 4, word);
+        }
+        let output = memory.alloc(12, 6).unwrap();
+        for (i, word) in [greater, custom, 42].into_iter().enumerate() {
+            memory.put_u32(output + i as u32 * 4, word);
+        }
+        let value = AbiValue::read_class(&mut memory, output, &schema().class("Output").unwrap()).unwrap();
+        // The second slot of `custom` is null and skipped.
+        assert_eq!(value.to_json(), serde_json::json!({ "greater": "bitcoin", "custom": [{ "usd": 104700.5 }], "primi_i": 42 }));
+    }
+
+    /// Writes `value` into a slot of its layout and reads it back as `ty`.
+    fn write_read(memory: &mut TestMemory, value: &AbiValue, ty: &AbiTypeDesc) -> AbiValue {
+        let slot = memory.alloc(value.layout().size, ARRAY_BUFFER_ID).unwrap();
+        value.write_to_memory(memory, slot).unwrap();
+        AbiValue::read_from_memory(memory, slot, ty).unwrap()
+    }
+
+    #[test]
+    fn written_values_read_back() {
+        let mut memory = TestMemory::default();
+        let ty = schema().resolve("Output").unwrap();
+        let output = ("btc", vec![1.5f64.to_bits(), 2.25f64.to_bits()], 7i32).encode();
+        let value = AbiValue::decode_scale(&mut &output[..], &ty).unwrap();
+        assert_eq!(write_read(&mut memory, &value, &ty), value);
+        assert_eq!(memory.ids, ["Output", "Array<CryptoValue>", "CryptoValue"]);
+
+        let slot = memory.alloc(4, ARRAY_BUFFER_ID).unwrap();
+        "orascript".to_string().write_to_memory(&mut memory, slot).unwrap();
+        let object = memory.read_u32(slot).unwrap();
+        assert_eq!(memory.read_u32(object - 8).unwrap(), STRING_ID);
+        assert_eq!(String::read_from_memory(&mut memory, slot, &AbiTypeDesc::String).unwrap(), "orascript");
+    }
+
+    #[test]
+    fn primitives_write_and_read_back() {
+        let mut memory = TestMemory::default();
+        let schema = schema();
+        let cases = [
+            ("bool", true.encode()),
+            ("i8", (-8i8).encode()),
+            ("u8", 200u8.encode()),
+            ("i16", (-300i16).encode()),
+            ("u16", 60000u16.encode()),
+            ("isize", i32::MIN.encode()),
+            ("usize", u32::MAX.encode()),
+            ("i64", i64::MIN.encode()),
+            ("u64", u64::MAX.encode()),
+            ("f32", 2523.13f32.to_bits().encode()),
+            ("f64", (-0.1f64).to_bits().encode()),
+            ("u128", u128::MAX.encode()),
+            ("i128", i128::MIN.encode()),
+            ("u256", U256([1, 2, 3, u64::MAX]).encode()),
+            ("FixedU64", 1_500_000_000u64.encode()),
+            ("FixedI128", (-2_500_000_000_000_000_000i128).encode()),
+            ("string", "orascript".encode()),
+            ("string | null", None::<String>.encode()),
+        ];
+        for (typ, bytes) in cases {
+            let ty = schema.resolve(typ).unwrap();
+            let value = AbiValue::decode_scale(&mut &bytes[..], &ty).unwrap();
+            assert_eq!(value.layout(), ty.layout(), "{}", typ);
+            assert_eq!(write_read(&mut memory, &value, &ty), value, "{}", typ);
+        }
+
+        let slot = memory.alloc(8, ARRAY_BUFFER_ID).unwrap();
+        (-2i16).write_to_memory(&mut memory, slot).unwrap();
+        assert_eq!(memory.bytes[slot as usize..slot as usize + 2], (-2i16).to_le_bytes());
+        Some(7u64).write_to_memory(&mut memory, slot).unwrap_err();
+        None::<u32>.write_to_memory(&mut memory, slot).unwrap_err();
+        Some("s".to_string()).write_to_memory(&mut memory, slot).unwrap();
+    }
+
+    #[test]
+    fn sequences_write_and_read_back() {
+        let mut memory = TestMemory::default();
+        let schema = schema();
+        let cases = [
+            ("Array<i32>", vec![1i32, -2, 3].encode()),
+            ("Array<string | null>", vec![Some("a"), None, Some("c")].encode()),
+            ("Array<Array<u8>>", vec![vec![1u8], vec![], vec![2, 3]].encode()),
+            ("StaticArray<i16>", vec![-1i16, 2].encode()),
+            ("StaticArray<string>", vec!["x", "y"].encode()),
+            ("Uint8Array", vec![1u8, 255].encode()),
+            ("Int64Array", vec![i64::MIN, 0, i64::MAX].encode()),
+            ("Float64Array", vec![1.5f64.to_bits(), (-2.0f64).to_bits()].encode()),
+            ("Array<u128>", vec![u128::MAX, 1].encode()),
+            ("ArrayBuffer", vec![0xdeu8, 0xad, 0xbe, 0xef].encode()),
+            ("Array<CryptoValue>", Vec::<u64>::new().encode()),
+        ];
+        for (typ, bytes) in cases {
+            let ty = schema.resolve(typ).unwrap();
+            let value = AbiValue::decode_scale(&mut &bytes[..], &ty).unwrap();
+            assert_eq!(write_read(&mut memory, &value, &ty), value, "{}", typ);
+        }
+
+        // `Vec<T>` is written as an `Array<T>`: a header pointing to a buffer of the elements.
+        let slot = memory.alloc(4, ARRAY_BUFFER_ID).unwrap();
+        vec![10u16, 20, 30].write_to_memory(&mut memory, slot).unwrap();
+        let array = memory.read_u32(slot).unwrap();
+        let buffer = memory.read_u32(array).unwrap();
+        assert_eq!(memory.read_u32(array - 8).unwrap(), memory.runtime_id("Array<u16>").unwrap());
+        let header = [4, 8, 12].map(|offset| memory.read_u32(array + offset).unwrap());
+        assert_eq!(header, [buffer, 6, 3]);
+        assert_eq!(memory.read_u32(buffer - 8).unwrap(), ARRAY_BUFFER_ID);
+        let ty = schema.resolve("Array<u16>").unwrap();
+        assert_eq!(Vec::<u16>::read_from_memory(&mut memory, slot, &ty).unwrap(), [10, 20, 30]);
+
+        // A typed array is a view of three words, a static array stores its elements inline.
+        let ty = schema.resolve("Uint32Array").unwrap();
+        let value = AbiValue::decode_scale(&mut &vec![7u32, 8].encode()[..], &ty).unwrap();
+        value.write_to_memory(&mut memory, slot).unwrap();
+        let view = memory.read_u32(slot).unwrap();
+        let buffer = memory.read_u32(view).unwrap();
+        let header = [4, 8].map(|offset| memory.read_u32(view + offset).unwrap());
+        assert_eq!(header, [buffer, 8]);
+        assert_eq!(memory.bytes[buffer as usize..buffer as usize + 8], [7, 0, 0, 0, 8, 0, 0, 0]);
+        let ty = schema.resolve("StaticArray<u8>").unwrap();
+        let value = AbiValue::decode_scale(&mut &vec![4u8, 5, 6].encode()[..], &ty).unwrap();
+        value.write_to_memory(&mut memory, slot).unwrap();
+        let object = memory.read_u32(slot).unwrap();
+        assert_eq!(memory.read_u32(object - 4).unwrap(), 3);
+        assert_eq!(memory.bytes[object as usize..object as usize + 3], [4, 5, 6]);
+    }
+
+    #[test]
+    fn rejects_pointers_into_the_first_word() {
+        let mut memory = TestMemory::default();
+        memory.alloc(16, 3).unwrap();
+        for object in 1..4 {
+            assert!(read_object_bytes(&mut memory, object).is_err());
+        }
+    }
+
+    #[test]
+    fn rejects_lengths_beyond_the_memory() {
+        let mut memory = TestMemory::default();
+        let string = memory.alloc(4, STRING_ID).unwrap();
+        memory.put_u32(string - 4, u32::MAX);
+        assert!(read_string(&mut memory, string).is_err());
+
+        let bytes = AbiTypeDesc::Array(ArrayKind::Array, Box::new(AbiTypeDesc::U8));
+        let slot = object(&mut memory, 16, &[string, string, u32::MAX, u32::MAX]);
+        assert!(AbiValue::read_from_memory(&mut memory, slot, &bytes).is_err());
+
+        let words = AbiTypeDesc::Array(ArrayKind::Typed, Box::new(AbiTypeDesc::U64));
+        let slot = object(&mut memory, 12, &[string, u32::MAX - 4, 64]);
+        assert!(AbiValue::read_from_memory(&mut memory, slot, &words).is_err());
+
+        let map = schema().resolve("Map<string, i32>").unwrap();
+        let slot = object(&mut memory, 24, &[string, 3, string, 4, u32::MAX, u32::MAX]);
+        assert!(AbiValue::read_from_memory(&mut memory, slot, &map).is_err());
+    }
+
+    #[test]
+    fn rejects_objects_past_the_end_of_memory() {
+        let mut memory = TestMemory::default();
+        memory.alloc(16, 3).unwrap();
+        let end = memory.data_size() as u32 - 4;
+        let class = schema().class("Output").unwrap();
+        assert!(AbiValue::read_class(&mut memory, end, &class).is_err());
+        assert!(AbiValue::read_class(&mut memory, u32::MAX - 2, &class).is_err());
+        let slot = memory.alloc(4, 3).unwrap();
+        let array = memory.alloc(4, 3).unwrap();
+        memory.put_u32(slot, array);
+        memory.put_u32(array - 4, 64);
+        let fixed = AbiTypeDesc::Array(ArrayKind::Static, Box::new(AbiTypeDesc::U32));
+        assert!(AbiValue::read_from_memory(&mut memory, slot, &fixed).is_err());
+    }
+
+    /// Decodes `bytes` as `typ`, checks that the value encodes back to them and returns its JSON.
+    fn round_trip(schema: &AbiSchema, typ: &str, bytes: &[u8]) -> Value {
+        let ty = schema.resolve(typ).unwrap();
+        let mut input = bytes;
+        let value = AbiValue::decode_scale(&mut input, &ty).unwrap();
+        assert!(input.is_empty(), "{} left bytes", typ);
+        assert_eq!(value.type_name(), ty.to_string());
+        let mut encoded = Vec::new();
+        value.encode_scale(&mut encoded);
+        assert_eq!(encoded, bytes, "{} does not encode back", typ);
+        value.to_json()
+    }
+
+    #[test]
+    fn primitives_round_trip_through_scale() {
+        let schema = schema();
+        assert_eq!(round_trip(&schema, "bool", &true.encode()), json!(true));
+        assert_eq!(round_trip(&schema, "i8", &(-8i8).encode()), json!(-8));
+        assert_eq!(round_trip(&schema, "u8", &200u8.encode()), json!(200));
+        assert_eq!(round_trip(&schema, "i16", &(-300i16).encode()), json!(-300));
+        assert_eq!(round_trip(&schema, "u16", &60000u16.encode()), json!(60000));
+        assert_eq!(round_trip(&schema, "i32", &(-70000i32).encode()), json!(-70000));
+        assert_eq!(round_trip(&schema, "usize", &u32::MAX.encode()), json!(u32::MAX));
+        assert_eq!(round_trip(&schema, "isize", &i32::MIN.encode()), json!(i32::MIN));
+        assert_eq!(round_trip(&schema, "i64", &i64::MIN.encode()), json!(i64::MIN));
+        assert_eq!(round_trip(&schema, "u64", &u64::MAX.encode()), json!(u64::MAX));
+        assert_eq!(round_trip(&schema, "f32", &2523.13f32.to_bits().encode()), json!(2523.13));
+        assert_eq!(round_trip(&schema, "f64", &0.1f64.to_bits().encode()), json!(0.1));
+        assert_eq!(round_trip(&schema, "string", &"orascript".encode()), json!("orascript"));
+    }
+
+    #[test]
+    fn sequences_round_trip_through_scale() {
+        let schema = schema();
+        assert_eq!(round_trip(&schema, "StaticArray<i16>", &vec![-1i16, 2].encode()), json!([-1, 2]));
+        assert_eq!(round_trip(&schema, "Float64Array", &vec![1.5f64.to_bits()].encode()), json!([1.5]));
+        assert_eq!(round_trip(&schema, "Uint8Array", &vec![1u8, 255].encode()), json!([1, 255]));
+        assert_eq!(round_trip(&schema, "ArrayBuffer", &vec![0xdeu8, 0xad].encode()), json!("0xdead"));
+        assert_eq!(round_trip(&schema, "Array<string | null>", &vec![Some("a"), None].encode()), json!(["a", null]));
+        assert_eq!(round_trip(&schema, "CryptoValue | null", &None::<u64>.encode()), json!(null));
+        let output = ("btc", vec![1.5f64.to_bits()], 7i32).encode();
+        assert_eq!(round_trip(&schema, "Output", &output), json!({ "greater": "btc", "custom": [{ "usd": 1.5 }], "primi_i": 7 }));
+        assert!(AbiValue::decode_scale(&mut &[2u8][..], &schema.resolve("string | null").unwrap()).is_err());
+        assert!(AbiValue::decode_scale(&mut &[2u8][..], &schema.resolve("bool").unwrap()).is_err());
+    }
+
+    #[test]
+    fn reads_primitives_and_typed_arrays_from_memory() {
+        let mut memory = TestMemory::default();
+        let schema = schema();
+        let scalars = memory.alloc(16, 3).unwrap();
+        memory.write(scalars, &[1, 0xff]).unwrap();
+        memory.write(scalars + 2, &(-2i16).to_le_bytes()).unwrap();
+        memory.write(scalars + 8, &u64::MAX.to_le_bytes()).unwrap();
+        let read = |memory: &mut TestMemory, ptr, typ: &str| AbiValue::read_from_memory(memory, ptr, &schema.resolve(typ).unwrap()).unwrap().to_json();
+        assert_eq!(read(&mut memory, scalars, "bool"), json!(true));
+        assert_eq!(read(&mut memory, scalars + 1, "i8"), json!(-1));
+        assert_eq!(read(&mut memory, scalars + 1, "u8"), json!(255));
+        assert_eq!(read(&mut memory, scalars + 2, "i16"), json!(-2));
+        assert_eq!(read(&mut memory, scalars + 8, "u64"), json!(u64::MAX));
+
+        let buffer = memory.alloc(8, ARRAY_BUFFER_ID).unwrap();
+        memory.write(buffer, &[1, 0, 2, 0, 3, 0, 4, 0]).unwrap();
+        let typed = object(&mut memory, 12, &[buffer, buffer + 2, 6]);
+        assert_eq!(read(&mut memory, typed, "Uint16Array"), json!([2, 3, 4]));
+        let slot = object(&mut memory, 4, &[]);
+        memory.put_u32(slot, buffer);
+        assert_eq!(read(&mut memory, slot, "ArrayBuffer"), json!("0x0100020003000400"));
+        assert_eq!(read(&mut memory, slot, "StaticArray<u16>"), json!([1, 2, 3, 4]));
+        memory.put_u32(slot, 0);
+        assert_eq!(read(&mut memory, slot, "string | null"), json!(null));
+        assert!(AbiValue::read_from_memory(&mut memory, slot, &AbiTypeDesc::String).is_err());
+    }
+
+    #[test]
+    fn maps_and_sets_are_read_sorted_and_encode_like_btree_collections() {
+        let mut memory = TestMemory::default();
+        let schema = schema();
+        let (eth, btc, dot) = (alloc_string(&mut memory, "eth").unwrap(), alloc_string(&mut memory, "btc").unwrap(), alloc_string(&mut memory, "dot").unwrap());
+        // Entries of a `Map<string, i32>` are `key`, `value` and `taggedNext`, in insertion order.
+        let entries = memory.alloc(36, ARRAY_BUFFER_ID).unwrap();
+        for (i, (key, value, tagged_next)) in [(eth, 2, 0), (dot, 3, HASH_TABLE_EMPTY), (btc, 1, 0)].into_iter().enumerate() {
+            let entry = entries + i as u32 * 12;
+            memory.put_u32(entry, key);
+            memory.put_u32(entry + 4, value);
+            memory.put_u32(entry + 8, tagged_next);
+        }
+        let map = object(&mut memory, 24, &[0, 3, entries, 3, 3, 2]);
+        let ty = schema.resolve("Map<string, i32>").unwrap();
+        let value = AbiValue::read_from_memory(&mut memory, map, &ty).unwrap();
+        assert_eq!(value.to_json(), json!({ "btc": 1, "eth": 2 }));
+        let mut encoded = Vec::new();
+        value.encode_scale(&mut encoded);
+        assert_eq!(encoded, BTreeMap::from([("btc".to_string(), 1i32), ("eth".to_string(), 2)]).encode());
+        assert_eq!(round_trip(&schema, "Map<string, i32>", &encoded), json!({ "btc": 1, "eth": 2 }));
+
+        let items = memory.alloc(24, ARRAY_BUFFER_ID).unwrap();
+        for (i, item) in [5i32, -3, 9].into_iter().enumerate() {
+            memory.put_u32(items + i as u32 * 8, item as u32);
+        }
+        let set = object(&mut memory, 24, &[0, 3, items, 3, 3, 3]);
+        let value = AbiValue::read_from_memory(&mut memory, set, &schema.resolve("Set<i32>").unwrap()).unwrap();
+        assert_eq!(value.to_json(), json!([-3, 5, 9]));
+        let mut encoded = Vec::new();
+        value.encode_scale(&mut encoded);
+        assert_eq!(encoded, BTreeSet::from([5i32, -3, 9]).encode());
+    }
+
+    #[test]
+    fn map_keys_other_than_strings_render_as_json_text() {
+        let schema = schema();
+        let map = BTreeMap::from([(1u64, true), (20, false)]).encode();
+        assert_eq!(round_trip(&schema, "Map<u64, bool>", &map), json!({ "1": true, "20": false }));
+        let set = BTreeSet::from(["a".to_string(), "b".to_string()]).encode();
+        assert_eq!(round_trip(&schema, "Set<string>", &set), json!(["a", "b"]));
+        assert_eq!(round_trip(&schema, "Map<string, CryptoValue | null>", &vec![("x", None::<u64>)].encode()), json!({ "x": null }));
+    }
+
+    #[test]
+    fn xxh32_matches_the_reference_vectors() {
+        assert_eq!(xxh32(b""), 0x02CC5D05);
+        assert_eq!(xxh32(b"a"), 0x550D7456);
+        assert_eq!(xxh32(b"abc"), 0x32D153FF);
+        assert_eq!(xxh32(b"Nobody inspects the spammish repetition"), 0xE2293B2F);
+        // AssemblyScript's `hash32` of a full word is XXH32 of its bytes.
+        assert_eq!(hash32(0xdeadbeef, 4), xxh32(&0xdeadbeefu32.to_le_bytes()));
+    }
+
+    #[test]
+    fn written_maps_and_sets_read_back() {
+        let mut memory = TestMemory::default();
+        let schema = schema();
+        let cases = [
+            ("Map<string, i32>", vec![("b", 2i32), ("a", 1)].encode()),
+            ("Map<u64, bool>", vec![(1u64, true), (20, false)].encode()),
+            ("Map<i8, string>", vec![(-1i8, "minus"), (1, "plus")].encode()),
+            ("Map<string, CryptoValue | null>", vec![("x", None::<u64>), ("y", Some(2.5f64.to_bits()))].encode()),
+            ("Map<string, Array<u8>>", Vec::<(String, Vec<u8>)>::new().encode()),
+            ("Set<f64>", vec![1.5f64.to_bits(), (-0.5f64).to_bits()].encode()),
+            ("Set<string>", vec!["eth", "btc", "dot", "sol", "ada"].encode()),
+            ("Set<u128>", vec![u128::MAX, 0].encode()),
+        ];
+        for (typ, bytes) in cases {
+            let ty = schema.resolve(typ).unwrap();
+            let value = AbiValue::decode_scale(&mut &bytes[..], &ty).unwrap();
+            assert_eq!(write_read(&mut memory, &value, &ty), value, "{}", typ);
+        }
+
+        let slot = memory.alloc(4, ARRAY_BUFFER_ID).unwrap();
+        let map = BTreeMap::from([("eth".to_string(), 2u32), ("btc".to_string(), 1)]);
+        map.write_to_memory(&mut memory, slot).unwrap();
+        let ty = schema.resolve("Map<string, u32>").unwrap();
+        assert_eq!(BTreeMap::<String, u32>::read_from_memory(&mut memory, slot, &ty).unwrap(), map);
+        let set = BTreeSet::from([-7i64, 7, 0]);
+        set.write_to_memory(&mut memory, slot).unwrap();
+        let ty = schema.resolve("Set<i64>").unwrap();
+        assert_eq!(BTreeSet::<i64>::read_from_memory(&mut memory, slot, &ty).unwrap(), set);
+    }
+
+    #[test]
+    fn written_tables_link_every_entry_into_the_bucket_of_its_hash() {
+        let mut memory = TestMemory::default();
+        let schema = schema();
+        let pairs: Vec<(String, i32)> = (0..9).map(|i| (format!("key{}", i), i)).collect();
+        let ty = schema.resolve("Map<string, i32>").unwrap();
+        let value = AbiValue::decode_scale(&mut &pairs.encode()[..], &ty).unwrap();
+        let slot = memory.alloc(4, ARRAY_BUFFER_ID).unwrap();
+        value.write_to_memory(&mut memory, slot).unwrap();
+        let map = memory.read_u32(slot).unwrap();
+        let [buckets, mask, entries, capacity, offset, count] = [0, 4, 8, 12, 16, 20].map(|field| memory.read_u32(map + field).unwrap());
+        assert_eq!((mask, capacity, offset, count), (15, 42, 9, 9));
+        let first = memory.read_u32(entries).unwrap();
+        assert_eq!(read_string(&mut memory, first).unwrap(), "key0");
+        // The script looks a key up in the bucket of its hash and follows `taggedNext`.
+        for (key, value) in &pairs {
+            let utf16: Vec<u8> = key.encode_utf16().flat_map(u16::to_le_bytes).collect();
+            let mut entry = memory.read_u32(buckets + (xxh32(&utf16) & mask) * 4).unwrap();
+            loop {
+                assert_ne!(entry, 0, "{} is not in its bucket", key);
+                let object = memory.read_u32(entry).unwrap();
+                if read_string(&mut memory, object).unwrap() == *key {
+                    break;
+                }
+                entry = memory.read_u32(entry + 8).unwrap() & !HASH_TABLE_EMPTY;
+            }
+            assert_eq!(memory.read_u32(entry + 4).unwrap(), *value as u32);
+        }
+
+        // Keys narrower than a word are hashed sign extended, entries of a `Set<i16>` are 8 bytes.
+        let ty = schema.resolve("Set<i16>").unwrap();
+        let value = AbiValue::decode_scale(&mut &vec![-2i16, 300].encode()[..], &ty).unwrap();
+        value.write_to_memory(&mut memory, slot).unwrap();
+        let set = memory.read_u32(slot).unwrap();
+        let buckets = memory.read_u32(set).unwrap();
+        for item in [-2i16, 300] {
+            let mut entry = memory.read_u32(buckets + (hash32(item as u32, 2) & 3) * 4).unwrap();
+            while memory.read_u32(entry).unwrap() as u16 != item as u16 {
+                entry = memory.read_u32(entry + 4).unwrap();
+                assert_ne!(entry, 0, "{} is not in its bucket", item);
+            }
+        }
+    }
+
+    #[test]
+    fn decoded_maps_and_sets_are_sorted_and_deduplicated() {
+        let schema = schema();
+        let map = vec![("b", 1i32), ("a", 2), ("b", 3)].encode();
+        let expected: BTreeMap<String, i32> = [("b".to_string(), 1), ("a".to_string(), 2), ("b".to_string(), 3)].into_iter().collect();
+        let value = AbiValue::decode_scale(&mut &map[..], &schema.resolve("Map<string, i32>").unwrap()).unwrap();
+        assert_eq!(value.to_json(), json!({ "a": 2, "b": 3 }));
+        let mut encoded = Vec::new();
+        value.encode_scale(&mut encoded);
+        assert_eq!(encoded, expected.encode());
+
+        let set = vec![3u32, 1, 3, 2].encode();
+        let value = AbiValue::decode_scale(&mut &set[..], &schema.resolve("Set<u32>").unwrap()).unwrap();
+        let mut encoded = Vec::new();
+        value.encode_scale(&mut encoded);
+        assert_eq!(encoded, BTreeSet::from([1u32, 2, 3]).encode());
+    }
+}
@@ -0,0 +1,79 @@
+use std::thread;
+use std::time::{Duration, Instant};
+use anyhow::{anyhow, Result};
+use crate::core::module_cache::{build_engine, EngineOptions, ModuleCache};
+use crate::core::runtime::{describe_abi, execute, ExecutionLimits, SelectorRegistry, DEFAULT_ENTRYPOINT};
+
+/// Cold runs compile the script every time, a handful is enough to see the cost.
+const COLD_ITERATIONS: usize = 20;
+
+pub struct BenchOptions {
+    pub iterations: usize,
+    pub concurrency: usize,
+}
+
+struct BenchCase {
+    name: &'static str,
+    runs: usize,
+    elapsed: Duration,
+}
+
+/// Measures execution throughput of a script with and without module caching, pre-instantiation
+/// and the pooling allocator.
+pub fn run_bench(abi: &str, wasm: &[u8], input: &str, options: &BenchOptions) -> Result<()> {
+    let concurrency = options.concurrency.max(1);
+    if options.iterations < concurrency {
+        return Err(anyhow!("ERROR: {} iterations can not be spread over {} threads", options.iterations, concurrency));
+    }
+    let script_hash = describe_abi(abi)?.hash_alg.header_hash(wasm);
+    let abi = SelectorRegistry::from_abi(abi)?;
+    let selector = abi.selector_of(DEFAULT_ENTRYPOINT)?;
+    let on_demand = EngineOptions::default();
+    let pooling = EngineOptions { pooling: true, max_instances: (concurrency as u32).max(on_demand.max_instances) };
+    let limits = ExecutionLimits::default();
+    let mut cases = Vec::new();
+
+    let cold_runs = options.iterations.min(COLD_ITERATIONS);
+    let start = Instant::now();
+    for _ in 0..cold_runs {
+        let modules = ModuleCache::new(build_engine(&on_demand)?, None, 1)?;
+        execute(&modules, &script_hash, &abi, wasm, &selector, input, &limits)?;
+    }
+    cases.push(BenchCase { name: "cold (compile every run)", runs: cold_runs, elapsed: start.elapsed() });
+
+    for (name, engine_options) in [("instance pre", &on_demand), ("instance pre + pooling", &pooling)] {
+        let modules = ModuleCache::new(build_engine(engine_options)?, None, 1)?;
+        execute(&modules, &script_hash, &abi, wasm, &selector, input, &limits)?;
+        let start = Instant::now();
+        for _ in 0..options.iterations {
+            execute(&modules, &script_hash, &abi, wasm, &selector, input, &limits)?;
+        }
+        cases.push(BenchCase { name, runs: options.iterations, elapsed: start.elapsed() });
+    }
+
+    for (name, engine_options) in [("concurrent", &on_demand), ("concurrent + pooling", &pooling)] {
+        let modules = ModuleCache::new(build_engine(engine_options)?, None, 1)?;
+        execute(&modules, &script_hash, &abi, wasm, &selector, input, &limits)?;
+        let per_thread = options.iterations / concurrency;
+        let start = Instant::now();
+        thread::scope(|scope| {
+            let workers: Vec<_> = (0..concurrency).map(|_| scope.spawn(|| {
+                for _ in 0..per_thread {
+                    execute(&modules, &script_hash, &abi, wasm, &selector, input, &limits)?;
+                }
+                anyhow::Ok(())
+            })).collect();
+            workers.into_iter().try_for_each(|worker| worker.join().expect("bench worker panicked"))
+        })?;
+        cases.push(BenchCase { name, runs: per_thread * concurrency, elapsed: start.elapsed() });
+    }
+
+    println!("{:<28} {:>8} {:>12} {:>12}", "case", "runs", "us/run", "runs/s");
+    for case in cases {
+        let seconds = case.elapsed.as_secs_f64();
+        println!("{:<28} {:>8} {:>12.1} {:>12.0}",
+            case.name, case.runs, seconds * 1e6 / case.runs.max(1) as f64, case.runs as f64 / seconds);
+    }
+    println!("concurrency: {} threads", concurrency);
+    Ok(())
+}
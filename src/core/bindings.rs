@@ -0,0 +1,77 @@
+//! Typed clients of the scripts shipped with the repository, generated from their ABI at compile time.
+use dynamic_struct::include_abi;
+
+include_abi!(Orscript2, "orascript/output/orscript2ABI.json", crate::traits::traits::ScriptRuntime);
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use parity_scale_codec::{DecodeAll, Encode};
+    use crate::core::runtime::{decode_output, ExecutionLimits, DEFAULT_INPUT};
+    use crate::core::testing::{orscript2, temp_registry};
+    use super::orscript2::{Orscript2, Output};
+
+    mod generated {
+        dynamic_struct::generate_struct!("orascript/output/orscript2ABI.json");
+    }
+
+    mod numeric {
+        dynamic_struct::generate_struct!(
+            r#"{ "name": "Balance", "fields": [{ "name": "free", "type": "u256" }, { "name": "rate", "type": "FixedU128" }] }"#,
+            numeric = crate::core::numeric,
+        );
+    }
+
+    #[test]
+    fn generate_struct_expands_the_orscript2_abi() {
+        let (_, abi) = orscript2();
+        let output = generated::Output {
+            greater: "bitcoin".to_string(),
+            custom: vec![generated::CryptoValue { usd: 104700.0 }, generated::CryptoValue { usd: 2523.125 }],
+            primi_i: 7,
+            pmimi_f: 1.5,
+        };
+        let bytes = output.encode();
+        assert_eq!(generated::Output::decode_all(&mut &bytes[..]).unwrap(), output);
+        assert_eq!(decode_output(&abi, "Output", &bytes).unwrap(), serde_json::to_value(&output).unwrap());
+        let input = generated::Input { bitcoin: generated::CryptoValue { usd: 1.0 }, ethereum: generated::CryptoValue { usd: 2.0 } };
+        assert_eq!(serde_json::to_string(&input).unwrap(), r#"{"bitcoin":{"usd":1.0},"ethereum":{"usd":2.0}}"#);
+    }
+
+    #[test]
+    fn numeric_fields_use_the_given_module() {
+        use crate::core::numeric::{FixedU128, U256};
+        let balance = numeric::Balance { free: U256::from(7u128), rate: FixedU128(3) };
+        let bytes = balance.encode();
+        assert_eq!(bytes.len(), 48);
+        assert_eq!(numeric::Balance::decode_all(&mut &bytes[..]).unwrap(), balance);
+    }
+
+    #[test]
+    fn generated_structs_decode_script_output() {
+        let (root, registry) = temp_registry("bindings-generated");
+        let (wasm, abi) = orscript2();
+        let hash = registry.insert(&wasm, &abi).unwrap();
+        let bytes = registry.execute(&hash, None, DEFAULT_INPUT, &ExecutionLimits::default()).unwrap();
+        let output = generated::Output::decode_all(&mut &bytes[..]).unwrap();
+        assert_eq!(output, generated::Output {
+            greater: "bitcoin".to_string(),
+            custom: vec![generated::CryptoValue { usd: 104700.0 }, generated::CryptoValue { usd: 2523.13 }],
+            primi_i: 4200,
+            pmimi_f: 69.0,
+        });
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn include_abi_client_runs_the_script() {
+        let (root, registry) = temp_registry("bindings-client");
+        let (wasm, abi) = orscript2();
+        assert_eq!(registry.insert(&wasm, &abi).unwrap(), Orscript2::SCRIPT_HASH);
+        let output: Output = Orscript2::process(&*registry, DEFAULT_INPUT).unwrap();
+        assert_eq!(output.greater, "bitcoin");
+        assert_eq!(output.custom.len(), 2);
+        assert_eq!(output.primi_i, 4200);
+        fs::remove_dir_all(root).unwrap();
+    }
+}
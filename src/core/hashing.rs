@@ -0,0 +1,116 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sp_core_hashing::blake2_256;
+
+/// Hash of a script's header and selectors, declared by `headers.hash_alg` of its ABI. ABIs
+/// without it use SHA-256; `blake2-256` matches the `BlakeTwo256` hashing of Substrate runtimes,
+/// so the pallet computes the same script hash on chain.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HashAlgorithm {
+    #[default]
+    #[serde(rename = "sha256")]
+    Sha256,
+    #[serde(rename = "blake2-256")]
+    Blake2_256,
+}
+
+impl HashAlgorithm {
+    pub fn digest(&self, data: &[u8]) -> [u8; 32] {
+        match self {
+            HashAlgorithm::Sha256 => Sha256::digest(data).into(),
+            HashAlgorithm::Blake2_256 => blake2_256(data),
+        }
+    }
+
+    /// Script hash stored in `headers.header`, the digest of the wasm bytecode.
+    pub fn header_hash(&self, wasm: &[u8]) -> String {
+        format!("0x{}", hex::encode(self.digest(wasm)))
+    }
+
+    /// Selector of `name(param, ...)`, the first 4 bytes of the digest of that signature.
+    pub fn selector<'a>(&self, name: &str, params: impl IntoIterator<Item = &'a str>) -> String {
+        let signature = format!("{}({})", name, params.into_iter().collect::<Vec<_>>().join(","));
+        format!("0x{}", hex::encode(&self.digest(signature.as_bytes())[..4]))
+    }
+}
+
+impl std::str::FromStr for HashAlgorithm {
+    type Err = anyhow::Error;
+
+    fn from_str(alg: &str) -> Result<Self> {
+        match alg {
+            "sha256" => Ok(HashAlgorithm::Sha256),
+            "blake2-256" => Ok(HashAlgorithm::Blake2_256),
+            _ => Err(anyhow!("ERROR: unknown hash algorithm {:?}", alg)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use serde_json::Value;
+    use super::*;
+    use crate::core::runtime::{ExecutionLimits, DEFAULT_INPUT};
+    use crate::core::testing::{orscript2, temp_registry};
+
+    /// `abi` with `hash_alg` set to `alg` and its header and selectors recomputed under it.
+    fn rehash(abi: &str, wasm: &[u8], alg: HashAlgorithm) -> String {
+        let mut abi: Value = serde_json::from_str(abi).unwrap();
+        abi["headers"]["hash_alg"] = serde_json::to_value(alg).unwrap();
+        abi["headers"]["header"] = alg.header_hash(wasm).into();
+        let signed = |function: &mut Value| {
+            let params: Vec<String> = function["params"].as_array().unwrap().iter()
+                .map(|param| param["name"].as_str().unwrap().to_string()).collect();
+            let name = function["name"].as_str().unwrap().to_string();
+            function["selector"] = alg.selector(&name, params.iter().map(String::as_str)).into();
+        };
+        abi["functions"].as_array_mut().unwrap().iter_mut().for_each(signed);
+        for class in abi["classes"].as_array_mut().unwrap() {
+            let name = class["name"].as_str().unwrap().to_string();
+            class["class_selector"] = alg.selector(&name, []).into();
+            class["methods"].as_array_mut().unwrap().iter_mut().for_each(signed);
+        }
+        abi.to_string()
+    }
+
+    #[test]
+    fn digests_match_the_reference_hashes() {
+        assert_eq!(HashAlgorithm::Sha256.header_hash(b""), "0xe3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+        assert_eq!(HashAlgorithm::Blake2_256.header_hash(b""), "0x0e5751c026e543b2e8ab2eb06099daa1d1e5df47778f7787faab45cdf12fe3a8");
+        assert_eq!(HashAlgorithm::Sha256.selector("process", ["json_ptr", "len"]), "0x480104f2");
+        assert_eq!(HashAlgorithm::Blake2_256.selector("process", ["json_ptr", "len"]), "0x07ed0811");
+        assert_eq!(HashAlgorithm::Blake2_256.selector("Output", []), "0xbb05484e");
+        assert_eq!("blake2-256".parse::<HashAlgorithm>().unwrap(), HashAlgorithm::Blake2_256);
+        assert!("blake2-128".parse::<HashAlgorithm>().is_err());
+    }
+
+    #[test]
+    fn runs_scripts_hashed_with_blake2() {
+        let (root, registry) = temp_registry("hashing-blake2");
+        let (wasm, abi) = orscript2();
+        let blake2 = rehash(&abi, &wasm, HashAlgorithm::Blake2_256);
+        let hash = registry.insert(&wasm, &blake2).unwrap();
+        assert_eq!(hash, "0xadbb23ec0b401aadadf36efe33ead175efa1e34fed6d1ea2890153e44506a484");
+        let sha256 = registry.insert(&wasm, &abi).unwrap();
+        let limits = ExecutionLimits::default();
+        assert_eq!(registry.execute(&hash, Some("0x07ed0811"), DEFAULT_INPUT, &limits).unwrap(),
+            registry.execute(&sha256, None, DEFAULT_INPUT, &limits).unwrap());
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn rejects_selectors_of_the_other_algorithm() {
+        let (root, registry) = temp_registry("hashing-mixed");
+        let (wasm, abi) = orscript2();
+        let mut mixed: Value = serde_json::from_str(&rehash(&abi, &wasm, HashAlgorithm::Blake2_256)).unwrap();
+        mixed["functions"][0]["selector"] = "0x480104f2".into();
+        assert!(registry.insert(&wasm, &mixed.to_string()).is_err());
+        // A SHA-256 header does not pass as a blake2 one either.
+        let mut header: Value = serde_json::from_str(&abi).unwrap();
+        header["headers"]["hash_alg"] = "blake2-256".into();
+        assert!(registry.insert(&wasm, &header.to_string()).is_err());
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,278 @@
+use std::cell::OnceCell;
+use anyhow::{anyhow, Result};
+use serde_json::Value;
+use wasmtime::{Caller, Extern, Linker};
+use crate::core::abi_value::{alloc_string, read_string};
+use crate::core::runtime::ScriptMemory;
+use crate::traits::traits::AbiMemory;
+
+/// Import module of the functions the node provides to scripts, see `orascript/assembly/host.ts`.
+pub const HOST_MODULE: &str = "orascript";
+/// Import module of AssemblyScript's runtime functions, of which only `abort` is provided.
+pub const ENV_MODULE: &str = "env";
+/// Handle of the script's input document.
+pub const INPUT_DOCUMENT: i32 = 0;
+/// Longest JSON path accepted from a script, in UTF-16 bytes.
+const MAX_PATH_BYTES: u32 = 1024;
+
+/// State of one execution the host functions work on.
+pub struct HostState {
+    input: String,
+    /// The input parsed on first use, `None` when it is not JSON.
+    document: OnceCell<Option<Value>>,
+}
+
+impl HostState {
+    pub fn new(input: &str) -> Self {
+        HostState { input: input.to_string(), document: OnceCell::new() }
+    }
+
+    fn document(&self, doc: i32) -> Result<&Value> {
+        if doc != INPUT_DOCUMENT {
+            return Err(anyhow!("ERROR: unknown JSON document {}", doc));
+        }
+        self.document.get_or_init(|| serde_json::from_str(&self.input).ok()).as_ref()
+            .ok_or_else(|| anyhow!("ERROR: the script input is not a JSON document"))
+    }
+}
+
+/// Links the JSON accessors, so scripts read their input without parsing it in wasm:
+///
+/// - `json_get_f64(doc: i32, path: string): f64`, NaN when the value is missing or not a number
+/// - `json_get_string(doc: i32, path: string): string | null`
+/// - `json_array_len(doc: i32, path: string): i32`, -1 when the value is missing or not an array
+///
+/// Paths are JSONPath subsets such as `$.bitcoin.usd`, `$.prices[0]` or `$['usd-coin'].usd`. A
+/// malformed path or an input that is not JSON traps the script.
+///
+/// AssemblyScript's `env.abort(message: string | null, file: string | null, line: u32, column: u32)`
+/// is linked too, it traps the script with the message and location it was given.
+pub fn link_host_functions(linker: &mut Linker<HostState>) -> Result<()> {
+    linker.func_wrap(ENV_MODULE, "abort", |mut caller: Caller<'_, HostState>, message: i32, file: i32, line: i32, column: i32| -> Result<()> {
+        let mut memory = script_memory(&mut caller)?;
+        let mut read = |object: i32| match object {
+            0 => Ok("null".to_string()),
+            object => read_string(&mut memory, object as u32),
+        };
+        let message = read(message)?;
+        let file = read(file)?;
+        Err(anyhow!("ERROR: script aborted: {} at {}:{}:{}", message, file, line as u32, column as u32))
+    })?;
+    linker.func_wrap(HOST_MODULE, "json_get_f64", |mut caller: Caller<'_, HostState>, doc: i32, path: i32| {
+        lookup(&mut caller, doc, path, |value| value.and_then(Value::as_f64).unwrap_or(f64::NAN))
+    })?;
+    linker.func_wrap(HOST_MODULE, "json_get_string", |mut caller: Caller<'_, HostState>, doc: i32, path: i32| {
+        let value = lookup(&mut caller, doc, path, |value| value.and_then(Value::as_str).map(str::to_string))?;
+        match value {
+            Some(value) => Ok(alloc_string(&mut script_memory(&mut caller)?, &value)? as i32),
+            None => Ok(0),
+        }
+    })?;
+    linker.func_wrap(HOST_MODULE, "json_array_len", |mut caller: Caller<'_, HostState>, doc: i32, path: i32| {
+        let len = lookup(&mut caller, doc, path, |value| value.and_then(Value::as_array).map(Vec::len))?;
+        match len {
+            Some(len) => i32::try_from(len).map_err(|_| anyhow!("ERROR: JSON array of {} items is too long for i32", len)),
+            None => Ok(-1),
+        }
+    })?;
+    Ok(())
+}
+
+/// Resolves the path string at `path` in the document `doc` and maps the value found, if any.
+fn lookup<R>(caller: &mut Caller<'_, HostState>, doc: i32, path: i32, map: impl FnOnce(Option<&Value>) -> R) -> Result<R> {
+    let path = read_path(caller, path as u32)?;
+    let segments = parse_path(&path)?;
+    Ok(map(select(caller.data().document(doc)?, &segments)))
+}
+
+fn script_memory<'a, 'b>(caller: &'a mut Caller<'b, HostState>) -> Result<ScriptMemory<&'a mut Caller<'b, HostState>>> {
+    let memory = caller.get_export("memory").and_then(Extern::into_memory)
+        .ok_or_else(|| anyhow!("ERROR: script does not export its memory"))?;
+    let new = caller.get_export("__new").and_then(Extern::into_func)
+        .map(|new| new.typed::<(i32, i32), i32>(&*caller))
+        .transpose()?;
+    // Objects handed to the script are its own to keep alive, they are not pinned.
+    Ok(ScriptMemory { memory, store: caller, new, pins: None, pinned: Vec::new() })
+}
+
+fn read_path(caller: &mut Caller<'_, HostState>, object: u32) -> Result<String> {
+    let mut memory = script_memory(caller)?;
+    let header = object.checked_sub(4).ok_or_else(|| anyhow!("ERROR: invalid JSON path pointer {}", object))?;
+    let byte_len = memory.read_u32(header)?;
+    if byte_len > MAX_PATH_BYTES {
+        return Err(anyhow!("ERROR: JSON path of {} bytes exceeds {} bytes", byte_len, MAX_PATH_BYTES));
+    }
+    read_string(&mut memory, object)
+}
+
+#[derive(Debug, PartialEq)]
+enum Segment {
+    Key(String),
+    Index(usize),
+}
+
+fn parse_path(path: &str) -> Result<Vec<Segment>> {
+    let invalid = || anyhow!("ERROR: invalid JSON path {:?}", path);
+    let mut rest = path.strip_prefix('$').ok_or_else(invalid)?;
+    let mut segments = Vec::new();
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix('.') {
+            let end = after.find(['.', '[', ']']).unwrap_or(after.len());
+            if end == 0 {
+                return Err(invalid());
+            }
+            segments.push(Segment::Key(after[..end].to_string()));
+            rest = &after[end..];
+        } else if let Some(after) = rest.strip_prefix('[') {
+            // Quoted keys may hold any character but their quote, `$['a.b]']` is the key `a.b]`.
+            let (segment, tail) = match after.chars().next() {
+                Some(quote @ ('\'' | '"')) => {
+                    let end = after[1..].find(quote).ok_or_else(invalid)? + 1;
+                    (Segment::Key(after[1..end].to_string()), &after[end + 1..])
+                }
+                _ => {
+                    let end = after.find(']').ok_or_else(invalid)?;
+                    (Segment::Index(after[..end].parse().map_err(|_| invalid())?), &after[end..])
+                }
+            };
+            segments.push(segment);
+            rest = tail.strip_prefix(']').ok_or_else(invalid)?;
+        } else {
+            return Err(invalid());
+        }
+    }
+    Ok(segments)
+}
+
+fn select<'a>(document: &'a Value, segments: &[Segment]) -> Option<&'a Value> {
+    segments.iter().try_fold(document, |value, segment| match segment {
+        Segment::Key(key) => value.get(key.as_str()),
+        Segment::Index(index) => value.get(*index),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use serde_json::json;
+    use super::*;
+    use crate::core::runtime::{decode_output, ExecutionLimits};
+    use crate::core::testing::{script_abi, temp_registry};
+
+    /// `(data)` segment placing the string object `text` at `object`, behind its header.
+    fn string_object(object: u32, text: &str, byte_len: u32) -> String {
+        let bytes: Vec<u8> = [2u32.to_le_bytes(), byte_len.to_le_bytes()].concat().into_iter()
+            .chain(text.encode_utf16().flat_map(u16::to_le_bytes))
+            .collect();
+        let escaped: String = bytes.iter().map(|byte| format!("\\{:02x}", byte)).collect();
+        format!(r#"(data (i32.const {}) "{}")"#, object - 8, escaped)
+    }
+
+    /// Runs a script whose `Output { value }` is the result of the host function `function`
+    /// called with the input document and `path`, given as the field type and its store instruction.
+    fn call_host(name: &str, function: &str, (field, store, result): (&str, &str, &str), path: &str, path_bytes: u32, input: &str) -> Result<Value> {
+        let (root, registry) = temp_registry(name);
+        let wat = format!(r#"(module
+            (import "orascript" "{function}" (func $host (param i32 i32) (result {result})))
+            (memory (export "memory") 1)
+            (global $heap (mut i32) (i32.const 8192))
+            (func (export "__new") (param $size i32) (param $id i32) (result i32)
+                (local $object i32)
+                (local.set $object (i32.add (global.get $heap) (i32.const 16)))
+                (i32.store (i32.sub (local.get $object) (i32.const 8)) (local.get $id))
+                (i32.store (i32.sub (local.get $object) (i32.const 4)) (local.get $size))
+                (global.set $heap (i32.and (i32.add (i32.add (local.get $object) (local.get $size)) (i32.const 15)) (i32.const -16)))
+                (local.get $object))
+            {path}
+            (func (export "process") (param i32 i32) (result i32)
+                ({store} (i32.const 4096) (call $host (i32.const {doc}) (i32.const 1024)))
+                i32.const 4096))"#, path = string_object(1024, path, path_bytes), doc = INPUT_DOCUMENT);
+        let wasm = wat.as_bytes();
+        let abi = script_abi(wasm, "Output", &[("Output", &[("value", field)])]);
+        let output = registry.insert(wasm, &abi)
+            .and_then(|hash| registry.execute(&hash, None, input, &ExecutionLimits::default()))
+            .and_then(|output| decode_output(&abi, "Output", &output));
+        fs::remove_dir_all(root).unwrap();
+        output
+    }
+
+    const STRING: (&str, &str, &str) = ("string | null", "i32.store", "i32");
+    const ARRAY_LEN: (&str, &str, &str) = ("i32", "i32.store", "i32");
+    const F64: (&str, &str, &str) = ("f64", "f64.store", "f64");
+
+    fn utf16_len(path: &str) -> u32 {
+        path.encode_utf16().count() as u32 * 2
+    }
+
+    fn lookup_path(name: &str, function: &str, ty: (&str, &str, &str), path: &str, input: &str) -> Result<Value> {
+        call_host(name, function, ty, path, utf16_len(path), input)
+    }
+
+    #[test]
+    fn json_get_string_allocates_the_value() {
+        let input = r#"{"usd-coin":{"symbol":"usdc"}}"#;
+        let output = lookup_path("host-string", "json_get_string", STRING, "$['usd-coin'].symbol", input).unwrap();
+        assert_eq!(output, json!({ "value": "usdc" }));
+    }
+
+    #[test]
+    fn missing_values_come_back_empty() {
+        let input = r#"{"prices":[1,2,3],"bitcoin":{"usd":104700}}"#;
+        assert_eq!(lookup_path("host-null", "json_get_string", STRING, "$.bitcoin.eur", input).unwrap(), json!({ "value": null }));
+        assert_eq!(lookup_path("host-len", "json_array_len", ARRAY_LEN, "$.prices", input).unwrap(), json!({ "value": 3 }));
+        assert_eq!(lookup_path("host-no-len", "json_array_len", ARRAY_LEN, "$.bitcoin", input).unwrap(), json!({ "value": -1 }));
+        assert_eq!(lookup_path("host-f64", "json_get_f64", F64, "$.bitcoin.usd", input).unwrap(), json!({ "value": 104700.0 }));
+    }
+
+    #[test]
+    fn long_paths_trap() {
+        let error = call_host("host-long", "json_get_f64", F64, "$.a", MAX_PATH_BYTES + 2, "{}").unwrap_err();
+        assert!(format!("{:?}", error).contains("exceeds 1024 bytes"), "{:?}", error);
+    }
+
+    #[test]
+    fn non_json_input_traps() {
+        let error = lookup_path("host-not-json", "json_get_f64", F64, "$.a", "not json").unwrap_err();
+        assert!(format!("{:?}", error).contains("not a JSON document"), "{:?}", error);
+    }
+
+    fn key(key: &str) -> Segment {
+        Segment::Key(key.to_string())
+    }
+
+    #[test]
+    fn parses_dotted_keys_and_indices() {
+        assert_eq!(parse_path("$").unwrap(), []);
+        assert_eq!(parse_path("$.bitcoin.usd").unwrap(), [key("bitcoin"), key("usd")]);
+        assert_eq!(parse_path("$.prices[0][12]").unwrap(), [key("prices"), Segment::Index(0), Segment::Index(12)]);
+    }
+
+    #[test]
+    fn parses_quoted_keys() {
+        assert_eq!(parse_path("$['usd-coin'].symbol").unwrap(), [key("usd-coin"), key("symbol")]);
+        assert_eq!(parse_path(r#"$["a.b"]['0']"#).unwrap(), [key("a.b"), key("0")]);
+        assert_eq!(parse_path("$['x]y'][\"it's\"]").unwrap(), [key("x]y"), key("it's")]);
+        assert_eq!(parse_path("$['']").unwrap(), [key("")]);
+    }
+
+    #[test]
+    fn rejects_malformed_paths() {
+        for path in ["", "bitcoin", "$bitcoin", "$.", "$..a", "$.a.", "$[", "$[0", "$[-1]", "$[a]", "$['a'", "$['a]", "$['a'x]", "$[\"a']", "$.a]"] {
+            assert!(parse_path(path).is_err(), "{:?} was accepted", path);
+        }
+    }
+
+    #[test]
+    fn selects_values() {
+        let document = json!({ "bitcoin": { "usd": 104700 }, "usd-coin": { "symbol": "usdc" }, "prices": [1.5, [2, 3]] });
+        let get = |path: &str| select(&document, &parse_path(path).unwrap()).cloned();
+        assert_eq!(get("$.bitcoin.usd"), Some(json!(104700)));
+        assert_eq!(get("$['usd-coin'].symbol"), Some(json!("usdc")));
+        assert_eq!(get("$.prices[1][0]"), Some(json!(2)));
+        assert_eq!(get("$"), Some(document.clone()));
+        assert_eq!(get("$.prices[5]"), None);
+        assert_eq!(get("$.bitcoin[0]"), None);
+        assert_eq!(get("$.prices.len"), None);
+        assert_eq!(get("$.ethereum.usd"), None);
+    }
+}
@@ -1,2 +1,14 @@
 pub mod abi_parser;
 pub mod runtime;
+pub mod registry;
+pub mod module_cache;
+pub mod bench;
+pub mod scheduler;
+pub mod bindings;
+pub mod abi_value;
+pub mod numeric;
+pub mod hashing;
+pub mod host;
+pub mod type_registry;
+#[cfg(test)]
+pub mod testing;
@@ -0,0 +1,337 @@
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::Duration;
+use anyhow::{anyhow, Result};
+use sha2::{Digest, Sha256};
+use wasmtime::{Config, Engine, InstanceAllocationStrategy, InstancePre, Linker, Module, PoolingAllocationConfig};
+use crate::core::host::{link_host_functions, HostState, ENV_MODULE, HOST_MODULE};
+use crate::core::runtime::trace;
+
+pub const DEFAULT_MODULE_CACHE_CAPACITY: usize = 64;
+/// Interval at which execution limits (timeouts, cancellation) are checked.
+pub const EPOCH_TICK: Duration = Duration::from_millis(10);
+/// Age after which a staging file is considered left behind by a crashed writer.
+const STALE_STAGING_AGE: Duration = Duration::from_secs(3600);
+
+/// Distinguishes the staging files of concurrent compilations within this process.
+static STAGING_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Settings of the engine shared by every execution of a node.
+#[derive(Debug, Clone)]
+pub struct EngineOptions {
+    /// Use wasmtime's pooling allocator, which reserves instance slots up front so
+    /// instantiation only has to reset memory instead of mapping it.
+    pub pooling: bool,
+    /// Number of instances that can be alive at once when pooling.
+    pub max_instances: u32,
+}
+
+impl Default for EngineOptions {
+    fn default() -> Self {
+        EngineOptions { pooling: false, max_instances: 128 }
+    }
+}
+
+/// Builds an engine with epoch interruption enabled and a background thread ticking its epoch
+/// every `EPOCH_TICK` for as long as the engine is alive.
+pub fn build_engine(options: &EngineOptions) -> Result<Engine> {
+    let mut config = Config::new();
+    config.epoch_interruption(true);
+    if options.pooling {
+        let mut pooling = PoolingAllocationConfig::default();
+        pooling.total_core_instances(options.max_instances)
+            .total_memories(options.max_instances)
+            .total_tables(options.max_instances);
+        config.allocation_strategy(InstanceAllocationStrategy::Pooling(pooling));
+    }
+    let engine = Engine::new(&config)?;
+    let weak = engine.weak();
+    thread::spawn(move || {
+        while let Some(engine) = weak.upgrade() {
+            engine.increment_epoch();
+            drop(engine);
+            thread::sleep(EPOCH_TICK);
+        }
+    });
+    Ok(engine)
+}
+
+/// Compiled module cache keyed by the script hash and a fingerprint of the engine.
+///
+/// Modules are kept pre-instantiated (imports already resolved) in an in-memory LRU and, when a
+/// directory is given, as `Module::serialize` artifacts on disk. The fingerprint covers the
+/// wasmtime version and every compilation setting, so artifacts from another wasmtime build or
+/// config are never picked up.
+pub struct ModuleCache {
+    engine: Engine,
+    fingerprint: String,
+    dir: Option<PathBuf>,
+    capacity: usize,
+    memory: Mutex<LruModules>,
+}
+
+#[derive(Default)]
+struct LruModules {
+    modules: HashMap<String, InstancePre<HostState>>,
+    order: VecDeque<String>,
+}
+
+/// Adapter feeding `Hash` implementations into SHA-256, unlike `DefaultHasher` it is stable
+/// across Rust releases.
+struct Sha256Hasher(Sha256);
+
+impl Hasher for Sha256Hasher {
+    fn finish(&self) -> u64 {
+        let digest = self.0.clone().finalize();
+        u64::from_le_bytes(digest[..8].try_into().unwrap())
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        self.0.update(bytes);
+    }
+}
+
+impl ModuleCache {
+    pub fn new(engine: Engine, dir: Option<PathBuf>, capacity: usize) -> Result<Self> {
+        if let Some(dir) = &dir {
+            fs::create_dir_all(dir)
+                .map_err(|e| anyhow!("ERROR: failed to create module cache at {:?}: {}", dir, e))?;
+        }
+        let mut hasher = Sha256Hasher(Sha256::new());
+        engine.precompile_compatibility_hash().hash(&mut hasher);
+        let fingerprint = hex::encode(&hasher.0.finalize()[..8]);
+        if let Some(dir) = &dir {
+            remove_stale_artifacts(dir, &fingerprint);
+        }
+        Ok(ModuleCache {
+            engine,
+            fingerprint,
+            dir,
+            capacity: capacity.max(1),
+            memory: Mutex::new(LruModules::default()),
+        })
+    }
+
+    pub fn engine(&self) -> &Engine {
+        &self.engine
+    }
+
+    /// Returns the script ready to be instantiated into a fresh `Store`, compiling and linking it
+    /// only when it is neither in memory nor on disk. `script_hash` is the header hash `wasm` was
+    /// verified against, so the wasm is never hashed again on the execution path.
+    pub fn instance_pre(&self, script_hash: &str, wasm: &[u8]) -> Result<InstancePre<HostState>> {
+        let key = self.key(script_hash);
+        if let Some(instance_pre) = self.memory.lock().unwrap().get(&key) {
+            return Ok(instance_pre);
+        }
+        let module = match self.load_artifact(&key) {
+            Some(module) => module,
+            None => {
+                let module = Module::new(&self.engine, wasm)?;
+                self.store_artifact(&key, &module);
+                module
+            }
+        };
+        let instance_pre = link_imports(&self.engine, &module)?.instantiate_pre(&module)?;
+        self.memory.lock().unwrap().insert(key, instance_pre.clone(), self.capacity);
+        Ok(instance_pre)
+    }
+
+    /// Drops the compiled module of `script_hash` from memory and disk.
+    pub fn evict(&self, script_hash: &str) {
+        let key = self.key(script_hash);
+        self.memory.lock().unwrap().remove(&key);
+        if let Some(path) = self.artifact_path(&key) {
+            let _ = fs::remove_file(path);
+        }
+    }
+
+    fn key(&self, script_hash: &str) -> String {
+        format!("{}-{}", script_hash.trim_start_matches("0x"), self.fingerprint)
+    }
+
+    fn artifact_path(&self, key: &str) -> Option<PathBuf> {
+        self.dir.as_ref().map(|dir| dir.join(format!("{}.cwasm", key)))
+    }
+
+    fn load_artifact(&self, key: &str) -> Option<Module> {
+        let path = self.artifact_path(key)?;
+        if !path.exists() {
+            return None;
+        }
+        // SAFETY: artifacts are only ever written by `store_artifact` for this engine fingerprint,
+        // wasmtime additionally rejects artifacts that were produced for an incompatible engine.
+        match unsafe { Module::deserialize_file(&self.engine, &path) } {
+            Ok(module) => Some(module),
+            Err(e) => {
+                trace!("Discarding module artifact {:?}: {}", path, e);
+                let _ = fs::remove_file(&path);
+                None
+            }
+        }
+    }
+
+    fn store_artifact(&self, key: &str, module: &Module) {
+        let Some(path) = self.artifact_path(key) else { return };
+        // A unique staging file renamed over the artifact, so a module mapped by another thread or
+        // process is never rewritten in place.
+        let staging = path.with_extension(format!("{}.{}.tmp", std::process::id(), STAGING_COUNTER.fetch_add(1, Ordering::Relaxed)));
+        let written = module.serialize()
+            .and_then(|bytes| Ok(fs::write(&staging, bytes)?))
+            .and_then(|_| Ok(fs::rename(&staging, &path)?));
+        if let Err(e) = written {
+            trace!("Failed to write module artifact {:?}: {}", path, e);
+            let _ = fs::remove_file(&staging);
+        }
+    }
+}
+
+/// Removes artifacts compiled for another engine fingerprint, which are never loaded again, and
+/// staging files older than `STALE_STAGING_AGE`.
+fn remove_stale_artifacts(dir: &Path, fingerprint: &str) {
+    let Ok(entries) = fs::read_dir(dir) else { return };
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let stale = if name.ends_with(".tmp") {
+            entry.metadata().and_then(|metadata| metadata.modified())
+                .is_ok_and(|modified| modified.elapsed().is_ok_and(|age| age > STALE_STAGING_AGE))
+        } else {
+            name.strip_suffix(".cwasm").is_some_and(|key| !key.ends_with(&format!("-{}", fingerprint)))
+        };
+        if stale {
+            let _ = fs::remove_file(entry.path());
+        }
+    }
+}
+
+impl LruModules {
+    fn get(&mut self, key: &str) -> Option<InstancePre<HostState>> {
+        let instance_pre = self.modules.get(key)?.clone();
+        self.touch(key);
+        Some(instance_pre)
+    }
+
+    fn insert(&mut self, key: String, instance_pre: InstancePre<HostState>, capacity: usize) {
+        if self.modules.insert(key.clone(), instance_pre).is_some() {
+            self.touch(&key);
+            return;
+        }
+        self.order.push_back(key);
+        while self.order.len() > capacity {
+            if let Some(evicted) = self.order.pop_front() {
+                self.modules.remove(&evicted);
+            }
+        }
+    }
+
+    fn remove(&mut self, key: &str) {
+        if self.modules.remove(key).is_some() {
+            self.order.retain(|k| k != key);
+        }
+    }
+
+    fn touch(&mut self, key: &str) {
+        if let Some(position) = self.order.iter().position(|k| k == key) {
+            let key = self.order.remove(position).unwrap();
+            self.order.push_back(key);
+        }
+    }
+}
+
+/// Scripts only import host functions, the node's own (`orascript.*`) and AssemblyScript's
+/// `env.abort`. Any other import is rejected, so a script that needs it fails at deploy time
+/// instead of when it first runs.
+fn link_imports(engine: &Engine, module: &Module) -> Result<Linker<HostState>> {
+    let mut linker = Linker::new(engine);
+    link_host_functions(&mut linker)?;
+    for import in module.imports() {
+        if import.module() != HOST_MODULE && (import.module(), import.name()) != (ENV_MODULE, "abort") {
+            return Err(anyhow!("ERROR: script imports {}.{}, only {} host functions and {}.abort are available", import.module(), import.name(), HOST_MODULE, ENV_MODULE));
+        }
+    }
+    Ok(linker)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::runtime::ExecutionLimits;
+    use crate::core::testing::{insert_wat, orscript2, script_abi, temp_dir, temp_registry};
+
+    const SCRIPT_HASH: &str = "0x0101010101010101010101010101010101010101010101010101010101010101";
+    /// Aborts with the message "boom" in the file "a.ts" at 3:7.
+    const ABORTS: &str = r#"(module
+        (import "env" "abort" (func $abort (param i32 i32 i32 i32)))
+        (memory (export "memory") 1)
+        (data (i32.const 4092) "\08\00\00\00b\00o\00o\00m\00")
+        (data (i32.const 4124) "\08\00\00\00a\00.\00t\00s\00")
+        (func (export "process") (param i32 i32) (result i32)
+            (call $abort (i32.const 4096) (i32.const 4128) (i32.const 3) (i32.const 7))
+            i32.const 0))"#;
+
+    #[test]
+    fn concurrent_compilations_share_one_artifact() {
+        let dir = temp_dir("modules-concurrent");
+        let engine = build_engine(&EngineOptions::default()).unwrap();
+        let (wasm, _) = orscript2();
+        thread::scope(|scope| {
+            for _ in 0..4 {
+                let cache = ModuleCache::new(engine.clone(), Some(dir.clone()), 4).unwrap();
+                let wasm = &wasm;
+                scope.spawn(move || cache.instance_pre(SCRIPT_HASH, wasm).unwrap());
+            }
+        });
+        let names: Vec<String> = fs::read_dir(&dir).unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(names.len(), 1, "{:?}", names);
+        assert!(names[0].ends_with(".cwasm"));
+        let cache = ModuleCache::new(engine, Some(dir.clone()), 4).unwrap();
+        let key = names[0].trim_end_matches(".cwasm");
+        assert!(cache.load_artifact(key).is_some());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn removes_artifacts_of_other_engines() {
+        let dir = temp_dir("modules-stale");
+        fs::write(dir.join("00-0000000000000000.cwasm"), b"old").unwrap();
+        let engine = build_engine(&EngineOptions::default()).unwrap();
+        let cache = ModuleCache::new(engine, Some(dir.clone()), 4).unwrap();
+        cache.instance_pre(SCRIPT_HASH, &orscript2().0).unwrap();
+        let names: Vec<String> = fs::read_dir(&dir).unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(names.len(), 1);
+        assert!(names[0].ends_with(&format!("-{}.cwasm", cache.fingerprint)));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn abort_traps_the_script() {
+        let (root, registry) = temp_registry("modules-abort");
+        let hash = insert_wat(&registry, ABORTS);
+        let error = registry.execute(&hash, None, "{}", &ExecutionLimits::default()).unwrap_err();
+        assert!(format!("{:?}", error).contains("script aborted: boom at a.ts:3:7"), "{:?}", error);
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn unknown_imports_are_rejected_at_deploy() {
+        let (root, registry) = temp_registry("modules-imports");
+        for import in [r#""env" "seed""#, r#""foo" "bar""#] {
+            let wat = format!(r#"(module (import {} (func (result f64))) (memory (export "memory") 1)
+                (func (export "process") (param i32 i32) (result i32) i32.const 0))"#, import);
+            let wasm = wat.as_bytes();
+            let error = registry.insert(wasm, &script_abi(wasm, "Output", &[("Output", &[("value", "i32")])])).unwrap_err();
+            assert!(error.to_string().contains("only orascript host functions"), "{}", error);
+        }
+        assert!(registry.list().unwrap().is_empty());
+        fs::remove_dir_all(root).unwrap();
+    }
+}
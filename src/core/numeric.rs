@@ -0,0 +1,225 @@
+use std::cmp::Ordering;
+use std::fmt;
+use parity_scale_codec_derive::{Decode, Encode};
+use serde::{Deserialize, Serialize};
+
+/// Unsigned 256-bit integer as little endian 64-bit words, the layout of `as-bignum`'s `u256`.
+/// SCALE encoded as its 32 little endian bytes like `primitive_types::U256`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Encode, Decode, Serialize, Deserialize)]
+pub struct U256(pub [u64; 4]);
+
+impl U256 {
+    pub fn from_le_bytes(bytes: [u8; 32]) -> Self {
+        let mut words = [0u64; 4];
+        for (word, chunk) in words.iter_mut().zip(bytes.chunks_exact(8)) {
+            *word = u64::from_le_bytes(chunk.try_into().expect("chunks of 8 bytes"));
+        }
+        U256(words)
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.0.iter().all(|word| *word == 0)
+    }
+
+    pub fn as_f64(&self) -> f64 {
+        self.0.iter().rev().fold(0.0, |value, word| value * 2f64.powi(64) + *word as f64)
+    }
+
+    /// Divides in place and returns the remainder.
+    fn div_rem(&mut self, divisor: u64) -> u64 {
+        let mut rem = 0u128;
+        for word in self.0.iter_mut().rev() {
+            let current = (rem << 64) | *word as u128;
+            *word = (current / divisor as u128) as u64;
+            rem = current % divisor as u128;
+        }
+        rem as u64
+    }
+}
+
+impl From<u128> for U256 {
+    fn from(value: u128) -> Self {
+        U256([value as u64, (value >> 64) as u64, 0, 0])
+    }
+}
+
+impl Ord for U256 {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.iter().rev().cmp(other.0.iter().rev())
+    }
+}
+
+impl PartialOrd for U256 {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for U256 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Peels off 19 decimal digits at a time, the most that fit in a u64.
+        const CHUNK: u64 = 10_000_000_000_000_000_000;
+        let mut value = *self;
+        let mut chunks = Vec::new();
+        loop {
+            chunks.push(value.div_rem(CHUNK));
+            if value.is_zero() {
+                break;
+            }
+        }
+        let mut chunks = chunks.iter().rev();
+        write!(f, "{}", chunks.next().expect("at least one chunk"))?;
+        for chunk in chunks {
+            write!(f, "{:019}", chunk)?;
+        }
+        Ok(())
+    }
+}
+
+macro_rules! fixed_point {
+    ($($(#[$doc:meta])* $name:ident($inner:ty, $unsigned:ty, $div:expr);)*) => {$(
+        $(#[$doc])*
+        #[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Encode, Decode, Serialize, Deserialize)]
+        pub struct $name(pub $inner);
+
+        impl $name {
+            /// Number of inner units in 1.
+            pub const DIV: $inner = $div;
+
+            pub fn from_le_bytes(bytes: [u8; size_of::<$inner>()]) -> Self {
+                $name(<$inner>::from_le_bytes(bytes))
+            }
+
+            pub fn as_f64(&self) -> f64 {
+                self.0 as f64 / Self::DIV as f64
+            }
+        }
+
+        /// Decimal with trailing zeros trimmed, e.g. `104700.25`.
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                let sign = if self.0 < Self::default().0 { "-" } else { "" };
+                let abs = self.0.unsigned_abs_or_self();
+                let div = Self::DIV as $unsigned;
+                let frac = abs % div;
+                write!(f, "{}{}", sign, abs / div)?;
+                if frac != 0 {
+                    let digits = format!("{:0width$}", frac, width = div.ilog10() as usize);
+                    write!(f, ".{}", digits.trim_end_matches('0'))?;
+                }
+                Ok(())
+            }
+        }
+    )*};
+}
+
+/// `unsigned_abs` for signed integers, identity for unsigned ones.
+trait UnsignedAbs {
+    type Unsigned;
+    fn unsigned_abs_or_self(self) -> Self::Unsigned;
+}
+
+macro_rules! unsigned_abs {
+    ($($signed:ty => $unsigned:ty;)*) => {$(
+        impl UnsignedAbs for $signed {
+            type Unsigned = $unsigned;
+            fn unsigned_abs_or_self(self) -> $unsigned {
+                self.unsigned_abs()
+            }
+        }
+
+        impl UnsignedAbs for $unsigned {
+            type Unsigned = $unsigned;
+            fn unsigned_abs_or_self(self) -> $unsigned {
+                self
+            }
+        }
+    )*};
+}
+
+unsigned_abs! {
+    i64 => u64;
+    i128 => u128;
+}
+
+fixed_point! {
+    /// `sp_arithmetic::FixedU64`, 9 decimals.
+    FixedU64(u64, u64, 1_000_000_000);
+    /// `sp_arithmetic::FixedI64`, 9 decimals.
+    FixedI64(i64, u64, 1_000_000_000);
+    /// `sp_arithmetic::FixedU128`, 18 decimals.
+    FixedU128(u128, u128, 1_000_000_000_000_000_000);
+    /// `sp_arithmetic::FixedI128`, 18 decimals.
+    FixedI128(i128, u128, 1_000_000_000_000_000_000);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use parity_scale_codec::{Decode, Encode};
+    use serde_json::json;
+    use super::*;
+    use crate::core::abi_value::{AbiSchema, AbiValue};
+    use crate::core::testing::TestMemory;
+    use crate::traits::traits::{ABIType, AbiMemory};
+
+    #[test]
+    fn fixed_point_numbers_render_as_decimals() {
+        assert_eq!(FixedU128(104_700_250_000_000_000_000_000).to_string(), "104700.25");
+        assert_eq!(FixedU128(1).to_string(), "0.000000000000000001");
+        assert_eq!(FixedI128(-1_500_000_000_000_000_000).to_string(), "-1.5");
+        assert_eq!(FixedI128(i128::MIN).to_string(), "-170141183460469231731.687303715884105728");
+        assert_eq!(FixedU64(2_000_000_000).to_string(), "2");
+        assert_eq!(FixedI64(-1).to_string(), "-0.000000001");
+        assert_eq!(FixedU64(1_250_000_000).as_f64(), 1.25);
+    }
+
+    #[test]
+    fn u256_renders_and_orders_like_an_integer() {
+        assert_eq!(U256::default().to_string(), "0");
+        assert_eq!(U256::from(u128::MAX).to_string(), u128::MAX.to_string());
+        assert_eq!(U256([u64::MAX; 4]).to_string(), "115792089237316195423570985008687907853269984665640564039457584007913129639935");
+        assert!(U256([0, 0, 0, 1]) > U256([u64::MAX, u64::MAX, u64::MAX, 0]));
+        let mut bytes = [0u8; 32];
+        bytes[8] = 1;
+        assert_eq!(U256::from_le_bytes(bytes), U256::from(1u128 << 64));
+    }
+
+    #[test]
+    fn encodes_like_substrate() {
+        // `sp_arithmetic` fixed-point numbers encode as their inner integer, `U256` as 32 little
+        // endian bytes.
+        assert_eq!(FixedU128(5).encode(), 5u128.encode());
+        assert_eq!(FixedI64(-5).encode(), (-5i64).encode());
+        let value = U256::from(u128::MAX - 1);
+        let encoded = value.encode();
+        assert_eq!(encoded.len(), 32);
+        assert_eq!(&encoded[..16], &(u128::MAX - 1).to_le_bytes());
+        assert_eq!(U256::decode(&mut &encoded[..]).unwrap(), value);
+    }
+
+    #[test]
+    fn big_numbers_round_trip_through_the_abi() {
+        let schema = AbiSchema::new(HashMap::new());
+        for (typ, bytes, expected) in [
+            ("FixedU128", 104_700_250_000_000_000_000_000u128.encode(), json!("104700.25")),
+            ("FixedI64", (-2_500_000_000i64).encode(), json!("-2.5")),
+            ("i128", (-7i128).encode(), json!("-7")),
+            ("u256", U256([1, 0, 0, 0]).encode(), json!("1")),
+        ] {
+            let value = AbiValue::decode_scale(&mut &bytes[..], &schema.resolve(typ).unwrap()).unwrap();
+            assert_eq!(value.to_json(), expected);
+            let mut encoded = Vec::new();
+            value.encode_scale(&mut encoded);
+            assert_eq!(encoded, bytes);
+        }
+        // `as-bignum` numbers are objects holding their little endian bytes.
+        let mut memory = TestMemory::default();
+        let object = memory.alloc(16, 3).unwrap();
+        memory.write(object, &u128::MAX.to_le_bytes()).unwrap();
+        let slot = memory.alloc(4, 3).unwrap();
+        memory.put_u32(slot, object);
+        let value = AbiValue::read_from_memory(&mut memory, slot, &schema.resolve("u128").unwrap()).unwrap();
+        assert_eq!(value.to_json(), json!(u128::MAX.to_string()));
+    }
+}
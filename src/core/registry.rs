@@ -0,0 +1,465 @@
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use anyhow::{anyhow, Result};
+use crate::core::module_cache::{build_engine, EngineOptions, ModuleCache, DEFAULT_MODULE_CACHE_CAPACITY};
+use crate::core::runtime::{check_selectors, describe_abi, execute, trace, ExecutionLimits, ScriptInfo, SelectorRegistry, DEFAULT_ENTRYPOINT};
+use crate::traits::traits::{ScriptRuntime, ScriptSource};
+
+const WASM_FILE: &str = "script.wasm";
+const ABI_FILE: &str = "abi.json";
+const MODULE_CACHE_DIR: &str = ".modules";
+/// Verified scripts kept in memory, the oldest loaded is dropped first.
+const LOADED_CAPACITY: usize = 256;
+/// Time a source has to fetch a script, a hung fetch would hold up every job waiting for it.
+const FETCH_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// Distinguishes the staging directories of concurrent inserts within this process.
+static STAGING_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Content addressed store of wasm + ABI pairs, keyed by the header hash of the wasm.
+///
+/// Every script lives in `<root>/<hash>/` so a hash is all that is needed to run it.
+pub struct ScriptRegistry {
+    root: PathBuf,
+    modules: ModuleCache,
+    /// Scripts already verified by `load`, their content can never change under the same hash.
+    loaded: Mutex<LoadedScripts>,
+    /// Consulted by `resolve` for scripts the registry does not hold.
+    source: Option<Arc<dyn ScriptSource>>,
+    /// One lock per script being resolved, so concurrent jobs of a missing script fetch it only
+    /// once while other scripts resolve in parallel.
+    resolving: Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>,
+}
+
+/// Holds the lock of a script being resolved in `resolving`, removing it once the last job
+/// waiting on it is done, including jobs whose resolve was cancelled by a timeout.
+struct ResolvingScript<'a> {
+    resolving: &'a Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>,
+    hash: String,
+    lock: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl<'a> ResolvingScript<'a> {
+    fn new(resolving: &'a Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>, hash: &str) -> Self {
+        let lock = resolving.lock().unwrap().entry(hash.to_string()).or_default().clone();
+        ResolvingScript { resolving, hash: hash.to_string(), lock }
+    }
+}
+
+impl Drop for ResolvingScript<'_> {
+    fn drop(&mut self) {
+        let mut resolving = self.resolving.lock().unwrap();
+        // Only the map and this holder are left, new holders clone it under the same lock.
+        if Arc::strong_count(&self.lock) == 2 {
+            resolving.remove(&self.hash);
+        }
+    }
+}
+
+#[derive(Default)]
+struct LoadedScripts {
+    scripts: HashMap<String, Arc<StoredScript>>,
+    order: VecDeque<String>,
+}
+
+pub struct StoredScript {
+    pub abi: String,
+    pub wasm: Vec<u8>,
+    /// `abi` parsed, so executions do not parse it again.
+    pub selectors: SelectorRegistry,
+}
+
+impl ScriptRegistry {
+    /// Opens the registry, every execution going through it shares one engine built from `engine`.
+    pub fn open(root: impl Into<PathBuf>, engine: &EngineOptions) -> Result<Self> {
+        let root = root.into();
+        fs::create_dir_all(&root)
+            .map_err(|e| anyhow!("ERROR: failed to create registry at {:?}: {}", root, e))?;
+        let modules = ModuleCache::new(build_engine(engine)?, Some(root.join(MODULE_CACHE_DIR)), DEFAULT_MODULE_CACHE_CAPACITY)?;
+        Ok(ScriptRegistry { root, modules, loaded: Mutex::new(LoadedScripts::default()), source: None, resolving: Default::default() })
+    }
+
+    /// Resolves unknown scripts from `source`, see `resolve`.
+    pub fn with_source(mut self, source: Arc<dyn ScriptSource>) -> Self {
+        self.source = Some(source);
+        self
+    }
+
+    /// Compiled module cache shared by every execution going through this registry.
+    pub fn modules(&self) -> &ModuleCache {
+        &self.modules
+    }
+
+    pub fn insert(&self, wasm: &[u8], abi: &str) -> Result<String> {
+        let hash = verify_script(wasm, abi)?;
+        // Compiling and linking up front rejects scripts with imports the node does not provide.
+        self.modules.instance_pre(&hash, wasm)?;
+        let dir = self.script_dir(&hash)?;
+        if dir.exists() {
+            // The hash only covers the wasm, the same script with another ABI is a conflict.
+            return self.check_stored_abi(&hash, &dir, abi).map(|_| hash);
+        }
+        // Write into a staging directory first so a crash never leaves a half written script behind.
+        // Every insert stages on its own, the first rename wins and the others find the script there.
+        let staging = self.root.join(format!(".{}.{}.{}.tmp", &hash[2..], std::process::id(), STAGING_COUNTER.fetch_add(1, Ordering::Relaxed)));
+        let staged = fs::create_dir_all(&staging)
+            .and_then(|_| fs::write(staging.join(WASM_FILE), wasm))
+            .and_then(|_| fs::write(staging.join(ABI_FILE), abi))
+            .and_then(|_| fs::rename(&staging, &dir));
+        if let Err(e) = staged {
+            let _ = fs::remove_dir_all(&staging);
+            if !dir.exists() {
+                return Err(anyhow!("ERROR: failed to store script {}: {}", hash, e));
+            }
+            self.check_stored_abi(&hash, &dir, abi)?;
+        }
+        Ok(hash)
+    }
+
+    fn check_stored_abi(&self, hash: &str, dir: &Path, abi: &str) -> Result<()> {
+        let stored = fs::read_to_string(dir.join(ABI_FILE))
+            .map_err(|e| anyhow!("ERROR: failed to read the stored ABI of {}: {}", hash, e))?;
+        let same = match (serde_json::from_str::<serde_json::Value>(&stored), serde_json::from_str::<serde_json::Value>(abi)) {
+            (Ok(stored), Ok(abi)) => stored == abi,
+            _ => stored == abi,
+        };
+        if !same {
+            return Err(anyhow!("ERROR: script {} is already stored with another ABI, remove it first", hash));
+        }
+        Ok(())
+    }
+
+    pub fn insert_files(&self, abi_path: &Path, wasm_path: &Path) -> Result<String> {
+        let abi = fs::read_to_string(abi_path)
+            .map_err(|e| anyhow!("ERROR: failed to read ABI {:?}: {}", abi_path, e))?;
+        let wasm = fs::read(wasm_path)
+            .map_err(|e| anyhow!("ERROR: failed to read WASM file {:?}: {}", wasm_path, e))?;
+        self.insert(&wasm, &abi)
+    }
+
+    pub fn contains(&self, hash: &str) -> bool {
+        self.script_dir(hash).map(|dir| dir.exists()).unwrap_or(false)
+    }
+
+    /// Loads a script and re-checks its content against the hash it is stored under.
+    pub fn load(&self, hash: &str) -> Result<Arc<StoredScript>> {
+        let hash = &hash.to_ascii_lowercase();
+        if let Some(script) = self.loaded.lock().unwrap().scripts.get(hash) {
+            return Ok(script.clone());
+        }
+        let dir = self.script_dir(hash)?;
+        if !dir.exists() {
+            return Err(anyhow!("ERROR: script {} is not in the registry", hash));
+        }
+        let wasm = fs::read(dir.join(WASM_FILE))?;
+        let abi = fs::read_to_string(dir.join(ABI_FILE))?;
+        let actual = verify_script(&wasm, &abi)?;
+        if actual != *hash {
+            return Err(anyhow!("ERROR: script stored under {} hashes to {}", hash, actual));
+        }
+        let selectors = SelectorRegistry::from_abi(&abi)?;
+        let script = Arc::new(StoredScript { abi, wasm, selectors });
+        self.loaded.lock().unwrap().insert(hash, script.clone());
+        Ok(script)
+    }
+
+    /// Makes sure the script `hash` is stored locally, fetching it from the source if there is
+    /// one. A fetched script must match the requested hash before it is cached in the registry.
+    pub async fn resolve(self: &Arc<Self>, hash: &str) -> Result<()> {
+        let hash = &hash.to_ascii_lowercase();
+        if self.contains(hash) {
+            return Ok(());
+        }
+        let Some(source) = &self.source else {
+            return Err(anyhow!("ERROR: script {} is not in the registry", hash));
+        };
+        validate_hash(hash)?;
+        let resolving = ResolvingScript::new(&self.resolving, hash);
+        let _resolving = resolving.lock.lock().await;
+        if self.contains(hash) {
+            return Ok(());
+        }
+        let script = tokio::time::timeout(FETCH_TIMEOUT, source.fetch(hash)).await
+            .map_err(|_| anyhow!("ERROR: fetching script {} timed out after {:?}", hash, FETCH_TIMEOUT))??
+            .ok_or_else(|| anyhow!("ERROR: script {} is neither in the registry nor in its source", hash))?;
+        let actual = verify_script(&script.wasm, &script.abi)?;
+        if actual != *hash {
+            return Err(anyhow!("ERROR: script fetched for {} hashes to {}", hash, actual));
+        }
+        // Inserting compiles the module, which must not stall the async workers.
+        let registry = self.clone();
+        tokio::task::spawn_blocking(move || registry.insert(&script.wasm, &script.abi)).await
+            .map_err(|e| anyhow!("ERROR: storing script {} failed: {}", hash, e))??;
+        Ok(())
+    }
+
+    /// Describes every stored script, unreadable entries are skipped.
+    pub fn list(&self) -> Result<Vec<ScriptInfo>> {
+        let mut scripts = Vec::new();
+        for entry in fs::read_dir(&self.root)? {
+            let name = entry?.file_name().to_string_lossy().into_owned();
+            if validate_hash(&name).is_err() {
+                continue;
+            }
+            let info = fs::read_to_string(self.root.join(&name).join(ABI_FILE))
+                .map_err(anyhow::Error::from)
+                .and_then(|abi| describe_abi(&abi));
+            match info {
+                Ok(info) => scripts.push(info),
+                Err(e) => trace!("Skipping registry entry {}: {}", name, e),
+            }
+        }
+        scripts.sort_by(|a, b| a.header.cmp(&b.header));
+        Ok(scripts)
+    }
+
+    /// Number of stored scripts, counted from the directory names without reading the entries.
+    pub fn count(&self) -> Result<usize> {
+        let mut count = 0;
+        for entry in fs::read_dir(&self.root)? {
+            if validate_hash(&entry?.file_name().to_string_lossy()).is_ok() {
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
+    pub fn remove(&self, hash: &str) -> Result<bool> {
+        let hash = &hash.to_ascii_lowercase();
+        let dir = self.script_dir(hash)?;
+        self.loaded.lock().unwrap().remove(hash);
+        self.modules.evict(hash);
+        if !dir.exists() {
+            return Ok(false);
+        }
+        fs::remove_dir_all(dir)?;
+        Ok(true)
+    }
+
+    /// Runs a stored script, `selector` defaults to the script's entrypoint.
+    pub fn execute(&self, hash: &str, selector: Option<&str>, input: &str, limits: &ExecutionLimits) -> Result<Vec<u8>> {
+        let hash = &hash.to_ascii_lowercase();
+        let script = self.load(hash)?;
+        let selector = match selector {
+            Some(selector) => selector.to_string(),
+            None => script.selectors.selector_of(DEFAULT_ENTRYPOINT)?,
+        };
+        execute(&self.modules, hash, &script.selectors, &script.wasm, &selector, input, limits)
+    }
+
+    fn script_dir(&self, hash: &str) -> Result<PathBuf> {
+        let hash = hash.to_ascii_lowercase();
+        validate_hash(&hash)?;
+        Ok(self.root.join(hash))
+    }
+}
+
+impl LoadedScripts {
+    fn insert(&mut self, hash: &str, script: Arc<StoredScript>) {
+        if self.scripts.insert(hash.to_string(), script).is_some() {
+            return;
+        }
+        self.order.push_back(hash.to_string());
+        while self.order.len() > LOADED_CAPACITY {
+            if let Some(evicted) = self.order.pop_front() {
+                self.scripts.remove(&evicted);
+            }
+        }
+    }
+
+    fn remove(&mut self, hash: &str) {
+        if self.scripts.remove(hash).is_some() {
+            self.order.retain(|h| h != hash);
+        }
+    }
+}
+
+impl ScriptRuntime for ScriptRegistry {
+    fn call(&self, script_hash: &str, selector: &str, input: &str) -> Result<Vec<u8>> {
+        self.execute(script_hash, Some(selector), input, &ExecutionLimits::default())
+    }
+}
+
+/// Checks that the ABI header and selectors match the wasm under the ABI's `hash_alg` and
+/// returns the header hash.
+fn verify_script(wasm: &[u8], abi: &str) -> Result<String> {
+    let info = describe_abi(abi)?;
+    let hash = info.hash_alg.header_hash(wasm);
+    if info.header != hash {
+        return Err(anyhow!("ERROR: wasm hash {} does not match ABI header {}", hash, info.header));
+    }
+    check_selectors(abi)?;
+    Ok(hash)
+}
+
+fn validate_hash(hash: &str) -> Result<()> {
+    let valid = hash.len() == 66
+        && hash.starts_with("0x")
+        && hash[2..].chars().all(|c| c.is_ascii_digit() || ('a'..='f').contains(&c));
+    if !valid {
+        return Err(anyhow!("ERROR: {:?} is not a script hash", hash));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::sync::atomic::AtomicUsize;
+    use tokio::sync::Semaphore;
+    use super::*;
+    use crate::core::testing::{orscript2, temp_dir, temp_registry};
+    use crate::traits::traits::FetchedScript;
+
+    /// Source holding one script, every fetch of it waits for a permit of `gate` (or forever
+    /// when `hang` is set). Other hashes are unknown to it.
+    struct GatedSource {
+        wasm: Vec<u8>,
+        abi: String,
+        gate: Semaphore,
+        hang: bool,
+        fetches: AtomicUsize,
+    }
+
+    impl ScriptSource for GatedSource {
+        fn fetch<'a>(&'a self, hash: &'a str) -> Pin<Box<dyn Future<Output = Result<Option<FetchedScript>>> + Send + 'a>> {
+            Box::pin(async move {
+                if verify_script(&self.wasm, &self.abi)? != hash {
+                    return Ok(None);
+                }
+                self.fetches.fetch_add(1, Ordering::SeqCst);
+                if self.hang {
+                    std::future::pending::<()>().await;
+                }
+                self.gate.acquire().await?.forget();
+                Ok(Some(FetchedScript { wasm: self.wasm.clone(), abi: self.abi.clone() }))
+            })
+        }
+    }
+
+    fn gated(name: &str, hang: bool) -> (PathBuf, Arc<GatedSource>, Arc<ScriptRegistry>, String) {
+        let (wasm, abi) = orscript2();
+        let hash = verify_script(&wasm, &abi).unwrap();
+        let source = Arc::new(GatedSource { wasm, abi, gate: Semaphore::new(0), hang, fetches: AtomicUsize::new(0) });
+        let root = temp_dir(name);
+        let registry = ScriptRegistry::open(&root, &EngineOptions::default()).unwrap().with_source(source.clone());
+        (root, source, Arc::new(registry), hash)
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn resolves_each_script_under_its_own_lock() {
+        let (root, source, registry, hash) = gated("registry-resolve", false);
+        let waiting: Vec<_> = (0..3).map(|_| {
+            let (registry, hash) = (registry.clone(), hash.clone());
+            tokio::spawn(async move { registry.resolve(&hash).await })
+        }).collect();
+        while source.fetches.load(Ordering::SeqCst) == 0 {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        // Another script resolves while the first one is still being fetched.
+        let other = format!("0x{}", "33".repeat(32));
+        let e = registry.resolve(&other).await.unwrap_err();
+        assert_eq!(e.to_string(), format!("ERROR: script {} is neither in the registry nor in its source", other));
+        source.gate.add_permits(1);
+        for resolved in waiting {
+            resolved.await.unwrap().unwrap();
+        }
+        assert_eq!(source.fetches.load(Ordering::SeqCst), 1);
+        assert!(registry.resolving.lock().unwrap().is_empty());
+
+        // Hashes are matched regardless of case.
+        let upper = format!("0x{}", hash[2..].to_uppercase());
+        registry.resolve(&upper).await.unwrap();
+        assert_eq!(registry.load(&upper).unwrap().wasm, registry.load(&hash).unwrap().wasm);
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn hung_fetches_time_out() {
+        let (root, _source, registry, hash) = gated("registry-fetch-timeout", true);
+        let e = registry.resolve(&hash).await.unwrap_err();
+        assert_eq!(e.to_string(), format!("ERROR: fetching script {} timed out after 20s", hash));
+        assert!(registry.resolving.lock().unwrap().is_empty());
+        assert!(!registry.contains(&hash));
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn concurrent_inserts_of_one_script_succeed() {
+        let (root, registry) = temp_registry("registry-concurrent");
+        let (wasm, abi) = orscript2();
+        let hashes: Vec<String> = std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..8).map(|_| scope.spawn(|| registry.insert(&wasm, &abi).unwrap())).collect();
+            handles.into_iter().map(|handle| handle.join().unwrap()).collect()
+        });
+        assert!(hashes.iter().all(|hash| *hash == hashes[0]));
+        assert_eq!(registry.load(&hashes[0]).unwrap().wasm, wasm);
+        let leftovers = fs::read_dir(&root).unwrap()
+            .filter(|entry| entry.as_ref().unwrap().file_name().to_string_lossy().ends_with(".tmp"))
+            .count();
+        assert_eq!(leftovers, 0);
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn list_skips_corrupt_entries() {
+        let (root, registry) = temp_registry("registry-list");
+        let (wasm, abi) = orscript2();
+        let hash = registry.insert(&wasm, &abi).unwrap();
+        let corrupt = root.join(format!("0x{}", "ab".repeat(32)));
+        fs::create_dir_all(&corrupt).unwrap();
+        fs::write(corrupt.join(ABI_FILE), "not json").unwrap();
+        let scripts = registry.list().unwrap();
+        assert_eq!(scripts.len(), 1);
+        assert_eq!(scripts[0].header, hash);
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn insert_rejects_mismatched_abi() {
+        let (root, registry) = temp_registry("registry-mismatch");
+        let (mut wasm, abi) = orscript2();
+        wasm.push(0);
+        assert!(registry.insert(&wasm, &abi).is_err());
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn insert_rejects_another_abi_for_a_stored_script() {
+        let (root, registry) = temp_registry("registry-abi");
+        let (wasm, abi) = orscript2();
+        let hash = registry.insert(&wasm, &abi).unwrap();
+        // The same ABI formatted differently is the same document.
+        let compact = serde_json::from_str::<serde_json::Value>(&abi).unwrap().to_string();
+        assert_eq!(registry.insert(&wasm, &compact).unwrap(), hash);
+        let renamed = abi.replacen("\"name\": null", "\"name\": \"renamed\"", 1);
+        assert_ne!(renamed, abi);
+        let error = registry.insert(&wasm, &renamed).unwrap_err().to_string();
+        assert!(error.contains("another ABI"), "{}", error);
+        assert_eq!(registry.load(&hash).unwrap().abi, abi);
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn remove_drops_compiled_modules() {
+        let (root, registry) = temp_registry("registry-remove");
+        let (wasm, abi) = orscript2();
+        let hash = registry.insert(&wasm, &abi).unwrap();
+        registry.execute(&hash, None, crate::core::runtime::DEFAULT_INPUT, &ExecutionLimits::default()).unwrap();
+        let artifacts = || fs::read_dir(root.join(MODULE_CACHE_DIR)).unwrap()
+            .filter(|entry| entry.as_ref().unwrap().file_name().to_string_lossy().starts_with(&hash[2..]))
+            .count();
+        assert_eq!(artifacts(), 1);
+        assert!(registry.remove(&hash).unwrap());
+        assert_eq!(artifacts(), 0);
+        assert!(registry.load(&hash).is_err());
+        assert_eq!(registry.count().unwrap(), 0);
+        assert!(!registry.remove(&hash).unwrap());
+        fs::remove_dir_all(root).unwrap();
+    }
+}
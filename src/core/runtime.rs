@@ -1,17 +1,33 @@
-use std::any::Any;
-use std::collections::{BTreeMap, HashMap};
+use std::collections::HashMap;
 use std::fs;
-use std::fs::File;
-use std::io::Read;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
 use anyhow::{anyhow,Result};
 use serde::{Deserialize, Serialize};
-use sha2::{Digest, Sha256};
-use wasmtime::{Engine, Func, Instance, Module, Store, Val, Linker, Caller, ImportType, Table, Extern, ExternType, ValType, V128, Ref, WasmTy, TypedFunc, Memory};
-use dynamic_struct::generate_struct;
-use parity_scale_codec;
-use parity_scale_codec::{Decode, Encode};
-use parity_scale_codec_derive::{Decode, Encode};
+use wasmtime::{Store, TypedFunc, Memory, UpdateDeadline, AsContextMut};
 use serde_json::Value;
+use crate::core::abi_value::{AbiSchema, AbiTypeDesc, AbiValue, ARRAY_BUFFER_ID};
+use crate::core::hashing::HashAlgorithm;
+use crate::core::host::HostState;
+use crate::core::module_cache::ModuleCache;
+use crate::traits::traits::{ABIType, AbiMemory};
+
+pub(crate) static TRACE: AtomicBool = AtomicBool::new(false);
+
+/// Turns the per-execution debug output on or off, it is off by default.
+pub fn set_trace(enabled: bool) {
+    TRACE.store(enabled, Ordering::Relaxed);
+}
+
+macro_rules! trace {
+    ($($arg:tt)*) => {
+        if $crate::core::runtime::TRACE.load(std::sync::atomic::Ordering::Relaxed) {
+            println!($($arg)*);
+        }
+    };
+}
+pub(crate) use trace;
 
 #[derive(Debug, Deserialize)]
 struct Root {
@@ -25,6 +41,8 @@ struct Root {
 struct Header {
     name : Option<String>,
     header : String,
+    #[serde(default)]
+    hash_alg : HashAlgorithm,
 }
 
 #[derive(Debug, Deserialize)]
@@ -58,329 +76,398 @@ struct Variable {
     selector: String,
 }
 
+/// Parsed ABI of a script: its functions by selector and the classes of its values. Parsed once
+/// per script and reused by every execution.
 #[derive(Default)]
-struct SelectorRegistry {
+pub struct SelectorRegistry {
     origin : String,
+    hash_alg : HashAlgorithm,
     functions : HashMap<String,Function>,
     variables : HashMap<String,Variable>,
-    classes_schema : HashMap<String,Vec<Param>>
+    classes_schema : HashMap<String,Vec<Param>>,
+    schema : AbiSchema,
 }
 
-pub fn abi_reader(abi_path: &str, wasm_bytecode_path: &str) -> anyhow::Result<()> {
-    let file_content = fs::read_to_string(abi_path)?;
-    let root: Root = serde_json::from_str(&file_content)?;
+#[derive(Debug, Serialize)]
+pub struct ScriptInfo {
+    pub name: Option<String>,
+    pub header: String,
+    pub hash_alg: HashAlgorithm,
+    pub functions: Vec<FunctionInfo>,
+}
 
-    let mut selector_registry = SelectorRegistry {
-        origin: root.headers.header.clone(),
-        functions: HashMap::default(),
-        variables: HashMap::default(),
-        classes_schema : HashMap::default(),
-    };
+#[derive(Debug, Serialize)]
+pub struct FunctionInfo {
+    pub name: String,
+    pub selector: String,
+    pub result: String,
+}
 
-    // assert_eq!(check_header_hash(&root.headers.header,wasm_bytecode_path), true,
-    //            "The hash from {:?} is not equal to the header {:?}",abi_path,root.headers.header
-    // );
+/// Bounds of a single execution, enforced through wasmtime's epoch interruption.
+#[derive(Default, Clone)]
+pub struct ExecutionLimits {
+    pub deadline: Option<Instant>,
+    pub cancel: Option<Arc<AtomicBool>>,
+}
 
-    for (_,value) in root.functions.into_iter().enumerate() {
-        selector_registry.functions.insert(value.selector.clone(),value);
+/// Error returned when an execution was stopped by its `ExecutionLimits`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interruption {
+    TimedOut,
+    Cancelled,
+}
+
+impl std::fmt::Display for Interruption {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Interruption::TimedOut => write!(f, "execution timed out"),
+            Interruption::Cancelled => write!(f, "execution cancelled"),
+        }
     }
-    for value in root.classes.into_iter().flat_map(|class| {
-        selector_registry.classes_schema.insert(class.name,class.fields);
-        class.methods.into_iter()
-    }) {
-        selector_registry.functions.insert(value.selector.clone(),value);
+}
+
+impl std::error::Error for Interruption {}
+
+/// Name of the exported function run when no selector is given.
+pub const DEFAULT_ENTRYPOINT: &str = "process";
+pub const DEFAULT_INPUT: &str = r#"{"bitcoin":{"usd":104700},"ethereum":{"usd":2523.13}}"#;
+
+impl SelectorRegistry {
+    pub fn from_abi(abi_json: &str) -> Result<Self> {
+        let root: Root = serde_json::from_str(abi_json)?;
+
+        let mut selector_registry = SelectorRegistry {
+            origin: root.headers.header.clone(),
+            hash_alg: root.headers.hash_alg,
+            functions: HashMap::default(),
+            variables: HashMap::default(),
+            classes_schema : HashMap::default(),
+            schema : AbiSchema::default(),
+        };
+
+        for (_,value) in root.functions.into_iter().enumerate() {
+            selector_registry.functions.insert(value.selector.clone(),value);
+        }
+        for value in root.classes.into_iter().flat_map(|class| {
+            selector_registry.classes_schema.insert(class.name,class.fields);
+            class.methods.into_iter()
+        }) {
+            selector_registry.functions.insert(value.selector.clone(),value);
+        }
+        for (_,value) in root.variables.into_iter().enumerate() {
+            selector_registry.variables.insert(value.selector.clone(),value);
+        }
+        selector_registry.schema = AbiSchema::new(selector_registry.classes_schema.iter()
+            .map(|(name, fields)| {
+                let fields = fields.iter().map(|field| (field.name.clone(), field.param_type.clone())).collect();
+                (name.clone(), fields)
+            })
+            .collect());
+        Ok(selector_registry)
     }
-    for (_,value) in root.variables.into_iter().enumerate() {
-        selector_registry.variables.insert(value.selector.clone(),value);
+
+    pub fn selector_of(&self, name: &str) -> Result<String> {
+        self.functions.values()
+            .find(|function| function.name == name)
+            .map(|function| function.selector.clone())
+            .ok_or_else(|| anyhow!("ERROR: function {} not found in ABI {}", name, self.origin))
     }
+}
 
-    wasmtime_runner(wasm_bytecode_path,selector_registry).expect("DEBUG: wasmtime runner run");
-    Ok(())
+/// Runs the default entrypoint of a script given by its files, outside of the registry, and
+/// returns its output with the header hash of the wasm file.
+pub fn abi_reader(modules: &ModuleCache, abi_path: &str, wasm_bytecode_path: &str, input: &str) -> anyhow::Result<(Vec<u8>, String)> {
+    let file_content = fs::read_to_string(abi_path)
+        .map_err(|e| anyhow!("ERROR: failed to read ABI {:?}: {}", abi_path, e))?;
+    let selector_registry = SelectorRegistry::from_abi(&file_content)?;
+    let wasm = fs::read(wasm_bytecode_path)
+        .map_err(|e| anyhow!("ERROR: failed to read WASM file {:?}: {}", wasm_bytecode_path, e))?;
+    let script_hash = selector_registry.hash_alg.header_hash(&wasm);
+    let selector = selector_registry.selector_of(DEFAULT_ENTRYPOINT)?;
+    let output = wasmtime_runner(modules,&script_hash,&wasm,&selector_registry,&selector,input,&ExecutionLimits::default())?;
+    Ok((output, script_hash))
 }
 
-fn check_header_hash(header : &str,wasm_bytecode_path: &str) -> bool {
-    let wasm_hash = {
-        let mut header_hasher = Sha256::new();
-        let mut wasm_reader = File::open(wasm_bytecode_path)
-            .map_err(|e| anyhow!("ERROR: failed to read WASM file: {}", e)).expect("Re check bro 1");
-        let mut wasm_content = Vec::new();
-        wasm_reader.read_to_end(&mut wasm_content)
-            .map_err(|e| anyhow!("ERROR: failed to parse the wasm content to variable: {}", e)).expect("Re check bro 2");
-        header_hasher.update(&wasm_content);
-        let hash = header_hasher.finalize();
-        format!("0x{:x}", hash)
-    };
-    header.eq(&wasm_hash)
+/// Runs the function behind `selector` with `input` written into guest memory and returns
+/// the SCALE encoded result class. `script_hash` is the header hash of `wasm`, its compiled module
+/// is cached under it.
+pub fn execute(modules: &ModuleCache, script_hash: &str, abi: &SelectorRegistry, wasm: &[u8], selector: &str, input: &str, limits: &ExecutionLimits) -> Result<Vec<u8>> {
+    wasmtime_runner(modules,script_hash,wasm,abi,selector,input,limits)
 }
 
+pub fn default_selector(abi_json: &str) -> Result<String> {
+    SelectorRegistry::from_abi(abi_json)?.selector_of(DEFAULT_ENTRYPOINT)
+}
 
+/// Reads a top-level numeric field out of the SCALE output of `selector`, walking the result
+/// class the same way `wasmtime_runner` encoded it.
+pub fn read_numeric_field(abi_json: &str, selector: &str, output: &[u8], field: &str) -> Result<f64> {
+    let register = SelectorRegistry::from_abi(abi_json)?;
+    let function = register.functions.get(selector)
+        .ok_or_else(|| anyhow!("ERROR: selector {} not found in ABI {}", selector, register.origin))?;
+    let class = register.schema.class(&function.result)?;
+    let value = AbiValue::decode_scale(&mut &output[..], &AbiTypeDesc::Class(class))?;
+    value.field(field)
+        .ok_or_else(|| anyhow!("ERROR: field {} not found in {}", field, function.result))?
+        .as_f64()
+        .ok_or_else(|| anyhow!("ERROR: field {} of {} is not numeric", field, function.result))
+}
 
-fn cal_output_size(register: &SelectorRegistry) -> (Vec<i32>, i32){
-    let mut vec_of_size = Vec::new();
-    let mut total_input_size : i32 = 0;
-    for (k, v) in register.classes_schema.iter() {
-        match k.as_str() {
-            "Output" => {
-                for field in v.iter() {
-                    let size = match field.param_type.as_str() {
-                        "i32" => {vec_of_size.push(4);4},
-                        "i64" => {vec_of_size.push(4);4},
-                        "f32" => {vec_of_size.push(8);8},
-                        "f64" => {vec_of_size.push(8);8},
-                        typ if typ.starts_with("Array<") => {
-
-                            vec_of_size.push(4);
-                            4
-                        },
-                        typ if typ.eq("string") => {vec_of_size.push(4);4},
-                        _ => {
-                            vec_of_size.push(4);
-                            4
-                        }
-                    };
-                    total_input_size += size;
-                }
-                println!("{} total input size: {}",k.as_str(),total_input_size);
-            }
-            _ => continue
-        }
+/// Decodes the SCALE encoding of the ABI type `type_name` (a class, primitive or `Array<T>`) into
+/// JSON, driven by the classes of the ABI instead of a compiled-in struct.
+pub fn decode_output(abi_json: &str, type_name: &str, output: &[u8]) -> Result<Value> {
+    let ty = abi_schema(abi_json)?.resolve(type_name)?;
+    let mut input = output;
+    let value = AbiValue::decode_scale(&mut input, &ty)
+        .map_err(|e| anyhow!("ERROR: failed to decode {}: {}", type_name, e))?;
+    if !input.is_empty() {
+        return Err(anyhow!("ERROR: {} trailing bytes after {}", input.len(), type_name));
     }
-    (vec_of_size,total_input_size)
+    Ok(value.to_json())
 }
 
-fn read_utf16_string(memory: &Memory, store: &mut Store<()>, ptr: usize, max_len: usize) -> anyhow::Result<String> {
-    // Read 2 * max_len bytes since UTF-16 uses 2 bytes per character
-    let mut raw_bytes = vec![0u8; max_len * 2];
-    memory.read(store, ptr, &mut raw_bytes)?;
-
-    // Convert raw bytes into u16s (little endian)
-    let utf16_units: Vec<u16> = raw_bytes
-        .chunks_exact(2)
-        .map(|chunk| u16::from_le_bytes([chunk[0], chunk[1]]))
-        // stop at null terminator
-        .take_while(|&u| u != 0)
+/// Classes of an ABI, for resolving the types of script values.
+pub fn abi_schema(abi_json: &str) -> Result<AbiSchema> {
+    Ok(SelectorRegistry::from_abi(abi_json)?.schema)
+}
+
+pub fn describe_abi(abi_json: &str) -> Result<ScriptInfo> {
+    let root: Root = serde_json::from_str(abi_json)?;
+    let functions = root.functions.into_iter()
+        .chain(root.classes.into_iter().flat_map(|class| class.methods))
+        .map(|function| FunctionInfo {
+            name: function.name,
+            selector: function.selector,
+            result: function.result,
+        })
         .collect();
+    Ok(ScriptInfo {
+        name: root.headers.name,
+        header: root.headers.header,
+        hash_alg: root.headers.hash_alg,
+        functions,
+    })
+}
 
-    let decoded = String::from_utf16(&utf16_units)?;
-    Ok(decoded)
+/// Checks every function, method, class and variable selector of an ABI against its signature,
+/// hashed with the ABI's `hash_alg`.
+pub fn check_selectors(abi_json: &str) -> Result<()> {
+    let root: Root = serde_json::from_str(abi_json)?;
+    let alg = root.headers.hash_alg;
+    let check = |name: &str, params: &[Param], selector: &str| {
+        let expected = alg.selector(name, params.iter().map(|param| param.name.as_str()));
+        if expected != selector {
+            return Err(anyhow!("ERROR: selector {} of {} does not match the hash of its signature {}", selector, name, expected));
+        }
+        Ok(())
+    };
+    for function in root.functions.iter().chain(root.classes.iter().flat_map(|class| &class.methods)) {
+        check(&function.name, &function.params, &function.selector)?;
+    }
+    for class in &root.classes {
+        check(&class.name, &[], &class.class_selector)?;
+    }
+    for variable in &root.variables {
+        check(&variable.name, &[], &variable.selector)?;
+    }
+    Ok(())
 }
 
+/// Memory of a script instance, reached through its store or, from host functions, its caller.
+/// Objects are allocated through the exported `__new` of the AssemblyScript runtime (built with
+/// `--exportRuntime`).
+pub(crate) struct ScriptMemory<S: AsContextMut> {
+    pub(crate) memory: Memory,
+    pub(crate) store: S,
+    pub(crate) new: Option<TypedFunc<(i32, i32), i32>>,
+    /// `__pin` and `__unpin`, when set every allocated object stays pinned until `release`, so
+    /// the collector can not free an object before it is linked to the value being written.
+    pub(crate) pins: Option<(TypedFunc<i32, i32>, TypedFunc<i32, ()>)>,
+    pub(crate) pinned: Vec<u32>,
+}
 
-fn wasmtime_runner(path: &str, register : SelectorRegistry) -> anyhow::Result<()> {
-    let engine = Engine::default();
-    let mut store = Store::new(&engine, ());
-    let module = Module::from_file(&engine, path)?;
-
-    // DEBUG:
-    // ------
-    // for (k,v) in register.functions.iter() {
-    //     println!("Key: {k:#?} - Value: {v:#?}");
-    // }
-    // for (k,v) in register.classes_schema.iter() {
-    //         println!("Key: {k:#?} - Value: {v:#?}")
-    //
-    // }
-    // -----
-    let mut externlts : Vec<Extern> = Vec::new();
-    if module.imports().len() != 0 {
-        for import in module.imports() {
-            println!(
-                "Import module: {:#?}, name: {:#?}",
-                import.module(),
-                import.name(),
-            );
-            match import.ty() {
-                ExternType::Func(func_ty) => {
-                        let func = Func::new(&mut store, func_ty.clone(), |caller, params, results| {
-                            if params.len() > 0 {
-                                for i in params {
-                                    println!("param is for: {:#?}", i);
-                                }
-                            }
-                            if results.len() > 0 {
-                                for i in results {
-                                    println!("res {i:#?}")
-                                }
-                            }
-                            else {
-                                // results[0] = Val::I32(0);
-                            }
-                            Ok(())
-                        });
-                    externlts.push(func.into());
-                }
-                ExternType::Global(global_ty) => {
-                    let val = match global_ty.content() {
-                        ValType::I32 => Val::I32(0),
-                        ValType::I64 => Val::I64(0),
-                        ValType::F32 => Val::F32(0),
-                        ValType::F64 => Val::F64(0),
-                        _ => unimplemented!("Unsupported for dummy global type"),
-                    };
-                }
-                ExternType::Table(table_ty) => {
-                    let table = Table::new(&mut store, table_ty, Ref::Func(None))?;
-                    externlts.push(table.into());
-                }
-                _ => unimplemented!("Don't care bruv"),
+impl<S: AsContextMut> ScriptMemory<S> {
+    /// Unpins the objects allocated so far, once the script holds its own references to them.
+    pub(crate) fn release(&mut self) -> Result<()> {
+        if let Some((_, unpin)) = &self.pins {
+            for object in self.pinned.drain(..) {
+                unpin.call(&mut self.store, object as i32)?;
             }
         }
+        Ok(())
     }
+}
 
-    let instance = Instance::new(&mut store, &module, &externlts)?;
-    let memory = instance.get_memory(&mut store, "memory").expect("Memory not found");
-    // let alloc = instance.get_typed_func::<(i32,i32),i32>(&mut store, "__new")?;
-    let process_func = instance.get_typed_func::<(i32,i32),i32>(&mut store, "process")?;
-
-    let json = r#"{"bitcoin":{"usd":104700},"ethereum":{"usd":2523.13}}"#;
-    let json_bytes = json.as_bytes();
-    let json_offset = 0;
+impl<S: AsContextMut> AbiMemory for ScriptMemory<S> {
+    fn read(&mut self, ptr: u32, buf: &mut [u8]) -> Result<()> {
+        Ok(self.memory.read(&mut self.store, ptr as usize, buf)?)
+    }
 
-    memory.write(&mut store, json_offset as usize, json_bytes)?;
+    fn write(&mut self, ptr: u32, bytes: &[u8]) -> Result<()> {
+        Ok(self.memory.write(&mut self.store, ptr as usize, bytes)?)
+    }
 
-    let mut confirm = [0u8;10240];
-    memory.read(&mut store, json_offset as usize, &mut confirm)?;
+    fn alloc(&mut self, size: u32, rt_id: u32) -> Result<u32> {
+        let new = self.new.as_ref()
+            .ok_or_else(|| anyhow!("ERROR: script does not export __new, rebuild it with --exportRuntime"))?;
+        let object = new.call(&mut self.store, (size as i32, rt_id as i32))? as u32;
+        if let Some((pin, _)) = &self.pins {
+            pin.call(&mut self.store, object as i32)?;
+            self.pinned.push(object);
+        }
+        Ok(object)
+    }
 
-    let ret_ptr = process_func.call(&mut store, (json_offset, json_bytes.len() as i32))?;
-    let output_offset = ret_ptr as usize;
+    fn data_size(&mut self) -> usize {
+        self.memory.data_size(&self.store)
+    }
+}
 
-    let mut bytes_result: Vec<u8> = Vec::new();
+/// Writes an input value into the script and returns what the function parameter receives, the
+/// value is stored in an argument slot of its own layout and passed by the slot's content.
+fn stage_input(memory: &mut dyn AbiMemory, value: &AbiValue) -> Result<i32> {
+    let slot = memory.alloc(value.layout().size, ARRAY_BUFFER_ID)?;
+    value.write_to_memory(memory, slot)?;
+    Ok(memory.read_u32(slot)? as i32)
+}
 
-    if register.classes_schema.contains_key("Output") {
-        let mut offset_val = 0;
-        match  register.classes_schema.get("Output") {
-            None => {}
-            Some(val) => {
-                for params in val {
-                    println!("Val {:?}", params);
-                    println!("Offset val: {}", offset_val);
-                    match params.param_type.as_str() {
-                        "i32" => {
-                            let mut result = [0u8;4];
-                            memory.read(&mut store, output_offset + offset_val, &mut result)?;
-                            offset_val = offset_val + 4;
-                            let ptr = i32::from_le_bytes(result);
-                            println!("process returned pointer: {}", ptr);
-                            println!("process returned pointer: {:?}", ptr.encode());
-                            bytes_result.extend_from_slice(&ptr.encode());
-                        },
-                        "f32" => {
-                            let mut result = [0u8;4];
-                            memory.read(&mut store, output_offset + offset_val, &mut result)?;
-                            offset_val = offset_val + 4;
-                            let ptr = f32::from_le_bytes(result);
-                            println!("process returned pointer: {}", ptr);
-                            println!("process returned pointer: {:?}", ptr.encode());
-                            bytes_result.extend_from_slice(&ptr.encode());
-                        },
-                        "i64" => {
-                            let mut result = [0u8;8];
-                            memory.read(&mut store, output_offset + offset_val, &mut result)?;
-                            offset_val = offset_val + 8;
-                            let ptr = i64::from_le_bytes(result);
-                            println!("process returned pointer: {}", ptr);
-                            println!("process returned pointer: {:?}", ptr.encode());
-                            bytes_result.extend_from_slice(&ptr.encode());
-                        },
-                        "f64" => {
-                            let mut result = [0u8;8];
-                            memory.read(&mut store, output_offset + offset_val, &mut result)?;
-                            offset_val = offset_val + 8;
-                            let ptr = f64::from_le_bytes(result);
-                            println!("process returned pointer: {}", ptr);
-                            println!("process returned pointer: {:?}", ptr.encode());
-                            bytes_result.extend_from_slice(&ptr.encode());
-                        },
-                        typ if typ.starts_with("Array<") => {
-                            if let Some(inner) = typ.strip_prefix("Array<").and_then(|s| s.strip_suffix('>')) {
-                                if register.classes_schema.contains_key(inner) {
-                                    println!("Array of class {}", inner);
-                                    let mut result = [0u8; 4];
-                                    memory.read(&mut store, output_offset + offset_val, &mut result)?;
-                                    offset_val = offset_val + 4;
-                                    let custom_arr_ptr = u32::from_le_bytes(result) as usize;
-                                    // println!("process returned pointer: {}", custom_arr_ptr);
-                                    let mut array_header_bytes = [0u8; 16];
-                                    memory.read(&store, custom_arr_ptr as usize, &mut array_header_bytes)?;
-                                    // println!("array_header_bytes: {:?}", array_header_bytes);
-                                    let pass_res_data_ptr = u32::from_le_bytes(array_header_bytes[4..8].try_into().unwrap()) as usize;
-                                    let pass_res_data_cap = u32::from_le_bytes(array_header_bytes[12..16].try_into().unwrap()) as usize;
-                                    if pass_res_data_ptr == 0 {
-                                        panic!("pass_res data pointer is null (0)");
-                                    }
-
-                                    let mut crypto_values = Vec::new();
-                                    for i in 0..pass_res_data_cap {
-                                        let elem_offset = pass_res_data_ptr + (i * 4);
-                                        let mut elem_bytes = [0u8; 4];
-                                        memory.read(&store, elem_offset, &mut elem_bytes)?;
-                                        let ptr = u32::from_le_bytes(elem_bytes) as usize;
-
-                                        if ptr == 0 {
-                                            continue;
-                                        }
-                                        let mut usd_bytes = [0u8; 4];
-                                        memory.read(&store, ptr, &mut usd_bytes)?;
-                                        let value = f32::from_le_bytes(usd_bytes);
-
-                                        println!("CryptoValue[{}].usd: {}", i, value);
-                                        println!("process returned pointer: {:?}", value.encode());
-                                        crypto_values.push(value);
-                                    }
-                                    bytes_result.extend_from_slice(&crypto_values.encode());
-                                } else {
-                                    println!("Not an Array<T> type.");
-                                }
-                            }
-                        },
-                        typ if typ.eq("string") => {
-                            let mut result = [0u8;4];
-                            memory.read(&mut store, output_offset + offset_val, &mut result)?;
-                            offset_val = offset_val + 4;
-                            let ptr = u32::from_le_bytes(result) as usize;
-                            let string_out = read_utf16_string(&memory,&mut store, ptr,1024).ok().unwrap_or_default();
-                            println!("string out {:?}", string_out.clone());
-                            println!("string out {:?}", string_out.clone().encode());
-                            bytes_result.extend_from_slice(&string_out.encode());
-                        }
-                        _ => {
-                            let mut result = [0u8;4];
-                            memory.read(&mut store, output_offset + offset_val, &mut result)?;
-                            offset_val = offset_val + 4;
-                            let ptr = u32::from_le_bytes(result) as usize;
-                            println!("process returned pointer: {}", ptr);
-                        }
-                    }
-                    }
+fn wasmtime_runner(modules: &ModuleCache, script_hash: &str, wasm: &[u8], register : &SelectorRegistry, selector: &str, input: &str, limits: &ExecutionLimits) -> anyhow::Result<Vec<u8>> {
+    let function = register.functions.get(selector)
+        .ok_or_else(|| anyhow!("ERROR: selector {} not found in ABI {}", selector, register.origin))?;
+    let instance_pre = modules.instance_pre(script_hash, wasm)?;
+    let mut store = Store::new(modules.engine(), HostState::new(input));
+    // The engine ticks its epoch periodically, on every tick the limits are re-checked.
+    let limits = limits.clone();
+    store.set_epoch_deadline(1);
+    store.epoch_deadline_callback(move |_| {
+        if limits.cancel.as_ref().is_some_and(|cancel| cancel.load(Ordering::Relaxed)) {
+            return Err(Interruption::Cancelled.into());
+        }
+        if limits.deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            return Err(Interruption::TimedOut.into());
+        }
+        Ok(UpdateDeadline::Continue(1))
+    });
+    let instance = instance_pre.instantiate(&mut store)?;
+    let memory = instance.get_memory(&mut store, "memory")
+        .ok_or_else(|| anyhow!("ERROR: script does not export its memory"))?;
+    let new = instance.get_typed_func::<(i32, i32), i32>(&mut store, "__new").ok();
+    let pins = instance.get_typed_func::<i32, i32>(&mut store, "__pin").ok()
+        .zip(instance.get_typed_func::<i32, ()>(&mut store, "__unpin").ok());
+    let mut script_memory = ScriptMemory { memory, store: &mut store, new, pins, pinned: Vec::new() };
+
+    // Objects returned by `__new` can be collected as soon as the script allocates again, the
+    // staged input stays pinned until the call returns.
+    let ret_ptr = match function.params.as_slice() {
+        // `process(json: string)` receives the input as an AssemblyScript string.
+        [param] if param.param_type == "string" => {
+            let process_func = instance.get_typed_func::<i32, i32>(&mut script_memory.store, &function.name)?;
+            let input_ptr = stage_input(&mut script_memory, &AbiValue::String(input.to_string()))?;
+            process_func.call(&mut script_memory.store, input_ptr)?
+        }
+        // `process(json_ptr: usize, len: usize)` reads the raw UTF-8 input, from a buffer allocated
+        // by the script when it exports its runtime and from the start of memory otherwise.
+        _ => {
+            let process_func = instance.get_typed_func::<(i32,i32),i32>(&mut script_memory.store, &function.name)?;
+            let json_bytes = input.as_bytes();
+            let json_ptr = match script_memory.new {
+                Some(_) => stage_input(&mut script_memory, &AbiValue::ArrayBuffer(json_bytes.to_vec()))?,
+                None => {
+                    script_memory.write(0, json_bytes)?;
+                    0
                 }
-
-            }
+            };
+            process_func.call(&mut script_memory.store, (json_ptr, json_bytes.len() as i32))?
         }
-    println!("Byte res {:?}",bytes_result);
-    build_result(&bytes_result);
-    Ok(())
-}
-
+    };
+    script_memory.release()?;
 
-#[derive(Debug, Decode)]
-struct CryptoValue {
-    usd: f32,
+    let class = register.schema.class(&function.result)?;
+    let result = AbiValue::read_class(&mut script_memory, ret_ptr as u32, &class)?;
+    trace!("Result {}: {}", result.type_name(), result.to_json());
+    let mut bytes_result: Vec<u8> = Vec::new();
+    result.encode_scale(&mut bytes_result);
+    trace!("Byte res {:?}",bytes_result);
+    Ok(bytes_result)
 }
 
-#[derive(Debug, Decode)]
-struct Output {
-    greater: String,
-    custom: Vec<CryptoValue>,
-    primi_i: i32,
-    pmimi_f: f32,
-}
-fn build_result(val: &[u8]) {
-    let bytes: Vec<u8> = val.iter().map(|x| (x & 0xFF) as u8).collect();
-    match Output::decode(&mut &bytes[..]) {
-        Ok(decoded) => println!("{:#?}", decoded),
-        Err(e) => println!("Failed to decode: {}", e),
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use parity_scale_codec::Encode;
+    use serde_json::json;
+    use super::*;
+    use crate::core::testing::{orscript2, script_abi, temp_registry};
+
+    #[test]
+    fn decodes_script_outputs_from_the_abi() {
+        let (root, registry) = temp_registry("runtime-decode");
+        let (wasm, abi) = orscript2();
+        let hash = registry.insert(&wasm, &abi).unwrap();
+        let output = registry.execute(&hash, None, DEFAULT_INPUT, &ExecutionLimits::default()).unwrap();
+        let decoded = decode_output(&abi, "Output", &output).unwrap();
+        assert_eq!(decoded, json!({
+            "greater": "bitcoin",
+            "custom": [{ "usd": 104700.0 }, { "usd": 2523.13 }],
+            "primi_i": 4200,
+            "pmimi_f": 69.0,
+        }));
+        assert_eq!(read_numeric_field(&abi, &default_selector(&abi).unwrap(), &output, "primi_i").unwrap(), 4200.0);
+        fs::remove_dir_all(root).unwrap();
     }
-}
 
+    /// Takes `process(json: string)` and returns the byte length of the string it received and
+    /// the number of objects pinned during the call.
+    const STRING_INPUT: &str = r#"(module (memory (export "memory") 1)
+        (global $heap (mut i32) (i32.const 8192))
+        (global $pinned (mut i32) (i32.const 0))
+        (func (export "__new") (param $size i32) (param $id i32) (result i32)
+            (local $object i32)
+            (local.set $object (i32.add (global.get $heap) (i32.const 16)))
+            (i32.store (i32.sub (local.get $object) (i32.const 8)) (local.get $id))
+            (i32.store (i32.sub (local.get $object) (i32.const 4)) (local.get $size))
+            (global.set $heap (i32.and (i32.add (i32.add (local.get $object) (local.get $size)) (i32.const 15)) (i32.const -16)))
+            (local.get $object))
+        (func (export "__pin") (param i32) (result i32)
+            (global.set $pinned (i32.add (global.get $pinned) (i32.const 1)))
+            (local.get 0))
+        (func (export "__unpin") (param i32)
+            (global.set $pinned (i32.sub (global.get $pinned) (i32.const 1))))
+        (func (export "process") (param $json i32) (result i32)
+            (i32.store (i32.const 4096) (i32.load (i32.sub (local.get $json) (i32.const 4))))
+            (i32.store (i32.const 4100) (global.get $pinned))
+            i32.const 4096))"#;
+
+    #[test]
+    fn string_inputs_are_staged_and_pinned_for_the_call() {
+        let (root, registry) = temp_registry("runtime-string-input");
+        let wasm = STRING_INPUT.as_bytes();
+        let mut abi: Value = serde_json::from_str(&script_abi(wasm, "Output", &[("Output", &[("length", "i32"), ("pinned", "i32")])])).unwrap();
+        abi["functions"][0]["params"] = json!([{ "name": "json", "type": "string" }]);
+        abi["functions"][0]["selector"] = json!(HashAlgorithm::Sha256.selector("process", ["json"]));
+        let abi = abi.to_string();
+        let hash = registry.insert(wasm, &abi).unwrap();
+        let output = registry.execute(&hash, None, "{\"a\":1}", &ExecutionLimits::default()).unwrap();
+        // The argument slot and the string are pinned.
+        assert_eq!(decode_output(&abi, "Output", &output).unwrap(), json!({ "length": 14, "pinned": 2 }));
+        fs::remove_dir_all(root).unwrap();
+    }
 
+    #[test]
+    fn decodes_any_abi_type() {
+        let (_, abi) = orscript2();
+        let values = vec![1.5f32.to_bits(), 2.25f32.to_bits()].encode();
+        assert_eq!(decode_output(&abi, "Array<CryptoValue>", &values).unwrap(), json!([{ "usd": 1.5 }, { "usd": 2.25 }]));
+        assert_eq!(decode_output(&abi, "string", &"eth".encode()).unwrap(), json!("eth"));
+    }
 
+    #[test]
+    fn rejects_outputs_that_do_not_match_the_type() {
+        let (_, abi) = orscript2();
+        let value = 1.5f32.to_bits().encode();
+        assert_eq!(decode_output(&abi, "CryptoValue", &value).unwrap(), json!({ "usd": 1.5 }));
+        assert!(decode_output(&abi, "CryptoValue", &value[..3]).is_err());
+        assert!(decode_output(&abi, "CryptoValue", &[value.as_slice(), &[0]].concat()).is_err());
+        assert!(decode_output(&abi, "Missing", &value).is_err());
+    }
+}
@@ -0,0 +1,356 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{mpsc, Notify, Semaphore};
+use crate::core::registry::ScriptRegistry;
+use crate::core::runtime::{ExecutionLimits, Interruption};
+
+#[derive(Debug, Clone)]
+pub struct SchedulerOptions {
+    /// Jobs waiting for a worker, submissions beyond this are rejected.
+    pub queue_capacity: usize,
+    /// Executions running at the same time.
+    pub workers: usize,
+    /// Wall-clock limit of a job that does not set its own.
+    pub default_timeout: Duration,
+    /// Longest wall-clock limit a job may ask for, longer ones are clamped to it.
+    pub max_timeout: Duration,
+    /// Limit on fetching a script missing from the registry before its job fails.
+    pub resolve_timeout: Duration,
+    /// Finished jobs kept around for status queries.
+    pub retained_jobs: usize,
+}
+
+impl Default for SchedulerOptions {
+    fn default() -> Self {
+        SchedulerOptions {
+            queue_capacity: 256,
+            workers: 4,
+            default_timeout: Duration::from_secs(5),
+            max_timeout: Duration::from_secs(60),
+            resolve_timeout: Duration::from_secs(30),
+            retained_jobs: 4096,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct JobRequest {
+    pub script_hash: String,
+    #[serde(default)]
+    pub selector: Option<String>,
+    pub input: String,
+    #[serde(default)]
+    pub timeout_ms: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Succeeded { output: String },
+    Failed { error: String },
+    TimedOut,
+    Cancelled,
+}
+
+impl JobStatus {
+    pub fn is_finished(&self) -> bool {
+        !matches!(self, JobStatus::Queued | JobStatus::Running)
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct SchedulerStats {
+    pub queued: usize,
+    pub running: usize,
+    pub finished: usize,
+}
+
+struct JobEntry {
+    status: JobStatus,
+    cancel: Arc<AtomicBool>,
+}
+
+#[derive(Default)]
+struct JobTable {
+    jobs: HashMap<u64, JobEntry>,
+    finished: VecDeque<u64>,
+    next_id: u64,
+}
+
+struct QueuedJob {
+    id: u64,
+    request: JobRequest,
+    cancel: Arc<AtomicBool>,
+}
+
+/// Bounded queue of script executions served by a fixed number of workers on the tokio runtime.
+/// The dispatcher stops once every handle is dropped.
+#[derive(Clone)]
+pub struct Scheduler {
+    sender: mpsc::Sender<QueuedJob>,
+    jobs: Arc<Jobs>,
+}
+
+/// Status of a job whose execution task ended, a panicking execution fails its job instead of
+/// leaving it running forever.
+fn finished_status(execution: Result<JobStatus, tokio::task::JoinError>) -> JobStatus {
+    execution.unwrap_or_else(|e| JobStatus::Failed { error: format!("ERROR: execution panicked: {}", e) })
+}
+
+/// Job states shared by the handles and the dispatcher, which holds no sender of its own.
+struct Jobs {
+    table: Mutex<JobTable>,
+    finished: Notify,
+    retained_jobs: usize,
+}
+
+impl Scheduler {
+    /// Starts the dispatcher, must be called from within a tokio runtime.
+    pub fn start(registry: Arc<ScriptRegistry>, options: SchedulerOptions) -> Self {
+        let (sender, mut receiver) = mpsc::channel::<QueuedJob>(options.queue_capacity.max(1));
+        let jobs = Arc::new(Jobs {
+            table: Mutex::new(JobTable::default()),
+            finished: Notify::new(),
+            retained_jobs: options.retained_jobs,
+        });
+        let workers = Arc::new(Semaphore::new(options.workers.max(1)));
+        let dispatcher = jobs.clone();
+        tokio::spawn(async move {
+            // A worker is taken before the next job is dequeued, so queued jobs wait in the queue
+            // and count against its capacity.
+            loop {
+                let permit = workers.clone().acquire_owned().await.expect("worker semaphore closed");
+                let Some(job) = receiver.recv().await else { break };
+                if job.cancel.load(Ordering::Relaxed) {
+                    continue;
+                }
+                dispatcher.set_status(job.id, JobStatus::Running);
+                let registry = registry.clone();
+                let jobs = dispatcher.clone();
+                let timeout = job.request.timeout_ms.map(Duration::from_millis)
+                    .unwrap_or(options.default_timeout)
+                    .min(options.max_timeout);
+                let resolve_timeout = options.resolve_timeout;
+                tokio::spawn(async move {
+                    // Scripts missing locally are fetched from the registry's source first, under
+                    // their own limit, the job timeout only covers the execution itself.
+                    let resolved = tokio::time::timeout(resolve_timeout, registry.resolve(&job.request.script_hash)).await
+                        .unwrap_or_else(|_| Err(anyhow!("ERROR: fetching script {} timed out after {:?}", job.request.script_hash, resolve_timeout)));
+                    if let Err(e) = resolved {
+                        jobs.set_status(job.id, JobStatus::Failed { error: e.to_string() });
+                        return;
+                    }
+                    let id = job.id;
+                    let execution = tokio::task::spawn_blocking(move || {
+                        let limits = ExecutionLimits {
+                            deadline: Instant::now().checked_add(timeout),
+                            cancel: Some(job.cancel.clone()),
+                        };
+                        let request = &job.request;
+                        match registry.execute(&request.script_hash, request.selector.as_deref(), &request.input, &limits) {
+                            Ok(output) => JobStatus::Succeeded { output: format!("0x{}", hex::encode(output)) },
+                            Err(e) => match e.downcast_ref::<Interruption>() {
+                                Some(Interruption::TimedOut) => JobStatus::TimedOut,
+                                Some(Interruption::Cancelled) => JobStatus::Cancelled,
+                                None => JobStatus::Failed { error: e.to_string() },
+                            },
+                        }
+                    });
+                    jobs.set_status(id, finished_status(execution.await));
+                    drop(permit);
+                });
+            }
+        });
+        Scheduler { sender, jobs }
+    }
+
+    /// Queues a job, failing right away instead of waiting when the queue is full.
+    pub fn submit(&self, request: JobRequest) -> Result<u64> {
+        let (id, job) = self.register(request);
+        if let Err(e) = self.sender.try_send(job) {
+            self.jobs.table.lock().unwrap().jobs.remove(&id);
+            return Err(match e {
+                mpsc::error::TrySendError::Full(_) => anyhow!("ERROR: job queue is full"),
+                mpsc::error::TrySendError::Closed(_) => anyhow!("ERROR: scheduler is stopped"),
+            });
+        }
+        Ok(id)
+    }
+
+    /// Queues a job, waiting for room in the queue when it is full.
+    pub async fn submit_wait(&self, request: JobRequest) -> Result<u64> {
+        let (id, job) = self.register(request);
+        if self.sender.send(job).await.is_err() {
+            self.jobs.table.lock().unwrap().jobs.remove(&id);
+            return Err(anyhow!("ERROR: scheduler is stopped"));
+        }
+        Ok(id)
+    }
+
+    fn register(&self, request: JobRequest) -> (u64, QueuedJob) {
+        let cancel = Arc::new(AtomicBool::new(false));
+        let mut table = self.jobs.table.lock().unwrap();
+        let id = table.next_id;
+        table.next_id += 1;
+        table.jobs.insert(id, JobEntry { status: JobStatus::Queued, cancel: cancel.clone() });
+        (id, QueuedJob { id, request, cancel })
+    }
+
+    pub fn status(&self, id: u64) -> Option<JobStatus> {
+        self.jobs.table.lock().unwrap().jobs.get(&id).map(|job| job.status.clone())
+    }
+
+    /// Waits until the job finished and returns its final status.
+    pub async fn wait(&self, id: u64) -> Option<JobStatus> {
+        loop {
+            let notified = self.jobs.finished.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+            let status = self.status(id)?;
+            if status.is_finished() {
+                return Some(status);
+            }
+            notified.await;
+        }
+    }
+
+    /// Cancels a queued or running job, returns false if the job already finished.
+    pub fn cancel(&self, id: u64) -> Result<bool> {
+        let table = self.jobs.table.lock().unwrap();
+        let job = table.jobs.get(&id).ok_or_else(|| anyhow!("ERROR: unknown job {}", id))?;
+        if job.status.is_finished() {
+            return Ok(false);
+        }
+        job.cancel.store(true, Ordering::Relaxed);
+        let queued = job.status == JobStatus::Queued;
+        drop(table);
+        if queued {
+            self.jobs.set_status(id, JobStatus::Cancelled);
+        }
+        Ok(true)
+    }
+
+    pub fn stats(&self) -> SchedulerStats {
+        let table = self.jobs.table.lock().unwrap();
+        let mut stats = SchedulerStats::default();
+        for job in table.jobs.values() {
+            match job.status {
+                JobStatus::Queued => stats.queued += 1,
+                JobStatus::Running => stats.running += 1,
+                _ => stats.finished += 1,
+            }
+        }
+        stats
+    }
+}
+
+impl Jobs {
+    fn set_status(&self, id: u64, status: JobStatus) {
+        let mut table = self.table.lock().unwrap();
+        let finished = status.is_finished();
+        match table.jobs.get_mut(&id) {
+            // A cancelled job keeps its status even if the worker reports back later.
+            Some(job) if job.status.is_finished() => return,
+            Some(job) => job.status = status,
+            None => return,
+        }
+        if finished {
+            table.finished.push_back(id);
+            while table.finished.len() > self.retained_jobs {
+                if let Some(evicted) = table.finished.pop_front() {
+                    table.jobs.remove(&evicted);
+                }
+            }
+            drop(table);
+            self.finished.notify_waiters();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::testing::{insert_wat, temp_registry};
+
+    /// Exports no memory, which the runtime rejects.
+    const NO_MEMORY: &str = r#"(module (func (export "process") (param i32 i32) (result i32) i32.const 0))"#;
+    const SPINS: &str = r#"(module (memory (export "memory") 1)
+        (func (export "process") (param i32 i32) (result i32) (loop (br 0)) i32.const 0))"#;
+
+    fn request(script_hash: &str, timeout_ms: u64) -> JobRequest {
+        JobRequest { script_hash: script_hash.to_string(), selector: None, input: "{}".to_string(), timeout_ms: Some(timeout_ms) }
+    }
+
+    #[tokio::test]
+    async fn failing_execution_fails_its_job() {
+        let (root, registry) = temp_registry("scheduler-fail");
+        let hash = insert_wat(&registry, NO_MEMORY);
+        let scheduler = Scheduler::start(registry, SchedulerOptions::default());
+        let id = scheduler.submit(request(&hash, 1000)).unwrap();
+        let status = tokio::time::timeout(Duration::from_secs(10), scheduler.wait(id)).await.unwrap().unwrap();
+        assert!(matches!(status, JobStatus::Failed { ref error } if error.contains("does not export its memory")), "{:?}", status);
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn panicking_execution_fails_its_job() {
+        let execution = tokio::task::spawn_blocking(|| -> JobStatus { panic!("boom") }).await;
+        let status = finished_status(execution);
+        assert!(matches!(status, JobStatus::Failed { ref error } if error.contains("panicked")), "{:?}", status);
+    }
+
+    #[tokio::test]
+    async fn job_timeouts_are_clamped() {
+        let (root, registry) = temp_registry("scheduler-clamp");
+        let hash = insert_wat(&registry, SPINS);
+        let options = SchedulerOptions { max_timeout: Duration::from_millis(100), ..Default::default() };
+        let scheduler = Scheduler::start(registry, options);
+        let id = scheduler.submit(request(&hash, u64::MAX)).unwrap();
+        let status = tokio::time::timeout(Duration::from_secs(10), scheduler.wait(id)).await.unwrap();
+        assert_eq!(status, Some(JobStatus::TimedOut));
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn jobs_wait_in_the_queue_for_a_worker() {
+        let (root, registry) = temp_registry("scheduler-queue");
+        let hash = insert_wat(&registry, SPINS);
+        let options = SchedulerOptions { queue_capacity: 1, workers: 1, ..Default::default() };
+        let scheduler = Scheduler::start(registry, options);
+        let running = scheduler.submit(request(&hash, 300)).unwrap();
+        while scheduler.status(running) != Some(JobStatus::Running) {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        let queued = scheduler.submit(request(&hash, 300)).unwrap();
+        assert!(scheduler.submit(request(&hash, 300)).is_err());
+        assert_eq!(scheduler.status(queued), Some(JobStatus::Queued));
+        assert_eq!(scheduler.wait(running).await, Some(JobStatus::TimedOut));
+        assert!(scheduler.cancel(queued).unwrap());
+        assert_eq!(scheduler.wait(queued).await, Some(JobStatus::Cancelled));
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn dispatcher_stops_with_the_last_handle() {
+        let (root, registry) = temp_registry("scheduler-stop");
+        let hash = insert_wat(&registry, NO_MEMORY);
+        let scheduler = Scheduler::start(registry.clone(), SchedulerOptions::default());
+        let id = scheduler.clone().submit(request(&hash, 1000)).unwrap();
+        scheduler.wait(id).await.unwrap();
+        assert!(Arc::strong_count(&registry) > 1);
+        drop(scheduler);
+        // The dispatcher holds the only other reference to the registry.
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while Arc::strong_count(&registry) > 1 {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        }).await.unwrap();
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
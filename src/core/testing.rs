@@ -0,0 +1,127 @@
+//! Scripts and registries shared by the unit tests.
+use std::fs;
+use std::net::{SocketAddr, TcpListener};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use anyhow::{anyhow, Result};
+use serde_json::{json, Value};
+use crate::core::abi_value::{ARRAY_BUFFER_ID, STRING_ID};
+use crate::core::hashing::HashAlgorithm;
+use crate::core::module_cache::EngineOptions;
+use crate::core::registry::ScriptRegistry;
+use crate::traits::traits::AbiMemory;
+
+/// Empty directory under the system temp dir, unique to `name` and this process.
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("orascript-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Localhost address with a port that was free when asked for.
+pub fn free_addr() -> SocketAddr {
+    TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap()
+}
+
+pub fn temp_registry(name: &str) -> (PathBuf, Arc<ScriptRegistry>) {
+    let root = temp_dir(name);
+    let registry = ScriptRegistry::open(&root, &EngineOptions::default()).unwrap();
+    (root, Arc::new(registry))
+}
+
+/// The `orscript2` example script and its ABI.
+pub fn orscript2() -> (Vec<u8>, String) {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("orascript");
+    (
+        fs::read(root.join("assembly/orscript2.wasm")).unwrap(),
+        fs::read_to_string(root.join("output/orscript2ABI.json")).unwrap(),
+    )
+}
+
+/// ABI of a hand written script exporting `process(json_ptr: usize, len: usize)`, which returns
+/// `result`, one of `classes` given as `(name, [(field, type)])`.
+pub fn script_abi(wasm: &[u8], result: &str, classes: &[(&str, &[(&str, &str)])]) -> String {
+    let alg = HashAlgorithm::Sha256;
+    let params = [("json_ptr", "usize"), ("len", "usize")];
+    let classes: Vec<Value> = classes.iter().map(|(name, fields)| json!({
+        "class_selector": alg.selector(name, []),
+        "name": name,
+        "fields": fields.iter().map(|(name, ty)| json!({ "name": name, "type": ty })).collect::<Vec<_>>(),
+        "methods": [],
+    })).collect();
+    json!({
+        "headers": { "name": null, "header": alg.header_hash(wasm) },
+        "functions": [{
+            "name": "process",
+            "params": params.iter().map(|(name, ty)| json!({ "name": name, "type": ty })).collect::<Vec<_>>(),
+            "result": result,
+            "selector": alg.selector("process", params.iter().map(|(name, _)| *name)),
+        }],
+        "classes": classes,
+        "variables": [],
+    }).to_string()
+}
+
+/// Stores the WAT script `wat` returning an `Output { value: i32 }` and returns its hash.
+pub fn insert_wat(registry: &ScriptRegistry, wat: &str) -> String {
+    let wasm = wat.as_bytes();
+    registry.insert(wasm, &script_abi(wasm, "Output", &[("Output", &[("value", "i32")])])).unwrap()
+}
+
+/// Linear memory standing in for a script's, objects are bump allocated behind an
+/// AssemblyScript object header holding their runtime id and size. Runtime ids of types other
+/// than the built-in ones are handed out in the order they are first asked for.
+#[derive(Default)]
+pub struct TestMemory {
+    pub bytes: Vec<u8>,
+    pub ids: Vec<String>,
+}
+
+impl TestMemory {
+    pub fn put_u32(&mut self, ptr: u32, value: u32) {
+        self.write(ptr, &value.to_le_bytes()).unwrap();
+    }
+}
+
+impl AbiMemory for TestMemory {
+    fn read(&mut self, ptr: u32, buf: &mut [u8]) -> Result<()> {
+        let start = ptr as usize;
+        let bytes = self.bytes.get(start..start + buf.len()).ok_or_else(|| anyhow!("ERROR: out of bounds memory access"))?;
+        buf.copy_from_slice(bytes);
+        Ok(())
+    }
+
+    fn write(&mut self, ptr: u32, bytes: &[u8]) -> Result<()> {
+        let start = ptr as usize;
+        self.bytes.get_mut(start..start + bytes.len()).ok_or_else(|| anyhow!("ERROR: out of bounds memory access"))?
+            .copy_from_slice(bytes);
+        Ok(())
+    }
+
+    fn alloc(&mut self, size: u32, rt_id: u32) -> Result<u32> {
+        let object = (self.bytes.len() as u32 + 20).next_multiple_of(16);
+        self.bytes.resize((object + size).next_multiple_of(16) as usize, 0);
+        self.put_u32(object - 8, rt_id);
+        self.put_u32(object - 4, size);
+        Ok(object)
+    }
+
+    fn data_size(&mut self) -> usize {
+        self.bytes.len()
+    }
+
+    fn runtime_id(&mut self, type_name: &str) -> Result<u32> {
+        Ok(match type_name {
+            "ArrayBuffer" => ARRAY_BUFFER_ID,
+            "string" => STRING_ID,
+            name => {
+                let index = self.ids.iter().position(|id| id == name).unwrap_or_else(|| {
+                    self.ids.push(name.to_string());
+                    self.ids.len() - 1
+                });
+                index as u32 + 3
+            }
+        })
+    }
+}
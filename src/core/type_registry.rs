@@ -0,0 +1,302 @@
+use std::collections::{BTreeMap, HashMap};
+use anyhow::Result;
+use scale_info::form::PortableForm;
+use scale_info::{Field, Path, PortableRegistry, PortableRegistryBuilder, Type, TypeDefComposite, TypeDefPrimitive, TypeDefSequence, TypeDefTuple, TypeDefVariant, TypeParameter, Variant};
+use serde::{Deserialize, Serialize};
+use crate::core::abi_value::{AbiSchema, AbiTypeDesc, ArrayKind};
+use crate::core::runtime::abi_schema;
+
+/// Path prefix of every type registered for an ABI.
+const PATH_PREFIX: &str = "orascript";
+
+/// `scale-info` description of the classes of an ABI, lets Substrate tooling decode script
+/// outputs without knowing their layout up front.
+///
+/// SCALE has no floats, `f32`/`f64` are registered as single field composites
+/// `orascript::f32`/`orascript::f64` wrapping the `u32`/`u64` with the same little endian bytes.
+/// Every sequence (`Array<T>`, `StaticArray<T>`, typed arrays, `ArrayBuffer`) is a `Vec<T>` and
+/// `T | null` is an `Option<T>`, `Map<K, V>` a `BTreeMap<K, V>` and `Set<T>` a `BTreeSet<T>`.
+/// Fixed-point numbers are registered under their `sp_arithmetic::fixed_point` paths.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AbiTypeRegistry {
+    pub registry: PortableRegistry,
+    /// Type id of every ABI class.
+    pub classes: BTreeMap<String, u32>,
+}
+
+impl AbiTypeRegistry {
+    pub fn from_abi(abi_json: &str) -> Result<Self> {
+        Self::from_schema(&abi_schema(abi_json)?)
+    }
+
+    pub fn from_schema(schema: &AbiSchema) -> Result<Self> {
+        let mut builder = TypeBuilder { registry: PortableRegistryBuilder::new(), ids: HashMap::new() };
+        let mut classes = BTreeMap::new();
+        for name in schema.class_names() {
+            let id = builder.register(&schema.resolve(name)?);
+            classes.insert(name.to_string(), id);
+        }
+        Ok(AbiTypeRegistry { registry: builder.registry.finish(), classes })
+    }
+}
+
+/// Returns the ABI with its type registry embedded under `types`.
+pub fn embed_type_registry(abi_json: &str) -> Result<String> {
+    let types = AbiTypeRegistry::from_abi(abi_json)?;
+    let mut abi: serde_json::Value = serde_json::from_str(abi_json)?;
+    abi["types"] = serde_json::to_value(types)?;
+    Ok(serde_json::to_string_pretty(&abi)?)
+}
+
+struct TypeBuilder {
+    registry: PortableRegistryBuilder,
+    /// Already registered types by ABI type name.
+    ids: HashMap<String, u32>,
+}
+
+impl TypeBuilder {
+    fn register(&mut self, ty: &AbiTypeDesc) -> u32 {
+        // Every sequence of the same element is the same `Vec<T>`.
+        let key = match ty {
+            AbiTypeDesc::Array(_, elem) => format!("Vec<{}>", elem),
+            AbiTypeDesc::ArrayBuffer => format!("Vec<{}>", AbiTypeDesc::U8),
+            ty => ty.to_string(),
+        };
+        if let Some(id) = self.ids.get(&key) {
+            return *id;
+        }
+        let registered = match ty {
+            AbiTypeDesc::Bool => primitive(TypeDefPrimitive::Bool),
+            AbiTypeDesc::I8 => primitive(TypeDefPrimitive::I8),
+            AbiTypeDesc::U8 => primitive(TypeDefPrimitive::U8),
+            AbiTypeDesc::I16 => primitive(TypeDefPrimitive::I16),
+            AbiTypeDesc::U16 => primitive(TypeDefPrimitive::U16),
+            AbiTypeDesc::I32 => primitive(TypeDefPrimitive::I32),
+            AbiTypeDesc::U32 => primitive(TypeDefPrimitive::U32),
+            AbiTypeDesc::I64 => primitive(TypeDefPrimitive::I64),
+            AbiTypeDesc::U64 => primitive(TypeDefPrimitive::U64),
+            AbiTypeDesc::U128 => primitive(TypeDefPrimitive::U128),
+            AbiTypeDesc::I128 => primitive(TypeDefPrimitive::I128),
+            AbiTypeDesc::U256 => primitive(TypeDefPrimitive::U256),
+            AbiTypeDesc::FixedU64 => self.fixed_point("FixedU64", &AbiTypeDesc::U64),
+            AbiTypeDesc::FixedI64 => self.fixed_point("FixedI64", &AbiTypeDesc::I64),
+            AbiTypeDesc::FixedU128 => self.fixed_point("FixedU128", &AbiTypeDesc::U128),
+            AbiTypeDesc::FixedI128 => self.fixed_point("FixedI128", &AbiTypeDesc::I128),
+            AbiTypeDesc::F32 => self.float("f32", &AbiTypeDesc::U32),
+            AbiTypeDesc::F64 => self.float("f64", &AbiTypeDesc::U64),
+            AbiTypeDesc::String => primitive(TypeDefPrimitive::Str),
+            AbiTypeDesc::ArrayBuffer => self.sequence(&AbiTypeDesc::U8),
+            AbiTypeDesc::Array(_, elem) => self.sequence(elem),
+            AbiTypeDesc::Nullable(inner) => {
+                let inner_id = self.register(inner);
+                let some = Field::new(None, inner_id.into(), Some(inner.to_string()), Vec::new());
+                let variants = vec![
+                    Variant::new("None".to_string(), Vec::new(), 0, Vec::new()),
+                    Variant::new("Some".to_string(), vec![some], 1, Vec::new()),
+                ];
+                let params = vec![TypeParameter::new_portable("T".to_string(), Some(inner_id.into()))];
+                Type::new(Path::from_segments_unchecked(["Option".to_string()]), params, TypeDefVariant::new(variants), Vec::new())
+            }
+            AbiTypeDesc::Map(key, value) => {
+                let params = [("K", self.register(key)), ("V", self.register(value))];
+                let entry = self.tuple(&[key, value]);
+                let entries = self.registry.register_type(sequence_of(entry));
+                self.collection("BTreeMap", &params, entries)
+            }
+            AbiTypeDesc::Set(elem) => {
+                let params = [("T", self.register(elem))];
+                let items = self.sequence_id(elem);
+                self.collection("BTreeSet", &params, items)
+            }
+            AbiTypeDesc::Class(class) => {
+                let fields: Vec<Field<PortableForm>> = class.fields.iter().map(|(name, ty)| {
+                    Field::new(Some(name.clone()), self.register(ty).into(), Some(ty.to_string()), Vec::new())
+                }).collect();
+                Type::new(path(&class.name), Vec::new(), TypeDefComposite::new(fields), Vec::new())
+            }
+        };
+        let id = self.registry.register_type(registered);
+        self.ids.insert(key, id);
+        id
+    }
+
+    fn float(&mut self, name: &str, bits: &AbiTypeDesc) -> Type<PortableForm> {
+        let field = Field::new(None, self.register(bits).into(), None, Vec::new());
+        Type::new(path(name), Vec::new(), TypeDefComposite::new([field]), Vec::new())
+    }
+
+    fn fixed_point(&mut self, name: &str, inner: &AbiTypeDesc) -> Type<PortableForm> {
+        let field = Field::new(None, self.register(inner).into(), Some(inner.to_string()), Vec::new());
+        let path = Path::from_segments_unchecked(["sp_arithmetic", "fixed_point", name].map(str::to_string));
+        Type::new(path, Vec::new(), TypeDefComposite::new([field]), Vec::new())
+    }
+
+    fn sequence(&mut self, elem: &AbiTypeDesc) -> Type<PortableForm> {
+        sequence_of(self.register(elem))
+    }
+
+    fn sequence_id(&mut self, elem: &AbiTypeDesc) -> u32 {
+        self.register(&AbiTypeDesc::Array(ArrayKind::Array, Box::new(elem.clone())))
+    }
+
+    fn tuple(&mut self, fields: &[&AbiTypeDesc]) -> u32 {
+        let key = format!("({})", fields.iter().map(ToString::to_string).collect::<Vec<_>>().join(", "));
+        if let Some(id) = self.ids.get(&key) {
+            return *id;
+        }
+        let fields: Vec<_> = fields.iter().map(|field| self.register(field).into()).collect();
+        let id = self.registry.register_type(Type::new(Path::default(), Vec::new(), TypeDefTuple::new_portable(fields), Vec::new()));
+        self.ids.insert(key, id);
+        id
+    }
+
+    /// `BTreeMap`/`BTreeSet` as described by `scale-info`, a composite wrapping the sequence of
+    /// its entries.
+    fn collection(&mut self, name: &str, params: &[(&str, u32)], entries: u32) -> Type<PortableForm> {
+        let params = params.iter().map(|(name, id)| TypeParameter::new_portable(name.to_string(), Some((*id).into()))).collect::<Vec<_>>();
+        Type::new(Path::from_segments_unchecked([name.to_string()]), params, TypeDefComposite::new([Field::new(None, entries.into(), None, Vec::new())]), Vec::new())
+    }
+}
+
+fn sequence_of(elem: u32) -> Type<PortableForm> {
+    Type::new(Path::default(), Vec::new(), TypeDefSequence::new(elem.into()), Vec::new())
+}
+
+fn primitive(primitive: TypeDefPrimitive) -> Type<PortableForm> {
+    Type::new(Path::default(), Vec::new(), primitive, Vec::new())
+}
+
+fn path(name: &str) -> Path<PortableForm> {
+    Path::from_segments_unchecked([PATH_PREFIX.to_string(), name.to_string()])
+}
+
+#[cfg(test)]
+mod tests {
+    use parity_scale_codec::{Compact, Decode, Encode};
+    use scale_info::{TypeDef, TypeDefPrimitive};
+    use serde_json::{json, Value};
+    use super::*;
+    use crate::core::runtime::decode_output;
+    use crate::core::testing::{orscript2, script_abi};
+
+    /// Decodes `bytes` as the registry type `id` the way generic Substrate tooling would, with
+    /// nothing but the registry to go by.
+    fn decode(registry: &PortableRegistry, id: u32, input: &mut &[u8]) -> Value {
+        let ty = registry.resolve(id).unwrap();
+        match &ty.type_def {
+            TypeDef::Composite(composite) if composite.fields.len() == 1 && composite.fields[0].name.is_none() => {
+                decode(registry, composite.fields[0].ty.id, input)
+            }
+            TypeDef::Composite(composite) => Value::Object(composite.fields.iter()
+                .map(|field| (field.name.clone().unwrap(), decode(registry, field.ty.id, input)))
+                .collect()),
+            TypeDef::Sequence(sequence) => {
+                let len = Compact::<u32>::decode(input).unwrap().0;
+                Value::Array((0..len).map(|_| decode(registry, sequence.type_param.id, input)).collect())
+            }
+            TypeDef::Tuple(tuple) => Value::Array(tuple.fields.iter().map(|field| decode(registry, field.id, input)).collect()),
+            TypeDef::Variant(variant) => {
+                let index = u8::decode(input).unwrap();
+                let variant = variant.variants.iter().find(|variant| variant.index == index).unwrap();
+                match variant.fields.first() {
+                    Some(field) => decode(registry, field.ty.id, input),
+                    None => Value::Null,
+                }
+            }
+            TypeDef::Primitive(primitive) => match primitive {
+                TypeDefPrimitive::Bool => json!(bool::decode(input).unwrap()),
+                TypeDefPrimitive::Str => json!(String::decode(input).unwrap()),
+                TypeDefPrimitive::U8 => json!(u8::decode(input).unwrap()),
+                TypeDefPrimitive::I32 => json!(i32::decode(input).unwrap()),
+                TypeDefPrimitive::U32 => json!(u32::decode(input).unwrap()),
+                TypeDefPrimitive::U64 => json!(u64::decode(input).unwrap()),
+                TypeDefPrimitive::U128 => json!(u128::decode(input).unwrap().to_string()),
+                TypeDefPrimitive::U256 => json!(hex::encode(<[u8; 32]>::decode(input).unwrap())),
+                other => panic!("unexpected primitive {:?}", other),
+            },
+            other => panic!("unexpected type {:?}", other),
+        }
+    }
+
+    fn class_name(registry: &AbiTypeRegistry, name: &str) -> Vec<String> {
+        registry.registry.resolve(registry.classes[name]).unwrap().path.segments.clone()
+    }
+
+    #[test]
+    fn registry_decodes_orscript2_outputs() {
+        let (_, abi) = orscript2();
+        let types = AbiTypeRegistry::from_abi(&abi).unwrap();
+        assert_eq!(types.classes.keys().collect::<Vec<_>>(), ["CryptoValue", "Input", "Output"]);
+        assert_eq!(class_name(&types, "Output"), ["orascript", "Output"]);
+        let output = ("bitcoin".to_string(), vec![104700f32.to_bits(), 2523.13f32.to_bits()], 4200i32, 69f32.to_bits()).encode();
+        let mut input = &output[..];
+        let decoded = decode(&types.registry, types.classes["Output"], &mut input);
+        assert!(input.is_empty());
+        assert_eq!(decoded, json!({
+            "greater": "bitcoin",
+            "custom": [{ "usd": 104700f32.to_bits() }, { "usd": 2523.13f32.to_bits() }],
+            "primi_i": 4200,
+            "pmimi_f": 69f32.to_bits(),
+        }));
+        assert_eq!(decode_output(&abi, "Output", &output).unwrap(), json!({
+            "greater": "bitcoin",
+            "custom": [{ "usd": 104700.0 }, { "usd": 2523.13 }],
+            "primi_i": 4200,
+            "pmimi_f": 69.0,
+        }));
+    }
+
+    #[test]
+    fn registry_describes_collections_and_numbers() {
+        let abi = script_abi(b"", "Prices", &[("Prices", &[
+            ("symbols", "Set<string>"),
+            ("prices", "Map<string, FixedU128>"),
+            ("source", "string | null"),
+            ("raw", "ArrayBuffer"),
+            ("supply", "u256"),
+            ("flags", "StaticArray<bool>"),
+        ])]);
+        let types = AbiTypeRegistry::from_abi(&abi).unwrap();
+        let mut supply = [0u8; 32];
+        supply[0] = 7;
+        let output = (
+            vec!["btc".to_string(), "eth".to_string()],
+            vec![("btc".to_string(), 104_700_250_000_000_000_000_000u128)],
+            Some("coingecko".to_string()),
+            vec![1u8, 2],
+            supply,
+            vec![true, false],
+        ).encode();
+        let mut input = &output[..];
+        let decoded = decode(&types.registry, types.classes["Prices"], &mut input);
+        assert!(input.is_empty());
+        assert_eq!(decoded, json!({
+            "symbols": ["btc", "eth"],
+            "prices": [["btc", "104700250000000000000000"]],
+            "source": "coingecko",
+            "raw": [1, 2],
+            "supply": hex::encode(supply),
+            "flags": [true, false],
+        }));
+        assert_eq!(decode_output(&abi, "Prices", &output).unwrap(), json!({
+            "symbols": ["btc", "eth"],
+            "prices": { "btc": "104700.25" },
+            "source": "coingecko",
+            "raw": "0x0102",
+            "supply": "7",
+            "flags": [true, false],
+        }));
+        let fixed = types.registry.types.iter().find(|ty| ty.ty.path.segments.last().is_some_and(|name| name == "FixedU128")).unwrap();
+        assert_eq!(fixed.ty.path.segments, ["sp_arithmetic", "fixed_point", "FixedU128"]);
+    }
+
+    #[test]
+    fn embedded_registry_keeps_the_abi() {
+        let (_, abi) = orscript2();
+        let embedded: Value = serde_json::from_str(&embed_type_registry(&abi).unwrap()).unwrap();
+        let original: Value = serde_json::from_str(&abi).unwrap();
+        assert_eq!(embedded["functions"], original["functions"]);
+        let types: AbiTypeRegistry = serde_json::from_value(embedded["types"].clone()).unwrap();
+        assert_eq!(types.classes, AbiTypeRegistry::from_abi(&abi).unwrap().classes);
+    }
+}
@@ -2,6 +2,9 @@ mod core;
 mod traits;
 mod utils;
 mod server;
+mod oracle;
+mod chain;
+mod p2p;
 
 use std::collections::HashMap;
 use std::convert::Infallible;
@@ -14,14 +17,52 @@ use sha2::{Sha256, Digest};
 use crate::core::abi_parser::abi_parser;
 use crate::core::runtime::abi_reader;
 use crate::traits::traits::ABIType;
+use crate::utils::cli::{Cli, Command, RegistryAction};
+use crate::core::registry::ScriptRegistry;
+use crate::core::module_cache::EngineOptions;
+use crate::core::bench::{run_bench, BenchOptions};
+use crate::core::scheduler::{JobStatus, Scheduler, SchedulerOptions};
+use std::sync::Arc;
+use std::time::Duration;
+use crate::core::runtime::{decode_output, describe_abi, set_trace, ExecutionLimits, DEFAULT_INPUT};
+use crate::oracle::aggregator::{AggregatedReport, AggregationMode, Aggregator, SignedReport};
+use crate::oracle::keystore::{decode_hex, NodeKey};
+use crate::oracle::commit_reveal::{Commitment, CommitRevealRound, Reveal};
+use crate::oracle::simulator::{run_simulation, SimulationConfig};
+use crate::p2p::gossip::{start_gossip, GossipMessage, GossipOptions};
+use crate::oracle::reputation::{MisbehaviorEvidence, Reputation};
+use crate::oracle::extrinsic::{fixed_hex, CallConfig, SigningParams, SubmitResult};
+use crate::oracle::merkle::{MerkleProof, ResultBatch};
+use crate::oracle::feeds::{load_feeds, start_feeds};
+use crate::core::type_registry::{embed_type_registry, AbiTypeRegistry};
+use crate::chain::client::RpcChainClient;
+use crate::chain::listener::{default_requests_key, start_chain_listener, ListenerOptions};
+use crate::chain::mock::{serve_mock_chain, MockChain, MockRequest};
+use crate::chain::scripts::{default_scripts_prefix, ChainScriptSource, MapHasher, OnChainScript};
+use parity_scale_codec::Encode;
+use clap::Parser;
+use std::fs;
+use std::path::PathBuf;
 
 
 #[tokio::main]
 async fn main() {
     // abi_parser().expect("ERROR: error at abi_parser.rs file");
 
-    abi_reader("./orascript/output/orscript2ABI.json","./orascript/assembly/orscript2.wasm")
-            .expect("ERROR: Error occur at abi_reader() in main.rs");
+    let cli = Cli::parse();
+    let command = cli.command.unwrap_or(Command::Run {
+        abi: "./orascript/output/orscript2ABI.json".to_string(),
+        wasm: "./orascript/assembly/orscript2.wasm".to_string(),
+        script: None,
+        input: None,
+        key: None,
+        round: 0,
+        report: None,
+        commit: None,
+    });
+    set_trace(cli.trace);
+    let engine = EngineOptions { pooling: cli.pooling, ..Default::default() };
+    run_command(command, cli.registry, engine).await.expect("ERROR: Error occur at run_command() in main.rs");
 
     // let url = "https://catfact.ninja/fact";
     //
@@ -37,3 +78,363 @@ async fn main() {
     // println!("Without params:\n{}", json2);
 }
 
+async fn run_command(command: Command, registry: PathBuf, engine: EngineOptions) -> anyhow::Result<()> {
+    match command {
+        Command::Run { abi, wasm, script, input, key, round, report, commit } => {
+            let registry = ScriptRegistry::open(registry, &engine)?;
+            let input = input.as_deref().unwrap_or(DEFAULT_INPUT);
+            let (output, script_hash) = match script {
+                Some(hash) => (registry.execute(&hash, None, input, &ExecutionLimits::default())?, hash),
+                None => abi_reader(registry.modules(), &abi, &wasm, input)?,
+            };
+            println!("Output: 0x{}", hex::encode(&output));
+            if let (Some(key), Some(report)) = (key, report) {
+                let key = NodeKey::load(&key)?;
+                match commit {
+                    Some(commit) => {
+                        let (commitment, reveal) = Commitment::new(&key, round, &script_hash, &output)?;
+                        fs::write(&commit, serde_json::to_string_pretty(&commitment)?)?;
+                        fs::write(&report, serde_json::to_string_pretty(&reveal)?)?;
+                        println!("Commitment written to {:?}, reveal to {:?}", commit, report);
+                    }
+                    None => {
+                        let signed = SignedReport::new(&key, round, &script_hash, &output);
+                        fs::write(&report, serde_json::to_string_pretty(&signed)?)?;
+                        println!("Signed report written to {:?}", report);
+                    }
+                }
+            }
+        }
+        Command::Keygen { out } => {
+            let key = NodeKey::generate();
+            key.save(&out)?;
+            println!("{}", key.public());
+        }
+        Command::Aggregate { threshold, authorities, mode, out, reports } => {
+            let reports = reports.iter()
+                .map(|path| Ok(serde_json::from_str::<SignedReport>(&fs::read_to_string(path)?)?))
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            let first = reports.first().ok_or_else(|| anyhow::anyhow!("ERROR: no reports given"))?;
+            let mut aggregator = Aggregator::new(first.round, &first.script_hash, &authorities, threshold, mode.parse::<AggregationMode>()?)?;
+            for report in reports {
+                let signer = report.signer.clone();
+                if let Err(e) = aggregator.submit(report) {
+                    println!("Skipping report from {}: {}", signer, e);
+                }
+            }
+            let aggregated = aggregator.finalize()?;
+            fs::write(&out, serde_json::to_string_pretty(&aggregated)?)?;
+            println!("Aggregated {} of {} reports into {:?}", aggregated.attestations.len(), aggregator.report_count(), out);
+        }
+        Command::AggregateReveals { threshold, authorities, mode, commitments, out, reveals } => {
+            let commitments = commitments.iter()
+                .map(|path| Ok(serde_json::from_str::<Commitment>(&fs::read_to_string(path)?)?))
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            let first = commitments.first().ok_or_else(|| anyhow::anyhow!("ERROR: no commitments given"))?;
+            let mut round = CommitRevealRound::new(first.round, &first.script_hash, &authorities);
+            for commitment in commitments {
+                let signer = commitment.signer.clone();
+                if let Err(e) = round.commit(commitment) {
+                    println!("Skipping commitment from {}: {}", signer, e);
+                }
+            }
+            round.close_commits();
+            for path in &reveals {
+                let reveal: Reveal = serde_json::from_str(&fs::read_to_string(path)?)?;
+                let signer = reveal.report.signer.clone();
+                if let Err(e) = round.reveal(reveal) {
+                    println!("Skipping reveal from {}: {}", signer, e);
+                }
+            }
+            let revealed = round.finalize(threshold, mode.parse::<AggregationMode>()?)?;
+            for signer in &revealed.excluded {
+                println!("Excluding {}: committed without a matching reveal", signer);
+            }
+            fs::write(&out, serde_json::to_string_pretty(&revealed.report)?)?;
+            println!("Aggregated {} of {} committed nodes into {:?}", revealed.report.attestations.len(), round.commitment_count(), out);
+        }
+        Command::Verify { threshold, authorities, report } => {
+            let aggregated: AggregatedReport = serde_json::from_str(&fs::read_to_string(&report)?)?;
+            aggregated.verify(&authorities, threshold)?;
+            println!("Report for round {} verified, signed by {:?}", aggregated.round, aggregated.signers());
+        }
+        Command::Extrinsic {
+            pallet_index, call_index, extrinsic_version, request_id, threshold, authorities, key, nonce, tip,
+            spec_version, tx_version, genesis_hash, metadata_hash_check, out, report,
+        } => {
+            let aggregated: AggregatedReport = serde_json::from_str(&fs::read_to_string(&report)?)?;
+            aggregated.verify(&authorities, threshold)?;
+            let call = SubmitResult::from_report(&aggregated, request_id)?;
+            let config = CallConfig { pallet_index, call_index, extrinsic_version };
+            let extrinsic = match (key, spec_version, tx_version, genesis_hash) {
+                (Some(key), Some(spec_version), Some(transaction_version), Some(genesis_hash)) => {
+                    let params = SigningParams {
+                        nonce,
+                        tip,
+                        spec_version,
+                        transaction_version,
+                        genesis_hash: fixed_hex(&genesis_hash, "genesis hash")?,
+                        metadata_hash_check,
+                    };
+                    call.signed_extrinsic(&config, &NodeKey::load(&key)?, &params)?
+                }
+                _ => call.unsigned_extrinsic(&config)?,
+            };
+            let extrinsic = serde_json::to_string_pretty(&extrinsic)?;
+            match out {
+                Some(out) => fs::write(out, extrinsic)?,
+                None => println!("{}", extrinsic),
+            }
+        }
+        Command::Batch { hash, threshold, authorities, request_ids, out, reports } => {
+            if request_ids.len() != reports.len() {
+                return Err(anyhow::anyhow!("ERROR: {} request ids given for {} reports", request_ids.len(), reports.len()));
+            }
+            let mut batch = ResultBatch::new(hash.parse()?);
+            for (request_id, path) in request_ids.into_iter().zip(&reports) {
+                let report: AggregatedReport = serde_json::from_str(&fs::read_to_string(path)?)?;
+                report.verify(&authorities, threshold)
+                    .map_err(|e| anyhow::anyhow!("ERROR: report {:?} failed verification: {}", path, e))?;
+                batch.push(request_id, &decode_hex(&report.output)?)?;
+            }
+            let commitment = serde_json::to_string_pretty(&batch.commitment()?)?;
+            match out {
+                Some(out) => fs::write(out, commitment)?,
+                None => println!("{}", commitment),
+            }
+        }
+        Command::VerifyProof { proof, root, output } => {
+            let proof: MerkleProof = serde_json::from_str(&fs::read_to_string(&proof)?)?;
+            let trusted = fixed_hex::<32>(&root, "root")?;
+            match output {
+                Some(output) => proof.verify_output(&trusted, &decode_hex(&output)?)?,
+                None => proof.verify(&trusted)?,
+            }
+            println!("Request {} is included in root {}", proof.request_id, root);
+        }
+        Command::VerifyEvidence { evidence } => {
+            let evidence: MisbehaviorEvidence = serde_json::from_str(&fs::read_to_string(&evidence)?)?;
+            evidence.verify()?;
+            println!("{} signed conflicting outputs for round {} of {}", evidence.offender, evidence.round, evidence.script_hash);
+        }
+        Command::Bench { abi, wasm, script, input, iterations, concurrency } => {
+            let (abi, wasm) = match script {
+                Some(hash) => {
+                    let script = ScriptRegistry::open(registry, &engine)?.load(&hash)?;
+                    (script.abi.clone(), script.wasm.clone())
+                }
+                None => (fs::read_to_string(&abi)?, fs::read(&wasm)?),
+            };
+            let options = BenchOptions { iterations, concurrency };
+            run_bench(&abi, &wasm, input.as_deref().unwrap_or(DEFAULT_INPUT), &options)?;
+        }
+        Command::Feeds { config, key, out, workers } => {
+            let feeds = load_feeds(&config)?;
+            let key = key.map(|key| NodeKey::load(&key)).transpose()?;
+            if let Some(out) = &out {
+                fs::create_dir_all(out)?;
+            }
+            let registry = Arc::new(ScriptRegistry::open(registry, &engine)?);
+            let scheduler = Scheduler::start(registry.clone(), SchedulerOptions { workers, ..Default::default() });
+            let (sender, mut updates) = tokio::sync::mpsc::channel(64);
+            start_feeds(feeds, registry, scheduler, sender)?;
+            while let Some(update) = updates.recv().await {
+                println!("{}", serde_json::to_string(&update)?);
+                if let (Some(key), Some(out)) = (&key, &out) {
+                    let output = decode_hex(&update.output)?;
+                    let signed = SignedReport::new(key, update.round, &update.script_hash, &output);
+                    let path = out.join(format!("{}-{}.json", update.feed_id, update.round));
+                    fs::write(path, serde_json::to_string_pretty(&signed)?)?;
+                }
+            }
+        }
+        Command::Listen {
+            chain_url, chain_ws_url, requests_key, poll_ms, from_block, resolve_scripts, scripts_prefix, scripts_hasher, key, out, workers,
+        } => {
+            let key = key.map(|key| NodeKey::load(&key)).transpose()?;
+            if let Some(out) = &out {
+                fs::create_dir_all(out)?;
+            }
+            let mut client = RpcChainClient::new(&chain_url)?;
+            if let Some(ws_url) = &chain_ws_url {
+                client = client.with_subscriptions(ws_url);
+            }
+            let client = Arc::new(client);
+            let mut registry = ScriptRegistry::open(registry, &engine)?;
+            if resolve_scripts {
+                let prefix = scripts_prefix.map(|prefix| decode_hex(&prefix)).transpose()?.unwrap_or_else(|| default_scripts_prefix().to_vec());
+                registry = registry.with_source(Arc::new(ChainScriptSource::new(client.clone(), &prefix, scripts_hasher.parse()?)));
+            }
+            let scheduler = Scheduler::start(Arc::new(registry), SchedulerOptions { workers, ..Default::default() });
+            let options = ListenerOptions {
+                requests_key: requests_key.map(|key| decode_hex(&key)).transpose()?.unwrap_or_else(|| default_requests_key().to_vec()),
+                poll_interval: Duration::from_millis(poll_ms),
+                from_block,
+            };
+            let (sender, mut executions) = tokio::sync::mpsc::channel(64);
+            start_chain_listener(client, scheduler, options, sender);
+            while let Some(execution) = executions.recv().await {
+                println!("{}", serde_json::to_string(&execution)?);
+                if let (Some(key), Some(out), JobStatus::Succeeded { output }) = (&key, &out, &execution.status) {
+                    let signed = SignedReport::new(key, execution.request_id, &execution.script_hash, &decode_hex(output)?);
+                    let path = out.join(format!("request-{}.json", execution.request_id));
+                    fs::write(path, serde_json::to_string_pretty(&signed)?)?;
+                }
+            }
+        }
+        Command::MockChain { addr, requests, requests_key, scripts, scripts_prefix, scripts_hasher, block_ms } => {
+            let requests_key = requests_key.map(|key| decode_hex(&key)).transpose()?.unwrap_or_else(|| default_requests_key().to_vec());
+            let requests = match requests {
+                Some(path) => serde_json::from_str::<Vec<MockRequest>>(&fs::read_to_string(path)?)?
+                    .iter().map(MockRequest::to_event).collect::<anyhow::Result<Vec<_>>>()?,
+                None => Vec::new(),
+            };
+            let chain = Arc::new(MockChain::new(&requests_key));
+            let prefix = scripts_prefix.map(|prefix| decode_hex(&prefix)).transpose()?.unwrap_or_else(|| default_scripts_prefix().to_vec());
+            let hasher: MapHasher = scripts_hasher.parse()?;
+            for script in scripts.chunks_exact(2) {
+                let abi = fs::read_to_string(&script[0])?;
+                let wasm = fs::read(&script[1])?;
+                let hash = fixed_hex::<32>(&describe_abi(&abi)?.hash_alg.header_hash(&wasm), "script hash")?;
+                let value = OnChainScript { wasm, abi: abi.into_bytes() }.encode();
+                chain.set_storage(&hasher.storage_key(&prefix, &hash), Some(value));
+                println!("Deployed script 0x{}", hex::encode(hash));
+            }
+            let producer = chain.clone();
+            tokio::spawn(async move {
+                let mut requests = requests.into_iter();
+                loop {
+                    tokio::time::sleep(Duration::from_millis(block_ms)).await;
+                    let block = producer.produce_block(requests.next().as_slice());
+                    println!("Produced block {} {}", block.number, block.hash);
+                }
+            });
+            serve_mock_chain(addr, chain).await?
+        }
+        Command::Gossip { key, listen, peers, authorities, rate, burst, max_inbound, publish } => {
+            let messages = publish.iter()
+                .map(|path| GossipMessage::from_json(&fs::read_to_string(path)?))
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            let expected_peers = peers.len();
+            let options = GossipOptions { listen, peers, authorities, messages_per_sec: rate, burst, max_inbound, ..Default::default() };
+            let (gossip, mut delivered) = start_gossip(NodeKey::load(&key)?, options).await?;
+            println!("Gossip node {} listening on {}", gossip.public(), gossip.local_addr());
+            if !messages.is_empty() {
+                let deadline = tokio::time::Instant::now() + Duration::from_secs(10);
+                while gossip.peers().len() < expected_peers && tokio::time::Instant::now() < deadline {
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                }
+                for message in messages {
+                    let sent = gossip.publish(message)?;
+                    println!("Published to {} peers", sent);
+                }
+            }
+            loop {
+                let delivered = tokio::select! {
+                    delivered = delivered.recv() => match delivered {
+                        Some(delivered) => delivered,
+                        None => break,
+                    },
+                    _ = tokio::signal::ctrl_c() => break,
+                };
+                let kind = match &delivered.message {
+                    GossipMessage::Report(_) => "report",
+                    GossipMessage::Commitment(_) => "commitment",
+                    GossipMessage::Reveal(_) => "reveal",
+                };
+                println!(
+                    "Received {} of round {} by {} from {}",
+                    kind, delivered.message.round(), delivered.message.signer(), delivered.peer,
+                );
+            }
+            println!("{}", serde_json::to_string_pretty(&gossip.stats())?);
+        }
+        Command::Simulate { config, out } => {
+            let config: SimulationConfig = serde_json::from_str(&fs::read_to_string(&config)?)?;
+            let registry = Arc::new(ScriptRegistry::open(registry, &engine)?);
+            let report = run_simulation(config, registry).await?;
+            for round in &report.rounds {
+                match &round.error {
+                    None => println!("Round {}: {} of {} reveals agree", round.request_id, round.agreeing, round.revealed),
+                    Some(e) => println!("Round {}: not finalized, {}", round.request_id, e),
+                }
+            }
+            for node in &report.nodes {
+                println!(
+                    "Node {}: agreed {}, disagreed {}, missed {}, withheld {}, mean latency {:.1} ms",
+                    node.node, node.agreed, node.disagreed, node.missed, node.withheld, node.mean_latency_ms,
+                );
+            }
+            println!(
+                "Finalized {} of {} rounds, agreement rate {:.1}%",
+                report.finalized_rounds, report.rounds.len(), report.agreement_rate * 100.0,
+            );
+            if let Some(out) = out {
+                fs::write(out, serde_json::to_string_pretty(&report)?)?;
+            }
+        }
+        Command::Types { abi, embed, out } => {
+            let abi_json = fs::read_to_string(&abi)?;
+            if embed {
+                fs::write(&abi, embed_type_registry(&abi_json)?)?;
+            } else {
+                let types = serde_json::to_string_pretty(&AbiTypeRegistry::from_abi(&abi_json)?)?;
+                match out {
+                    Some(out) => fs::write(out, types)?,
+                    None => println!("{}", types),
+                }
+            }
+        }
+        Command::Decode { abi, type_name, output } => {
+            let decoded = decode_output(&fs::read_to_string(&abi)?, &type_name, &decode_hex(&output)?)?;
+            println!("{}", serde_json::to_string_pretty(&decoded)?);
+        }
+        Command::Registry { action } => {
+            let registry = ScriptRegistry::open(registry, &engine)?;
+            match action {
+                RegistryAction::Add { abi, wasm } => println!("{}", registry.insert_files(&abi, &wasm)?),
+                RegistryAction::List => println!("{}", serde_json::to_string_pretty(&registry.list()?)?),
+                RegistryAction::Remove { hash } => {
+                    if !registry.remove(&hash)? {
+                        println!("Script {} is not in the registry", hash);
+                    }
+                }
+            }
+        }
+        Command::Serve { addr, workers, queue_capacity, timeout_ms, max_timeout_ms, key, authorities, gossip_listen, peers } => {
+            let registry = Arc::new(ScriptRegistry::open(registry, &engine)?);
+            let options = SchedulerOptions {
+                workers,
+                queue_capacity,
+                default_timeout: Duration::from_millis(timeout_ms),
+                max_timeout: Duration::from_millis(max_timeout_ms),
+                ..Default::default()
+            };
+            let scheduler = Scheduler::start(registry.clone(), options);
+            let reputation = key.as_ref().map(|key| NodeKey::load(key)).transpose()?
+                .map(|key| Reputation::new(key, &authorities));
+            if let (Some(listen), Some(key), Some(reputation)) = (gossip_listen, &key, &reputation) {
+                let options = GossipOptions { listen, peers, authorities, ..Default::default() };
+                let (gossip, mut delivered) = start_gossip(NodeKey::load(key)?, options).await?;
+                println!("Gossip node {} listening on {}", gossip.public(), gossip.local_addr());
+                let reputation = reputation.clone();
+                tokio::spawn(async move {
+                    while let Some(delivered) = delivered.recv().await {
+                        let report = match delivered.message {
+                            GossipMessage::Report(report) => report,
+                            GossipMessage::Reveal(reveal) => reveal.report,
+                            GossipMessage::Commitment(_) => continue,
+                        };
+                        match reputation.observe(report) {
+                            Ok(Some(evidence)) => println!("Node {} equivocated in round {}", evidence.offender, evidence.round),
+                            Ok(None) => {}
+                            Err(e) => println!("{}", e),
+                        }
+                    }
+                });
+            }
+            crate::server::rpc::serve(addr, registry, scheduler, reputation).await?
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,378 @@
+use std::collections::{BTreeMap, BTreeSet};
+use anyhow::{anyhow, Result};
+use parity_scale_codec::{Decode, Encode};
+use serde::{Deserialize, Serialize};
+use crate::oracle::keystore::{decode_hex, verify_signature, NodeKey};
+
+/// How reports of the same round are combined into one output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AggregationMode {
+    /// At least `threshold` nodes must have produced byte-identical outputs.
+    Exact,
+    /// Outputs are SCALE encoded numbers of the given type, the lower median is reported.
+    Median(NumericKind),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NumericKind {
+    I32,
+    I64,
+    F32,
+    F64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SignedReport {
+    pub round: u64,
+    pub script_hash: String,
+    pub output: String,
+    pub signer: String,
+    pub signature: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Attestation {
+    pub signer: String,
+    pub output: String,
+    pub signature: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AggregatedReport {
+    pub round: u64,
+    pub script_hash: String,
+    pub mode: AggregationMode,
+    pub output: String,
+    pub attestations: Vec<Attestation>,
+}
+
+/// Collects the signed reports of one round and produces an M-of-N aggregated report.
+pub struct Aggregator {
+    round: u64,
+    script_hash: String,
+    authorities: BTreeSet<String>,
+    threshold: usize,
+    mode: AggregationMode,
+    reports: BTreeMap<String, SignedReport>,
+}
+
+fn report_payload(round: u64, script_hash: &str, output: &[u8]) -> Vec<u8> {
+    (b"orascript/report", round, script_hash, output).encode()
+}
+
+impl SignedReport {
+    pub fn new(key: &NodeKey, round: u64, script_hash: &str, output: &[u8]) -> Self {
+        SignedReport {
+            round,
+            script_hash: script_hash.to_string(),
+            output: format!("0x{}", hex::encode(output)),
+            signer: key.public(),
+            signature: key.sign(&report_payload(round, script_hash, output)),
+        }
+    }
+
+    pub fn output_bytes(&self) -> Result<Vec<u8>> {
+        decode_hex(&self.output)
+    }
+
+    pub fn verify(&self) -> Result<()> {
+        let payload = report_payload(self.round, &self.script_hash, &self.output_bytes()?);
+        verify_signature(&self.signer, &payload, &self.signature)
+    }
+}
+
+impl AggregatedReport {
+    pub fn signers(&self) -> Vec<&str> {
+        self.attestations.iter().map(|a| a.signer.as_str()).collect()
+    }
+
+    /// Checks the report offline against a known authority set and the required threshold.
+    pub fn verify(&self, authorities: &[String], threshold: usize) -> Result<()> {
+        let authorities: BTreeSet<&String> = authorities.iter().collect();
+        validate_threshold(threshold, authorities.len())?;
+        let mut seen = BTreeSet::new();
+        let mut outputs = Vec::new();
+        for attestation in &self.attestations {
+            if !authorities.contains(&attestation.signer) {
+                return Err(anyhow!("ERROR: signer {} is not an authority", attestation.signer));
+            }
+            if !seen.insert(attestation.signer.as_str()) {
+                return Err(anyhow!("ERROR: signer {} attested more than once", attestation.signer));
+            }
+            let output = decode_hex(&attestation.output)?;
+            let payload = report_payload(self.round, &self.script_hash, &output);
+            verify_signature(&attestation.signer, &payload, &attestation.signature)?;
+            outputs.push(output);
+        }
+        if seen.len() < threshold {
+            return Err(anyhow!("ERROR: {} signatures do not meet threshold {}", seen.len(), threshold));
+        }
+        let expected = combine(self.mode, &outputs)?;
+        if decode_hex(&self.output)? != expected {
+            return Err(anyhow!("ERROR: aggregated output does not match the attested outputs"));
+        }
+        Ok(())
+    }
+}
+
+impl Aggregator {
+    pub fn new(round: u64, script_hash: &str, authorities: &[String], threshold: usize, mode: AggregationMode) -> Result<Self> {
+        let authorities: BTreeSet<String> = authorities.iter().cloned().collect();
+        validate_threshold(threshold, authorities.len())?;
+        Ok(Aggregator {
+            round,
+            script_hash: script_hash.to_string(),
+            authorities,
+            threshold,
+            mode,
+            reports: BTreeMap::new(),
+        })
+    }
+
+    pub fn submit(&mut self, report: SignedReport) -> Result<()> {
+        if report.round != self.round || report.script_hash != self.script_hash {
+            return Err(anyhow!("ERROR: report for round {} of {} does not belong to round {} of {}",
+                report.round, report.script_hash, self.round, self.script_hash));
+        }
+        if !self.authorities.contains(&report.signer) {
+            return Err(anyhow!("ERROR: signer {} is not an authority", report.signer));
+        }
+        report.verify()?;
+        if let Some(existing) = self.reports.get(&report.signer) {
+            if existing.output != report.output {
+                return Err(anyhow!("ERROR: signer {} submitted conflicting reports for round {}", report.signer, self.round));
+            }
+            return Ok(());
+        }
+        self.reports.insert(report.signer.clone(), report);
+        Ok(())
+    }
+
+    pub fn report_count(&self) -> usize {
+        self.reports.len()
+    }
+
+    pub fn finalize(&self) -> Result<AggregatedReport> {
+        let selected: Vec<&SignedReport> = match self.mode {
+            AggregationMode::Exact => {
+                let mut groups: BTreeMap<&str, Vec<&SignedReport>> = BTreeMap::new();
+                for report in self.reports.values() {
+                    groups.entry(report.output.as_str()).or_default().push(report);
+                }
+                groups.into_values().max_by_key(|group| group.len()).unwrap_or_default()
+            }
+            AggregationMode::Median(_) => self.reports.values().collect(),
+        };
+        if selected.len() < self.threshold {
+            return Err(anyhow!("ERROR: only {} agreeing reports for round {}, threshold is {}",
+                selected.len(), self.round, self.threshold));
+        }
+        let outputs = selected.iter().map(|r| r.output_bytes()).collect::<Result<Vec<_>>>()?;
+        let output = combine(self.mode, &outputs)?;
+        Ok(AggregatedReport {
+            round: self.round,
+            script_hash: self.script_hash.clone(),
+            mode: self.mode,
+            output: format!("0x{}", hex::encode(output)),
+            attestations: selected.into_iter().map(|r| Attestation {
+                signer: r.signer.clone(),
+                output: r.output.clone(),
+                signature: r.signature.clone(),
+            }).collect(),
+        })
+    }
+}
+
+/// The threshold must be a strict majority of the distinct authorities: in exact mode two
+/// disjoint groups of agreeing reports can then never both reach it, in median mode the honest
+/// reports always outnumber the ones an aggregator leaves out.
+fn validate_threshold(threshold: usize, authorities: usize) -> Result<()> {
+    if threshold == 0 || threshold > authorities {
+        return Err(anyhow!("ERROR: threshold {} is invalid for {} authorities", threshold, authorities));
+    }
+    if threshold <= authorities / 2 {
+        return Err(anyhow!("ERROR: threshold {} of {} authorities is not a majority, aggregation needs more than {}",
+            threshold, authorities, authorities / 2));
+    }
+    Ok(())
+}
+
+fn combine(mode: AggregationMode, outputs: &[Vec<u8>]) -> Result<Vec<u8>> {
+    match mode {
+        AggregationMode::Exact => {
+            let first = outputs.first().ok_or_else(|| anyhow!("ERROR: no outputs to aggregate"))?;
+            if outputs.iter().any(|o| o != first) {
+                return Err(anyhow!("ERROR: outputs disagree in exact aggregation mode"));
+            }
+            Ok(first.clone())
+        }
+        AggregationMode::Median(kind) => median(kind, outputs),
+    }
+}
+
+fn median(kind: NumericKind, outputs: &[Vec<u8>]) -> Result<Vec<u8>> {
+    if outputs.is_empty() {
+        return Err(anyhow!("ERROR: no outputs to aggregate"));
+    }
+    let mid = (outputs.len() - 1) / 2;
+    match kind {
+        NumericKind::I32 => Ok(lower_median(decode_all::<i32>(outputs)?, mid).encode()),
+        NumericKind::I64 => Ok(lower_median(decode_all::<i64>(outputs)?, mid).encode()),
+        NumericKind::F32 => {
+            let mut values = decode_all::<f32>(outputs)?;
+            values.sort_by(|a, b| a.total_cmp(b));
+            Ok(values[mid].encode())
+        }
+        NumericKind::F64 => {
+            let mut values = decode_all::<f64>(outputs)?;
+            values.sort_by(|a, b| a.total_cmp(b));
+            Ok(values[mid].encode())
+        }
+    }
+}
+
+fn lower_median<T: Ord + Copy>(mut values: Vec<T>, mid: usize) -> T {
+    values.sort();
+    values[mid]
+}
+
+fn decode_all<T: Decode>(outputs: &[Vec<u8>]) -> Result<Vec<T>> {
+    outputs.iter().map(|output| {
+        let mut input = &output[..];
+        let value = T::decode(&mut input).map_err(|e| anyhow!("ERROR: failed to decode numeric output: {}", e))?;
+        if !input.is_empty() {
+            return Err(anyhow!("ERROR: numeric output has {} trailing bytes", input.len()));
+        }
+        Ok(value)
+    }).collect()
+}
+
+impl std::str::FromStr for AggregationMode {
+    type Err = anyhow::Error;
+
+    fn from_str(mode: &str) -> Result<Self> {
+        let kind = match mode {
+            "exact" => return Ok(AggregationMode::Exact),
+            "median-i32" => NumericKind::I32,
+            "median-i64" => NumericKind::I64,
+            "median-f32" => NumericKind::F32,
+            "median-f64" => NumericKind::F64,
+            _ => return Err(anyhow!("ERROR: unknown aggregation mode {:?}", mode)),
+        };
+        Ok(AggregationMode::Median(kind))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCRIPT: &str = "0x01";
+
+    fn nodes(count: u8) -> (Vec<NodeKey>, Vec<String>) {
+        let keys: Vec<NodeKey> = (0..count).map(|i| NodeKey::from_seed([i + 1; 32])).collect();
+        let authorities = keys.iter().map(|key| key.public()).collect();
+        (keys, authorities)
+    }
+
+    #[test]
+    fn exact_round_reaches_threshold() {
+        let (keys, authorities) = nodes(4);
+        let mut aggregator = Aggregator::new(7, SCRIPT, &authorities, 3, AggregationMode::Exact).unwrap();
+        for key in &keys[..3] {
+            aggregator.submit(SignedReport::new(key, 7, SCRIPT, b"price")).unwrap();
+        }
+        aggregator.submit(SignedReport::new(&keys[3], 7, SCRIPT, b"other")).unwrap();
+        let report = aggregator.finalize().unwrap();
+        assert_eq!(report.output, format!("0x{}", hex::encode(b"price")));
+        assert_eq!(report.attestations.len(), 3);
+        assert!(!report.signers().contains(&keys[3].public().as_str()));
+        report.verify(&authorities, 3).unwrap();
+    }
+
+    #[test]
+    fn exact_round_below_threshold_fails() {
+        let (keys, authorities) = nodes(4);
+        let mut aggregator = Aggregator::new(1, SCRIPT, &authorities, 3, AggregationMode::Exact).unwrap();
+        aggregator.submit(SignedReport::new(&keys[0], 1, SCRIPT, b"a")).unwrap();
+        aggregator.submit(SignedReport::new(&keys[1], 1, SCRIPT, b"a")).unwrap();
+        aggregator.submit(SignedReport::new(&keys[2], 1, SCRIPT, b"b")).unwrap();
+        assert!(aggregator.finalize().is_err());
+    }
+
+    #[test]
+    fn threshold_must_be_a_majority() {
+        let (_, authorities) = nodes(4);
+        assert!(Aggregator::new(1, SCRIPT, &authorities, 2, AggregationMode::Exact).is_err());
+        assert!(Aggregator::new(1, SCRIPT, &authorities, 3, AggregationMode::Exact).is_ok());
+        assert!(Aggregator::new(1, SCRIPT, &authorities, 2, AggregationMode::Median(NumericKind::I64)).is_err());
+        assert!(Aggregator::new(1, SCRIPT, &authorities, 3, AggregationMode::Median(NumericKind::I64)).is_ok());
+        assert!(Aggregator::new(1, SCRIPT, &authorities, 5, AggregationMode::Exact).is_err());
+    }
+
+    #[test]
+    fn duplicate_authorities_count_once() {
+        let (keys, mut authorities) = nodes(3);
+        authorities.extend(authorities.clone());
+        // Six entries but three authorities: four can never be reached, two is a majority.
+        assert!(Aggregator::new(1, SCRIPT, &authorities, 4, AggregationMode::Exact).is_err());
+        let mut aggregator = Aggregator::new(1, SCRIPT, &authorities, 2, AggregationMode::Exact).unwrap();
+        for key in &keys[..2] {
+            aggregator.submit(SignedReport::new(key, 1, SCRIPT, b"a")).unwrap();
+        }
+        let report = aggregator.finalize().unwrap();
+        report.verify(&authorities, 2).unwrap();
+        assert!(report.verify(&authorities, 4).is_err());
+    }
+
+    #[test]
+    fn rejects_foreign_and_conflicting_reports() {
+        let (keys, authorities) = nodes(3);
+        let outsider = NodeKey::from_seed([9; 32]);
+        let mut aggregator = Aggregator::new(1, SCRIPT, &authorities, 2, AggregationMode::Exact).unwrap();
+        assert!(aggregator.submit(SignedReport::new(&outsider, 1, SCRIPT, b"a")).is_err());
+        assert!(aggregator.submit(SignedReport::new(&keys[0], 2, SCRIPT, b"a")).is_err());
+        aggregator.submit(SignedReport::new(&keys[0], 1, SCRIPT, b"a")).unwrap();
+        aggregator.submit(SignedReport::new(&keys[0], 1, SCRIPT, b"a")).unwrap();
+        assert!(aggregator.submit(SignedReport::new(&keys[0], 1, SCRIPT, b"b")).is_err());
+        let mut forged = SignedReport::new(&keys[1], 1, SCRIPT, b"a");
+        forged.output = "0x62".to_string();
+        assert!(aggregator.submit(forged).is_err());
+        assert_eq!(aggregator.report_count(), 1);
+    }
+
+    #[test]
+    fn median_round_reports_lower_median() {
+        let (keys, authorities) = nodes(4);
+        let mode = AggregationMode::Median(NumericKind::I64);
+        let mut aggregator = Aggregator::new(3, SCRIPT, &authorities, 3, mode).unwrap();
+        for (key, value) in keys.iter().zip([40i64, 10, 30, 20]) {
+            aggregator.submit(SignedReport::new(key, 3, SCRIPT, &value.encode())).unwrap();
+        }
+        let report = aggregator.finalize().unwrap();
+        assert_eq!(decode_hex(&report.output).unwrap(), 20i64.encode());
+        assert_eq!(report.attestations.len(), 4);
+        report.verify(&authorities, 3).unwrap();
+    }
+
+    #[test]
+    fn verify_rejects_tampered_reports() {
+        let (keys, authorities) = nodes(3);
+        let mut aggregator = Aggregator::new(1, SCRIPT, &authorities, 2, AggregationMode::Exact).unwrap();
+        for key in &keys {
+            aggregator.submit(SignedReport::new(key, 1, SCRIPT, b"a")).unwrap();
+        }
+        let report = aggregator.finalize().unwrap();
+        let mut tampered = report.clone();
+        tampered.output = "0x62".to_string();
+        assert!(tampered.verify(&authorities, 2).is_err());
+        let mut duplicated = report.clone();
+        duplicated.attestations[1] = duplicated.attestations[0].clone();
+        duplicated.attestations.truncate(2);
+        assert!(duplicated.verify(&authorities, 2).is_err());
+        assert!(report.verify(&authorities[..2], 2).is_err());
+        assert!(report.verify(&authorities, 4).is_err());
+    }
+}
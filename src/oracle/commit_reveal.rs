@@ -0,0 +1,267 @@
+use std::collections::{BTreeMap, BTreeSet};
+use anyhow::{anyhow, Result};
+use parity_scale_codec::Encode;
+use rand::RngCore;
+use rand::rngs::OsRng;
+use serde::{Deserialize, Serialize};
+use sp_core_hashing::blake2_256;
+use crate::oracle::aggregator::{AggregatedReport, AggregationMode, Aggregator, SignedReport};
+use crate::oracle::extrinsic::fixed_hex;
+use crate::oracle::keystore::{decode_hex, verify_signature, NodeKey};
+
+/// Length of a commitment salt, short salts would let the others brute force the output.
+const SALT_BYTES: usize = 32;
+
+/// Signed `blake2_256(output || salt || node_id)` a node publishes before any output is revealed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Commitment {
+    pub round: u64,
+    pub script_hash: String,
+    pub signer: String,
+    pub commitment: String,
+    pub signature: String,
+}
+
+/// The signed report behind a commitment and the salt that opens it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Reveal {
+    #[serde(flatten)]
+    pub report: SignedReport,
+    pub salt: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Phase {
+    Commit,
+    Reveal,
+}
+
+/// Outcome of a commit-reveal round.
+#[derive(Debug, Clone, Serialize)]
+pub struct RevealedRound {
+    pub report: AggregatedReport,
+    /// Authorities that committed but did not reveal, or revealed something else.
+    pub excluded: Vec<String>,
+}
+
+/// Collects the commitments of one round, then the reveals matching them. Outputs only become
+/// visible once every node is bound to its own, so a node cannot copy the others' results.
+pub struct CommitRevealRound {
+    round: u64,
+    script_hash: String,
+    authorities: BTreeSet<String>,
+    phase: Phase,
+    commitments: BTreeMap<String, Commitment>,
+    reveals: BTreeMap<String, SignedReport>,
+}
+
+fn commit_payload(round: u64, script_hash: &str, commitment: &str) -> Vec<u8> {
+    (b"orascript/commit", round, script_hash, commitment).encode()
+}
+
+/// `blake2_256(output || salt || node_id)`, `node_id` being the signer's public key.
+pub fn commitment_hash(output: &[u8], salt: &[u8], signer: &str) -> Result<String> {
+    let node_id = fixed_hex::<32>(signer, "node id")?;
+    Ok(format!("0x{}", hex::encode(blake2_256(&[output, salt, &node_id].concat()))))
+}
+
+impl Commitment {
+    /// Commits to `output` with a fresh random salt and returns the commitment to publish now
+    /// together with the reveal to publish once commitments are closed.
+    pub fn new(key: &NodeKey, round: u64, script_hash: &str, output: &[u8]) -> Result<(Commitment, Reveal)> {
+        let mut salt = [0u8; SALT_BYTES];
+        OsRng.fill_bytes(&mut salt);
+        let commitment = commitment_hash(output, &salt, &key.public())?;
+        let signature = key.sign(&commit_payload(round, script_hash, &commitment));
+        Ok((
+            Commitment { round, script_hash: script_hash.to_string(), signer: key.public(), commitment, signature },
+            Reveal { report: SignedReport::new(key, round, script_hash, output), salt: format!("0x{}", hex::encode(salt)) },
+        ))
+    }
+
+    pub fn verify(&self) -> Result<()> {
+        verify_signature(&self.signer, &commit_payload(self.round, &self.script_hash, &self.commitment), &self.signature)
+    }
+}
+
+impl Reveal {
+    /// The decoded salt, which must be `SALT_BYTES` long.
+    pub fn salt_bytes(&self) -> Result<Vec<u8>> {
+        let salt = decode_hex(&self.salt)?;
+        if salt.len() != SALT_BYTES {
+            return Err(anyhow!("ERROR: salt of {} is {} bytes, expected {}", self.report.signer, salt.len(), SALT_BYTES));
+        }
+        Ok(salt)
+    }
+
+    /// Checks the report signature and that output and salt open `commitment`.
+    pub fn verify(&self, commitment: &Commitment) -> Result<()> {
+        let report = &self.report;
+        if report.signer != commitment.signer || report.round != commitment.round || report.script_hash != commitment.script_hash {
+            return Err(anyhow!("ERROR: reveal of {} does not belong to its commitment", report.signer));
+        }
+        let salt = self.salt_bytes()?;
+        report.verify()?;
+        let opened = commitment_hash(&report.output_bytes()?, &salt, &report.signer)?;
+        if opened != commitment.commitment {
+            return Err(anyhow!("ERROR: reveal of {} does not match its commitment", report.signer));
+        }
+        Ok(())
+    }
+}
+
+impl CommitRevealRound {
+    pub fn new(round: u64, script_hash: &str, authorities: &[String]) -> Self {
+        CommitRevealRound {
+            round,
+            script_hash: script_hash.to_string(),
+            authorities: authorities.iter().cloned().collect(),
+            phase: Phase::Commit,
+            commitments: BTreeMap::new(),
+            reveals: BTreeMap::new(),
+        }
+    }
+
+    pub fn commit(&mut self, commitment: Commitment) -> Result<()> {
+        if self.phase != Phase::Commit {
+            return Err(anyhow!("ERROR: commitments of round {} are closed", self.round));
+        }
+        if commitment.round != self.round || commitment.script_hash != self.script_hash {
+            return Err(anyhow!("ERROR: commitment for round {} of {} does not belong to round {} of {}",
+                commitment.round, commitment.script_hash, self.round, self.script_hash));
+        }
+        if !self.authorities.contains(&commitment.signer) {
+            return Err(anyhow!("ERROR: signer {} is not an authority", commitment.signer));
+        }
+        commitment.verify()?;
+        if let Some(existing) = self.commitments.get(&commitment.signer) {
+            if existing.commitment != commitment.commitment {
+                return Err(anyhow!("ERROR: signer {} submitted conflicting commitments for round {}", commitment.signer, self.round));
+            }
+            return Ok(());
+        }
+        self.commitments.insert(commitment.signer.clone(), commitment);
+        Ok(())
+    }
+
+    /// Ends the commit phase, reveals are accepted from now on.
+    pub fn close_commits(&mut self) {
+        self.phase = Phase::Reveal;
+    }
+
+    pub fn reveal(&mut self, reveal: Reveal) -> Result<()> {
+        if self.phase != Phase::Reveal {
+            return Err(anyhow!("ERROR: round {} is still collecting commitments", self.round));
+        }
+        let signer = reveal.report.signer.clone();
+        let commitment = self.commitments.get(&signer)
+            .ok_or_else(|| anyhow!("ERROR: signer {} did not commit in round {}", signer, self.round))?;
+        reveal.verify(commitment)?;
+        self.reveals.insert(signer, reveal.report);
+        Ok(())
+    }
+
+    pub fn commitment_count(&self) -> usize {
+        self.commitments.len()
+    }
+
+    /// Committed authorities that have not revealed a matching output.
+    pub fn missing_reveals(&self) -> Vec<String> {
+        self.commitments.keys().filter(|signer| !self.reveals.contains_key(*signer)).cloned().collect()
+    }
+
+    /// Aggregates the verified reveals, nodes that did not reveal are excluded.
+    pub fn finalize(&self, threshold: usize, mode: AggregationMode) -> Result<RevealedRound> {
+        let authorities: Vec<String> = self.authorities.iter().cloned().collect();
+        let mut aggregator = Aggregator::new(self.round, &self.script_hash, &authorities, threshold, mode)?;
+        for report in self.reveals.values() {
+            aggregator.submit(report.clone())?;
+        }
+        Ok(RevealedRound { report: aggregator.finalize()?, excluded: self.missing_reveals() })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCRIPT: &str = "0x01";
+
+    fn nodes(count: u8) -> (Vec<NodeKey>, Vec<String>) {
+        let keys: Vec<NodeKey> = (0..count).map(|i| NodeKey::from_seed([i + 1; 32])).collect();
+        let authorities = keys.iter().map(|key| key.public()).collect();
+        (keys, authorities)
+    }
+
+    #[test]
+    fn revealed_outputs_are_aggregated() {
+        let (keys, authorities) = nodes(3);
+        let mut round = CommitRevealRound::new(4, SCRIPT, &authorities);
+        let reveals: Vec<Reveal> = keys.iter().map(|key| {
+            let (commitment, reveal) = Commitment::new(key, 4, SCRIPT, b"price").unwrap();
+            round.commit(commitment.clone()).unwrap();
+            // Publishing the same commitment twice is harmless.
+            round.commit(commitment).unwrap();
+            reveal
+        }).collect();
+        assert_eq!(round.commitment_count(), 3);
+        assert!(round.reveal(reveals[0].clone()).is_err());
+        round.close_commits();
+        for reveal in reveals {
+            round.reveal(reveal).unwrap();
+        }
+        let revealed = round.finalize(3, AggregationMode::Exact).unwrap();
+        assert_eq!(revealed.report.output, format!("0x{}", hex::encode(b"price")));
+        assert!(revealed.excluded.is_empty());
+        revealed.report.verify(&authorities, 3).unwrap();
+    }
+
+    #[test]
+    fn nodes_that_do_not_reveal_are_excluded() {
+        let (keys, authorities) = nodes(4);
+        let mut round = CommitRevealRound::new(1, SCRIPT, &authorities);
+        let mut reveals = Vec::new();
+        for key in &keys {
+            let (commitment, reveal) = Commitment::new(key, 1, SCRIPT, b"a").unwrap();
+            round.commit(commitment).unwrap();
+            reveals.push(reveal);
+        }
+        round.close_commits();
+        let late = Commitment::new(&keys[0], 1, SCRIPT, b"b").unwrap().0;
+        assert!(round.commit(late).is_err());
+        for reveal in reveals.drain(..3) {
+            round.reveal(reveal).unwrap();
+        }
+        assert_eq!(round.missing_reveals(), vec![keys[3].public()]);
+        let revealed = round.finalize(3, AggregationMode::Exact).unwrap();
+        assert_eq!(revealed.excluded, vec![keys[3].public()]);
+        assert!(!revealed.report.signers().contains(&keys[3].public().as_str()));
+    }
+
+    #[test]
+    fn mismatched_openings_are_rejected() {
+        let (keys, authorities) = nodes(2);
+        let mut round = CommitRevealRound::new(2, SCRIPT, &authorities);
+        let (commitment, reveal) = Commitment::new(&keys[0], 2, SCRIPT, b"a").unwrap();
+        round.commit(commitment.clone()).unwrap();
+        let (other, _) = Commitment::new(&keys[0], 2, SCRIPT, b"b").unwrap();
+        assert!(round.commit(other).is_err());
+        round.close_commits();
+
+        // Another output signed by the same node does not open the commitment.
+        let changed = Reveal { report: SignedReport::new(&keys[0], 2, SCRIPT, b"b"), salt: reveal.salt.clone() };
+        assert!(round.reveal(changed).is_err());
+        let salted = Reveal { salt: format!("0x{}", "00".repeat(SALT_BYTES)), ..reveal.clone() };
+        assert!(round.reveal(salted).is_err());
+        // A salt of the wrong length is refused even when it opens the commitment.
+        let short = [0u8; 4];
+        let mut weak = commitment.clone();
+        weak.commitment = commitment_hash(b"a", &short, &keys[0].public()).unwrap();
+        let weak_reveal = Reveal { salt: format!("0x{}", hex::encode(short)), ..reveal.clone() };
+        assert!(weak_reveal.verify(&weak).unwrap_err().to_string().contains("salt"));
+        // Only authorities that committed can reveal.
+        let (_, stranger) = Commitment::new(&keys[1], 2, SCRIPT, b"a").unwrap();
+        assert!(round.reveal(stranger).is_err());
+        round.reveal(reveal).unwrap();
+    }
+}
@@ -0,0 +1,207 @@
+use anyhow::{anyhow, Result};
+use parity_scale_codec::{Compact, Encode};
+use serde::{Deserialize, Serialize};
+use sp_core_hashing::blake2_256;
+use crate::oracle::aggregator::{AggregatedReport, SignedReport};
+use crate::oracle::keystore::{decode_hex, NodeKey};
+use crate::oracle::reputation::MisbehaviorEvidence;
+
+/// Signed payloads longer than this are hashed before signing, as the chain does when verifying.
+const MAX_SIGNED_PAYLOAD: usize = 256;
+/// Bit of the version byte marking a signed extrinsic.
+const SIGNED_BIT: u8 = 0b1000_0000;
+/// `MultiAddress::Id` and `MultiSignature::Ed25519` variant indices.
+const ADDRESS_ID: u8 = 0;
+const SIGNATURE_ED25519: u8 = 0;
+
+/// Where a `pallet_oracle` call such as `submit_result` lives in the target runtime and which
+/// extrinsic format its metadata declares. All three differ between chains, so they come from the relayer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CallConfig {
+    pub pallet_index: u8,
+    pub call_index: u8,
+    /// Extrinsic format version of the runtime metadata, 4 or 5. Version 5 only defines bare
+    /// (unsigned) extrinsics here.
+    pub extrinsic_version: u8,
+}
+
+/// One attestation of the report. `output` is what the node signed, the submitted output itself
+/// in exact mode and the node's own sample in median mode.
+#[derive(Debug, Clone, PartialEq, Eq, Encode)]
+pub struct ResultSignature {
+    pub signer: [u8; 32],
+    pub output: Vec<u8>,
+    pub signature: [u8; 64],
+}
+
+/// Arguments of `pallet_oracle::submit_result(request_id, script_hash, output, signatures)`.
+#[derive(Debug, Clone, PartialEq, Eq, Encode)]
+pub struct SubmitResult {
+    pub request_id: u64,
+    pub script_hash: [u8; 32],
+    pub output: Vec<u8>,
+    pub signatures: Vec<ResultSignature>,
+}
+
+/// One of the two conflicting reports of `report_equivocation`.
+#[derive(Debug, Clone, PartialEq, Eq, Encode)]
+pub struct SignedOutput {
+    pub output: Vec<u8>,
+    pub signature: [u8; 64],
+}
+
+/// Arguments of `pallet_oracle::report_equivocation(offender, round, script_hash, first, second)`.
+/// The pallet checks both signatures against the report payload and slashes the offender.
+#[derive(Debug, Clone, PartialEq, Eq, Encode)]
+pub struct ReportEquivocation {
+    pub offender: [u8; 32],
+    pub round: u64,
+    pub script_hash: [u8; 32],
+    pub first: SignedOutput,
+    pub second: SignedOutput,
+}
+
+/// Chain state a signed extrinsic commits to. The transaction is immortal, so it stays valid
+/// until the account nonce moves past `nonce`.
+#[derive(Debug, Clone)]
+pub struct SigningParams {
+    pub nonce: u32,
+    pub tip: u128,
+    pub spec_version: u32,
+    pub transaction_version: u32,
+    pub genesis_hash: [u8; 32],
+    /// Whether the runtime has the `CheckMetadataHash` extension. It is included disabled.
+    pub metadata_hash_check: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EncodedExtrinsic {
+    /// Hex encoded call data, for relayers that wrap it themselves.
+    pub call: String,
+    /// Hex encoded, length prefixed extrinsic ready for `author_submitExtrinsic`.
+    pub extrinsic: String,
+}
+
+impl SubmitResult {
+    /// Builds the call arguments answering the on-chain request `request_id` from an aggregated
+    /// report.
+    pub fn from_report(report: &AggregatedReport, request_id: u64) -> Result<Self> {
+        Ok(SubmitResult {
+            request_id,
+            script_hash: fixed_hex(&report.script_hash, "script hash")?,
+            output: decode_hex(&report.output)?,
+            signatures: report.attestations.iter().map(|attestation| Ok(ResultSignature {
+                signer: fixed_hex(&attestation.signer, "signer")?,
+                output: decode_hex(&attestation.output)?,
+                signature: fixed_hex(&attestation.signature, "signature")?,
+            })).collect::<Result<Vec<_>>>()?,
+        })
+    }
+
+    /// Call data: pallet index, call index and the SCALE encoded arguments.
+    pub fn call_data(&self, config: &CallConfig) -> Vec<u8> {
+        let mut call = vec![config.pallet_index, config.call_index];
+        self.encode_to(&mut call);
+        call
+    }
+
+    /// Unsigned extrinsic, for runtimes that validate `submit_result` through `ValidateUnsigned`.
+    pub fn unsigned_extrinsic(&self, config: &CallConfig) -> Result<EncodedExtrinsic> {
+        check_version(config.extrinsic_version)?;
+        let call = self.call_data(config);
+        let mut body = vec![config.extrinsic_version];
+        body.extend_from_slice(&call);
+        Ok(EncodedExtrinsic::new(&call, &body))
+    }
+
+    /// Extrinsic signed by `key` as an `Ed25519` `MultiSignature` from its `AccountId32`.
+    pub fn signed_extrinsic(&self, config: &CallConfig, key: &NodeKey, params: &SigningParams) -> Result<EncodedExtrinsic> {
+        check_version(config.extrinsic_version)?;
+        if config.extrinsic_version != 4 {
+            return Err(anyhow!("ERROR: signed extrinsics are only supported in version 4"));
+        }
+        let call = self.call_data(config);
+        let extra = params.extra();
+        let mut payload = [call.as_slice(), &extra, &params.additional()].concat();
+        if payload.len() > MAX_SIGNED_PAYLOAD {
+            payload = blake2_256(&payload).to_vec();
+        }
+        let mut body = vec![SIGNED_BIT | config.extrinsic_version, ADDRESS_ID];
+        body.extend_from_slice(&key.public_bytes());
+        body.push(SIGNATURE_ED25519);
+        body.extend_from_slice(&key.sign_bytes(&payload));
+        body.extend_from_slice(&extra);
+        body.extend_from_slice(&call);
+        Ok(EncodedExtrinsic::new(&call, &body))
+    }
+}
+
+impl ReportEquivocation {
+    pub fn from_evidence(evidence: &MisbehaviorEvidence) -> Result<Self> {
+        let signed = |report: &SignedReport| -> Result<SignedOutput> {
+            Ok(SignedOutput { output: report.output_bytes()?, signature: fixed_hex(&report.signature, "signature")? })
+        };
+        Ok(ReportEquivocation {
+            offender: fixed_hex(&evidence.offender, "offender")?,
+            round: evidence.round,
+            script_hash: fixed_hex(&evidence.script_hash, "script hash")?,
+            first: signed(&evidence.first)?,
+            second: signed(&evidence.second)?,
+        })
+    }
+
+    /// Call data: pallet index, call index and the SCALE encoded arguments.
+    pub fn call_data(&self, config: &CallConfig) -> Vec<u8> {
+        let mut call = vec![config.pallet_index, config.call_index];
+        self.encode_to(&mut call);
+        call
+    }
+}
+
+impl SigningParams {
+    /// Values of the signed extensions carried in the extrinsic: immortal era, nonce, tip and
+    /// the disabled metadata hash mode.
+    fn extra(&self) -> Vec<u8> {
+        let mut extra = vec![0u8];
+        Compact(self.nonce).encode_to(&mut extra);
+        Compact(self.tip).encode_to(&mut extra);
+        if self.metadata_hash_check {
+            extra.push(0);
+        }
+        extra
+    }
+
+    /// Values only signed over: spec and transaction versions, genesis hash and the era's
+    /// checkpoint, which is the genesis block for an immortal era.
+    fn additional(&self) -> Vec<u8> {
+        let mut additional = (self.spec_version, self.transaction_version, self.genesis_hash, self.genesis_hash).encode();
+        if self.metadata_hash_check {
+            None::<[u8; 32]>.encode_to(&mut additional);
+        }
+        additional
+    }
+}
+
+impl EncodedExtrinsic {
+    fn new(call: &[u8], body: &[u8]) -> Self {
+        let mut extrinsic = Compact(body.len() as u32).encode();
+        extrinsic.extend_from_slice(body);
+        EncodedExtrinsic {
+            call: format!("0x{}", hex::encode(call)),
+            extrinsic: format!("0x{}", hex::encode(extrinsic)),
+        }
+    }
+}
+
+fn check_version(version: u8) -> Result<()> {
+    if version != 4 && version != 5 {
+        return Err(anyhow!("ERROR: unsupported extrinsic version {}", version));
+    }
+    Ok(())
+}
+
+pub fn fixed_hex<const N: usize>(value: &str, what: &str) -> Result<[u8; N]> {
+    decode_hex(value)?
+        .try_into()
+        .map_err(|_| anyhow!("ERROR: {} must be {} bytes", what, N))
+}
@@ -0,0 +1,347 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use tokio::time::sleep;
+use crate::core::registry::ScriptRegistry;
+use crate::core::runtime::{default_selector, read_numeric_field};
+use crate::core::scheduler::{JobRequest, JobStatus, Scheduler};
+use crate::oracle::keystore::decode_hex;
+use crate::server::server::{fetch_with_query, header_map};
+
+/// Limit on fetching one data source.
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FeedDefinition {
+    pub id: String,
+    pub script_hash: String,
+    #[serde(default)]
+    pub selector: Option<String>,
+    /// Script input, every `{{name}}` is replaced by the body fetched from the data source `name`.
+    pub input_template: String,
+    #[serde(default)]
+    pub data_sources: Vec<DataSource>,
+    pub schedule: FeedSchedule,
+    /// Relative change in percent that triggers a new report before the heartbeat.
+    pub deviation_threshold: f64,
+    /// A report is published at least this often, even if the value did not move.
+    pub heartbeat_secs: u64,
+    /// Numeric field of the result class tracked for deviation.
+    pub value_field: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DataSource {
+    pub name: String,
+    pub url: String,
+    #[serde(default)]
+    pub params: HashMap<String, String>,
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FeedSchedule {
+    Interval { secs: u64 },
+    /// Cron expression with a seconds field, e.g. `0 */5 * * * *`.
+    Cron { expression: String },
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FeedUpdate {
+    pub feed_id: String,
+    pub script_hash: String,
+    /// Derived from the scheduled tick, its unix time divided by the interval or the unix time
+    /// itself for cron feeds, so every node reports the same round and restarts do not reuse one.
+    pub round: u64,
+    pub value: f64,
+    /// Hex encoded SCALE output of the script.
+    pub output: String,
+    pub timestamp: u64,
+    pub reason: PublishReason,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PublishReason {
+    First,
+    Deviation,
+    Heartbeat,
+}
+
+enum Ticker {
+    /// Ticks at the unix times that are multiples of the interval.
+    Interval(u64),
+    Cron(Box<cron::Schedule>),
+}
+
+impl Ticker {
+    /// Unix time of the first tick after `now` and the round it starts.
+    fn next_tick(&self, now: DateTime<Utc>) -> Option<(DateTime<Utc>, u64)> {
+        match self {
+            Ticker::Interval(secs) => {
+                let round = u64::try_from(now.timestamp()).ok()? / secs + 1;
+                let tick = DateTime::from_timestamp(i64::try_from(round.checked_mul(*secs)?).ok()?, 0)?;
+                Some((tick, round))
+            }
+            Ticker::Cron(schedule) => {
+                let tick = schedule.after(&now).next()?;
+                Some((tick, u64::try_from(tick.timestamp()).ok()?))
+            }
+        }
+    }
+}
+
+struct LastPublished {
+    value: f64,
+    at: Instant,
+}
+
+impl FeedDefinition {
+    /// Checks what can be checked before the first tick: the cron expression and the headers of
+    /// every data source.
+    pub fn validate(&self) -> Result<()> {
+        if let FeedSchedule::Cron { expression } = &self.schedule {
+            cron::Schedule::from_str(expression)
+                .map_err(|e| anyhow!("ERROR: invalid cron expression {:?} for feed {}: {}", expression, self.id, e))?;
+        }
+        for source in &self.data_sources {
+            header_map(&source.headers)
+                .map_err(|e| anyhow!("ERROR: data source {} of feed {}: {}", source.name, self.id, e))?;
+        }
+        Ok(())
+    }
+}
+
+/// Reads and validates a feeds config, a JSON array of feed definitions.
+pub fn load_feeds(path: &Path) -> Result<Vec<FeedDefinition>> {
+    let config = fs::read_to_string(path)
+        .map_err(|e| anyhow!("ERROR: failed to read feeds config {:?}: {}", path, e))?;
+    let feeds: Vec<FeedDefinition> = serde_json::from_str(&config)
+        .map_err(|e| anyhow!("ERROR: invalid feeds config {:?}: {}", path, e))?;
+    for feed in &feeds {
+        feed.validate()?;
+    }
+    Ok(feeds)
+}
+
+/// Starts one task per feed, every published update is sent to `updates`.
+pub fn start_feeds(
+    feeds: Vec<FeedDefinition>,
+    registry: Arc<ScriptRegistry>,
+    scheduler: Scheduler,
+    updates: mpsc::Sender<FeedUpdate>,
+) -> Result<()> {
+    for feed in &feeds {
+        feed.validate()?;
+    }
+    for feed in feeds {
+        let registry = registry.clone();
+        let scheduler = scheduler.clone();
+        let updates = updates.clone();
+        tokio::spawn(async move {
+            if let Err(e) = run_feed(feed, registry, scheduler, updates).await {
+                println!("{}", e);
+            }
+        });
+    }
+    Ok(())
+}
+
+async fn run_feed(
+    feed: FeedDefinition,
+    registry: Arc<ScriptRegistry>,
+    scheduler: Scheduler,
+    updates: mpsc::Sender<FeedUpdate>,
+) -> Result<()> {
+    // The script is resolved on the first tick and retried on the following ones while it is
+    // unavailable, e.g. not yet deployed to the registry's source.
+    let mut script: Option<(String, String)> = None;
+    let mut last: Option<LastPublished> = None;
+    let ticker = match &feed.schedule {
+        FeedSchedule::Interval { secs } => Ticker::Interval((*secs).max(1)),
+        FeedSchedule::Cron { expression } => Ticker::Cron(Box::new(cron::Schedule::from_str(expression)?)),
+    };
+    loop {
+        let (tick, round) = ticker.next_tick(Utc::now())
+            .ok_or_else(|| anyhow!("ERROR: schedule of feed {} never fires again", feed.id))?;
+        sleep((tick - Utc::now()).to_std().unwrap_or_default()).await;
+        if script.is_none() {
+            match resolve_script(&feed, &registry).await {
+                Ok(resolved) => script = Some(resolved),
+                Err(e) => {
+                    println!("Feed {} script unavailable: {}", feed.id, e);
+                    continue;
+                }
+            }
+        }
+        let Some((abi, selector)) = &script else { continue };
+        let (value, output) = match run_once(&feed, abi, selector, &scheduler).await {
+            Ok(result) => result,
+            Err(e) => {
+                println!("Feed {} update failed: {}", feed.id, e);
+                continue;
+            }
+        };
+        let Some(reason) = publish_reason(&feed, last.as_ref(), value) else { continue };
+        last = Some(LastPublished { value, at: Instant::now() });
+        let update = FeedUpdate {
+            feed_id: feed.id.clone(),
+            script_hash: feed.script_hash.clone(),
+            round,
+            value,
+            output: format!("0x{}", hex::encode(output)),
+            timestamp: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+            reason,
+        };
+        if updates.send(update).await.is_err() {
+            return Ok(());
+        }
+    }
+}
+
+/// ABI and selector of the feed's script, fetched from the registry's source when missing.
+async fn resolve_script(feed: &FeedDefinition, registry: &Arc<ScriptRegistry>) -> Result<(String, String)> {
+    registry.resolve(&feed.script_hash).await?;
+    let abi = registry.load(&feed.script_hash)?.abi.clone();
+    let selector = match &feed.selector {
+        Some(selector) => selector.clone(),
+        None => default_selector(&abi)?,
+    };
+    Ok((abi, selector))
+}
+
+async fn run_once(feed: &FeedDefinition, abi: &str, selector: &str, scheduler: &Scheduler) -> Result<(f64, Vec<u8>)> {
+    let mut input = feed.input_template.clone();
+    for source in &feed.data_sources {
+        let params: HashMap<&str, &str> = source.params.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+        let body = fetch_with_query(&source.url, Some(&params), Some(source.headers.clone()), FETCH_TIMEOUT).await?;
+        input = input.replace(&format!("{{{{{}}}}}", source.name), body.trim());
+    }
+    let job_id = scheduler.submit(JobRequest {
+        script_hash: feed.script_hash.clone(),
+        selector: Some(selector.to_string()),
+        input,
+        timeout_ms: None,
+    })?;
+    let output = match scheduler.wait(job_id).await {
+        Some(JobStatus::Succeeded { output }) => decode_hex(&output)?,
+        Some(status) => return Err(anyhow!("ERROR: job {} ended as {:?}", job_id, status)),
+        None => return Err(anyhow!("ERROR: job {} disappeared", job_id)),
+    };
+    let value = read_numeric_field(abi, selector, &output, &feed.value_field)?;
+    Ok((value, output))
+}
+
+fn publish_reason(feed: &FeedDefinition, last: Option<&LastPublished>, value: f64) -> Option<PublishReason> {
+    let Some(last) = last else { return Some(PublishReason::First) };
+    let deviation = if last.value == 0.0 {
+        if value == 0.0 { 0.0 } else { f64::INFINITY }
+    } else {
+        ((value - last.value) / last.value).abs() * 100.0
+    };
+    if deviation > feed.deviation_threshold {
+        Some(PublishReason::Deviation)
+    } else if last.at.elapsed() >= Duration::from_secs(feed.heartbeat_secs) {
+        Some(PublishReason::Heartbeat)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(secs: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp(secs, 0).unwrap()
+    }
+
+    fn feed(deviation_threshold: f64, heartbeat_secs: u64) -> FeedDefinition {
+        FeedDefinition {
+            id: "btc".to_string(),
+            script_hash: String::new(),
+            selector: None,
+            input_template: String::new(),
+            data_sources: Vec::new(),
+            schedule: FeedSchedule::Interval { secs: 10 },
+            deviation_threshold,
+            heartbeat_secs,
+            value_field: "value".to_string(),
+        }
+    }
+
+    fn published(value: f64, secs_ago: u64) -> LastPublished {
+        LastPublished { value, at: Instant::now().checked_sub(Duration::from_secs(secs_ago)).unwrap() }
+    }
+
+    #[test]
+    fn first_value_is_published() {
+        assert_eq!(publish_reason(&feed(1.0, 60), None, 100.0), Some(PublishReason::First));
+    }
+
+    #[test]
+    fn deviation_above_the_threshold_is_published() {
+        let feed = feed(1.0, 60);
+        assert_eq!(publish_reason(&feed, Some(&published(100.0, 0)), 100.5), None);
+        assert_eq!(publish_reason(&feed, Some(&published(100.0, 0)), 101.0), None);
+        assert_eq!(publish_reason(&feed, Some(&published(100.0, 0)), 101.5), Some(PublishReason::Deviation));
+        assert_eq!(publish_reason(&feed, Some(&published(100.0, 0)), 98.5), Some(PublishReason::Deviation));
+    }
+
+    #[test]
+    fn heartbeat_publishes_unchanged_values() {
+        let feed = feed(1.0, 60);
+        assert_eq!(publish_reason(&feed, Some(&published(100.0, 59)), 100.0), None);
+        assert_eq!(publish_reason(&feed, Some(&published(100.0, 60)), 100.0), Some(PublishReason::Heartbeat));
+        assert_eq!(publish_reason(&feed, Some(&published(100.0, 120)), 150.0), Some(PublishReason::Deviation));
+    }
+
+    #[test]
+    fn any_move_away_from_zero_is_a_deviation() {
+        let feed = feed(1.0, 60);
+        assert_eq!(publish_reason(&feed, Some(&published(0.0, 0)), 0.0), None);
+        assert_eq!(publish_reason(&feed, Some(&published(0.0, 0)), 0.001), Some(PublishReason::Deviation));
+        assert_eq!(publish_reason(&feed, Some(&published(0.0, 0)), -5.0), Some(PublishReason::Deviation));
+    }
+
+    #[test]
+    fn invalid_headers_are_rejected() {
+        let mut feed = feed(1.0, 60);
+        let source = |name: &str, value: &str| DataSource {
+            name: "prices".to_string(),
+            url: "http://127.0.0.1".to_string(),
+            params: HashMap::new(),
+            headers: HashMap::from([(name.to_string(), value.to_string())]),
+        };
+        feed.data_sources = vec![source("x-api-key", "secret")];
+        assert!(feed.validate().is_ok());
+        feed.data_sources = vec![source("x api key", "secret")];
+        assert!(feed.validate().is_err());
+        feed.data_sources = vec![source("x-api-key", "line\nbreak")];
+        assert!(feed.validate().is_err());
+    }
+
+    #[test]
+    fn interval_rounds_follow_the_clock() {
+        let ticker = Ticker::Interval(30);
+        assert_eq!(ticker.next_tick(at(1_700_000_000)), Some((at(1_700_000_010), 56_666_667)));
+        assert_eq!(ticker.next_tick(at(1_700_000_010)), Some((at(1_700_000_040), 56_666_668)));
+        assert_eq!(ticker.next_tick(at(1_700_000_039)), Some((at(1_700_000_040), 56_666_668)));
+    }
+
+    #[test]
+    fn cron_rounds_are_the_tick_time() {
+        let ticker = Ticker::Cron(Box::new(cron::Schedule::from_str("0 * * * * *").unwrap()));
+        assert_eq!(ticker.next_tick(at(1_700_000_000)), Some((at(1_700_000_040), 1_700_000_040)));
+        assert_eq!(ticker.next_tick(at(1_700_000_040)), Some((at(1_700_000_100), 1_700_000_100)));
+    }
+}
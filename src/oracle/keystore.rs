@@ -0,0 +1,118 @@
+use std::fs;
+use std::path::Path;
+use anyhow::{anyhow, Result};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use rand::rngs::OsRng;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize)]
+struct KeyFile {
+    public: String,
+    secret: String,
+}
+
+/// Ed25519 identity of an oracle node. The hex encoded public key doubles as the node id.
+pub struct NodeKey {
+    signing: SigningKey,
+}
+
+impl NodeKey {
+    pub fn generate() -> Self {
+        NodeKey { signing: SigningKey::generate(&mut OsRng) }
+    }
+
+    pub fn from_seed(seed: [u8; 32]) -> Self {
+        NodeKey { signing: SigningKey::from_bytes(&seed) }
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| anyhow!("ERROR: failed to read key file {:?}: {}", path, e))?;
+        let key_file: KeyFile = serde_json::from_str(&content)?;
+        let seed: [u8; 32] = decode_hex(&key_file.secret)?
+            .try_into()
+            .map_err(|_| anyhow!("ERROR: secret key in {:?} must be 32 bytes", path))?;
+        let key = NodeKey::from_seed(seed);
+        if key.public() != key_file.public {
+            return Err(anyhow!("ERROR: public key in {:?} does not match its secret", path));
+        }
+        Ok(key)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let key_file = KeyFile {
+            public: self.public(),
+            secret: format!("0x{}", hex::encode(self.signing.to_bytes())),
+        };
+        fs::write(path, serde_json::to_string_pretty(&key_file)?)?;
+        Ok(())
+    }
+
+    pub fn public(&self) -> String {
+        format!("0x{}", hex::encode(self.signing.verifying_key().to_bytes()))
+    }
+
+    pub fn sign(&self, message: &[u8]) -> String {
+        format!("0x{}", hex::encode(self.sign_bytes(message)))
+    }
+
+    pub fn public_bytes(&self) -> [u8; 32] {
+        self.signing.verifying_key().to_bytes()
+    }
+
+    pub fn sign_bytes(&self, message: &[u8]) -> [u8; 64] {
+        self.signing.sign(message).to_bytes()
+    }
+}
+
+pub fn verify_signature(public: &str, message: &[u8], signature: &str) -> Result<()> {
+    let public: [u8; 32] = decode_hex(public)?
+        .try_into()
+        .map_err(|_| anyhow!("ERROR: public key must be 32 bytes"))?;
+    let signature: [u8; 64] = decode_hex(signature)?
+        .try_into()
+        .map_err(|_| anyhow!("ERROR: signature must be 64 bytes"))?;
+    VerifyingKey::from_bytes(&public)?
+        .verify(message, &Signature::from_bytes(&signature))
+        .map_err(|e| anyhow!("ERROR: invalid signature: {}", e))
+}
+
+pub fn decode_hex(value: &str) -> Result<Vec<u8>> {
+    hex::decode(value.trim_start_matches("0x"))
+        .map_err(|e| anyhow!("ERROR: invalid hex value {:?}: {}", value, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::testing::temp_dir;
+
+    #[test]
+    fn saved_keys_load_back() {
+        let dir = temp_dir("keystore");
+        let key = NodeKey::generate();
+        key.save(&dir.join("node.json")).unwrap();
+        let loaded = NodeKey::load(&dir.join("node.json")).unwrap();
+        assert_eq!(loaded.public(), key.public());
+        verify_signature(&key.public(), b"message", &loaded.sign(b"message")).unwrap();
+
+        let mut file: KeyFile = serde_json::from_str(&fs::read_to_string(dir.join("node.json")).unwrap()).unwrap();
+        file.public = NodeKey::from_seed([1; 32]).public();
+        fs::write(dir.join("other.json"), serde_json::to_string(&file).unwrap()).unwrap();
+        assert!(NodeKey::load(&dir.join("other.json")).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn rejects_foreign_and_tampered_signatures() {
+        let key = NodeKey::from_seed([1; 32]);
+        let other = NodeKey::from_seed([2; 32]);
+        let signature = key.sign(b"message");
+        verify_signature(&key.public(), b"message", &signature).unwrap();
+        assert!(verify_signature(&key.public(), b"other message", &signature).is_err());
+        assert!(verify_signature(&other.public(), b"message", &signature).is_err());
+        assert!(verify_signature(&key.public(), b"message", &other.sign(b"message")).is_err());
+        assert!(verify_signature(&key.public(), b"message", &signature[..signature.len() - 2]).is_err());
+        assert!(verify_signature("0x01", b"message", &signature).is_err());
+    }
+}
@@ -0,0 +1,318 @@
+use anyhow::{anyhow, Result};
+use parity_scale_codec::Encode;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sp_core_hashing::blake2_256;
+use crate::oracle::extrinsic::fixed_hex;
+
+/// Hash of the tree nodes and of the outputs committed to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MerkleHash {
+    #[serde(rename = "blake2-256")]
+    Blake2_256,
+    #[serde(rename = "sha256")]
+    Sha256,
+}
+
+/// One committed result, its leaf is the SCALE encoded `(request_id, output_hash)`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BatchLeaf {
+    pub request_id: u64,
+    pub output_hash: [u8; 32],
+}
+
+/// Results of one batch, committed to by a single root. The tree matches Substrate's
+/// `binary_merkle_tree`: leaves are hashed, a node hashes its children concatenated and the last
+/// node of an odd layer is promoted unchanged, so the pallet can check proofs with its
+/// `verify_proof`.
+#[derive(Debug, Clone)]
+pub struct ResultBatch {
+    hash: MerkleHash,
+    leaves: Vec<BatchLeaf>,
+}
+
+/// Inclusion proof of one result in a batch root.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MerkleProof {
+    pub hash: MerkleHash,
+    pub root: String,
+    pub leaf_index: usize,
+    pub number_of_leaves: usize,
+    pub request_id: u64,
+    pub output_hash: String,
+    /// Sibling hashes from the leaf up to the root.
+    pub proof: Vec<String>,
+}
+
+/// Root of a batch together with the proof of every result.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatchCommitment {
+    pub hash: MerkleHash,
+    pub root: String,
+    pub proofs: Vec<MerkleProof>,
+}
+
+impl MerkleHash {
+    pub fn hash(&self, data: &[u8]) -> [u8; 32] {
+        match self {
+            MerkleHash::Blake2_256 => blake2_256(data),
+            MerkleHash::Sha256 => Sha256::digest(data).into(),
+        }
+    }
+
+    fn hash_pair(&self, left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+        self.hash(&[left.as_slice(), right].concat())
+    }
+}
+
+impl std::str::FromStr for MerkleHash {
+    type Err = anyhow::Error;
+
+    fn from_str(hash: &str) -> Result<Self> {
+        match hash {
+            "blake2-256" => Ok(MerkleHash::Blake2_256),
+            "sha256" => Ok(MerkleHash::Sha256),
+            _ => Err(anyhow!("ERROR: unknown merkle hash {:?}", hash)),
+        }
+    }
+}
+
+impl BatchLeaf {
+    fn encode_leaf(&self) -> Vec<u8> {
+        (self.request_id, self.output_hash).encode()
+    }
+}
+
+impl ResultBatch {
+    pub fn new(hash: MerkleHash) -> Self {
+        ResultBatch { hash, leaves: Vec::new() }
+    }
+
+    /// Adds a result, the output is committed to by its hash. A request can only be answered once
+    /// per batch.
+    pub fn push(&mut self, request_id: u64, output: &[u8]) -> Result<()> {
+        if self.leaves.iter().any(|leaf| leaf.request_id == request_id) {
+            return Err(anyhow!("ERROR: request {} is already in the batch", request_id));
+        }
+        let output_hash = self.hash.hash(output);
+        self.leaves.push(BatchLeaf { request_id, output_hash });
+        Ok(())
+    }
+
+    /// Root submitted on chain, all zeros for an empty batch.
+    pub fn root(&self) -> [u8; 32] {
+        let mut layer = self.leaf_hashes();
+        if layer.is_empty() {
+            return [0u8; 32];
+        }
+        while layer.len() > 1 {
+            layer = self.next_layer(&layer);
+        }
+        layer[0]
+    }
+
+    pub fn proof(&self, leaf_index: usize) -> Result<MerkleProof> {
+        let leaf = self.leaves.get(leaf_index)
+            .ok_or_else(|| anyhow!("ERROR: leaf {} is out of range for {} leaves", leaf_index, self.leaves.len()))?;
+        let mut layer = self.leaf_hashes();
+        let mut position = leaf_index;
+        let mut proof = Vec::new();
+        while layer.len() > 1 {
+            let sibling = position ^ 1;
+            if sibling < layer.len() {
+                proof.push(format!("0x{}", hex::encode(layer[sibling])));
+            }
+            layer = self.next_layer(&layer);
+            position /= 2;
+        }
+        Ok(MerkleProof {
+            hash: self.hash,
+            root: format!("0x{}", hex::encode(layer[0])),
+            leaf_index,
+            number_of_leaves: self.leaves.len(),
+            request_id: leaf.request_id,
+            output_hash: format!("0x{}", hex::encode(leaf.output_hash)),
+            proof,
+        })
+    }
+
+    pub fn commitment(&self) -> Result<BatchCommitment> {
+        Ok(BatchCommitment {
+            hash: self.hash,
+            root: format!("0x{}", hex::encode(self.root())),
+            proofs: (0..self.leaves.len()).map(|index| self.proof(index)).collect::<Result<Vec<_>>>()?,
+        })
+    }
+
+    fn leaf_hashes(&self) -> Vec<[u8; 32]> {
+        self.leaves.iter().map(|leaf| self.hash.hash(&leaf.encode_leaf())).collect()
+    }
+
+    fn next_layer(&self, layer: &[[u8; 32]]) -> Vec<[u8; 32]> {
+        layer.chunks(2).map(|pair| match pair {
+            [left, right] => self.hash.hash_pair(left, right),
+            [single] => *single,
+            _ => unreachable!("chunks of at most 2"),
+        }).collect()
+    }
+}
+
+impl MerkleProof {
+    /// Recomputes the root from the leaf and the sibling hashes and checks it against `root`, the
+    /// trusted root submitted on chain rather than the one the proof carries.
+    pub fn verify(&self, root: &[u8; 32]) -> Result<()> {
+        if self.leaf_index >= self.number_of_leaves {
+            return Err(anyhow!("ERROR: leaf {} is out of range for {} leaves", self.leaf_index, self.number_of_leaves));
+        }
+        let leaf = BatchLeaf { request_id: self.request_id, output_hash: fixed_hex(&self.output_hash, "output hash")? };
+        let mut computed = self.hash.hash(&leaf.encode_leaf());
+        let mut siblings = self.proof.iter();
+        let mut position = self.leaf_index;
+        let mut width = self.number_of_leaves;
+        while width > 1 {
+            // The last node of an odd layer has no sibling and is promoted as is.
+            if position % 2 == 1 || position + 1 < width {
+                let sibling = siblings.next()
+                    .ok_or_else(|| anyhow!("ERROR: merkle proof of request {} is too short", self.request_id))?;
+                let sibling = fixed_hex::<32>(sibling, "proof item")?;
+                computed = if position % 2 == 1 {
+                    self.hash.hash_pair(&sibling, &computed)
+                } else {
+                    self.hash.hash_pair(&computed, &sibling)
+                };
+            }
+            position /= 2;
+            width = width.div_ceil(2);
+        }
+        if siblings.next().is_some() {
+            return Err(anyhow!("ERROR: merkle proof of request {} is too long", self.request_id));
+        }
+        if computed != *root {
+            return Err(anyhow!("ERROR: merkle proof of request {} does not match root 0x{}", self.request_id, hex::encode(root)));
+        }
+        Ok(())
+    }
+
+    /// Checks the proof against `root` and that it commits to `output`.
+    pub fn verify_output(&self, root: &[u8; 32], output: &[u8]) -> Result<()> {
+        if self.hash.hash(output) != fixed_hex::<32>(&self.output_hash, "output hash")? {
+            return Err(anyhow!("ERROR: output does not match the hash committed for request {}", self.request_id));
+        }
+        self.verify(root)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::hash_map::DefaultHasher;
+    use hash_db::Hasher;
+    use super::*;
+
+    struct Blake2Hasher;
+    struct Sha256Hasher;
+
+    impl Hasher for Blake2Hasher {
+        type Out = [u8; 32];
+        type StdHasher = DefaultHasher;
+        const LENGTH: usize = 32;
+
+        fn hash(data: &[u8]) -> [u8; 32] {
+            MerkleHash::Blake2_256.hash(data)
+        }
+    }
+
+    impl Hasher for Sha256Hasher {
+        type Out = [u8; 32];
+        type StdHasher = DefaultHasher;
+        const LENGTH: usize = 32;
+
+        fn hash(data: &[u8]) -> [u8; 32] {
+            MerkleHash::Sha256.hash(data)
+        }
+    }
+
+    fn batch(hash: MerkleHash, len: u64) -> ResultBatch {
+        let mut batch = ResultBatch::new(hash);
+        for request_id in 0..len {
+            batch.push(100 + request_id, &request_id.to_le_bytes()).unwrap();
+        }
+        batch
+    }
+
+    /// Root and proofs as `binary_merkle_tree` computes them for the same leaves.
+    fn check_against_substrate<H: Hasher<Out = [u8; 32]>>(batch: &ResultBatch) {
+        let leaves: Vec<Vec<u8>> = batch.leaves.iter().map(BatchLeaf::encode_leaf).collect();
+        let root = batch.root();
+        assert_eq!(root, binary_merkle_tree::merkle_root::<H, _>(&leaves), "{} leaves", leaves.len());
+        for (index, leaf) in leaves.iter().enumerate() {
+            let proof = batch.proof(index).unwrap();
+            proof.verify(&root).unwrap();
+            let expected = binary_merkle_tree::merkle_proof::<H, _, _>(&leaves, index as u32);
+            let items: Vec<[u8; 32]> = proof.proof.iter().map(|item| fixed_hex(item, "proof item").unwrap()).collect();
+            assert_eq!(items, expected.proof, "leaf {} of {}", index, leaves.len());
+            assert!(binary_merkle_tree::verify_proof::<H, _, _>(&root, items, leaves.len() as u32, index as u32, leaf));
+        }
+    }
+
+    #[test]
+    fn roots_and_proofs_match_binary_merkle_tree() {
+        for len in 1..=9 {
+            check_against_substrate::<Blake2Hasher>(&batch(MerkleHash::Blake2_256, len));
+            check_against_substrate::<Sha256Hasher>(&batch(MerkleHash::Sha256, len));
+        }
+    }
+
+    #[test]
+    fn single_leaves_and_odd_layers() {
+        let one = batch(MerkleHash::Blake2_256, 1);
+        let leaf = one.leaf_hashes()[0];
+        assert_eq!(one.root(), leaf);
+        assert!(one.proof(0).unwrap().proof.is_empty());
+        one.proof(0).unwrap().verify(&leaf).unwrap();
+
+        // The third leaf has no sibling and is promoted unchanged to the next layer.
+        let three = batch(MerkleHash::Sha256, 3);
+        let [a, b, c] = three.leaf_hashes()[..] else { unreachable!() };
+        let hash = MerkleHash::Sha256;
+        assert_eq!(three.root(), hash.hash_pair(&hash.hash_pair(&a, &b), &c));
+        assert_eq!(three.proof(2).unwrap().proof, [format!("0x{}", hex::encode(hash.hash_pair(&a, &b)))]);
+        assert_eq!(ResultBatch::new(hash).root(), [0u8; 32]);
+    }
+
+    #[test]
+    fn tampered_proofs_are_rejected() {
+        let batch = batch(MerkleHash::Blake2_256, 5);
+        let root = batch.root();
+        let proof = batch.proof(1).unwrap();
+        proof.verify_output(&root, &1u64.to_le_bytes()).unwrap();
+
+        let mut sibling = proof.clone();
+        sibling.proof[0] = format!("0x{}", "00".repeat(32));
+        assert!(sibling.verify(&root).is_err());
+        let mut index = proof.clone();
+        index.leaf_index = 0;
+        assert!(index.verify(&root).is_err());
+        index.leaf_index = 5;
+        assert!(index.verify(&root).is_err());
+        let e = proof.verify_output(&root, &2u64.to_le_bytes()).unwrap_err();
+        assert_eq!(e.to_string(), "ERROR: output does not match the hash committed for request 101");
+        // A proof is checked against the root submitted on chain, not the one it carries.
+        let mut forged_batch = ResultBatch::new(MerkleHash::Blake2_256);
+        forged_batch.push(101, b"forged").unwrap();
+        let forged = forged_batch.proof(0).unwrap();
+        forged.verify(&fixed_hex(&forged.root, "root").unwrap()).unwrap();
+        assert!(forged.verify(&root).is_err());
+        // Output hashes are compared as bytes, whatever the case of their hex.
+        let mut upper = proof.clone();
+        upper.output_hash = upper.output_hash.to_uppercase().replacen("0X", "0x", 1);
+        upper.verify_output(&root, &1u64.to_le_bytes()).unwrap();
+    }
+
+    #[test]
+    fn requests_are_batched_once() {
+        let mut batch = batch(MerkleHash::Sha256, 2);
+        let e = batch.push(101, b"again").unwrap_err();
+        assert_eq!(e.to_string(), "ERROR: request 101 is already in the batch");
+        assert_eq!(batch.leaves.len(), 2);
+    }
+}
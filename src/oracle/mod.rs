@@ -0,0 +1,8 @@
+pub mod aggregator;
+pub mod keystore;
+pub mod feeds;
+pub mod extrinsic;
+pub mod merkle;
+pub mod commit_reveal;
+pub mod simulator;
+pub mod reputation;
\ No newline at end of file
@@ -0,0 +1,394 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use anyhow::{anyhow, Result};
+use parity_scale_codec::Encode;
+use serde::{Deserialize, Serialize};
+use crate::oracle::aggregator::{AggregatedReport, SignedReport};
+use crate::oracle::keystore::{decode_hex, verify_signature, NodeKey};
+
+/// Rounds whose reports are kept for equivocation checks and scoring, oldest dropped first.
+const RETAINED_ROUNDS: usize = 1024;
+/// Pieces of evidence kept for submission, oldest dropped first.
+const RETAINED_EVIDENCE: usize = 1024;
+
+/// Proof that `offender` signed two different outputs for the same round of a script, signed
+/// by the node that observed it. Both reports verify on their own, so anyone can check it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MisbehaviorEvidence {
+    pub offender: String,
+    pub round: u64,
+    pub script_hash: String,
+    pub first: SignedReport,
+    pub second: SignedReport,
+    pub reporter: String,
+    pub signature: String,
+}
+
+/// Track record of one node across the rounds recorded so far.
+#[derive(Debug, Clone, Serialize)]
+pub struct NodeMetrics {
+    pub signer: String,
+    pub rounds: u64,
+    /// Rounds the node's report was attested in or matched the aggregated output.
+    pub agreed: u64,
+    pub disagreed: u64,
+    /// Recorded rounds without a report from the node.
+    pub missed: u64,
+    /// Share of the recorded rounds the node agreed in.
+    pub agreement_rate: f64,
+    /// Mean delay behind the first report seen in a round.
+    pub mean_latency_ms: f64,
+    pub evidence: usize,
+}
+
+/// How the nodes did in one recorded round.
+#[derive(Debug, Clone, Serialize)]
+pub struct RoundSummary {
+    pub round: u64,
+    pub script_hash: String,
+    pub agreed: Vec<String>,
+    pub disagreed: Vec<String>,
+    pub missed: Vec<String>,
+    /// Signers of attestations that failed verification, skipped when scoring.
+    pub rejected: Vec<String>,
+}
+
+/// Tracks per-node metrics from the reports and aggregated results a node sees and keeps the
+/// evidence of equivocating nodes for submission to the chain.
+#[derive(Clone)]
+pub struct Reputation {
+    key: Arc<NodeKey>,
+    state: Arc<Mutex<ReputationState>>,
+}
+
+#[derive(Default)]
+struct ReputationState {
+    /// Nodes scored in every recorded round, the nodes seen so far when empty.
+    authorities: BTreeSet<String>,
+    nodes: BTreeMap<String, NodeRecord>,
+    rounds: BTreeMap<(u64, String), RoundRecord>,
+    evidence: Vec<MisbehaviorEvidence>,
+}
+
+#[derive(Default)]
+struct NodeRecord {
+    agreed: u64,
+    disagreed: u64,
+    missed: u64,
+    latency_total: Duration,
+    latency_samples: u64,
+    evidence: usize,
+}
+
+struct RoundRecord {
+    first_seen: Instant,
+    reports: BTreeMap<String, (SignedReport, Duration)>,
+    /// Offenders evidence was produced for, one piece of evidence per node and round.
+    equivocated: BTreeSet<String>,
+    recorded: bool,
+}
+
+fn evidence_payload(first: &SignedReport, second: &SignedReport) -> Vec<u8> {
+    (
+        b"orascript/evidence",
+        first.signer.as_str(),
+        first.round,
+        first.script_hash.as_str(),
+        [first.output.as_str(), first.signature.as_str()],
+        [second.output.as_str(), second.signature.as_str()],
+    ).encode()
+}
+
+impl MisbehaviorEvidence {
+    /// Builds evidence from two conflicting reports, signed by `reporter`.
+    pub fn new(reporter: &NodeKey, first: SignedReport, second: SignedReport) -> Result<Self> {
+        check_conflict(&first, &second)?;
+        let signature = reporter.sign(&evidence_payload(&first, &second));
+        Ok(MisbehaviorEvidence {
+            offender: first.signer.clone(),
+            round: first.round,
+            script_hash: first.script_hash.clone(),
+            first,
+            second,
+            reporter: reporter.public(),
+            signature,
+        })
+    }
+
+    pub fn verify(&self) -> Result<()> {
+        check_conflict(&self.first, &self.second)?;
+        if self.first.signer != self.offender || self.first.round != self.round || self.first.script_hash != self.script_hash {
+            return Err(anyhow!("ERROR: evidence does not match its reports"));
+        }
+        verify_signature(&self.reporter, &evidence_payload(&self.first, &self.second), &self.signature)
+    }
+}
+
+/// Both reports must be validly signed by the same node for the same round and disagree.
+fn check_conflict(first: &SignedReport, second: &SignedReport) -> Result<()> {
+    if first.signer != second.signer || first.round != second.round || first.script_hash != second.script_hash {
+        return Err(anyhow!("ERROR: reports are not from the same node and round"));
+    }
+    if first.output_bytes()? == second.output_bytes()? {
+        return Err(anyhow!("ERROR: reports of {} for round {} do not conflict", first.signer, first.round));
+    }
+    first.verify()?;
+    second.verify()
+}
+
+impl Reputation {
+    /// `key` signs the evidence produced. Without `authorities` every node seen is scored.
+    pub fn new(key: NodeKey, authorities: &[String]) -> Self {
+        let state = ReputationState { authorities: authorities.iter().cloned().collect(), ..Default::default() };
+        Reputation { key: Arc::new(key), state: Arc::new(Mutex::new(state)) }
+    }
+
+    /// Records a signed report seen for a round. A second report of the same node for the same
+    /// round with another output yields evidence of equivocation.
+    pub fn observe(&self, report: SignedReport) -> Result<Option<MisbehaviorEvidence>> {
+        report.verify()?;
+        let mut state = self.state.lock().unwrap();
+        if !state.authorities.is_empty() && !state.authorities.contains(&report.signer) {
+            return Err(anyhow!("ERROR: signer {} is not an authority", report.signer));
+        }
+        let round = state.round(report.round, &report.script_hash);
+        let latency = round.first_seen.elapsed();
+        let first = match round.reports.get(&report.signer) {
+            None => {
+                round.reports.insert(report.signer.clone(), (report, latency));
+                return Ok(None);
+            }
+            // Outputs are compared decoded, the same bytes may be hex encoded in another case.
+            Some((first, _)) if first.output_bytes()? == report.output_bytes()? => return Ok(None),
+            Some((first, _)) => first.clone(),
+        };
+        if round.equivocated.contains(&report.signer) {
+            return Ok(None);
+        }
+        let evidence = MisbehaviorEvidence::new(&self.key, first, report)?;
+        round.equivocated.insert(evidence.offender.clone());
+        state.nodes.entry(evidence.offender.clone()).or_default().evidence += 1;
+        state.push_evidence(evidence.clone());
+        Ok(Some(evidence))
+    }
+
+    /// Scores every node against the aggregated result of a round, its attestations count as
+    /// observed reports. Attestations that fail verification are skipped and listed as rejected.
+    pub fn record(&self, aggregated: &AggregatedReport) -> Result<RoundSummary> {
+        let output = decode_hex(&aggregated.output)?;
+        let mut rejected = Vec::new();
+        for attestation in &aggregated.attestations {
+            let report = SignedReport {
+                round: aggregated.round,
+                script_hash: aggregated.script_hash.clone(),
+                output: attestation.output.clone(),
+                signer: attestation.signer.clone(),
+                signature: attestation.signature.clone(),
+            };
+            if self.observe(report).is_err() {
+                rejected.push(attestation.signer.clone());
+            }
+        }
+        let mut state = self.state.lock().unwrap();
+        let nodes: Vec<String> = if state.authorities.is_empty() {
+            state.nodes.keys().chain(state.rounds.values().flat_map(|round| round.reports.keys()))
+                .cloned().collect::<BTreeSet<_>>().into_iter().collect()
+        } else {
+            state.authorities.iter().cloned().collect()
+        };
+        let round = state.round(aggregated.round, &aggregated.script_hash);
+        if round.recorded {
+            return Err(anyhow!("ERROR: round {} of {} is already recorded", aggregated.round, aggregated.script_hash));
+        }
+        round.recorded = true;
+        // Kept reports verified, so their outputs decode.
+        let reports: BTreeMap<String, (Vec<u8>, Duration)> = round.reports.iter()
+            .map(|(signer, (report, latency))| Ok((signer.clone(), (report.output_bytes()?, *latency))))
+            .collect::<Result<_>>()?;
+        let mut summary = RoundSummary {
+            round: aggregated.round,
+            script_hash: aggregated.script_hash.clone(),
+            agreed: Vec::new(),
+            disagreed: Vec::new(),
+            missed: Vec::new(),
+            rejected,
+        };
+        for signer in nodes {
+            let record = state.nodes.entry(signer.clone()).or_default();
+            match reports.get(&signer) {
+                None => {
+                    record.missed += 1;
+                    summary.missed.push(signer);
+                }
+                Some((reported, latency)) => {
+                    record.latency_total += *latency;
+                    record.latency_samples += 1;
+                    if *reported == output || aggregated.signers().contains(&signer.as_str()) {
+                        record.agreed += 1;
+                        summary.agreed.push(signer);
+                    } else {
+                        record.disagreed += 1;
+                        summary.disagreed.push(signer);
+                    }
+                }
+            }
+        }
+        Ok(summary)
+    }
+
+    pub fn metrics(&self) -> Vec<NodeMetrics> {
+        let state = self.state.lock().unwrap();
+        state.nodes.iter().map(|(signer, record)| {
+            let rounds = record.agreed + record.disagreed + record.missed;
+            NodeMetrics {
+                signer: signer.clone(),
+                rounds,
+                agreed: record.agreed,
+                disagreed: record.disagreed,
+                missed: record.missed,
+                agreement_rate: if rounds == 0 { 0.0 } else { record.agreed as f64 / rounds as f64 },
+                mean_latency_ms: if record.latency_samples == 0 {
+                    0.0
+                } else {
+                    record.latency_total.as_secs_f64() * 1000.0 / record.latency_samples as f64
+                },
+                evidence: record.evidence,
+            }
+        }).collect()
+    }
+
+    pub fn evidence(&self) -> Vec<MisbehaviorEvidence> {
+        self.state.lock().unwrap().evidence.clone()
+    }
+}
+
+impl ReputationState {
+    fn push_evidence(&mut self, evidence: MisbehaviorEvidence) {
+        if self.evidence.len() >= RETAINED_EVIDENCE {
+            self.evidence.remove(0);
+        }
+        self.evidence.push(evidence);
+    }
+
+    fn round(&mut self, round: u64, script_hash: &str) -> &mut RoundRecord {
+        let key = (round, script_hash.to_string());
+        if !self.rounds.contains_key(&key) && self.rounds.len() >= RETAINED_ROUNDS {
+            self.rounds.pop_first();
+        }
+        self.rounds.entry(key).or_insert_with(|| RoundRecord {
+            first_seen: Instant::now(),
+            reports: BTreeMap::new(),
+            equivocated: BTreeSet::new(),
+            recorded: false,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::oracle::aggregator::{AggregationMode, Aggregator};
+
+    const SCRIPT: &str = "0x01";
+
+    fn nodes(count: u8) -> (Vec<NodeKey>, Vec<String>) {
+        let keys: Vec<NodeKey> = (0..count).map(|i| NodeKey::from_seed([i + 1; 32])).collect();
+        let authorities = keys.iter().map(|key| key.public()).collect();
+        (keys, authorities)
+    }
+
+    #[test]
+    fn equivocation_yields_evidence_once() {
+        let (keys, authorities) = nodes(2);
+        let reputation = Reputation::new(NodeKey::from_seed([9; 32]), &authorities);
+        assert!(reputation.observe(SignedReport::new(&keys[0], 1, SCRIPT, b"a")).unwrap().is_none());
+        let evidence = reputation.observe(SignedReport::new(&keys[0], 1, SCRIPT, b"b")).unwrap().unwrap();
+        evidence.verify().unwrap();
+        assert_eq!(evidence.offender, keys[0].public());
+        assert!(reputation.observe(SignedReport::new(&keys[0], 1, SCRIPT, b"c")).unwrap().is_none());
+        assert_eq!(reputation.evidence().len(), 1);
+
+        let mut forged = evidence.clone();
+        forged.second = SignedReport::new(&keys[1], 1, SCRIPT, b"b");
+        assert!(forged.verify().is_err());
+    }
+
+    #[test]
+    fn same_output_in_another_hex_case_is_not_equivocation() {
+        let (keys, authorities) = nodes(1);
+        let reputation = Reputation::new(NodeKey::from_seed([9; 32]), &authorities);
+        let report = SignedReport::new(&keys[0], 1, SCRIPT, &[0xab]);
+        reputation.observe(report.clone()).unwrap();
+        let upper = SignedReport { output: report.output.to_uppercase().replace("0X", "0x"), ..report };
+        assert!(reputation.observe(upper).unwrap().is_none());
+        assert!(reputation.evidence().is_empty());
+    }
+
+    #[test]
+    fn rejected_reports_do_not_mark_the_node() {
+        let (keys, authorities) = nodes(1);
+        let reputation = Reputation::new(NodeKey::from_seed([9; 32]), &authorities);
+        reputation.observe(SignedReport::new(&keys[0], 1, SCRIPT, b"a")).unwrap();
+        // A second report for another script of the same round is a separate record.
+        assert!(reputation.observe(SignedReport::new(&keys[0], 1, "0x02", b"b")).unwrap().is_none());
+        let mut unsigned = SignedReport::new(&keys[0], 1, SCRIPT, b"b");
+        unsigned.signature = SignedReport::new(&keys[0], 1, SCRIPT, b"c").signature;
+        assert!(reputation.observe(unsigned).is_err());
+        assert!(reputation.observe(SignedReport::new(&keys[0], 1, SCRIPT, b"b")).unwrap().is_some());
+    }
+
+    #[test]
+    fn rounds_are_scored_against_the_aggregated_report() {
+        let (keys, authorities) = nodes(4);
+        let reputation = Reputation::new(NodeKey::from_seed([9; 32]), &authorities);
+        let mut aggregator = Aggregator::new(3, SCRIPT, &authorities, 3, AggregationMode::Exact).unwrap();
+        for key in &keys[..3] {
+            aggregator.submit(SignedReport::new(key, 3, SCRIPT, b"a")).unwrap();
+        }
+        let dissent = SignedReport::new(&keys[3], 3, SCRIPT, b"b");
+        reputation.observe(dissent).unwrap();
+        let summary = reputation.record(&aggregator.finalize().unwrap()).unwrap();
+        assert_eq!(summary.agreed.len(), 3);
+        assert_eq!(summary.disagreed, vec![keys[3].public()]);
+        assert!(summary.missed.is_empty());
+        assert!(reputation.record(&aggregator.finalize().unwrap()).is_err());
+        let metrics = reputation.metrics();
+        assert!(metrics.iter().all(|node| node.rounds == 1));
+        assert_eq!(metrics.iter().filter(|node| node.agreement_rate == 1.0).count(), 3);
+    }
+
+    #[test]
+    fn forged_attestations_are_skipped_and_outputs_compared_decoded() {
+        let (keys, authorities) = nodes(4);
+        let reputation = Reputation::new(NodeKey::from_seed([9; 32]), &authorities);
+        let mut aggregator = Aggregator::new(3, SCRIPT, &authorities, 3, AggregationMode::Exact).unwrap();
+        for key in &keys[..3] {
+            aggregator.submit(SignedReport::new(key, 3, SCRIPT, &[0xab])).unwrap();
+        }
+        let late = SignedReport::new(&keys[3], 3, SCRIPT, &[0xab]);
+        reputation.observe(SignedReport { output: late.output.to_uppercase().replace("0X", "0x"), ..late }).unwrap();
+        let mut aggregated = aggregator.finalize().unwrap();
+        aggregated.attestations[0].signature = aggregated.attestations[1].signature.clone();
+        let summary = reputation.record(&aggregated).unwrap();
+        assert_eq!(summary.rejected, vec![aggregated.attestations[0].signer.clone()]);
+        assert_eq!(summary.agreed.len(), 3);
+        assert!(summary.agreed.contains(&keys[3].public()));
+        assert_eq!(summary.missed, vec![aggregated.attestations[0].signer.clone()]);
+    }
+
+    #[test]
+    fn evidence_is_trimmed_oldest_first() {
+        let (keys, authorities) = nodes(1);
+        let reputation = Reputation::new(NodeKey::from_seed([9; 32]), &authorities);
+        reputation.observe(SignedReport::new(&keys[0], 0, SCRIPT, b"a")).unwrap();
+        let evidence = reputation.observe(SignedReport::new(&keys[0], 0, SCRIPT, b"b")).unwrap().unwrap();
+        for round in 1..=RETAINED_EVIDENCE as u64 {
+            reputation.state.lock().unwrap().push_evidence(MisbehaviorEvidence { round, ..evidence.clone() });
+        }
+        let evidence = reputation.evidence();
+        assert_eq!(evidence.len(), RETAINED_EVIDENCE);
+        assert_eq!(evidence[0].round, 1);
+        assert_eq!(evidence[RETAINED_EVIDENCE - 1].round, RETAINED_EVIDENCE as u64);
+    }
+}
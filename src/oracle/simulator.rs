@@ -0,0 +1,395 @@
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{mpsc, watch};
+use tokio::time::timeout_at;
+use crate::chain::listener::{default_requests_key, requests_at, OracleRequested};
+use crate::chain::mock::MockChain;
+use crate::core::registry::ScriptRegistry;
+use crate::core::scheduler::{JobRequest, JobStatus, Scheduler, SchedulerOptions};
+use crate::oracle::aggregator::AggregationMode;
+use crate::oracle::commit_reveal::{Commitment, CommitRevealRound, Reveal};
+use crate::oracle::extrinsic::fixed_hex;
+use crate::oracle::keystore::{decode_hex, NodeKey};
+use crate::traits::traits::BlockRef;
+
+/// Network simulated by `run_simulation`, read from the JSON file given to `simulate`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct SimulationConfig {
+    pub abi: PathBuf,
+    pub wasm: PathBuf,
+    pub nodes: usize,
+    /// Agreeing reveals needed to finalize a round, two thirds of the nodes plus one by default.
+    #[serde(default)]
+    pub threshold: Option<usize>,
+    /// `exact`, or `median-<i32|i64|f32|f64>` for numeric outputs
+    #[serde(default = "default_mode")]
+    pub mode: String,
+    pub rounds: u64,
+    /// Input of every request, `{{name}}` is replaced by each node's view of the data source `name`.
+    pub input_template: String,
+    #[serde(default)]
+    pub data_sources: Vec<MockSource>,
+    #[serde(default)]
+    pub faults: Vec<NodeFault>,
+    #[serde(default = "default_phase_ms")]
+    pub commit_timeout_ms: u64,
+    #[serde(default = "default_phase_ms")]
+    pub reveal_timeout_ms: u64,
+}
+
+/// A data source every node reads the same body from, unless it has a `divergent` fault.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MockSource {
+    pub name: String,
+    pub body: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NodeFault {
+    pub node: usize,
+    #[serde(flatten)]
+    pub fault: Fault,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Fault {
+    /// Reports `output` instead of the script result, or the result with its last byte flipped.
+    Byzantine {
+        #[serde(default)]
+        output: Option<String>,
+    },
+    /// Commits `ms` after its execution finished.
+    Delay { ms: u64 },
+    /// Reads `body` from the data source `source`.
+    Divergent { source: String, body: String },
+    /// Commits but never reveals.
+    Withhold,
+    /// Never takes part.
+    Offline,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SimulationReport {
+    pub script_hash: String,
+    pub threshold: usize,
+    pub rounds: Vec<RoundOutcome>,
+    pub nodes: Vec<NodeStats>,
+    pub finalized_rounds: u64,
+    /// Share of the reveals of finalized rounds that were attested in the aggregated report.
+    pub agreement_rate: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RoundOutcome {
+    pub request_id: u64,
+    pub block: u64,
+    pub committed: usize,
+    pub revealed: usize,
+    /// Reveals attested in the aggregated report.
+    pub agreeing: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct NodeStats {
+    pub node: usize,
+    pub signer: String,
+    pub faults: Vec<Fault>,
+    /// Rounds the node committed in before the commit timeout.
+    pub committed: u64,
+    /// Finalized rounds the node's reveal was attested in.
+    pub agreed: u64,
+    /// Finalized rounds the node's reveal was left out of the aggregated report.
+    pub disagreed: u64,
+    /// Rounds without an accepted commitment.
+    pub missed: u64,
+    /// Rounds the node committed in but did not reveal.
+    pub withheld: u64,
+    /// Mean time from the request block to the node's commitment.
+    pub mean_latency_ms: f64,
+    #[serde(skip)]
+    latency_total: Duration,
+}
+
+struct SimNode {
+    index: usize,
+    key: NodeKey,
+    faults: Vec<Fault>,
+    scheduler: Scheduler,
+}
+
+struct CommitMessage {
+    node: usize,
+    commitment: Commitment,
+    latency: Duration,
+}
+
+fn default_mode() -> String {
+    "exact".to_string()
+}
+
+fn default_phase_ms() -> u64 {
+    1000
+}
+
+/// Runs `config.rounds` oracle rounds over `config.nodes` virtual nodes in this process. Every
+/// round raises a request on a shared mock chain; each node reads it from the chain, fills the
+/// input from its view of the data sources, executes the script on its own scheduler and takes
+/// part in a commit-reveal round whose outcome is aggregated like on a live network.
+pub async fn run_simulation(config: SimulationConfig, registry: Arc<ScriptRegistry>) -> Result<SimulationReport> {
+    if config.nodes == 0 {
+        return Err(anyhow!("ERROR: a simulation needs at least one node"));
+    }
+    if let Some(fault) = config.faults.iter().find(|fault| fault.node >= config.nodes) {
+        return Err(anyhow!("ERROR: fault for node {} of a network of {} nodes", fault.node, config.nodes));
+    }
+    let script_hash = registry.insert_files(&config.abi, &config.wasm)?;
+    // Compile up front, so the latencies of the first round do not include compilation.
+    registry.modules().instance_pre(&script_hash, &registry.load(&script_hash)?.wasm)?;
+    let threshold = config.threshold.unwrap_or(config.nodes * 2 / 3 + 1);
+    let mode = config.mode.parse::<AggregationMode>()?;
+    let requests_key = default_requests_key();
+    let chain = Arc::new(MockChain::new(&requests_key));
+    let nodes: Vec<Arc<SimNode>> = (0..config.nodes).map(|index| Arc::new(SimNode {
+        index,
+        key: NodeKey::generate(),
+        faults: config.faults.iter().filter(|fault| fault.node == index).map(|fault| fault.fault.clone()).collect(),
+        scheduler: Scheduler::start(registry.clone(), SchedulerOptions { workers: 1, ..Default::default() }),
+    })).collect();
+    let authorities: Vec<String> = nodes.iter().map(|node| node.key.public()).collect();
+    let mut stats: Vec<NodeStats> = nodes.iter().map(|node| NodeStats {
+        node: node.index,
+        signer: node.key.public(),
+        faults: node.faults.clone(),
+        ..Default::default()
+    }).collect();
+    let sources = Arc::new(config.data_sources.clone());
+    let mut rounds = Vec::new();
+    for request_id in 1..=config.rounds {
+        let block = chain.produce_block(&[OracleRequested {
+            request_id,
+            script_hash: fixed_hex(&script_hash, "script hash")?,
+            input: config.input_template.clone().into_bytes(),
+        }]);
+        let started = Instant::now();
+        let (commit_sender, mut commits) = mpsc::channel::<CommitMessage>(config.nodes);
+        let (reveal_sender, mut reveals) = mpsc::channel::<(usize, Reveal)>(config.nodes);
+        let (open_reveals, reveals_opened) = watch::channel(false);
+        for node in &nodes {
+            let node = node.clone();
+            let chain = chain.clone();
+            let block = block.clone();
+            let sources = sources.clone();
+            let commit_sender = commit_sender.clone();
+            let reveal_sender = reveal_sender.clone();
+            let mut reveals_opened = reveals_opened.clone();
+            tokio::spawn(async move {
+                let result = async {
+                    let Some((commitment, reveal)) = node.take_part(&chain, &requests_key, &block, &sources).await? else {
+                        return Ok(());
+                    };
+                    let message = CommitMessage { node: node.index, commitment, latency: started.elapsed() };
+                    if commit_sender.send(message).await.is_err() || reveals_opened.wait_for(|open| *open).await.is_err() {
+                        return Ok(());
+                    }
+                    if !node.faults.iter().any(|fault| matches!(fault, Fault::Withhold)) {
+                        let _ = reveal_sender.send((node.index, reveal)).await;
+                    }
+                    Ok::<_, anyhow::Error>(())
+                }.await;
+                if let Err(e) = result {
+                    println!("Node {} failed in round {}: {}", node.index, request_id, e);
+                }
+            });
+        }
+        drop((commit_sender, reveal_sender, reveals_opened));
+
+        let mut round = CommitRevealRound::new(request_id, &script_hash, &authorities);
+        let mut committed = BTreeSet::new();
+        let deadline = tokio::time::Instant::now() + Duration::from_millis(config.commit_timeout_ms);
+        while committed.len() < config.nodes {
+            let Ok(Some(message)) = timeout_at(deadline, commits.recv()).await else { break };
+            match round.commit(message.commitment) {
+                Ok(()) => {
+                    stats[message.node].committed += 1;
+                    stats[message.node].latency_total += message.latency;
+                    committed.insert(message.node);
+                }
+                Err(e) => println!("Rejected commitment of node {}: {}", message.node, e),
+            }
+        }
+        round.close_commits();
+        let _ = open_reveals.send(true);
+        let mut revealed = BTreeSet::new();
+        let deadline = tokio::time::Instant::now() + Duration::from_millis(config.reveal_timeout_ms);
+        while revealed.len() < committed.len() {
+            let Ok(Some((node, reveal))) = timeout_at(deadline, reveals.recv()).await else { break };
+            match round.reveal(reveal) {
+                Ok(()) => {
+                    revealed.insert(node);
+                }
+                Err(e) => println!("Rejected reveal of node {}: {}", node, e),
+            }
+        }
+
+        let finalized = round.finalize(threshold, mode);
+        let output = finalized.as_ref().ok().map(|finalized| finalized.report.output.clone());
+        // A median differs from most reveals, so agreement is being attested in the report.
+        let attested: Option<BTreeSet<&str>> = finalized.as_ref().ok().map(|finalized| finalized.report.signers().into_iter().collect());
+        for node in &mut stats {
+            match (committed.contains(&node.node), revealed.contains(&node.node)) {
+                (false, _) => node.missed += 1,
+                (true, false) => node.withheld += 1,
+                (true, true) => match &attested {
+                    Some(attested) if attested.contains(node.signer.as_str()) => node.agreed += 1,
+                    Some(_) => node.disagreed += 1,
+                    None => {}
+                },
+            }
+        }
+        rounds.push(RoundOutcome {
+            request_id,
+            block: block.number,
+            committed: committed.len(),
+            revealed: revealed.len(),
+            agreeing: attested.as_ref().map_or(0, BTreeSet::len),
+            output,
+            error: finalized.as_ref().err().map(|e| e.to_string()),
+        });
+    }
+
+    for node in &mut stats {
+        if node.committed > 0 {
+            node.mean_latency_ms = node.latency_total.as_secs_f64() * 1000.0 / node.committed as f64;
+        }
+    }
+    let finalized_rounds = rounds.iter().filter(|round| round.output.is_some()).count() as u64;
+    let (agreeing, revealed) = rounds.iter().filter(|round| round.output.is_some())
+        .fold((0, 0), |(agreeing, revealed), round| (agreeing + round.agreeing, revealed + round.revealed));
+    Ok(SimulationReport {
+        script_hash,
+        threshold,
+        rounds,
+        nodes: stats,
+        finalized_rounds,
+        agreement_rate: if revealed == 0 { 0.0 } else { agreeing as f64 / revealed as f64 },
+    })
+}
+
+impl SimNode {
+    /// Executes the request of `block` and commits to the result, `None` when the node is offline.
+    async fn take_part(
+        &self,
+        chain: &MockChain,
+        requests_key: &[u8],
+        block: &BlockRef,
+        sources: &[MockSource],
+    ) -> Result<Option<(Commitment, Reveal)>> {
+        if self.faults.iter().any(|fault| matches!(fault, Fault::Offline)) {
+            return Ok(None);
+        }
+        let request = requests_at(chain, requests_key, block.number, block).await?.into_iter().next()
+            .ok_or_else(|| anyhow!("ERROR: block {} raised no request", block.number))?;
+        let script_hash = format!("0x{}", hex::encode(request.script_hash));
+        let mut input = String::from_utf8(request.input)
+            .map_err(|_| anyhow!("ERROR: input of request {} is not UTF-8", request.request_id))?;
+        for source in sources {
+            let body = self.faults.iter().find_map(|fault| match fault {
+                Fault::Divergent { source: name, body } if *name == source.name => Some(body),
+                _ => None,
+            }).unwrap_or(&source.body);
+            input = input.replace(&format!("{{{{{}}}}}", source.name), body.trim());
+        }
+        let job_id = self.scheduler.submit(JobRequest { script_hash: script_hash.clone(), selector: None, input, timeout_ms: None })?;
+        let mut output = match self.scheduler.wait(job_id).await {
+            Some(JobStatus::Succeeded { output }) => decode_hex(&output)?,
+            Some(status) => return Err(anyhow!("ERROR: job {} ended as {:?}", job_id, status)),
+            None => return Err(anyhow!("ERROR: job {} disappeared", job_id)),
+        };
+        for fault in &self.faults {
+            match fault {
+                Fault::Byzantine { output: Some(forged) } => output = decode_hex(forged)?,
+                Fault::Byzantine { output: None } => match output.last_mut() {
+                    Some(last) => *last ^= 0xff,
+                    None => output.push(0xff),
+                },
+                Fault::Delay { ms } => tokio::time::sleep(Duration::from_millis(*ms)).await,
+                _ => {}
+            }
+        }
+        Commitment::new(&self.key, request.request_id, &script_hash, &output).map(Some)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use super::*;
+    use crate::core::testing::{script_abi, temp_dir, temp_registry};
+
+    /// Returns `Output { value: 42 }` from behind the input written at the start of memory.
+    const ANSWERS: &str = r#"(module (memory (export "memory") 1)
+        (data (i32.const 4088) "\01\00\00\00\04\00\00\00\2a\00\00\00")
+        (func (export "process") (param i32 i32) (result i32) i32.const 4096))"#;
+
+    fn config(name: &str, mode: &str, faults: Vec<NodeFault>) -> SimulationConfig {
+        let dir = temp_dir(name);
+        fs::write(dir.join("script.wat"), ANSWERS).unwrap();
+        fs::write(dir.join("abi.json"), script_abi(ANSWERS.as_bytes(), "Output", &[("Output", &[("value", "i32")])])).unwrap();
+        SimulationConfig {
+            abi: dir.join("abi.json"),
+            wasm: dir.join("script.wat"),
+            nodes: 4,
+            threshold: None,
+            mode: mode.to_string(),
+            rounds: 2,
+            input_template: "{}".to_string(),
+            data_sources: Vec::new(),
+            faults,
+            commit_timeout_ms: 5000,
+            reveal_timeout_ms: 5000,
+        }
+    }
+
+    fn byzantine(node: usize) -> NodeFault {
+        NodeFault { node, fault: Fault::Byzantine { output: None } }
+    }
+
+    #[tokio::test]
+    async fn exact_rounds_single_out_byzantine_nodes() {
+        let (root, registry) = temp_registry("simulator-exact");
+        let mut config = config("simulator-exact-files", "exact", vec![byzantine(3), NodeFault { node: 2, fault: Fault::Withhold }]);
+        config.nodes = 5;
+        config.threshold = Some(3);
+        let files = config.abi.parent().unwrap().to_path_buf();
+        let report = run_simulation(config, registry).await.unwrap();
+        assert_eq!(report.finalized_rounds, 2);
+        assert!(report.rounds.iter().all(|round| round.output.as_deref() == Some("0x2a000000") && round.agreeing == 3));
+        assert_eq!((report.nodes[0].agreed, report.nodes[0].disagreed), (2, 0));
+        assert_eq!((report.nodes[2].withheld, report.nodes[2].agreed), (2, 0));
+        assert_eq!((report.nodes[3].agreed, report.nodes[3].disagreed), (0, 2));
+        fs::remove_dir_all(root).unwrap();
+        fs::remove_dir_all(files).unwrap();
+    }
+
+    #[tokio::test]
+    async fn median_rounds_count_every_attested_reveal() {
+        let (root, registry) = temp_registry("simulator-median");
+        let config = config("simulator-median-files", "median-i32", vec![byzantine(3)]);
+        let files = config.abi.parent().unwrap().to_path_buf();
+        let report = run_simulation(config, registry).await.unwrap();
+        assert!(report.rounds.iter().all(|round| round.output.as_deref() == Some("0x2a000000") && round.agreeing == 4));
+        assert!(report.nodes.iter().all(|node| node.agreed == 2 && node.disagreed == 0));
+        assert_eq!(report.agreement_rate, 1.0);
+        fs::remove_dir_all(root).unwrap();
+        fs::remove_dir_all(files).unwrap();
+    }
+}
@@ -0,0 +1,614 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use anyhow::{anyhow, Result};
+use parity_scale_codec::Encode;
+use rand::RngCore;
+use rand::rngs::OsRng;
+use serde::{Deserialize, Serialize};
+use sp_core_hashing::blake2_256;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::sync::{mpsc, Semaphore};
+use tokio::time::timeout;
+use crate::oracle::aggregator::SignedReport;
+use crate::oracle::commit_reveal::{Commitment, Reveal};
+use crate::oracle::keystore::{decode_hex, verify_signature, NodeKey};
+
+/// Largest frame accepted from a peer.
+const MAX_FRAME_BYTES: u32 = 1 << 20;
+/// Largest frame accepted before the peer proved its key, a hello or proof is a few hundred bytes.
+const MAX_HANDSHAKE_FRAME_BYTES: u32 = 1024;
+/// Frames queued for a peer before new ones are dropped.
+const PEER_QUEUE: usize = 256;
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone)]
+pub struct GossipOptions {
+    pub listen: SocketAddr,
+    /// Peers dialed at startup and redialed whenever their connection drops.
+    pub peers: Vec<SocketAddr>,
+    /// Keys allowed to connect and to originate messages, any key when empty.
+    pub authorities: Vec<String>,
+    /// Sustained messages accepted per second from one peer, excess messages are dropped.
+    pub messages_per_sec: f64,
+    /// Messages a peer may send in a burst above the sustained rate.
+    pub burst: f64,
+    /// Inbound connections open at once, handshaking or not; further ones are closed on accept.
+    pub max_inbound: usize,
+    /// Message ids remembered for deduplication.
+    pub seen_capacity: usize,
+    pub redial_interval: Duration,
+}
+
+impl Default for GossipOptions {
+    fn default() -> Self {
+        GossipOptions {
+            listen: SocketAddr::from(([127, 0, 0, 1], 7700)),
+            peers: Vec::new(),
+            authorities: Vec::new(),
+            messages_per_sec: 50.0,
+            burst: 100.0,
+            max_inbound: 64,
+            seen_capacity: 8192,
+            redial_interval: Duration::from_secs(2),
+        }
+    }
+}
+
+/// Payload gossiped between nodes, each one signed by the node that produced it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", content = "body", rename_all = "snake_case")]
+pub enum GossipMessage {
+    Report(SignedReport),
+    Commitment(Commitment),
+    Reveal(Reveal),
+}
+
+/// A message received from the network, `peer` being the node that forwarded it.
+#[derive(Debug, Clone)]
+pub struct Delivered {
+    pub peer: String,
+    pub message: GossipMessage,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct GossipStats {
+    pub peers: usize,
+    pub delivered: u64,
+    pub duplicates: u64,
+    pub rate_limited: u64,
+    pub invalid: u64,
+    /// Inbound connections closed because `max_inbound` were already open.
+    pub refused: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Frame {
+    Hello { public: String, nonce: String },
+    /// Signature over both keys and both nonces, proving ownership of the key sent in `Hello`.
+    Proof { signature: String },
+    Message { message: GossipMessage },
+}
+
+/// Handle of a running gossip node.
+#[derive(Clone)]
+pub struct Gossip {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    key: NodeKey,
+    options: GossipOptions,
+    local_addr: SocketAddr,
+    peers: Mutex<HashMap<String, Peer>>,
+    seen: Mutex<SeenCache>,
+    stats: Mutex<GossipStats>,
+    /// Rate limits by peer key, so a peer opening more connections gets no more messages through.
+    limiters: Mutex<HashMap<String, RateLimiter>>,
+    delivered: mpsc::Sender<Delivered>,
+    next_connection: AtomicU64,
+}
+
+struct Peer {
+    connection: u64,
+    /// Node that opened the connection.
+    dialer: String,
+    frames: mpsc::Sender<Arc<Vec<u8>>>,
+}
+
+/// Bounded set of the ids of recently seen messages.
+struct SeenCache {
+    ids: HashSet<[u8; 32]>,
+    order: VecDeque<[u8; 32]>,
+    capacity: usize,
+}
+
+/// Token bucket limiting the messages accepted from one peer key.
+struct RateLimiter {
+    tokens: f64,
+    burst: f64,
+    per_sec: f64,
+    last: Instant,
+}
+
+impl GossipMessage {
+    /// Reads a reveal, commitment or signed report as written by `run`.
+    pub fn from_json(json: &str) -> Result<Self> {
+        if let Ok(reveal) = serde_json::from_str::<Reveal>(json) {
+            return Ok(GossipMessage::Reveal(reveal));
+        }
+        if let Ok(commitment) = serde_json::from_str::<Commitment>(json) {
+            return Ok(GossipMessage::Commitment(commitment));
+        }
+        serde_json::from_str::<SignedReport>(json).map(GossipMessage::Report)
+            .map_err(|e| anyhow!("ERROR: not a signed report, commitment or reveal: {}", e))
+    }
+
+    /// Node that produced and signed the message.
+    pub fn signer(&self) -> &str {
+        match self {
+            GossipMessage::Report(report) => &report.signer,
+            GossipMessage::Commitment(commitment) => &commitment.signer,
+            GossipMessage::Reveal(reveal) => &reveal.report.signer,
+        }
+    }
+
+    pub fn round(&self) -> u64 {
+        match self {
+            GossipMessage::Report(report) => report.round,
+            GossipMessage::Commitment(commitment) => commitment.round,
+            GossipMessage::Reveal(reveal) => reveal.report.round,
+        }
+    }
+
+    /// Checks the signature of the message and the length of a reveal's salt. Whether a reveal
+    /// opens its commitment is left to the round collecting them.
+    pub fn verify(&self) -> Result<()> {
+        match self {
+            GossipMessage::Report(report) => report.verify(),
+            GossipMessage::Commitment(commitment) => commitment.verify(),
+            GossipMessage::Reveal(reveal) => {
+                reveal.salt_bytes()?;
+                reveal.report.verify()
+            }
+        }
+    }
+
+    /// Deduplication id, the hash of kind, signer, round and signature. The salt of a reveal is
+    /// not signed, so it is part of the id and a forged salt cannot shadow the real reveal.
+    fn id(&self) -> [u8; 32] {
+        let (kind, signature, salt) = match self {
+            GossipMessage::Report(report) => ("report", &report.signature, None),
+            GossipMessage::Commitment(commitment) => ("commitment", &commitment.signature, None),
+            GossipMessage::Reveal(reveal) => ("reveal", &reveal.report.signature, Some(&reveal.salt)),
+        };
+        blake2_256(&(b"orascript/gossip-id", kind, self.signer(), self.round(), signature, salt).encode())
+    }
+}
+
+impl SeenCache {
+    /// Records `id`, returns false if it was already seen.
+    fn insert(&mut self, id: [u8; 32]) -> bool {
+        if !self.ids.insert(id) {
+            return false;
+        }
+        self.order.push_back(id);
+        if self.order.len() > self.capacity && let Some(oldest) = self.order.pop_front() {
+            self.ids.remove(&oldest);
+        }
+        true
+    }
+}
+
+impl RateLimiter {
+    fn new(per_sec: f64, burst: f64) -> Self {
+        RateLimiter { tokens: burst, burst, per_sec, last: Instant::now() }
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        self.tokens = (self.tokens + now.duration_since(self.last).as_secs_f64() * self.per_sec).min(self.burst);
+        self.last = now;
+    }
+
+    /// A full bucket is where a new limiter starts, so the limiter of an idle peer can be dropped.
+    fn is_full(&mut self) -> bool {
+        self.refill();
+        self.tokens >= self.burst
+    }
+
+    fn allow(&mut self) -> bool {
+        self.refill();
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Signed by `public` in the handshake: binding both keys and both nonces keeps a proof from
+/// being relayed into another connection.
+fn handshake_payload(public: &str, nonce: &[u8], peer: &str, peer_nonce: &[u8]) -> Vec<u8> {
+    (b"orascript/gossip", public, nonce, peer, peer_nonce).encode()
+}
+
+/// Starts a gossip node listening on `options.listen` and dialing `options.peers`. Peers are
+/// identified by their keystore key, proven in the handshake; every valid message not seen
+/// before is sent to the returned receiver and forwarded to the other peers.
+pub async fn start_gossip(key: NodeKey, options: GossipOptions) -> Result<(Gossip, mpsc::Receiver<Delivered>)> {
+    let listener = TcpListener::bind(options.listen).await
+        .map_err(|e| anyhow!("ERROR: failed to listen on {}: {}", options.listen, e))?;
+    let (delivered, receiver) = mpsc::channel(1024);
+    let gossip = Gossip {
+        inner: Arc::new(Inner {
+            key,
+            local_addr: listener.local_addr()?,
+            peers: Mutex::new(HashMap::new()),
+            seen: Mutex::new(SeenCache { ids: HashSet::new(), order: VecDeque::new(), capacity: options.seen_capacity.max(1) }),
+            stats: Mutex::new(GossipStats::default()),
+            limiters: Mutex::new(HashMap::new()),
+            delivered,
+            next_connection: AtomicU64::new(0),
+            options,
+        }),
+    };
+    let accepting = gossip.clone();
+    let inbound = Arc::new(Semaphore::new(accepting.inner.options.max_inbound));
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, addr)) => {
+                    let Ok(permit) = inbound.clone().try_acquire_owned() else {
+                        accepting.inner.stats.lock().unwrap().refused += 1;
+                        println!("Gossip connection from {} refused: too many inbound connections", addr);
+                        continue;
+                    };
+                    let gossip = accepting.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = gossip.run_connection(stream, false).await {
+                            println!("Gossip connection from {} closed: {}", addr, e);
+                        }
+                        drop(permit);
+                    });
+                }
+                Err(e) => println!("ERROR: failed to accept gossip connection: {}", e),
+            }
+        }
+    });
+    for addr in gossip.inner.options.peers.clone() {
+        let gossip = gossip.clone();
+        tokio::spawn(async move { gossip.dial(addr).await });
+    }
+    Ok((gossip, receiver))
+}
+
+impl Gossip {
+    pub fn local_addr(&self) -> SocketAddr {
+        self.inner.local_addr
+    }
+
+    pub fn public(&self) -> String {
+        self.inner.key.public()
+    }
+
+    /// Keys of the currently connected peers.
+    pub fn peers(&self) -> Vec<String> {
+        self.inner.peers.lock().unwrap().keys().cloned().collect()
+    }
+
+    pub fn stats(&self) -> GossipStats {
+        let mut stats = self.inner.stats.lock().unwrap().clone();
+        stats.peers = self.inner.peers.lock().unwrap().len();
+        stats
+    }
+
+    /// Sends a message to every peer, returns the number of peers it was queued for.
+    pub fn publish(&self, message: GossipMessage) -> Result<usize> {
+        message.verify()?;
+        self.inner.seen.lock().unwrap().insert(message.id());
+        self.broadcast(&message, None)
+    }
+
+    /// Keeps a connection to `addr`, redialing after it drops. A peer already connected through
+    /// a connection it dialed itself is not dialed again.
+    async fn dial(&self, addr: SocketAddr) {
+        let mut known: Option<String> = None;
+        loop {
+            let connected = known.as_ref().is_some_and(|peer| self.inner.peers.lock().unwrap().contains_key(peer));
+            if !connected {
+                match TcpStream::connect(addr).await {
+                    Ok(stream) => match self.run_connection(stream, true).await {
+                        Ok(peer) => known = Some(peer),
+                        Err(e) => println!("Gossip connection to {} closed: {}", addr, e),
+                    },
+                    Err(e) => println!("Failed to dial gossip peer {}: {}", addr, e),
+                }
+            }
+            tokio::time::sleep(self.inner.options.redial_interval).await;
+        }
+    }
+
+    /// Runs the handshake and then serves the connection until it closes, returns the peer key.
+    async fn run_connection(&self, stream: TcpStream, outbound: bool) -> Result<String> {
+        stream.set_nodelay(true)?;
+        let (mut reader, mut writer) = stream.into_split();
+        let peer = timeout(HANDSHAKE_TIMEOUT, self.handshake(&mut reader, &mut writer)).await
+            .map_err(|_| anyhow!("ERROR: gossip handshake timed out"))??;
+        let dialer = if outbound { self.public() } else { peer.clone() };
+        let connection = self.inner.next_connection.fetch_add(1, Ordering::Relaxed);
+        let (frames, mut queued) = mpsc::channel::<Arc<Vec<u8>>>(PEER_QUEUE);
+        {
+            let mut peers = self.inner.peers.lock().unwrap();
+            if let Some(existing) = peers.get(&peer) {
+                // When both nodes dial each other, both keep the connection dialed by the lower key.
+                let preferred = std::cmp::min(self.public(), peer.clone());
+                if dialer != preferred && existing.dialer != dialer {
+                    return Ok(peer);
+                }
+            }
+            peers.insert(peer.clone(), Peer { connection, dialer, frames });
+        }
+        println!("Gossip peer {} connected", peer);
+        tokio::spawn(async move {
+            while let Some(frame) = queued.recv().await {
+                if writer.write_all(&frame).await.is_err() {
+                    return;
+                }
+            }
+        });
+        let result = self.serve(&peer, &mut reader).await;
+        // A connection replaced by a preferred one leaves the newer entry in place.
+        let mut peers = self.inner.peers.lock().unwrap();
+        if peers.get(&peer).is_some_and(|entry| entry.connection == connection) {
+            peers.remove(&peer);
+            println!("Gossip peer {} disconnected", peer);
+        }
+        drop(peers);
+        result.map(|_| peer)
+    }
+
+    async fn handshake(&self, reader: &mut OwnedReadHalf, writer: &mut OwnedWriteHalf) -> Result<String> {
+        let mut nonce = [0u8; 32];
+        OsRng.fill_bytes(&mut nonce);
+        let public = self.public();
+        writer.write_all(&encode_frame(&Frame::Hello { public: public.clone(), nonce: format!("0x{}", hex::encode(nonce)) })?).await?;
+        let Frame::Hello { public: peer, nonce: peer_nonce } = read_frame(reader, MAX_HANDSHAKE_FRAME_BYTES).await? else {
+            return Err(anyhow!("ERROR: expected a gossip hello"));
+        };
+        if peer == public {
+            return Err(anyhow!("ERROR: refusing to connect to itself"));
+        }
+        if !self.inner.options.authorities.is_empty() && !self.inner.options.authorities.contains(&peer) {
+            return Err(anyhow!("ERROR: gossip peer {} is not an authority", peer));
+        }
+        let peer_nonce = decode_hex(&peer_nonce)?;
+        let signature = self.inner.key.sign(&handshake_payload(&public, &nonce, &peer, &peer_nonce));
+        writer.write_all(&encode_frame(&Frame::Proof { signature })?).await?;
+        let Frame::Proof { signature } = read_frame(reader, MAX_HANDSHAKE_FRAME_BYTES).await? else {
+            return Err(anyhow!("ERROR: expected a gossip proof"));
+        };
+        verify_signature(&peer, &handshake_payload(&peer, &peer_nonce, &public, &nonce), &signature)
+            .map_err(|e| anyhow!("ERROR: gossip peer {} failed to prove its key: {}", peer, e))?;
+        Ok(peer)
+    }
+
+    async fn serve(&self, peer: &str, reader: &mut OwnedReadHalf) -> Result<()> {
+        let options = &self.inner.options;
+        loop {
+            let message = match read_frame(reader, MAX_FRAME_BYTES).await? {
+                Frame::Message { message } => message,
+                _ => return Err(anyhow!("ERROR: unexpected handshake frame from {}", peer)),
+            };
+            if !self.allow(peer) {
+                self.inner.stats.lock().unwrap().rate_limited += 1;
+                continue;
+            }
+            let id = message.id();
+            if self.inner.seen.lock().unwrap().ids.contains(&id) {
+                self.inner.stats.lock().unwrap().duplicates += 1;
+                continue;
+            }
+            let authorized = options.authorities.is_empty() || options.authorities.iter().any(|a| a == message.signer());
+            if !authorized || message.verify().is_err() {
+                self.inner.stats.lock().unwrap().invalid += 1;
+                continue;
+            }
+            if !self.inner.seen.lock().unwrap().insert(id) {
+                continue;
+            }
+            self.inner.stats.lock().unwrap().delivered += 1;
+            self.broadcast(&message, Some(peer))?;
+            let _ = self.inner.delivered.send(Delivered { peer: peer.to_string(), message }).await;
+        }
+    }
+
+    /// Takes a token from the bucket of `peer`, shared by all of its connections.
+    fn allow(&self, peer: &str) -> bool {
+        let options = &self.inner.options;
+        let mut limiters = self.inner.limiters.lock().unwrap();
+        if !limiters.contains_key(peer) {
+            limiters.retain(|_, limiter| !limiter.is_full());
+        }
+        limiters.entry(peer.to_string())
+            .or_insert_with(|| RateLimiter::new(options.messages_per_sec, options.burst))
+            .allow()
+    }
+
+    fn broadcast(&self, message: &GossipMessage, except: Option<&str>) -> Result<usize> {
+        let frame = Arc::new(encode_frame(&Frame::Message { message: message.clone() })?);
+        let peers = self.inner.peers.lock().unwrap();
+        Ok(peers.iter()
+            .filter(|(key, _)| Some(key.as_str()) != except)
+            .filter(|(_, peer)| peer.frames.try_send(frame.clone()).is_ok())
+            .count())
+    }
+}
+
+/// Frames are a big endian `u32` length followed by that many bytes of JSON.
+fn encode_frame(frame: &Frame) -> Result<Vec<u8>> {
+    let body = serde_json::to_vec(frame)?;
+    if body.len() > MAX_FRAME_BYTES as usize {
+        return Err(anyhow!("ERROR: gossip frame of {} bytes exceeds {} bytes", body.len(), MAX_FRAME_BYTES));
+    }
+    let mut bytes = (body.len() as u32).to_be_bytes().to_vec();
+    bytes.extend(body);
+    Ok(bytes)
+}
+
+async fn read_frame(reader: &mut OwnedReadHalf, max_bytes: u32) -> Result<Frame> {
+    let len = reader.read_u32().await?;
+    if len > max_bytes {
+        return Err(anyhow!("ERROR: gossip frame of {} bytes exceeds {} bytes", len, max_bytes));
+    }
+    let mut body = vec![0u8; len as usize];
+    reader.read_exact(&mut body).await?;
+    Ok(serde_json::from_slice(&body)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCRIPT: &str = "0x01";
+
+    async fn node(seed: u8, peers: &[&Gossip], options: GossipOptions) -> (Gossip, mpsc::Receiver<Delivered>) {
+        let options = GossipOptions {
+            listen: SocketAddr::from(([127, 0, 0, 1], 0)),
+            peers: peers.iter().map(|peer| peer.local_addr()).collect(),
+            redial_interval: Duration::from_millis(50),
+            ..options
+        };
+        start_gossip(NodeKey::from_seed([seed; 32]), options).await.unwrap()
+    }
+
+    async fn until(condition: impl Fn() -> bool) {
+        timeout(Duration::from_secs(10), async {
+            while !condition() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        }).await.expect("condition not reached in time");
+    }
+
+    async fn next(receiver: &mut mpsc::Receiver<Delivered>) -> Delivered {
+        timeout(Duration::from_secs(10), receiver.recv()).await.unwrap().unwrap()
+    }
+
+    #[tokio::test]
+    async fn messages_are_forwarded_once_across_the_network() {
+        let (hub, mut hub_received) = node(1, &[], GossipOptions::default()).await;
+        let (left, _) = node(2, &[&hub], GossipOptions::default()).await;
+        let (right, mut right_received) = node(3, &[&hub], GossipOptions::default()).await;
+        until(|| hub.peers().len() == 2 && left.peers().len() == 1 && right.peers().len() == 1).await;
+
+        let key = NodeKey::from_seed([2; 32]);
+        let report = GossipMessage::Report(SignedReport::new(&key, 1, SCRIPT, b"price"));
+        assert_eq!(left.publish(report.clone()).unwrap(), 1);
+        assert_eq!(next(&mut hub_received).await.peer, left.public());
+        let delivered = next(&mut right_received).await;
+        assert_eq!(delivered.peer, hub.public());
+        assert_eq!(delivered.message.signer(), key.public());
+
+        // A republished message is dropped by the hub instead of being delivered again.
+        left.publish(report).unwrap();
+        until(|| hub.stats().duplicates == 1).await;
+        let (commitment, reveal) = Commitment::new(&key, 2, SCRIPT, b"price").unwrap();
+        left.publish(GossipMessage::Commitment(commitment)).unwrap();
+        assert!(matches!(next(&mut right_received).await.message, GossipMessage::Commitment(_)));
+        assert_eq!(hub.stats().delivered, 2);
+
+        // The salt of a reveal is not signed, a reveal with another salt is a different message.
+        left.publish(GossipMessage::Reveal(reveal.clone())).unwrap();
+        let salted = Reveal { salt: format!("0x{}", "00".repeat(32)), ..reveal.clone() };
+        assert_ne!(GossipMessage::Reveal(reveal).id(), GossipMessage::Reveal(salted).id());
+        assert!(matches!(next(&mut right_received).await.message, GossipMessage::Reveal(_)));
+    }
+
+    #[tokio::test]
+    async fn peers_above_the_rate_limit_are_throttled() {
+        let (limited, mut received) = node(4, &[], GossipOptions { messages_per_sec: 0.001, burst: 2.0, ..Default::default() }).await;
+        let (sender, _) = node(5, &[&limited], GossipOptions::default()).await;
+        until(|| limited.peers().len() == 1 && sender.peers().len() == 1).await;
+        let key = NodeKey::from_seed([5; 32]);
+        for round in 0..5 {
+            sender.publish(GossipMessage::Report(SignedReport::new(&key, round, SCRIPT, b"price"))).unwrap();
+        }
+        until(|| limited.stats().rate_limited == 3).await;
+        assert_eq!(next(&mut received).await.message.round(), 0);
+        assert_eq!(next(&mut received).await.message.round(), 1);
+        assert_eq!(limited.stats().delivered, 2);
+    }
+
+    #[tokio::test]
+    async fn invalid_messages_and_peers_are_rejected() {
+        let authority = NodeKey::from_seed([6; 32]);
+        let options = GossipOptions { authorities: vec![authority.public(), NodeKey::from_seed([7; 32]).public()], ..Default::default() };
+        let (guarded, _) = node(6, &[], options).await;
+        let (outsider, _) = node(8, &[&guarded], GossipOptions::default()).await;
+        let (member, _) = node(7, &[&guarded], GossipOptions::default()).await;
+        until(|| member.peers().len() == 1 && guarded.peers().len() == 1).await;
+        assert!(outsider.peers().is_empty());
+        assert_eq!(guarded.peers(), vec![member.public()]);
+
+        let (_, mut reveal) = Commitment::new(&authority, 1, SCRIPT, b"price").unwrap();
+        reveal.salt = "0x00".to_string();
+        assert!(member.publish(GossipMessage::Reveal(reveal)).is_err());
+
+        // A hello larger than the handshake limit closes the connection before it is read.
+        let mut stream = TcpStream::connect(guarded.local_addr()).await.unwrap();
+        stream.write_all(&(MAX_HANDSHAKE_FRAME_BYTES + 1).to_be_bytes()).await.unwrap();
+        let mut rest = Vec::new();
+        let closed = timeout(HANDSHAKE_TIMEOUT / 2, async {
+            loop {
+                match stream.read_buf(&mut rest).await {
+                    Ok(0) | Err(_) => return,
+                    Ok(_) => {}
+                }
+            }
+        }).await;
+        assert!(closed.is_ok());
+    }
+
+    #[tokio::test]
+    async fn rate_limits_are_shared_by_all_connections_of_a_key() {
+        let (limited, _) = node(9, &[], GossipOptions { messages_per_sec: 0.001, burst: 2.0, ..Default::default() }).await;
+        let (peer, other) = (NodeKey::from_seed([10; 32]).public(), NodeKey::from_seed([11; 32]).public());
+        // A reconnecting peer finds its bucket as its previous connection left it.
+        assert!(limited.allow(&peer));
+        assert!(limited.allow(&peer));
+        assert!(!limited.allow(&peer));
+        assert!(limited.allow(&other));
+        assert_eq!(limited.inner.limiters.lock().unwrap().len(), 2);
+
+        // Buckets refilled to the burst are forgotten once another peer shows up.
+        let (fast, _) = node(12, &[], GossipOptions { messages_per_sec: 1000.0, burst: 1.0, ..Default::default() }).await;
+        assert!(fast.allow(&peer));
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(fast.allow(&other));
+        assert_eq!(fast.inner.limiters.lock().unwrap().keys().collect::<Vec<_>>(), [&other]);
+    }
+
+    #[tokio::test]
+    async fn inbound_connections_beyond_the_cap_are_refused() {
+        let (capped, _) = node(13, &[], GossipOptions { max_inbound: 1, ..Default::default() }).await;
+        let mut handshaking = TcpStream::connect(capped.local_addr()).await.unwrap();
+        let mut hello = Vec::new();
+        handshaking.read_buf(&mut hello).await.unwrap();
+        let mut refused = TcpStream::connect(capped.local_addr()).await.unwrap();
+        let mut rest = Vec::new();
+        let closed = timeout(HANDSHAKE_TIMEOUT / 2, refused.read_buf(&mut rest)).await.unwrap();
+        assert!(matches!(closed, Ok(0) | Err(_)));
+        assert_eq!(capped.stats().refused, 1);
+
+        // The slot is free again once the first connection closes.
+        drop(handshaking);
+        let (peer, _) = node(14, &[&capped], GossipOptions::default()).await;
+        until(|| capped.peers() == vec![peer.public()]).await;
+    }
+}
@@ -0,0 +1 @@
+pub mod gossip;
\ No newline at end of file
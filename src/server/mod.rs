@@ -1 +1,2 @@
-pub(crate) mod server;
\ No newline at end of file
+pub(crate) mod server;
+pub(crate) mod rpc;
\ No newline at end of file
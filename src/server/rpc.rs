@@ -0,0 +1,382 @@
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use hyper::body::HttpBody;
+use hyper::header::CONTENT_TYPE;
+use hyper::service::{make_service_fn, service_fn};
+use serde::{Deserialize, Serialize};
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use crate::core::registry::ScriptRegistry;
+use crate::core::runtime::decode_output;
+use crate::core::scheduler::{JobRequest, Scheduler};
+use crate::core::type_registry::AbiTypeRegistry;
+use crate::oracle::aggregator::{AggregatedReport, SignedReport};
+use crate::oracle::extrinsic::{CallConfig, ReportEquivocation};
+use crate::oracle::keystore::decode_hex;
+use crate::oracle::reputation::{MisbehaviorEvidence, Reputation};
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const SERVER_ERROR: i64 = -32000;
+const QUEUE_FULL: i64 = -32005;
+
+/// Largest request body accepted, enough for a hex encoded script and its ABI.
+const MAX_BODY_BYTES: usize = 8 << 20;
+
+#[derive(Deserialize)]
+struct RpcRequest {
+    jsonrpc: String,
+    #[serde(default)]
+    id: Value,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+#[derive(Debug)]
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl ToString) -> Self {
+        RpcError { code, message: message.to_string() }
+    }
+}
+
+impl From<anyhow::Error> for RpcError {
+    fn from(e: anyhow::Error) -> Self {
+        RpcError::new(SERVER_ERROR, e)
+    }
+}
+
+struct NodeState {
+    registry: Arc<ScriptRegistry>,
+    scheduler: Scheduler,
+    /// Only tracked when the node runs with a key to sign evidence with.
+    reputation: Option<Reputation>,
+}
+
+type SharedState = Arc<NodeState>;
+
+#[derive(Deserialize)]
+struct DeployParams {
+    /// Hex encoded wasm bytecode
+    wasm: String,
+    /// ABI document, either as JSON or as a JSON encoded string
+    abi: Value,
+}
+
+#[derive(Deserialize)]
+struct JobParams {
+    job_id: u64,
+}
+
+#[derive(Deserialize)]
+struct DecodeParams {
+    script_hash: String,
+    /// ABI type of the output, e.g. `Output`
+    #[serde(rename = "type")]
+    type_name: String,
+    /// Hex encoded SCALE output
+    output: String,
+}
+
+#[derive(Deserialize)]
+struct ScriptParams {
+    script_hash: String,
+}
+
+/// Location of `pallet_oracle::report_equivocation`, to include its call data with the evidence.
+#[derive(Deserialize)]
+struct EvidenceParams {
+    pallet_index: u8,
+    call_index: u8,
+}
+
+#[derive(Serialize)]
+struct EvidenceEntry {
+    #[serde(flatten)]
+    evidence: MisbehaviorEvidence,
+    /// Hex encoded `report_equivocation` call data.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    call: Option<String>,
+}
+
+/// Serves the JSON-RPC API until the process is stopped.
+pub(crate) async fn serve(
+    addr: SocketAddr,
+    registry: Arc<ScriptRegistry>,
+    scheduler: Scheduler,
+    reputation: Option<Reputation>,
+) -> anyhow::Result<()> {
+    let state: SharedState = Arc::new(NodeState { registry, scheduler, reputation });
+    let make_svc = make_service_fn(move |_conn| {
+        let state = state.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| handle(state.clone(), req)))
+        }
+    });
+    println!("JSON-RPC server listening on http://{}", addr);
+    Server::try_bind(&addr)?.serve(make_svc).await?;
+    Ok(())
+}
+
+async fn handle(state: SharedState, req: Request<Body>) -> Result<Response<Body>, Infallible> {
+    if req.method() != Method::POST {
+        return Ok(Response::builder()
+            .status(StatusCode::METHOD_NOT_ALLOWED)
+            .body(Body::from("JSON-RPC requests must be sent with POST"))
+            .unwrap());
+    }
+    let body = match read_body(req.into_body()).await {
+        Ok(Some(body)) => body,
+        Ok(None) => {
+            return Ok(Response::builder()
+                .status(StatusCode::PAYLOAD_TOO_LARGE)
+                .body(Body::from(format!("request body exceeds {} bytes", MAX_BODY_BYTES)))
+                .unwrap());
+        }
+        Err(e) => return Ok(rpc_response(error_body(Value::Null, RpcError::new(PARSE_ERROR, e)))),
+    };
+    let response = match serde_json::from_slice::<RpcRequest>(&body) {
+        Ok(request) if request.jsonrpc != "2.0" => {
+            error_body(request.id, RpcError::new(INVALID_REQUEST, "jsonrpc must be \"2.0\""))
+        }
+        Ok(request) => {
+            let id = request.id.clone();
+            match dispatch(state, request).await {
+                Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+                Err(e) => error_body(id, e),
+            }
+        }
+        Err(e) => error_body(Value::Null, RpcError::new(PARSE_ERROR, e)),
+    };
+    Ok(rpc_response(response))
+}
+
+/// Buffers the request body, `None` once it grows past `MAX_BODY_BYTES`.
+async fn read_body(mut body: Body) -> Result<Option<Vec<u8>>, hyper::Error> {
+    if body.size_hint().lower() > MAX_BODY_BYTES as u64 {
+        return Ok(None);
+    }
+    let mut bytes = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk?;
+        if bytes.len() + chunk.len() > MAX_BODY_BYTES {
+            return Ok(None);
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    Ok(Some(bytes))
+}
+
+fn rpc_response(body: Value) -> Response<Body> {
+    Response::builder()
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+fn error_body(id: Value, error: RpcError) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "error": { "code": error.code, "message": error.message } })
+}
+
+fn params<T: DeserializeOwned>(params: Value) -> Result<T, RpcError> {
+    serde_json::from_value(params).map_err(|e| RpcError::new(INVALID_PARAMS, e))
+}
+
+async fn dispatch(state: SharedState, request: RpcRequest) -> Result<Value, RpcError> {
+    match request.method.as_str() {
+        "orascript_deploy" => {
+            let params: DeployParams = params(request.params)?;
+            with_registry(&state, move |registry| deploy(registry, params)).await
+        }
+        "orascript_execute" => {
+            let request: JobRequest = params(request.params)?;
+            if !state.registry.contains(&request.script_hash) {
+                return Err(RpcError::new(INVALID_PARAMS, format!("unknown script {}", request.script_hash)));
+            }
+            let job_id = state.scheduler.submit(request).map_err(|e| RpcError::new(QUEUE_FULL, e))?;
+            Ok(json!({ "job_id": job_id }))
+        }
+        "orascript_getResult" => {
+            let JobParams { job_id } = params(request.params)?;
+            let status = state.scheduler.status(job_id)
+                .ok_or_else(|| RpcError::new(INVALID_PARAMS, format!("unknown job {}", job_id)))?;
+            Ok(json!(status))
+        }
+        "orascript_cancel" => {
+            let JobParams { job_id } = params(request.params)?;
+            let cancelled = state.scheduler.cancel(job_id).map_err(|e| RpcError::new(INVALID_PARAMS, e))?;
+            Ok(json!({ "cancelled": cancelled }))
+        }
+        "orascript_listScripts" => with_registry(&state, |registry| Ok(json!(registry.list()?))).await,
+        "orascript_removeScript" => {
+            let ScriptParams { script_hash } = params(request.params)?;
+            with_registry(&state, move |registry| {
+                let removed = registry.remove(&script_hash).map_err(|e| RpcError::new(INVALID_PARAMS, e))?;
+                Ok(json!({ "removed": removed }))
+            }).await
+        }
+        "orascript_getTypes" => {
+            let ScriptParams { script_hash } = params(request.params)?;
+            with_registry(&state, move |registry| {
+                let script = registry.load(&script_hash).map_err(|e| RpcError::new(INVALID_PARAMS, e))?;
+                Ok(json!(AbiTypeRegistry::from_abi(&script.abi)?))
+            }).await
+        }
+        "orascript_decode" => {
+            let DecodeParams { script_hash, type_name, output } = params(request.params)?;
+            let output = decode_hex(&output).map_err(|e| RpcError::new(INVALID_PARAMS, e))?;
+            with_registry(&state, move |registry| {
+                let script = registry.load(&script_hash).map_err(|e| RpcError::new(INVALID_PARAMS, e))?;
+                decode_output(&script.abi, &type_name, &output).map_err(|e| RpcError::new(INVALID_PARAMS, e))
+            }).await
+        }
+        "oracle_submitReport" => {
+            let report: SignedReport = params(request.params)?;
+            let evidence = reputation(&state)?.observe(report).map_err(|e| RpcError::new(INVALID_PARAMS, e))?;
+            Ok(json!({ "evidence": evidence }))
+        }
+        "oracle_recordRound" => {
+            let report: AggregatedReport = params(request.params)?;
+            let summary = reputation(&state)?.record(&report).map_err(|e| RpcError::new(INVALID_PARAMS, e))?;
+            Ok(json!(summary))
+        }
+        "oracle_getReputation" => Ok(json!(reputation(&state)?.metrics())),
+        "oracle_getEvidence" => {
+            let call: Option<EvidenceParams> = params(request.params)?;
+            let config = call.map(|call| CallConfig { pallet_index: call.pallet_index, call_index: call.call_index, extrinsic_version: 4 });
+            let entries = reputation(&state)?.evidence().into_iter().map(|evidence| {
+                let call = match &config {
+                    Some(config) => Some(format!("0x{}", hex::encode(ReportEquivocation::from_evidence(&evidence)?.call_data(config)))),
+                    None => None,
+                };
+                Ok(EvidenceEntry { evidence, call })
+            }).collect::<anyhow::Result<Vec<_>>>()?;
+            Ok(json!(entries))
+        }
+        "system_health" => {
+            let scripts = with_registry(&state, |registry| Ok(registry.count()?)).await?;
+            Ok(json!({ "status": "ok", "scripts": scripts, "jobs": state.scheduler.stats() }))
+        }
+        method => Err(RpcError::new(METHOD_NOT_FOUND, format!("method {} not found", method))),
+    }
+}
+
+fn reputation(state: &SharedState) -> Result<&Reputation, RpcError> {
+    state.reputation.as_ref()
+        .ok_or_else(|| RpcError::new(SERVER_ERROR, "reputation tracking is disabled, start the node with --key"))
+}
+
+/// Runs registry work, which reads and writes the disk, on the blocking thread pool.
+async fn with_registry<T, F>(state: &SharedState, work: F) -> Result<T, RpcError>
+where
+    T: Send + 'static,
+    F: FnOnce(&ScriptRegistry) -> Result<T, RpcError> + Send + 'static,
+{
+    let registry = state.registry.clone();
+    tokio::task::spawn_blocking(move || work(&registry)).await
+        .map_err(|e| RpcError::new(SERVER_ERROR, format!("ERROR: registry task failed: {}", e)))?
+}
+
+fn deploy(registry: &ScriptRegistry, params: DeployParams) -> Result<Value, RpcError> {
+    let wasm = decode_hex(&params.wasm).map_err(|e| RpcError::new(INVALID_PARAMS, e))?;
+    let abi = match params.abi {
+        Value::String(abi) => abi,
+        abi => abi.to_string(),
+    };
+    let script_hash = registry.insert(&wasm, &abi).map_err(|e| RpcError::new(INVALID_PARAMS, e))?;
+    Ok(json!({ "script_hash": script_hash }))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::time::Duration;
+    use super::*;
+    use crate::core::runtime::DEFAULT_INPUT;
+    use crate::core::scheduler::SchedulerOptions;
+    use crate::core::testing::{free_addr, orscript2, temp_registry};
+
+    struct TestServer {
+        url: String,
+        http: reqwest::Client,
+    }
+
+    impl TestServer {
+        async fn start(registry: Arc<ScriptRegistry>) -> Self {
+            let addr = free_addr();
+            let scheduler = Scheduler::start(registry.clone(), SchedulerOptions::default());
+            tokio::spawn(serve(addr, registry, scheduler, None));
+            let server = TestServer { url: format!("http://{}", addr), http: reqwest::Client::new() };
+            while server.http.get(&server.url).send().await.is_err() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            server
+        }
+
+        async fn post(&self, body: impl Into<reqwest::Body>) -> reqwest::Response {
+            self.http.post(&self.url).body(body).send().await.unwrap()
+        }
+
+        async fn call(&self, method: &str, params: Value) -> Value {
+            let request = json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params });
+            self.post(request.to_string()).await.json().await.unwrap()
+        }
+    }
+
+    #[tokio::test]
+    async fn deploys_and_executes_scripts() {
+        let (root, registry) = temp_registry("rpc-execute");
+        let server = TestServer::start(registry).await;
+        let (wasm, abi) = orscript2();
+        let abi: Value = serde_json::from_str(&abi).unwrap();
+        let deployed = server.call("orascript_deploy", json!({ "wasm": hex::encode(&wasm), "abi": abi })).await;
+        let script_hash = deployed["result"]["script_hash"].as_str().unwrap().to_string();
+        assert_eq!(script_hash, abi["headers"]["header"]);
+        assert_eq!(server.call("system_health", Value::Null).await["result"]["scripts"], 1);
+
+        let job = server.call("orascript_execute", json!({ "script_hash": script_hash, "input": DEFAULT_INPUT })).await;
+        let job_id = job["result"]["job_id"].as_u64().unwrap();
+        let status = loop {
+            let status = server.call("orascript_getResult", json!({ "job_id": job_id })).await["result"].clone();
+            if status["status"] != "queued" && status["status"] != "running" {
+                break status;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        };
+        assert_eq!(status["status"], "succeeded", "{}", status);
+        let decoded = server.call("orascript_decode", json!({ "script_hash": script_hash, "type": "Output", "output": status["output"] })).await;
+        assert_eq!(decoded["result"]["greater"], "bitcoin");
+
+        let unknown = server.call("orascript_execute", json!({ "script_hash": format!("0x{}", "00".repeat(32)), "input": "{}" })).await;
+        assert_eq!(unknown["error"]["code"], INVALID_PARAMS);
+        assert_eq!(server.call("orascript_getResult", json!({ "job_id": job_id + 100 })).await["error"]["code"], INVALID_PARAMS);
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_requests() {
+        let (root, registry) = temp_registry("rpc-invalid");
+        let server = TestServer::start(registry).await;
+        let malformed: Value = server.post("{\"jsonrpc\": \"2.0\", \"method\":").await.json().await.unwrap();
+        assert_eq!(malformed["error"]["code"], PARSE_ERROR);
+        let version: Value = server.post(json!({ "jsonrpc": "1.0", "id": 3, "method": "system_health" }).to_string()).await.json().await.unwrap();
+        assert_eq!((version["id"].clone(), version["error"]["code"].clone()), (json!(3), json!(INVALID_REQUEST)));
+        assert_eq!(server.call("orascript_missing", Value::Null).await["error"]["code"], METHOD_NOT_FOUND);
+        assert_eq!(server.call("orascript_getResult", json!({ "job": 1 })).await["error"]["code"], INVALID_PARAMS);
+        assert_eq!(server.call("oracle_getReputation", Value::Null).await["error"]["code"], SERVER_ERROR);
+
+        let oversized = server.post(vec![b' '; MAX_BODY_BYTES + 1]).await;
+        assert_eq!(oversized.status(), reqwest::StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(server.http.get(&server.url).send().await.unwrap().status(), reqwest::StatusCode::METHOD_NOT_ALLOWED);
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -1,24 +1,34 @@
+use anyhow::{anyhow, Result};
 use reqwest::header::{HeaderMap, HeaderValue, USER_AGENT, HeaderName};
 use std::collections::HashMap;
+use std::time::Duration;
+
+/// Parses request headers given as strings, failing on the first invalid name or value.
+pub(crate) fn header_map(headers: &HashMap<String, String>) -> Result<HeaderMap> {
+    let mut map = HeaderMap::new();
+    for (key, val) in headers {
+        let name = key.parse::<HeaderName>().map_err(|e| anyhow!("ERROR: invalid header name {:?}: {}", key, e))?;
+        let value = HeaderValue::from_str(val).map_err(|e| anyhow!("ERROR: invalid value of header {}: {}", key, e))?;
+        map.insert(name, value);
+    }
+    Ok(map)
+}
 
+/// GETs `url` and returns its body, the whole request is abandoned after `timeout`.
 pub(crate) async fn fetch_with_query(
     url: &str,
     params: Option<&HashMap<&str, &str>>,
     extra_headers: Option<HashMap<String, String>>,
-) -> Result<String, reqwest::Error> {
-    let client = reqwest::Client::new();
+    timeout: Duration,
+) -> Result<String> {
+    let client = reqwest::Client::builder().timeout(timeout).build()?;
 
     // Build headers with default User-Agent
     let mut headers = HeaderMap::new();
     headers.insert(USER_AGENT, HeaderValue::from_static("MyRustClient/0.1"));
 
     if let Some(hdrs) = extra_headers {
-        for (key, val) in hdrs {
-            headers.insert(
-                key.parse::<HeaderName>().unwrap(),
-                HeaderValue::from_str(&val).unwrap(),
-            );
-        }
+        headers.extend(header_map(&hdrs)?);
     }
 
     let req = client.get(url).headers(headers);
@@ -30,9 +40,8 @@ pub(crate) async fn fetch_with_query(
         req
     };
 
-    let res = req.send().await?;
+    let res = req.send().await.map_err(|e| anyhow!("ERROR: request to {} failed: {}", url, e))?;
 
-    let body = res.text().await?;
+    let body = res.text().await.map_err(|e| anyhow!("ERROR: failed to read the response of {}: {}", url, e))?;
     Ok(body)
 }
-
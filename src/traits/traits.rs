@@ -1,3 +1,114 @@
 use std::fmt::Debug;
+use std::pin::Pin;
+use anyhow::{anyhow, Result};
+use serde_json::Value;
+use tokio::sync::mpsc;
+use crate::core::abi_value::{AbiTypeDesc, ARRAY_BUFFER_ID, STRING_ID};
 
-pub trait ABIType : Debug {}
\ No newline at end of file
+/// Size and alignment of a value stored inline in a class field or array element.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Layout {
+    pub size: u32,
+    pub align: u32,
+}
+
+impl Layout {
+    pub const fn new(size: u32, align: u32) -> Self {
+        Layout { size, align }
+    }
+
+    /// Every managed AssemblyScript object (string, array, class) is stored as a 32-bit pointer.
+    pub const POINTER: Layout = Layout::new(4, 4);
+}
+
+/// Linear memory of a running script.
+pub trait AbiMemory {
+    fn read(&mut self, ptr: u32, buf: &mut [u8]) -> Result<()>;
+    fn write(&mut self, ptr: u32, bytes: &[u8]) -> Result<()>;
+    /// Allocates a managed object of `size` bytes with the AssemblyScript runtime id `rt_id`.
+    fn alloc(&mut self, size: u32, rt_id: u32) -> Result<u32>;
+    /// Current size of the memory in bytes, lengths read from the script are checked against it.
+    fn data_size(&mut self) -> usize;
+
+    fn read_u32(&mut self, ptr: u32) -> Result<u32> {
+        let mut bytes = [0u8; 4];
+        self.read(ptr, &mut bytes)?;
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    fn write_u32(&mut self, ptr: u32, value: u32) -> Result<()> {
+        self.write(ptr, &value.to_le_bytes())
+    }
+
+    /// Runtime id of the managed type `type_name`, e.g. `Array<CryptoValue>`. Only the ids of the
+    /// built-in `ArrayBuffer` and `string` are fixed, the others are assigned by the compiler.
+    fn runtime_id(&mut self, type_name: &str) -> Result<u32> {
+        match type_name {
+            "ArrayBuffer" => Ok(ARRAY_BUFFER_ID),
+            "string" => Ok(STRING_ID),
+            name => Err(anyhow!("ERROR: the runtime id of {} is unknown, only strings and buffers can be allocated in this script", name)),
+        }
+    }
+}
+
+/// A value that can cross the boundary between a script and the node: read from the script's
+/// memory in AssemblyScript layout, SCALE encoded for reports and rendered as JSON.
+///
+/// Constructors take the ABI type the value is read as, statically typed implementations reject
+/// any type other than their own. Managed values are written by allocating their objects through
+/// `AbiMemory::alloc`, with the runtime ids the memory knows for their types.
+pub trait ABIType : Debug + Sized {
+    /// Name of the type in the ABI, e.g. `f32`, `string` or `Array<CryptoValue>`.
+    fn type_name(&self) -> String;
+    /// Size and alignment of the value stored inline, `Layout::POINTER` for managed types.
+    fn layout(&self) -> Layout;
+    /// Reads the value stored at `ptr`, for managed types `ptr` holds the pointer to the object.
+    fn read_from_memory(memory: &mut dyn AbiMemory, ptr: u32, ty: &AbiTypeDesc) -> Result<Self>;
+    /// Stores the value at `ptr` the way `read_from_memory` reads it, managed values are allocated
+    /// first and `ptr` receives the pointer to their object.
+    fn write_to_memory(&self, memory: &mut dyn AbiMemory, ptr: u32) -> Result<()>;
+    fn encode_scale(&self, dest: &mut Vec<u8>);
+    fn decode_scale(input: &mut &[u8], ty: &AbiTypeDesc) -> Result<Self>;
+    fn to_json(&self) -> Value;
+}
+
+/// Executes scripts by hash and selector, used by the clients generated with `include_abi!`.
+pub trait ScriptRuntime {
+    /// Runs the function `selector` of the script `script_hash` and returns its SCALE output.
+    fn call(&self, script_hash: &str, selector: &str, input: &str) -> Result<Vec<u8>>;
+}
+
+/// Latest finalized block of a chain.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockRef {
+    pub number: u64,
+    /// Hex encoded block hash.
+    pub hash: String,
+}
+
+/// Read access to a Substrate chain, implemented over JSON-RPC by `RpcChainClient` and in
+/// process by `MockChain`.
+pub trait ChainClient: Send + Sync + 'static {
+    fn finalized_head(&self) -> impl Future<Output = Result<BlockRef>> + Send;
+    /// Hash of the canonical block `number`, `None` if it does not exist yet.
+    fn block_hash(&self, number: u64) -> impl Future<Output = Result<Option<String>>> + Send;
+    /// Raw value under the storage `key` at block `at`, `None` if it is not set.
+    fn storage(&self, key: &[u8], at: &str) -> impl Future<Output = Result<Option<Vec<u8>>>> + Send;
+    /// Subscribes to the finalized heads, which the node then pushes as blocks are finalized.
+    /// `None` when the client has no subscriptions and its heads are polled.
+    fn finalized_heads(&self) -> impl Future<Output = Result<Option<mpsc::Receiver<BlockRef>>>> + Send {
+        async { Ok(None) }
+    }
+}
+
+/// Wasm and ABI of a script fetched from outside the registry, not verified yet.
+pub struct FetchedScript {
+    pub wasm: Vec<u8>,
+    pub abi: String,
+}
+
+/// Where the registry looks up scripts it does not hold, e.g. `ChainScriptSource`.
+pub trait ScriptSource: Send + Sync {
+    /// Fetches the script stored under `hash`, `None` if the source does not know it.
+    fn fetch<'a>(&'a self, hash: &'a str) -> Pin<Box<dyn Future<Output = Result<Option<FetchedScript>>> + Send + 'a>>;
+}
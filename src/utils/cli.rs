@@ -1,7 +1,352 @@
+use std::net::SocketAddr;
 use std::path::PathBuf;
 
 use clap::{Parser, Subcommand};
 
-struct Cli {
-    
-}
\ No newline at end of file
+#[derive(Parser, Debug)]
+#[command(name = "orascript", about = "Orascript WASM Runner")]
+pub struct Cli {
+    /// Directory of the content addressed script registry
+    #[arg(long, global = true, default_value = "./registry")]
+    pub registry: PathBuf,
+    /// Use the pooling instance allocator for script executions
+    #[arg(long, global = true)]
+    pub pooling: bool,
+    /// Print the values and imports of every script execution
+    #[arg(long, global = true)]
+    pub trace: bool,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Execute a script and optionally sign its output as a round report
+    Run {
+        #[arg(long, default_value = "./orascript/output/orscript2ABI.json")]
+        abi: String,
+        #[arg(long, default_value = "./orascript/assembly/orscript2.wasm")]
+        wasm: String,
+        /// Run a registry script by its hash instead of the ABI/wasm files
+        #[arg(long, conflicts_with_all = ["abi", "wasm"])]
+        script: Option<String>,
+        /// JSON input passed to the script
+        #[arg(long)]
+        input: Option<String>,
+        /// Node key used to sign the output
+        #[arg(long, requires = "report")]
+        key: Option<PathBuf>,
+        #[arg(long, default_value_t = 0)]
+        round: u64,
+        /// Where to write the signed report
+        #[arg(long, requires = "key")]
+        report: Option<PathBuf>,
+        /// Write a commitment to the output here, the report then holds the salted reveal
+        #[arg(long, requires = "report")]
+        commit: Option<PathBuf>,
+    },
+    /// Generate a new node key
+    Keygen {
+        #[arg(long)]
+        out: PathBuf,
+    },
+    /// Aggregate signed reports of one round into an M-of-N report
+    Aggregate {
+        /// Agreeing reports required, more than half of the authorities in `exact` mode
+        #[arg(long)]
+        threshold: usize,
+        /// Public key of a node allowed to report, repeat for every authority
+        #[arg(long = "authority", required = true)]
+        authorities: Vec<String>,
+        /// `exact`, or `median-<i32|i64|f32|f64>` for numeric outputs
+        #[arg(long, default_value = "exact")]
+        mode: String,
+        #[arg(long)]
+        out: PathBuf,
+        #[arg(required = true)]
+        reports: Vec<PathBuf>,
+    },
+    /// Aggregate the reveals of a commit-reveal round, excluding nodes that did not reveal
+    AggregateReveals {
+        #[arg(long)]
+        threshold: usize,
+        #[arg(long = "authority", required = true)]
+        authorities: Vec<String>,
+        #[arg(long, default_value = "exact")]
+        mode: String,
+        /// Commitment published by a node, repeat for every commitment
+        #[arg(long = "commitment", required = true)]
+        commitments: Vec<PathBuf>,
+        #[arg(long)]
+        out: PathBuf,
+        reveals: Vec<PathBuf>,
+    },
+    /// Verify an aggregated report against an authority set
+    Verify {
+        #[arg(long)]
+        threshold: usize,
+        #[arg(long = "authority", required = true)]
+        authorities: Vec<String>,
+        report: PathBuf,
+    },
+    /// Wrap an aggregated report into a `pallet_oracle::submit_result` extrinsic for a relayer
+    Extrinsic {
+        #[arg(long)]
+        pallet_index: u8,
+        #[arg(long)]
+        call_index: u8,
+        /// Extrinsic format version of the runtime metadata
+        #[arg(long, default_value_t = 4)]
+        extrinsic_version: u8,
+        /// On-chain request the result answers
+        #[arg(long)]
+        request_id: u64,
+        /// Signatures the report needs from the authority set before it is submitted
+        #[arg(long)]
+        threshold: usize,
+        #[arg(long = "authority", required = true)]
+        authorities: Vec<String>,
+        /// Key signing the transaction, an unsigned extrinsic is built when omitted
+        #[arg(long, requires_all = ["spec_version", "tx_version", "genesis_hash"])]
+        key: Option<PathBuf>,
+        #[arg(long, default_value_t = 0)]
+        nonce: u32,
+        #[arg(long, default_value_t = 0)]
+        tip: u128,
+        #[arg(long, requires = "key")]
+        spec_version: Option<u32>,
+        #[arg(long, requires = "key")]
+        tx_version: Option<u32>,
+        #[arg(long, requires = "key")]
+        genesis_hash: Option<String>,
+        /// The runtime has the `CheckMetadataHash` signed extension
+        #[arg(long)]
+        metadata_hash_check: bool,
+        /// Where to write the extrinsic, printed when omitted
+        #[arg(long)]
+        out: Option<PathBuf>,
+        report: PathBuf,
+    },
+    /// Commit to the outputs of aggregated reports with one merkle root
+    Batch {
+        /// `blake2-256` or `sha256`
+        #[arg(long, default_value = "blake2-256")]
+        hash: String,
+        /// Signatures every report needs from the authority set
+        #[arg(long)]
+        threshold: usize,
+        #[arg(long = "authority", required = true)]
+        authorities: Vec<String>,
+        /// On-chain request answered by each report, repeat once per report in the same order
+        #[arg(long = "request-id", required = true)]
+        request_ids: Vec<u64>,
+        /// Where to write the root and proofs, printed when omitted
+        #[arg(long)]
+        out: Option<PathBuf>,
+        /// Aggregated reports
+        #[arg(required = true)]
+        reports: Vec<PathBuf>,
+    },
+    /// Verify a merkle inclusion proof written by `batch`
+    VerifyProof {
+        proof: PathBuf,
+        /// Hex encoded batch root submitted on chain, the proof is checked against it
+        #[arg(long)]
+        root: String,
+        /// Hex encoded output the proof must commit to
+        #[arg(long)]
+        output: Option<String>,
+    },
+    /// Verify misbehavior evidence returned by `oracle_getEvidence`
+    VerifyEvidence {
+        evidence: PathBuf,
+    },
+    /// Measure execution throughput of a script
+    Bench {
+        #[arg(long, default_value = "./orascript/output/orscript2ABI.json")]
+        abi: String,
+        #[arg(long, default_value = "./orascript/assembly/orscript2.wasm")]
+        wasm: String,
+        /// Benchmark a registry script by its hash instead of the ABI/wasm files
+        #[arg(long, conflicts_with_all = ["abi", "wasm"])]
+        script: Option<String>,
+        #[arg(long)]
+        input: Option<String>,
+        #[arg(long, default_value_t = 1000)]
+        iterations: usize,
+        /// Worker threads used by the concurrent cases
+        #[arg(long, default_value_t = 4)]
+        concurrency: usize,
+    },
+    /// Run scheduled oracle feeds and publish their updates
+    Feeds {
+        /// JSON file with the list of feed definitions
+        #[arg(long)]
+        config: PathBuf,
+        /// Node key used to sign every published update
+        #[arg(long, requires = "out")]
+        key: Option<PathBuf>,
+        /// Directory the signed reports are written to
+        #[arg(long, requires = "key")]
+        out: Option<PathBuf>,
+        #[arg(long, default_value_t = 4)]
+        workers: usize,
+    },
+    /// Execute the oracle requests raised in finalized blocks of a Substrate chain
+    Listen {
+        /// HTTP JSON-RPC endpoint of the chain node
+        #[arg(long, default_value = "http://127.0.0.1:9944")]
+        chain_url: String,
+        /// WebSocket JSON-RPC endpoint of the chain node, finalized heads are subscribed to
+        /// there instead of polled every `--poll-ms`
+        #[arg(long)]
+        chain_ws_url: Option<String>,
+        /// Storage value holding the requests of a block, `Oracle.BlockRequests` when omitted
+        #[arg(long)]
+        requests_key: Option<String>,
+        #[arg(long, default_value_t = 2000)]
+        poll_ms: u64,
+        /// First block to process, the current finalized block when omitted
+        #[arg(long)]
+        from_block: Option<u64>,
+        /// Fetch scripts missing from the registry from the chain's script storage map
+        #[arg(long)]
+        resolve_scripts: bool,
+        /// Prefix of the script storage map, `Oracle.Scripts` when omitted
+        #[arg(long)]
+        scripts_prefix: Option<String>,
+        /// `identity`, `blake2-128-concat` or `twox-64-concat`
+        #[arg(long, default_value = "blake2-128-concat")]
+        scripts_hasher: String,
+        /// Node key used to sign every result, with the request id as round
+        #[arg(long, requires = "out")]
+        key: Option<PathBuf>,
+        /// Directory the signed reports are written to
+        #[arg(long, requires = "key")]
+        out: Option<PathBuf>,
+        #[arg(long, default_value_t = 4)]
+        workers: usize,
+    },
+    /// Run an in-process mock chain serving the RPC methods `listen` uses
+    MockChain {
+        #[arg(long, default_value = "127.0.0.1:9944")]
+        addr: SocketAddr,
+        /// JSON list of `{ request_id, script_hash, input }`, raised one per block
+        #[arg(long)]
+        requests: Option<PathBuf>,
+        #[arg(long)]
+        requests_key: Option<String>,
+        /// ABI and wasm of a script deployed to the script storage map in block 1, repeatable
+        #[arg(long = "script", num_args = 2, value_names = ["ABI", "WASM"])]
+        scripts: Vec<PathBuf>,
+        #[arg(long)]
+        scripts_prefix: Option<String>,
+        #[arg(long, default_value = "blake2-128-concat")]
+        scripts_hasher: String,
+        #[arg(long, default_value_t = 1000)]
+        block_ms: u64,
+    },
+    /// Exchange signed reports, commitments and reveals with other nodes over TCP
+    Gossip {
+        #[arg(long)]
+        key: PathBuf,
+        #[arg(long, default_value = "127.0.0.1:7700")]
+        listen: SocketAddr,
+        /// Address of a peer to keep connected to, repeatable
+        #[arg(long = "peer")]
+        peers: Vec<SocketAddr>,
+        /// Key allowed to connect and publish, repeatable; any key when omitted
+        #[arg(long = "authority")]
+        authorities: Vec<String>,
+        /// Messages accepted per second from one peer
+        #[arg(long, default_value_t = 50.0)]
+        rate: f64,
+        #[arg(long, default_value_t = 100.0)]
+        burst: f64,
+        /// Inbound connections open at once
+        #[arg(long, default_value_t = 64)]
+        max_inbound: usize,
+        /// Signed report, commitment or reveal published once the peers are connected, repeatable
+        #[arg(long)]
+        publish: Vec<PathBuf>,
+    },
+    /// Run oracle rounds over a simulated network of nodes sharing a mock chain
+    Simulate {
+        /// JSON simulation config: script, nodes, rounds, data sources and faults
+        config: PathBuf,
+        /// Where to write the full simulation report
+        #[arg(long)]
+        out: Option<PathBuf>,
+    },
+    /// Convert the classes of an ABI into a scale-info type registry
+    Types {
+        abi: PathBuf,
+        /// Embed the registry into the ABI file under `types`
+        #[arg(long)]
+        embed: bool,
+        /// Where to write the registry, printed when omitted
+        #[arg(long, conflicts_with = "embed")]
+        out: Option<PathBuf>,
+    },
+    /// Decode a SCALE encoded output into JSON using the classes of an ABI
+    Decode {
+        #[arg(long)]
+        abi: PathBuf,
+        /// ABI type of the output, e.g. `Output` or `Array<CryptoValue>`
+        #[arg(long = "type")]
+        type_name: String,
+        /// Hex encoded output
+        output: String,
+    },
+    /// Manage the script registry
+    Registry {
+        #[command(subcommand)]
+        action: RegistryAction,
+    },
+    /// Serve the JSON-RPC API for deploying and executing scripts
+    Serve {
+        #[arg(long, default_value = "127.0.0.1:9933")]
+        addr: SocketAddr,
+        /// Executions running at the same time
+        #[arg(long, default_value_t = 4)]
+        workers: usize,
+        /// Jobs waiting for a worker before submissions are rejected
+        #[arg(long, default_value_t = 256)]
+        queue_capacity: usize,
+        /// Wall-clock limit of a job that does not set `timeout_ms`
+        #[arg(long, default_value_t = 5000)]
+        timeout_ms: u64,
+        /// Longest `timeout_ms` a job may set, longer ones are clamped
+        #[arg(long, default_value_t = 60000)]
+        max_timeout_ms: u64,
+        /// Node key, enables reputation tracking and signs misbehavior evidence
+        #[arg(long)]
+        key: Option<PathBuf>,
+        /// Node scored in every recorded round, repeatable; every node seen when omitted
+        #[arg(long = "authority", requires = "key")]
+        authorities: Vec<String>,
+        /// Also track the reports gossiped by peers, listening for them on this address
+        #[arg(long, requires = "key")]
+        gossip_listen: Option<SocketAddr>,
+        /// Gossip peer to keep connected to, repeatable
+        #[arg(long = "peer", requires = "gossip_listen")]
+        peers: Vec<SocketAddr>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum RegistryAction {
+    /// Store a wasm + ABI pair under its header hash
+    Add {
+        #[arg(long)]
+        abi: PathBuf,
+        #[arg(long)]
+        wasm: PathBuf,
+    },
+    /// List stored scripts
+    List,
+    /// Remove a stored script
+    Remove {
+        hash: String,
+    },
+}
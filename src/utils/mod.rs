@@ -1,2 +1,3 @@
 pub mod cli;
-pub mod regex_str;
\ No newline at end of file
+pub mod regex_str;
+pub mod twox;
@@ -0,0 +1,44 @@
+//! Storage keys built from the XXH64 based `twox` hashes Substrate uses for storage prefixes and
+//! map keys.
+
+use sp_core_hashing::twox_128;
+
+/// Key prefix of a storage item, `twox_128(pallet) ++ twox_128(item)`.
+pub fn storage_prefix(pallet: &str, item: &str) -> [u8; 32] {
+    let mut key = [0u8; 32];
+    key[..16].copy_from_slice(&twox_128(pallet.as_bytes()));
+    key[16..].copy_from_slice(&twox_128(item.as_bytes()));
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use sp_core_hashing::twox_64;
+    use super::*;
+
+    #[test]
+    fn twox_64_matches_the_xxh64_reference_vectors() {
+        assert_eq!(twox_64(b""), 0xef46db3751d8e999u64.to_le_bytes());
+        assert_eq!(twox_64(b"a"), 0xd24ec4f1a98c6e5bu64.to_le_bytes());
+        assert_eq!(twox_64(b"abc"), 0x44bc2cf5ad770999u64.to_le_bytes());
+        assert_eq!(twox_64(b"Nobody inspects the spammish repetition"), 0xfbcea83c8a378bf1u64.to_le_bytes());
+    }
+
+    #[test]
+    fn twox_128_matches_substrate_storage_prefixes() {
+        assert_eq!(hex::encode(twox_128(b"Timestamp")), "f0c365c3cf59d671eb72da0e7a4113c4");
+        assert_eq!(hex::encode(twox_128(b"TotalIssuance")), "57c875e4cff74148e4628f264b974c80");
+        assert_eq!(hex::encode(twox_128(b"Now")), "9f1f0515f462cdcf84e0f1d6045dfcbb");
+        assert_eq!(hex::encode(twox_128(b"Key")), "530ebca703c85910e7164cb7d1c9e47b");
+    }
+
+    #[test]
+    fn storage_prefix_of_system_account() {
+        assert_eq!(
+            hex::encode(storage_prefix("System", "Account")),
+            "26aa394eea5630e07c48ae0c9558cef7b99d880ec681799c0cf30e8886371da9",
+        );
+        assert_eq!(hex::encode(storage_prefix("Balances", "TotalIssuance")),
+            "c2261276cc9d1f8598ea4b6a74b15c2f57c875e4cff74148e4628f264b974c80");
+    }
+}